@@ -11,10 +11,12 @@
 import type * as actorOverride from "../actorOverride.js";
 import type * as dashboard from "../dashboard.js";
 import type * as deviceAliases from "../deviceAliases.js";
+import type * as deviceKeys from "../deviceKeys.js";
 import type * as dictation from "../dictation.js";
 import type * as http from "../http.js";
 import type * as lib_aggregation from "../lib/aggregation.js";
 import type * as lib_deviceAlias from "../lib/deviceAlias.js";
+import type * as lib_signing from "../lib/signing.js";
 import type * as lib_spanIngest from "../lib/spanIngest.js";
 import type * as migration from "../migration.js";
 import type * as rebuild from "../rebuild.js";
@@ -33,10 +35,12 @@ declare const fullApi: ApiFromModules<{
   actorOverride: typeof actorOverride;
   dashboard: typeof dashboard;
   deviceAliases: typeof deviceAliases;
+  deviceKeys: typeof deviceKeys;
   dictation: typeof dictation;
   http: typeof http;
   "lib/aggregation": typeof lib_aggregation;
   "lib/deviceAlias": typeof lib_deviceAlias;
+  "lib/signing": typeof lib_signing;
   "lib/spanIngest": typeof lib_spanIngest;
   migration: typeof migration;
   rebuild: typeof rebuild;
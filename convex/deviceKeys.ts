@@ -0,0 +1,169 @@
+import { v } from "convex/values";
+import { internalMutation, internalQuery } from "./_generated/server";
+import type { MutationCtx } from "./_generated/server";
+import { resolveCanonicalDevice } from "./lib/deviceAlias";
+import { claimNonce as claimLedgerNonce } from "./lib/signing";
+
+// Tracker signing keys registered by `backend enroll` (see
+// tracker/src/ingest/signing.rs for the header scheme; convex/http.ts checks
+// that the enroll request is signed by the key it registers). A leaked
+// bearer secret is enough to call /enroll, so a key is only trusted on sight
+// when it is the device name's first and that name has already sent spans;
+// anything else -- a reinstall, a name the server has never seen, someone
+// else claiming the device -- stays unapproved until approveDeviceKey is run
+// by hand. Re-enrolling a pending first key approves it once spans from the
+// device have arrived.
+export const enroll = internalMutation({
+    args: {
+        deviceName: v.string(),
+        keyId: v.string(),
+        publicKey: v.string(),
+    },
+    returns: v.object({
+        status: v.union(v.literal("enrolled"), v.literal("already-enrolled"), v.literal("pending-approval"), v.literal("key-conflict")),
+    }),
+    handler: async (ctx, args) => {
+        const existing = await ctx.db
+            .query("deviceKeys")
+            .withIndex("by_keyId", (q) => q.eq("keyId", args.keyId))
+            .unique();
+        if (existing && (existing.deviceName !== args.deviceName || existing.publicKey !== args.publicKey)) {
+            return { status: "key-conflict" } as const;
+        }
+        if (existing?.approved) {
+            return { status: "already-enrolled" } as const;
+        }
+
+        const approved = await approvableOnSight(ctx, args.deviceName, args.keyId);
+        if (existing) {
+            if (approved) await ctx.db.patch(existing._id, { approved });
+        } else {
+            await ctx.db.insert("deviceKeys", { ...args, approved, enrolledAt: Date.now() });
+        }
+        return { status: approved ? "enrolled" : "pending-approval" } as const;
+    },
+});
+
+// No other key for the device name, and spans from it already stored.
+async function approvableOnSight(ctx: MutationCtx, deviceName: string, keyId: string): Promise<boolean> {
+    const keys = await ctx.db
+        .query("deviceKeys")
+        .withIndex("by_deviceName", (q) => q.eq("deviceName", deviceName))
+        .collect();
+    if (keys.some((key) => key.keyId !== keyId)) return false;
+    const canonicalDevice = await resolveCanonicalDevice(ctx, deviceName);
+    const span = await ctx.db
+        .query("spans")
+        .withIndex("by_deviceName_startedAt", (q) => q.eq("deviceName", canonicalDevice))
+        .first();
+    return span !== null;
+}
+
+export const byKeyId = internalQuery({
+    args: { keyId: v.string() },
+    returns: v.union(
+        v.null(),
+        v.object({ deviceName: v.string(), publicKey: v.string(), approved: v.boolean() }),
+    ),
+    handler: async (ctx, args) => {
+        const key = await ctx.db
+            .query("deviceKeys")
+            .withIndex("by_keyId", (q) => q.eq("keyId", args.keyId))
+            .unique();
+        return key && { deviceName: key.deviceName, publicKey: key.publicKey, approved: key.approved };
+    },
+});
+
+// Which of `deviceNames` have an approved key, and so may no longer send
+// unsigned requests.
+export const withApprovedKeys = internalQuery({
+    args: { deviceNames: v.array(v.string()) },
+    returns: v.array(v.string()),
+    handler: async (ctx, args) => {
+        const locked: string[] = [];
+        for (const deviceName of new Set(args.deviceNames)) {
+            const keys = await ctx.db
+                .query("deviceKeys")
+                .withIndex("by_deviceName", (q) => q.eq("deviceName", deviceName))
+                .collect();
+            if (keys.some((key) => key.approved)) locked.push(deviceName);
+        }
+        return locked;
+    },
+});
+
+// Records a verified request's nonce (see claimNonce in lib/signing.ts);
+// false if it was already used. Runs as one transaction, so two copies of
+// a request racing each other can't both get in.
+export const claimNonce = internalMutation({
+    args: { keyId: v.string(), nonce: v.string(), timestampMs: v.number() },
+    returns: v.boolean(),
+    handler: async (ctx, args) => {
+        const find = (keyId: string, nonce: string) =>
+            ctx.db
+                .query("ingestNonces")
+                .withIndex("by_keyId_nonce", (q) => q.eq("keyId", keyId).eq("nonce", nonce))
+                .unique();
+        return await claimLedgerNonce(
+            {
+                expiresAt: async (keyId, nonce) => (await find(keyId, nonce))?.expiresAt ?? null,
+                record: async (keyId, nonce, expiresAt) => {
+                    const row = await find(keyId, nonce);
+                    if (row) {
+                        await ctx.db.patch(row._id, { expiresAt });
+                    } else {
+                        await ctx.db.insert("ingestNonces", { keyId, nonce, expiresAt });
+                    }
+                },
+                deleteExpired: async (nowMs, limit) => {
+                    const expired = await ctx.db
+                        .query("ingestNonces")
+                        .withIndex("by_expiresAt", (q) => q.lt("expiresAt", nowMs))
+                        .take(limit);
+                    for (const row of expired) await ctx.db.delete(row._id);
+                },
+            },
+            args.keyId,
+            args.nonce,
+            args.timestampMs,
+            Date.now(),
+        );
+    },
+});
+
+export const approveDeviceKey = internalMutation({
+    args: { keyId: v.string() },
+    returns: v.null(),
+    handler: async (ctx, args) => {
+        const key = await ctx.db
+            .query("deviceKeys")
+            .withIndex("by_keyId", (q) => q.eq("keyId", args.keyId))
+            .unique();
+        if (!key) throw new Error(`no enrolled key ${args.keyId}`);
+        await ctx.db.patch(key._id, { approved: true });
+        return null;
+    },
+});
+
+export const list = internalQuery({
+    args: {},
+    returns: v.array(
+        v.object({
+            deviceName: v.string(),
+            keyId: v.string(),
+            publicKey: v.string(),
+            approved: v.boolean(),
+            enrolledAt: v.number(),
+        }),
+    ),
+    handler: async (ctx) => {
+        const rows = await ctx.db.query("deviceKeys").collect();
+        return rows.map((row) => ({
+            deviceName: row.deviceName,
+            keyId: row.keyId,
+            publicKey: row.publicKey,
+            approved: row.approved,
+            enrolledAt: row.enrolledAt,
+        }));
+    },
+});
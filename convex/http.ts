@@ -1,6 +1,9 @@
 import { httpRouter } from "convex/server";
 import { httpAction } from "./_generated/server";
+import type { ActionCtx } from "./_generated/server";
 import { api, internal } from "./_generated/api";
+import { fromBase64, keyIdOf, readSignedHeaders, verifySignature } from "./lib/signing";
+import type { SignedHeaders } from "./lib/signing";
 import { statuslineHttpHandler } from "./statusline";
 
 // Machine-to-machine auth for both routes below: a single shared secret in
@@ -9,6 +12,10 @@ import { statuslineHttpHandler } from "./statusline";
 // accessible to the internet that check a shared secret ... before doing
 // anything else"). Set via `convex env set CHRONOMAXI_INGEST_SECRET <value>`
 // on the deployment; there is no default, a missing env var fails closed.
+// /ingest and /enroll also check per-device request signatures
+// (checkDeviceSignature below).
+// The tracker's default batch ceiling (DEFAULT_SPOOL_BATCH_SIZE_MAX in
+// tracker/src/config.rs) matches this; change both together.
 const MAX_INGEST_BATCH_SIZE = 500;
 
 function checkBearerSecret(request: Request): Response | null {
@@ -26,15 +33,88 @@ function checkBearerSecret(request: Request): Response | null {
     return null;
 }
 
+// The tracker gzips ingest bodies when `ingest_gzip` is on
+// (tracker/src/ingest/convex.rs); anything else arrives as plain JSON.
 async function readJsonBody(request: Request): Promise<unknown | typeof INVALID_JSON> {
+    return parseJsonBody(new Uint8Array(await request.arrayBuffer()), request);
+}
+
+// `readJsonBody` over bytes already read, for routes that also need them
+// as received (signatures cover the body before gunzip).
+async function parseJsonBody(raw: Uint8Array<ArrayBuffer>, request: Request): Promise<unknown | typeof INVALID_JSON> {
     try {
-        return await request.json();
+        if (request.headers.get("Content-Encoding") === "gzip") {
+            const inflated = new Blob([raw]).stream().pipeThrough(new DecompressionStream("gzip"));
+            return JSON.parse(await new Response(inflated).text());
+        }
+        return JSON.parse(new TextDecoder().decode(raw));
     } catch {
         return INVALID_JSON;
     }
 }
 const INVALID_JSON = Symbol("invalid-json");
 
+// Per-device signatures (convex/lib/signing.ts) on top of the bearer
+// secret. A request signed with an approved key must verify, carry only
+// rows from the device it was signed as, and use a fresh nonce. Anything
+// else -- unsigned, or signed with a key not enrolled or approved yet (a
+// tracker creates its key before `backend enroll`) -- is accepted only
+// while none of `deviceNames` has an approved key.
+async function checkDeviceSignature(
+    ctx: ActionCtx,
+    request: Request,
+    raw: Uint8Array<ArrayBuffer>,
+    deviceNames: string[],
+): Promise<Response | null> {
+    const signed = readSignedHeaders(request.headers);
+    if (signed.kind === "malformed") {
+        return new Response(signed.reason, { status: 401 });
+    }
+    if (signed.kind === "signed") {
+        const key = await ctx.runQuery(internal.deviceKeys.byKeyId, { keyId: signed.headers.keyId });
+        if (key?.approved) {
+            const signatureError = await checkSignature(ctx, signed.headers, key, raw);
+            if (signatureError) return signatureError;
+            const foreign = deviceNames.find((deviceName) => deviceName !== signed.headers.device);
+            if (foreign !== undefined) {
+                return new Response(`row deviceName ${foreign} is not the signing device ${signed.headers.device}`, {
+                    status: 403,
+                });
+            }
+            return null;
+        }
+    }
+    const locked = await ctx.runQuery(internal.deviceKeys.withApprovedKeys, { deviceNames });
+    if (locked.length > 0) {
+        return new Response(`${locked.join(", ")} must sign with an approved key`, { status: 401 });
+    }
+    return null;
+}
+
+// `verifySignature`, then spends the nonce.
+async function checkSignature(
+    ctx: ActionCtx,
+    headers: SignedHeaders,
+    key: { deviceName: string; publicKey: string },
+    raw: Uint8Array<ArrayBuffer>,
+): Promise<Response | null> {
+    const error = await verifySignature(headers, key, raw, Date.now());
+    if (error) {
+        return new Response(error, { status: 401 });
+    }
+    const fresh = await ctx.runMutation(internal.deviceKeys.claimNonce, {
+        keyId: headers.keyId,
+        nonce: headers.nonce,
+        timestampMs: headers.timestampMs,
+    });
+    return fresh ? null : new Response("nonce already used", { status: 401 });
+}
+
+function deviceNameOf(item: unknown): string | null {
+    if (typeof item !== "object" || item === null) return null;
+    return "deviceName" in item && typeof item.deviceName === "string" ? item.deviceName : null;
+}
+
 // serde_json (the Rust tracker's JSON serializer) encodes `Option::None` as
 // an explicit JSON `null`, not an omitted key, by default -- normalize null
 // to undefined for every top-level field so Convex's `v.optional(...)`
@@ -54,9 +134,10 @@ function nullsToUndefined(value: unknown): unknown {
 
 // Mirrors convex/spans.ts's ingestSpanValidator -- validated here (not just
 // cast) because this is untrusted network input crossing the HTTP boundary;
-// a shape failure should produce a clear 400 for the tracker's spool/retry
-// logic to act on, rather than an opaque error surfacing from deep inside
-// the downstream mutation's own argument validator.
+// a shape failure should produce a clear per-row rejection for the
+// tracker's spool/dead-letter logic to act on, rather than an opaque error
+// surfacing from deep inside the downstream mutation's own argument
+// validator.
 interface IngestSpanItem {
     sourceId: string;
     createdAt: number;
@@ -78,6 +159,7 @@ interface IngestSpanItem {
     rightClickCount?: number;
     middleClickCount?: number;
     tokensSpent?: number;
+    idleReason?: string;
 }
 
 function isIngestSpanItem(item: unknown): item is IngestSpanItem {
@@ -96,6 +178,15 @@ function isIngestSpanItem(item: unknown): item is IngestSpanItem {
     return true;
 }
 
+function hasSourceId(item: unknown): item is { sourceId: string } {
+    return (
+        typeof item === "object" &&
+        item !== null &&
+        "sourceId" in item &&
+        typeof item.sourceId === "string"
+    );
+}
+
 const http = httpRouter();
 
 http.route({
@@ -105,7 +196,8 @@ http.route({
         const authError = checkBearerSecret(request);
         if (authError) return authError;
 
-        const body = await readJsonBody(request);
+        const raw = new Uint8Array(await request.arrayBuffer());
+        const body = await parseJsonBody(raw, request);
         if (body === INVALID_JSON) {
             return new Response("Invalid JSON body", { status: 400 });
         }
@@ -116,30 +208,50 @@ http.route({
         if (!Array.isArray(batch)) {
             return new Response('Body must be { "batch": [...] }', { status: 400 });
         }
+        const deviceNames = batch.map(deviceNameOf).filter((deviceName) => deviceName !== null);
+        const signatureError = await checkDeviceSignature(ctx, request, raw, deviceNames);
+        if (signatureError) return signatureError;
         if (batch.length === 0) {
-            return new Response(JSON.stringify({ inserted: 0, skipped: 0 }), {
-                status: 200,
-                headers: { "content-type": "application/json" },
-            });
+            return new Response(
+                JSON.stringify({ inserted: 0, skipped: 0, accepted: [], duplicate: [], rejected: [] }),
+                { status: 200, headers: { "content-type": "application/json" } },
+            );
         }
+        // 413, not 400: the tracker sizes its batches adaptively and
+        // treats a 413 as "smaller, please" (tracker/src/ingest/batch.rs).
         if (batch.length > MAX_INGEST_BATCH_SIZE) {
             return new Response(
                 `batch exceeds max size of ${MAX_INGEST_BATCH_SIZE}`,
-                { status: 400 },
+                { status: 413 },
             );
         }
         const normalizedBatch = batch.map(nullsToUndefined);
-        if (!normalizedBatch.every(isIngestSpanItem)) {
-            return new Response("batch contains a malformed span item", {
+        // Per-row ack: a malformed item is reported back in `rejected` (the
+        // tracker dead-letters it) and the rest of the batch still goes in,
+        // so one bad row doesn't make the tracker resend the other 499. An
+        // item without even a sourceId can't be acked, so it still fails
+        // the whole batch (the tracker bisects those out).
+        if (!normalizedBatch.every(hasSourceId)) {
+            return new Response("batch contains an item without a sourceId", {
                 status: 400,
             });
         }
+        const valid: IngestSpanItem[] = [];
+        const rejected: { sourceId: string; reason: string }[] = [];
+        for (const item of normalizedBatch) {
+            if (isIngestSpanItem(item)) {
+                valid.push(item);
+            } else {
+                rejected.push({ sourceId: item.sourceId, reason: "malformed span item" });
+            }
+        }
 
-        const result = await ctx.runMutation(internal.spans.ingestSpanBatch, {
-            batch: normalizedBatch,
-        });
+        const result =
+            valid.length === 0
+                ? { inserted: 0, skipped: 0, accepted: [], duplicate: [] }
+                : await ctx.runMutation(internal.spans.ingestSpanBatch, { batch: valid });
 
-        return new Response(JSON.stringify(result), {
+        return new Response(JSON.stringify({ ...result, rejected }), {
             status: 200,
             headers: { "content-type": "application/json" },
         });
@@ -370,6 +482,65 @@ http.route({
     }),
 });
 
+interface EnrollBody {
+    deviceName: string;
+    keyId: string;
+    publicKey: string;
+}
+
+function isEnrollBody(body: unknown): body is EnrollBody {
+    if (typeof body !== "object" || body === null) return false;
+    if (!("deviceName" in body) || typeof body.deviceName !== "string") return false;
+    if (!("keyId" in body) || typeof body.keyId !== "string") return false;
+    if (!("publicKey" in body) || typeof body.publicKey !== "string") return false;
+    return true;
+}
+
+// `backend enroll` (tracker/src/cli.rs): registers the device's signing key.
+// The request must be signed by that key, as that device, so enrolling
+// proves possession of the private half.
+http.route({
+    path: "/enroll",
+    method: "POST",
+    handler: httpAction(async (ctx, request) => {
+        const authError = checkBearerSecret(request);
+        if (authError) return authError;
+
+        const raw = new Uint8Array(await request.arrayBuffer());
+        const body = await parseJsonBody(raw, request);
+        if (body === INVALID_JSON) {
+            return new Response("Invalid JSON body", { status: 400 });
+        }
+        if (!isEnrollBody(body)) {
+            return new Response('Body must be { "deviceName": string, "keyId": string, "publicKey": string }', { status: 400 });
+        }
+        const publicKey = fromBase64(body.publicKey);
+        if (publicKey === null || (await keyIdOf(publicKey)) !== body.keyId) {
+            return new Response("keyId is not the key id of publicKey", { status: 400 });
+        }
+        const signed = readSignedHeaders(request.headers);
+        if (signed.kind !== "signed") {
+            return new Response("enroll must be signed by the key being enrolled", { status: 401 });
+        }
+        if (signed.headers.keyId !== body.keyId) {
+            return new Response(`signed with ${signed.headers.keyId}, not the key being enrolled`, { status: 401 });
+        }
+        const signatureError = await checkSignature(ctx, signed.headers, body, raw);
+        if (signatureError) return signatureError;
+
+        const result = await ctx.runMutation(internal.deviceKeys.enroll, {
+            deviceName: body.deviceName,
+            keyId: body.keyId,
+            publicKey: body.publicKey,
+        });
+
+        return new Response(JSON.stringify(result), {
+            status: result.status === "key-conflict" ? 409 : 200,
+            headers: { "content-type": "application/json" },
+        });
+    }),
+});
+
 http.route({ path: "/statusline", method: "GET", handler: statuslineHttpHandler });
 
 export default http;
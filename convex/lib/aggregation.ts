@@ -226,10 +226,19 @@ export function localTimeParts(ms: number): LocalTimeParts {
 // it STARTED (no sub-span splitting across a midnight/hour boundary); spans
 // are capped at tens of seconds by the tracker's own checkpoint logic, so
 // the resulting misattribution at a boundary is at most a few seconds.
+//
+// Gap rows (category "Untracked", tracker/src/heartbeat.rs) record time the
+// tracker wasn't running at all. They get no deltas, not even zero ones, so
+// they never add an "untracked" program or category bucket to a day.
+
+export const GAP_CATEGORY = "Untracked";
 
 export function deriveSpanDeltas(
     span: SpanForAggregation,
-): SpanAggregateDeltas {
+): SpanAggregateDeltas | null {
+    if (span.category === GAP_CATEGORY) {
+        return null;
+    }
     const { dayKey, hour } = localTimeParts(span.startedAt);
     const isAgent = span.actor.startsWith("agent:");
     const active = !span.isIdle;
@@ -0,0 +1,159 @@
+// Ingest-side check of the tracker's per-device request signatures -- the
+// Convex port of `Verifier` in tracker/src/ingest/signing.rs, which
+// documents the header scheme. tracker/src/ingest/signing_vectors.json
+// pins both implementations to the same key ids, messages and signatures
+// (scripts/signing.test.ts).
+//
+// The signed message binds the device, key id, timestamp and nonce to the
+// exact bytes on the wire, before any Content-Encoding is undone:
+//
+//   chronomaxi-ingest-v1\n<device>\n<key id>\n<timestamp>\n<nonce>\n<hex sha256(body)>
+//
+// Replay protection is a nonce ledger (the `ingestNonces` table): every
+// nonce accepted stays there until its timestamp would fail the skew check
+// anyway, and `claimNonce` refuses one it already holds.
+
+export const DEVICE_HEADER = "x-chronomaxi-device";
+export const KEY_ID_HEADER = "x-chronomaxi-key-id";
+export const TIMESTAMP_HEADER = "x-chronomaxi-timestamp";
+export const NONCE_HEADER = "x-chronomaxi-nonce";
+export const SIGNATURE_HEADER = "x-chronomaxi-signature";
+
+const SIGNATURE_VERSION = "chronomaxi-ingest-v1";
+
+// How far a request's timestamp may be from the server's clock.
+export const MAX_SKEW_MS = 5 * 60 * 1000;
+
+// Expired nonces deleted per claim, so the ledger can't grow without
+// bound without any one mutation doing unbounded work.
+const NONCE_PRUNE_LIMIT = 100;
+
+export interface SignedHeaders {
+    device: string;
+    keyId: string;
+    timestampMs: number;
+    nonce: string;
+    signature: string;
+}
+
+export type SignedRequest =
+    | { kind: "unsigned" }
+    | { kind: "malformed"; reason: string }
+    | { kind: "signed"; headers: SignedHeaders };
+
+// "unsigned" only when none of the five headers is present; a partial set
+// is malformed, never silently treated as unsigned.
+export function readSignedHeaders(headers: Headers): SignedRequest {
+    const names = [DEVICE_HEADER, KEY_ID_HEADER, TIMESTAMP_HEADER, NONCE_HEADER, SIGNATURE_HEADER];
+    const values = names.map((name) => headers.get(name) ?? "");
+    if (values.every((value) => value === "")) {
+        return { kind: "unsigned" };
+    }
+    const missing = names.find((_, index) => values[index] === "");
+    if (missing !== undefined) {
+        return { kind: "malformed", reason: `missing ${missing} header` };
+    }
+    const [device, keyId, timestamp, nonce, signature] = values as [string, string, string, string, string];
+    const timestampMs = Number(timestamp);
+    if (!/^-?\d+$/.test(timestamp) || !Number.isSafeInteger(timestampMs)) {
+        return { kind: "malformed", reason: `malformed ${TIMESTAMP_HEADER} header` };
+    }
+    return { kind: "signed", headers: { device, keyId, timestampMs, nonce, signature } };
+}
+
+export function fromBase64(text: string): Uint8Array<ArrayBuffer> | null {
+    try {
+        return Uint8Array.from(atob(text.trim()), (char) => char.charCodeAt(0));
+    } catch {
+        return null;
+    }
+}
+
+function hex(bytes: Uint8Array<ArrayBuffer>): string {
+    return Array.from(bytes, (byte) => byte.toString(16).padStart(2, "0")).join("");
+}
+
+async function sha256(bytes: Uint8Array<ArrayBuffer>): Promise<Uint8Array<ArrayBuffer>> {
+    return new Uint8Array(await crypto.subtle.digest("SHA-256", bytes));
+}
+
+// First 8 bytes of sha256(public key), hex.
+export async function keyIdOf(publicKey: Uint8Array<ArrayBuffer>): Promise<string> {
+    return hex((await sha256(publicKey)).slice(0, 8));
+}
+
+export async function signingMessage(
+    device: string,
+    keyId: string,
+    timestampMs: number,
+    nonce: string,
+    body: Uint8Array<ArrayBuffer>,
+): Promise<string> {
+    return `${SIGNATURE_VERSION}\n${device}\n${keyId}\n${timestampMs}\n${nonce}\n${hex(await sha256(body))}`;
+}
+
+// Checks `headers` against `body` (the bytes as received) for a key
+// enrolled as `key.deviceName`. Returns why it fails, or null. Does not
+// touch the nonce ledger; call `claimNonce` once this passes, so garbage
+// requests can't burn nonces a real tracker is about to use.
+export async function verifySignature(
+    headers: SignedHeaders,
+    key: { deviceName: string; publicKey: string },
+    body: Uint8Array<ArrayBuffer>,
+    nowMs: number,
+): Promise<string | null> {
+    if (key.deviceName !== headers.device) {
+        return `key ${headers.keyId} is enrolled for ${key.deviceName}`;
+    }
+    const skewMs = headers.timestampMs - nowMs;
+    if (Math.abs(skewMs) > MAX_SKEW_MS) {
+        return `timestamp is ${skewMs}ms away from now`;
+    }
+
+    const publicKey = fromBase64(key.publicKey);
+    if (publicKey === null || publicKey.length !== 32) {
+        return "malformed public key";
+    }
+    const signature = fromBase64(headers.signature);
+    if (signature === null || signature.length !== 64) {
+        return `malformed ${SIGNATURE_HEADER} header`;
+    }
+    const message = await signingMessage(headers.device, headers.keyId, headers.timestampMs, headers.nonce, body);
+    try {
+        const verifying = await crypto.subtle.importKey("raw", publicKey, "Ed25519", false, ["verify"]);
+        const valid = await crypto.subtle.verify("Ed25519", verifying, signature, new TextEncoder().encode(message));
+        return valid ? null : "signature does not match";
+    } catch {
+        return "malformed public key";
+    }
+}
+
+// What `claimNonce` needs from the `ingestNonces` table; convex/deviceKeys.ts
+// backs it with ctx.db.
+export interface NonceLedger {
+    // When (keyId, nonce)'s row expires, if there is one.
+    expiresAt(keyId: string, nonce: string): Promise<number | null>;
+    // Inserts or replaces (keyId, nonce)'s row.
+    record(keyId: string, nonce: string, expiresAt: number): Promise<void>;
+    // Deletes up to `limit` rows that expired before `nowMs`.
+    deleteExpired(nowMs: number, limit: number): Promise<void>;
+}
+
+// Records a verified request's nonce; false if it was already used. A
+// nonce is kept until its timestamp falls outside the skew window, after
+// which the request is refused as stale anyway.
+export async function claimNonce(
+    ledger: NonceLedger,
+    keyId: string,
+    nonce: string,
+    timestampMs: number,
+    nowMs: number,
+): Promise<boolean> {
+    await ledger.deleteExpired(nowMs, NONCE_PRUNE_LIMIT);
+    const expiresAt = await ledger.expiresAt(keyId, nonce);
+    if (expiresAt !== null && expiresAt >= nowMs) {
+        return false;
+    }
+    await ledger.record(keyId, nonce, timestampMs + MAX_SKEW_MS);
+    return true;
+}
@@ -32,6 +32,7 @@ export interface NormalizedSpanInsert {
     rightClickCount: number;
     middleClickCount: number;
     tokensSpent?: number;
+    idleReason?: string;
     importBatch: string;
 }
 
@@ -193,7 +194,9 @@ export async function insertSpanAndAggregate(
         middleClickCount: span.middleClickCount,
     });
 
-    await applyAggregateDeltas(ctx, deltas);
+    if (deltas !== null) {
+        await applyAggregateDeltas(ctx, deltas);
+    }
 
     return true;
 }
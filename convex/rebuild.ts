@@ -138,7 +138,9 @@ async function runReplayPage(
             rightClickCount: span.rightClickCount,
             middleClickCount: span.middleClickCount,
         });
-        await applyAggregateDeltas(ctx, deltas);
+        if (deltas !== null) {
+            await applyAggregateDeltas(ctx, deltas);
+        }
         replayedThisPage += 1;
     }
 
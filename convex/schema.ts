@@ -53,6 +53,9 @@ export default defineSchema({
         rightClickCount: v.number(),
         middleClickCount: v.number(),
         tokensSpent: v.optional(v.number()),
+        // Why an idle span was idle (see convex/spans.ts); gap rows carry
+        // "shutdown"/"crash"/"boot"/"suspend"/"unknown".
+        idleReason: v.optional(v.string()),
         // "live" for HTTP-ingested spans, or a migration batch tag
         // (e.g. "backfill-big-bertha-2026-07-10T18:00Z") for rollback/audit.
         importBatch: v.string(),
@@ -200,6 +203,31 @@ export default defineSchema({
         note: v.optional(v.string()),
     }).index("by_alias", ["alias"]),
 
+    deviceKeys: defineTable({
+        // Raw device name the tracker signs as (X-Chronomaxi-Device).
+        deviceName: v.string(),
+        // First 16 hex chars of sha256(publicKey), sent as X-Chronomaxi-Key-Id.
+        keyId: v.string(),
+        // base64 ed25519 public key.
+        publicKey: v.string(),
+        // A device's first key is approved on sight once spans from that
+        // device name have been seen; any other waits for approveDeviceKey.
+        approved: v.boolean(),
+        enrolledAt: v.number(),
+    })
+        .index("by_keyId", ["keyId"])
+        .index("by_deviceName", ["deviceName"]),
+
+    // Nonces of signed requests already accepted (convex/lib/signing.ts),
+    // kept until the request's timestamp would be refused as stale.
+    ingestNonces: defineTable({
+        keyId: v.string(),
+        nonce: v.string(),
+        expiresAt: v.number(),
+    })
+        .index("by_keyId_nonce", ["keyId", "nonce"])
+        .index("by_expiresAt", ["expiresAt"]),
+
     migrationCheckpoints: defineTable({
         // Source archive identifier, e.g. "big-bertha", "big-ron".
         source: v.string(),
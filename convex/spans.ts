@@ -29,6 +29,9 @@ const ingestSpanValidator = v.object({
     rightClickCount: v.optional(v.number()),
     middleClickCount: v.optional(v.number()),
     tokensSpent: v.optional(v.number()),
+    // Why an idle span was idle; for a gap row (category "Untracked") the
+    // reason the tracker wasn't running. Absent on ordinary spans.
+    idleReason: v.optional(v.string()),
 });
 
 function agentNameFromActor(actor: string): string | undefined {
@@ -37,10 +40,17 @@ function agentNameFromActor(actor: string): string | undefined {
 
 export const ingestSpanBatch = internalMutation({
     args: { batch: v.array(ingestSpanValidator) },
-    returns: v.object({ inserted: v.number(), skipped: v.number() }),
+    returns: v.object({
+        inserted: v.number(),
+        skipped: v.number(),
+        // Per-row ack for the tracker's spool (see convex/http.ts): sourceIds
+        // newly stored vs. already present from an earlier delivery.
+        accepted: v.array(v.string()),
+        duplicate: v.array(v.string()),
+    }),
     handler: async (ctx, args) => {
-        let inserted = 0;
-        let skipped = 0;
+        const accepted: string[] = [];
+        const duplicate: string[] = [];
 
         for (const item of args.batch) {
             const deviceName = await resolveCanonicalDevice(ctx, item.deviceName);
@@ -83,15 +93,16 @@ export const ingestSpanBatch = internalMutation({
                 rightClickCount: item.rightClickCount ?? 0,
                 middleClickCount: item.middleClickCount ?? 0,
                 tokensSpent: item.tokensSpent,
+                idleReason: item.idleReason,
                 importBatch: "live",
             });
             if (wasInserted) {
-                inserted += 1;
+                accepted.push(item.sourceId);
             } else {
-                skipped += 1;
+                duplicate.push(item.sourceId);
             }
         }
 
-        return { inserted, skipped };
+        return { inserted: accepted.length, skipped: duplicate.length, accepted, duplicate };
     },
 });
@@ -0,0 +1,106 @@
+// Checks convex/lib/signing.ts against the tracker's published vectors
+// and exercises the nonce ledger's replay protection. Run with
+// `bun run test:signing`; exits non-zero on the first failure.
+import assert from "node:assert/strict";
+import vectors from "../tracker/src/ingest/signing_vectors.json";
+import {
+    MAX_SKEW_MS,
+    claimNonce,
+    fromBase64,
+    keyIdOf,
+    readSignedHeaders,
+    signingMessage,
+    verifySignature,
+} from "../convex/lib/signing";
+import type { NonceLedger, SignedHeaders } from "../convex/lib/signing";
+
+const encode = (text: string) => new TextEncoder().encode(text);
+
+for (const vector of vectors) {
+    assert.equal(await keyIdOf(fromBase64(vector.publicKey)!), vector.keyId);
+    const body = encode(vector.body);
+    assert.equal(await signingMessage(vector.device, vector.keyId, vector.timestampMs, vector.nonce, body), vector.message);
+
+    const headers: SignedHeaders = {
+        device: vector.device,
+        keyId: vector.keyId,
+        timestampMs: vector.timestampMs,
+        nonce: vector.nonce,
+        signature: vector.signature,
+    };
+    const key = { deviceName: vector.device, publicKey: vector.publicKey };
+    assert.equal(await verifySignature(headers, key, body, vector.timestampMs + 1_000), null);
+}
+
+// Tampering, staleness and a key presented for another device.
+{
+    const [vector] = vectors;
+    assert.ok(vector);
+    const body = encode(vector.body);
+    const now = vector.timestampMs;
+    const headers: SignedHeaders = {
+        device: vector.device,
+        keyId: vector.keyId,
+        timestampMs: now,
+        nonce: vector.nonce,
+        signature: vector.signature,
+    };
+    const key = { deviceName: vector.device, publicKey: vector.publicKey };
+    assert.equal(await verifySignature(headers, key, encode('{"batch":[{}]}'), now), "signature does not match");
+    assert.equal(await verifySignature({ ...headers, timestampMs: now + 1 }, key, body, now + 1), "signature does not match");
+    const later = now + MAX_SKEW_MS + 1;
+    assert.equal(await verifySignature(headers, key, body, later), `timestamp is ${now - later}ms away from now`);
+    assert.equal(
+        await verifySignature(headers, { ...key, deviceName: "some-other-laptop" }, body, now),
+        `key ${vector.keyId} is enrolled for some-other-laptop`,
+    );
+}
+
+// Header parsing: none is unsigned, some is malformed.
+{
+    assert.deepEqual(readSignedHeaders(new Headers()), { kind: "unsigned" });
+    assert.deepEqual(readSignedHeaders(new Headers({ "X-Chronomaxi-Device": "laptop" })), {
+        kind: "malformed",
+        reason: "missing x-chronomaxi-key-id header",
+    });
+    const signed = readSignedHeaders(
+        new Headers({
+            "X-Chronomaxi-Device": "laptop",
+            "X-Chronomaxi-Key-Id": "56475aa75463474c",
+            "X-Chronomaxi-Timestamp": "1767225600000",
+            "X-Chronomaxi-Nonce": "AAECAwQFBgcICQoLDA0ODw",
+            "X-Chronomaxi-Signature": "c2ln",
+        }),
+    );
+    assert.equal(signed.kind === "signed" && signed.headers.timestampMs, 1767225600000);
+}
+
+// Replays: a nonce is refused for as long as its timestamp is in the skew
+// window, and expired rows are pruned.
+{
+    const rows = new Map<string, number>();
+    const ledger: NonceLedger = {
+        expiresAt: async (keyId, nonce) => rows.get(`${keyId}:${nonce}`) ?? null,
+        record: async (keyId, nonce, expiresAt) => {
+            rows.set(`${keyId}:${nonce}`, expiresAt);
+        },
+        deleteExpired: async (nowMs, limit) => {
+            const expired = [...rows].filter(([, expiresAt]) => expiresAt < nowMs).slice(0, limit);
+            for (const [id] of expired) rows.delete(id);
+        },
+    };
+    const sentAt = 1_767_225_600_000;
+    assert.equal(await claimNonce(ledger, "key", "n1", sentAt, sentAt), true);
+    assert.equal(await claimNonce(ledger, "key", "n1", sentAt, sentAt + 10), false);
+    assert.equal(await claimNonce(ledger, "key", "n1", sentAt, sentAt + MAX_SKEW_MS), false);
+    assert.equal(await claimNonce(ledger, "other-key", "n1", sentAt, sentAt + 10), true);
+    assert.equal(await claimNonce(ledger, "key", "n2", sentAt, sentAt + 10), true);
+    assert.equal(rows.size, 3);
+
+    // Past the window the signature check already refuses the request as
+    // stale; the ledger just forgets it.
+    assert.equal(await claimNonce(ledger, "key", "n3", sentAt + 2 * MAX_SKEW_MS, sentAt + 2 * MAX_SKEW_MS), true);
+    assert.deepEqual([...rows.keys()], ["key:n3"]);
+}
+
+console.log("signing: ok");
@@ -2,6 +2,11 @@ use std::fs;
 use std::io::Write;
 use std::path::{Path, PathBuf};
 
+use crate::validate::{self, ConfigIssue};
+
+/// Unknown keys are ignored here so a stray "comment" (or a key from a
+/// newer version) never costs the custom rules at startup; `validate_file`
+/// reports them.
 #[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
 pub struct BucketRule {
     pub bucket: String,
@@ -40,6 +45,15 @@ impl BucketClassifier {
         Self { config }
     }
 
+    /// Strict reload path for the rules watcher (crate::rules_watch): never
+    /// seeds and never falls back, so a broken edit surfaces as an error
+    /// and the caller keeps its last-good classifier.
+    pub fn try_load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let text = fs::read_to_string(path)?;
+        let config: BucketConfig = serde_json::from_str(&text)?;
+        Ok(Self { config })
+    }
+
     pub fn classify(
         &self,
         program: &str,
@@ -68,6 +82,49 @@ pub fn default_path() -> PathBuf {
     config_home.join("chronomaxi/buckets.json")
 }
 
+/// Strict check for `validate` mode (crate::validate): parse errors with
+/// line/column, unknown keys, empty bucket names and empty/duplicate
+/// patterns. An empty result means the file is clean.
+pub fn validate_file(path: &Path) -> Vec<ConfigIssue> {
+    let raw: serde_json::Value = match validate::parse_json_file(path) {
+        Ok(raw) => raw,
+        Err(issue) => return vec![issue],
+    };
+    let mut issues = Vec::new();
+    validate::check_keys(path, "", &raw, &["rules", "default_bucket"], &mut issues);
+    for (index, rule) in raw["rules"].as_array().into_iter().flatten().enumerate() {
+        validate::check_keys(path, &format!("rules[{index}]"), rule, RULE_KEYS, &mut issues);
+    }
+    let config: BucketConfig = match serde_json::from_value(raw) {
+        Ok(config) => config,
+        Err(e) => {
+            issues.push(ConfigIssue::new(path, e.to_string()));
+            return issues;
+        }
+    };
+
+    if config.default_bucket.trim().is_empty() {
+        issues.push(ConfigIssue::new(path, "default_bucket is empty"));
+    }
+    for (index, rule) in config.rules.iter().enumerate() {
+        if rule.bucket.trim().is_empty() {
+            issues.push(ConfigIssue::new(path, format!("rules[{index}].bucket is empty")));
+        }
+        for (field, patterns) in [
+            ("program_patterns", &rule.program_patterns),
+            ("title_patterns", &rule.title_patterns),
+            ("sub_program_patterns", &rule.sub_program_patterns),
+            ("tmux_session_patterns", &rule.tmux_session_patterns),
+        ] {
+            validate::check_patterns(path, &format!("rules[{index}].{field}"), patterns, &mut issues);
+        }
+    }
+    issues
+}
+
+const RULE_KEYS: &[&str] =
+    &["bucket", "program_patterns", "title_patterns", "sub_program_patterns", "tmux_session_patterns"];
+
 fn load_or_seed(path: &Path) -> Result<BucketConfig, Box<dyn std::error::Error>> {
     if !path.exists() {
         seed_file(path)?;
@@ -138,6 +195,20 @@ mod tests {
         assert_eq!(classifier.classify("alacritty", None, Some("nvim"), None), "coding");
     }
 
+    #[test]
+    fn unknown_keys_are_loaded_past_and_reported_by_validate() {
+        let text = r#"{"comment":"mine","rules":[{"bucket":"x","title_pattern":["a"],"program_patterns":["y"]}]}"#;
+        let path = crate::test_support::temp_path("buckets");
+        fs::write(&path, text).unwrap();
+
+        let classifier = BucketClassifier::try_load(&path).unwrap();
+        assert_eq!(classifier.classify("y", None, None, None), "x");
+
+        let issues: Vec<String> = validate_file(&path).iter().map(|issue| issue.message.clone()).collect();
+        assert_eq!(issues, ["unknown key \"comment\"", "unknown key \"rules[0].title_pattern\""]);
+        let _ = fs::remove_file(path);
+    }
+
     #[test]
     fn falls_back_to_default_bucket() {
         let classifier = BucketClassifier { config: default_config() };
@@ -6,7 +6,7 @@
 
 mod ax;
 mod event_tap;
-mod permissions;
+pub mod permissions;
 
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
@@ -13,6 +13,11 @@ pub fn accessibility_trusted_with_prompt() -> bool {
     AXUIElement::application_is_trusted_with_prompt()
 }
 
+/// Current Accessibility trust state, without prompting (for `doctor`).
+pub fn accessibility_trusted() -> bool {
+    AXUIElement::application_is_trusted()
+}
+
 #[link(name = "ApplicationServices", kind = "framework")]
 extern "C" {
     fn CGRequestListenEventAccess() -> bool;
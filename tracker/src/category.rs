@@ -7,6 +7,9 @@ pub enum Category {
     Communication,
     Research,
     Other,
+    /// Gap rows only (crate::heartbeat): time the tracker wasn't running
+    /// or the machine was asleep. Never assigned by `CategoryMatcher`.
+    Untracked,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -172,6 +175,12 @@ impl CategoryMatcher {
     }
 }
 
+impl Default for CategoryMatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub fn get_category(
     program_name: &str,
     program_process_name: &str,
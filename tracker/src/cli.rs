@@ -0,0 +1,773 @@
+//! Subcommand surface of the tracker binary:
+//!
+//!   backend [run]                     capture + flusher (the service default)
+//!   backend validate                  strict rule-file check (crate::validate)
+//!   backend doctor                    environment/prerequisite report
+//!   backend enroll [--print]          register this device's signing key
+//!   backend spool status|flush|list|purge
+//!   backend dead-letter list|show|repair|requeue
+//!   backend summary [today|week] [--from D] [--to D] [--format table|json|markdown]
+//!   backend export [--from D] [--to D] [--bucket B] [--actor A] [--device D]
+//!                  [--include-idle] [--format jsonl|csv]
+//!   backend import <aw-export.json> [--dry-run]
+//!   backend classify --program <p> [--program-name <n>] [--title <t>]
+//!                    [--sub-program <s>] [--tmux-session <s>]
+//!
+//! Every command also accepts `--config <path>` and any
+//! `--<config-field> <value>` override (crate::config), so e.g.
+//! `backend spool status --spool-path /tmp/x.sqlite` inspects another spool.
+//! Command-specific flags are taken out first; whatever is left is handed
+//! to `config::parse_cli_overrides`, which rejects anything unknown.
+
+use std::path::{Path, PathBuf};
+
+use crate::actor;
+use crate::buckets::BucketClassifier;
+use crate::category::CategoryMatcher;
+use crate::config::{self, CliOverrides, Configuration};
+use crate::doctor;
+use crate::export;
+use crate::history;
+use crate::import;
+use crate::ingest;
+use crate::ingest::signing::DeviceKey;
+use crate::logger_v4::{self, LoggerV4};
+use crate::metrics;
+use crate::privacy::PrivacyScrubber;
+use crate::spool::archive::Archive;
+use crate::spool::{DeadLetter, IngestRow, Spool};
+use crate::summary::{self, Summary};
+use crate::validate;
+
+pub const USAGE: &str = "usage: backend [run | validate | doctor | enroll | spool <status|flush|list|purge>
+               | dead-letter <list|show|repair|requeue> | summary | export | import | classify] [options]
+
+  run                       capture spans and flush the spool (default)
+  validate                  parse buckets.json and privacy-denylist.json strictly
+  doctor                    report every capture prerequisite; exits 0 healthy,
+                            1 degraded, 2 broken
+  enroll [--print]          create the device signing key if needed and register
+                            it with ingest_url (--print only shows it)
+  spool status              pending/sent counts and oldest pending row
+  spool flush               send every pending row now
+  spool list [--limit N] [--pending]
+  spool purge [--include-pending]
+                            delete sent rows (and unsent ones with --include-pending);
+                            sent rows are archived first when archive_dir is set
+  dead-letter list [--sink NAME] [--limit N]
+                            rows a sink rejected too often (or couldn't parse)
+  dead-letter show ID       full payload, attempts, last error and HTTP status
+  dead-letter repair ID (--payload JSON | --payload-file PATH) [--sink NAME]
+                            replace the stored payload before requeueing
+  dead-letter requeue (ID | --all) [--sink NAME]
+                            send again, to the sink that gave up on it only
+  summary [today | week | --from D --to D] [--format table|json|markdown]
+                            time per bucket, category, program and sub-program,
+                            active/idle, input counts and human vs agent time,
+                            from the local spool and archive; dates are local
+                            YYYY-MM-DD (--to inclusive) or RFC 3339 timestamps
+  export [--from D] [--to D] [--bucket B] [--actor A] [--device D] [--include-idle]
+         [--format jsonl|csv]
+                            write spans from the spool and archive to stdout,
+                            non-idle only unless --include-idle; --actor agent
+                            also matches agent:<name>
+  import FILE [--dry-run]   convert an ActivityWatch export (window + afk buckets)
+                            into spans and spool them; re-importing is a no-op
+  classify --program P [--program-name N] [--title T] [--sub-program S] [--tmux-session S]
+                            run bucket, category and privacy rules on the given inputs
+
+  --config PATH             tracker.toml to load instead of the default
+  --<field> VALUE           override any tracker.toml field, e.g. --spool-path /tmp/s.sqlite";
+
+const DEFAULT_LIST_LIMIT: usize = 20;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Run,
+    Validate,
+    Doctor,
+    Enroll { print_only: bool },
+    Help,
+    Spool(SpoolCommand),
+    DeadLetter(DeadLetterCommand),
+    Summary(SummaryArgs),
+    Export(ExportArgs),
+    Import { path: PathBuf, dry_run: bool },
+    Classify(ClassifyArgs),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SpoolCommand {
+    Status,
+    Flush,
+    List { limit: usize, pending_only: bool },
+    Purge { include_pending: bool },
+}
+
+/// `source_id: None` on `Requeue` means `--all`. `sink` narrows to one
+/// sink's dead letters where a row was given up on by several.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeadLetterCommand {
+    List { sink: Option<String>, limit: usize },
+    Show { source_id: String },
+    Repair { source_id: String, sink: Option<String>, payload: String },
+    Requeue { source_id: Option<String>, sink: Option<String> },
+}
+
+/// Which spans `summary` covers; resolved against the local clock at
+/// dispatch (crate::history::Range).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Period {
+    Today,
+    Week,
+    Between { from: Option<String>, to: Option<String> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SummaryArgs {
+    pub period: Period,
+    pub format: summary::Format,
+}
+
+/// `from`/`to` as for `summary --from/--to`; both missing exports
+/// everything.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportArgs {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub filter: export::Filter,
+    pub format: export::Format,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClassifyArgs {
+    pub program: String,
+    pub program_name: Option<String>,
+    pub title: Option<String>,
+    pub sub_program: Option<String>,
+    pub tmux_session: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Invocation {
+    pub command: Command,
+    pub config: CliOverrides,
+}
+
+/// Parses argv (program name already stripped).
+pub fn parse(args: &[String]) -> Result<Invocation, String> {
+    let mut rest: Vec<String> = args.to_vec();
+
+    if rest.iter().any(|arg| arg == "--help" || arg == "-h") {
+        return Ok(Invocation { command: Command::Help, config: CliOverrides::default() });
+    }
+
+    let name = if rest.first().is_some_and(|arg| !arg.starts_with("--")) {
+        rest.remove(0)
+    } else {
+        "run".to_string()
+    };
+
+    let command = match name.as_str() {
+        "run" => Command::Run,
+        "validate" => Command::Validate,
+        "doctor" => Command::Doctor,
+        "enroll" => Command::Enroll { print_only: take_switch(&mut rest, "print") },
+        "help" => Command::Help,
+        "spool" => {
+            if rest.first().is_none_or(|arg| arg.starts_with("--")) {
+                return Err("spool needs an action: status, flush, list or purge".to_string());
+            }
+            let action = rest.remove(0);
+            Command::Spool(match action.as_str() {
+                "status" => SpoolCommand::Status,
+                "flush" => SpoolCommand::Flush,
+                "list" => SpoolCommand::List {
+                    limit: take_flag(&mut rest, "limit")?
+                        .map(|raw| raw.parse::<usize>().map_err(|e| format!("--limit: {e}")))
+                        .transpose()?
+                        .unwrap_or(DEFAULT_LIST_LIMIT),
+                    pending_only: take_switch(&mut rest, "pending"),
+                },
+                "purge" => SpoolCommand::Purge { include_pending: take_switch(&mut rest, "include-pending") },
+                other => return Err(format!("unknown spool action {other:?}")),
+            })
+        }
+        "dead-letter" => {
+            if rest.first().is_none_or(|arg| arg.starts_with("--")) {
+                return Err("dead-letter needs an action: list, show, repair or requeue".to_string());
+            }
+            let action = rest.remove(0);
+            let sink = take_flag(&mut rest, "sink")?;
+            Command::DeadLetter(match action.as_str() {
+                "list" => DeadLetterCommand::List {
+                    sink,
+                    limit: take_flag(&mut rest, "limit")?
+                        .map(|raw| raw.parse::<usize>().map_err(|e| format!("--limit: {e}")))
+                        .transpose()?
+                        .unwrap_or(DEFAULT_LIST_LIMIT),
+                },
+                "show" => DeadLetterCommand::Show {
+                    source_id: take_positional(&mut rest).ok_or("dead-letter show needs a sourceId")?,
+                },
+                "repair" => {
+                    let source_id = take_positional(&mut rest).ok_or("dead-letter repair needs a sourceId")?;
+                    let payload = match (take_flag(&mut rest, "payload")?, take_flag(&mut rest, "payload-file")?) {
+                        (Some(payload), None) => payload,
+                        (None, Some(path)) => {
+                            std::fs::read_to_string(&path).map_err(|e| format!("--payload-file {path}: {e}"))?
+                        }
+                        _ => return Err("dead-letter repair needs exactly one of --payload or --payload-file".to_string()),
+                    };
+                    DeadLetterCommand::Repair { source_id, sink, payload }
+                }
+                "requeue" => {
+                    let all = take_switch(&mut rest, "all");
+                    let source_id = take_positional(&mut rest);
+                    if all == source_id.is_some() {
+                        return Err("dead-letter requeue needs a sourceId or --all".to_string());
+                    }
+                    DeadLetterCommand::Requeue { source_id, sink }
+                }
+                other => return Err(format!("unknown dead-letter action {other:?}")),
+            })
+        }
+        "summary" => {
+            let format = take_flag(&mut rest, "format")?.map(|raw| raw.parse()).transpose()?;
+            let (from, to) = (take_flag(&mut rest, "from")?, take_flag(&mut rest, "to")?);
+            let period = match (take_positional(&mut rest).as_deref(), from.is_some() || to.is_some()) {
+                (None | Some("today"), false) => Period::Today,
+                (Some("week"), false) => Period::Week,
+                (None, true) => Period::Between { from, to },
+                (Some(period @ ("today" | "week")), true) => {
+                    return Err(format!("summary {period} doesn't take --from/--to"))
+                }
+                (Some(other), _) => return Err(format!("unknown summary period {other:?}, expected today or week")),
+            };
+            Command::Summary(SummaryArgs { period, format: format.unwrap_or(summary::Format::Table) })
+        }
+        "export" => Command::Export(ExportArgs {
+            from: take_flag(&mut rest, "from")?,
+            to: take_flag(&mut rest, "to")?,
+            filter: export::Filter {
+                bucket: take_flag(&mut rest, "bucket")?,
+                actor: take_flag(&mut rest, "actor")?,
+                device: take_flag(&mut rest, "device")?,
+                include_idle: take_switch(&mut rest, "include-idle"),
+            },
+            format: take_flag(&mut rest, "format")?.map(|raw| raw.parse()).transpose()?.unwrap_or(export::Format::Jsonl),
+        }),
+        "import" => Command::Import {
+            path: take_positional(&mut rest).ok_or("import needs an ActivityWatch export file")?.into(),
+            dry_run: take_switch(&mut rest, "dry-run"),
+        },
+        "classify" => Command::Classify(ClassifyArgs {
+            program: take_flag(&mut rest, "program")?.ok_or("classify needs --program")?,
+            program_name: take_flag(&mut rest, "program-name")?,
+            title: take_flag(&mut rest, "title")?,
+            sub_program: take_flag(&mut rest, "sub-program")?,
+            tmux_session: take_flag(&mut rest, "tmux-session")?,
+        }),
+        other => return Err(format!("unknown command {other:?}\n\n{USAGE}")),
+    };
+
+    Ok(Invocation { command, config: config::parse_cli_overrides(&rest)? })
+}
+
+/// Removes `--name value` / `--name=value` from `args`.
+fn take_flag(args: &mut Vec<String>, name: &str) -> Result<Option<String>, String> {
+    let flag = format!("--{name}");
+    let prefix = format!("--{name}=");
+    let Some(index) = args.iter().position(|arg| *arg == flag || arg.starts_with(&prefix)) else {
+        return Ok(None);
+    };
+
+    let arg = args.remove(index);
+    if let Some(value) = arg.strip_prefix(&prefix) {
+        return Ok(Some(value.to_string()));
+    }
+    if index >= args.len() {
+        return Err(format!("{flag} needs a value"));
+    }
+    Ok(Some(args.remove(index)))
+}
+
+/// Removes the leading non-flag argument from `args`, if there is one.
+fn take_positional(args: &mut Vec<String>) -> Option<String> {
+    if args.first().is_some_and(|arg| !arg.starts_with("--")) {
+        Some(args.remove(0))
+    } else {
+        None
+    }
+}
+
+/// Removes a bare `--name` switch from `args`.
+fn take_switch(args: &mut Vec<String>, name: &str) -> bool {
+    let flag = format!("--{name}");
+    match args.iter().position(|arg| *arg == flag) {
+        Some(index) => {
+            args.remove(index);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Runs `command` against an already-layered `config`. Returns the
+/// process exit code.
+pub async fn dispatch(command: Command, config: Configuration) -> Result<i32, Box<dyn std::error::Error>> {
+    match command {
+        Command::Run => {
+            run(config).await?;
+            Ok(0)
+        }
+        Command::Help => {
+            println!("{USAGE}");
+            Ok(0)
+        }
+        Command::Validate => Ok(if validate::run(&config) { 0 } else { 1 }),
+        Command::Doctor => {
+            let report = doctor::run(&config).await;
+            report.print();
+            Ok(report.exit_code())
+        }
+        Command::Enroll { print_only } => enroll(print_only, &config).await,
+        Command::Spool(spool_command) => spool(spool_command, &config).await,
+        Command::DeadLetter(dead_letter_command) => dead_letter(dead_letter_command, &config),
+        Command::Summary(args) => {
+            let now = chrono::Local::now();
+            let range = match args.period {
+                Period::Today => history::Range::today(&now),
+                Period::Week => history::Range::this_week(&now),
+                Period::Between { from, to } => history::Range::between(&chrono::Local, from.as_deref(), to.as_deref())?,
+            };
+            let spans = history::spans(&config, &range)?;
+            print!("{}", Summary::from_spans(&range, &spans).render(args.format));
+            Ok(0)
+        }
+        Command::Export(args) => {
+            let range = history::Range::between(&chrono::Local, args.from.as_deref(), args.to.as_deref())?;
+            let spans = history::spans(&config, &range)?;
+            let written = export::write(&mut std::io::stdout().lock(), args.format, &spans, &args.filter, &chrono::Local)?;
+            eprintln!("exported {written} spans");
+            Ok(0)
+        }
+        Command::Import { path, dry_run } => import_history(&path, dry_run, &config),
+        Command::Classify(args) => {
+            classify(&args, &config)?;
+            Ok(0)
+        }
+    }
+}
+
+async fn run(config: Configuration) -> Result<(), Box<dyn std::error::Error>> {
+    // First run: create the signing key so the flusher picks it up. Capture
+    // doesn't depend on it, so a failure is only logged; the Convex sinks
+    // then go out unsigned (ingest::convex::device_key).
+    match DeviceKey::load_or_create(&config.device_key_path) {
+        Ok((key, true)) => println!(
+            "chronomaxi ingest: created device key {} ({}), register it with `backend enroll`",
+            key.path().display(),
+            key.key_id()
+        ),
+        Ok((_, false)) => {}
+        Err(e) => println!("chronomaxi ingest: {e}, sending unsigned batches"),
+    }
+
+    let mut logger = LoggerV4::new(config).await?;
+
+    // Decoupled spool-to-Convex flusher: its own task, its own spool
+    // connection, never blocks capture on network. Each spooled span
+    // wakes it instead of a fixed poll.
+    let (spooled, wake) = ingest::enqueue_signal();
+    logger.spool.notify_on_enqueue(spooled);
+    let flusher_config = logger.config.clone();
+    let flusher_metrics = std::sync::Arc::clone(&logger.metrics);
+    tokio::spawn(async move {
+        ingest::run_flusher(flusher_config, wake, flusher_metrics).await;
+    });
+
+    if !logger.config.metrics_listen.is_empty() {
+        match metrics::bind(&logger.config.metrics_listen).await {
+            Ok(listener) => {
+                println!("chronomaxi metrics: serving /metrics and /healthz on {}", logger.config.metrics_listen);
+                tokio::spawn(metrics::serve(listener, std::sync::Arc::clone(&logger.metrics), logger.config.spool_path.clone()));
+            }
+            Err(e) => println!("chronomaxi metrics: {e}, listener not started"),
+        }
+    }
+
+    logger.run().await
+}
+
+async fn enroll(print_only: bool, config: &Configuration) -> Result<i32, Box<dyn std::error::Error>> {
+    let (key, created) = DeviceKey::load_or_create(&config.device_key_path)?;
+    if created {
+        println!("created {}", key.path().display());
+    }
+    println!("device:     {}", config.device_name);
+    println!("key id:     {}", key.key_id());
+    println!("public key: {}", key.public_key_base64());
+    if print_only {
+        return Ok(0);
+    }
+
+    match ingest::enroll(config, key).await {
+        Ok((status, body)) if status.is_success() => {
+            println!("enrolled with {}: {body}", config.ingest_url);
+            Ok(0)
+        }
+        Ok((status, body)) => {
+            println!("enrollment refused ({status}): {body}");
+            Ok(1)
+        }
+        Err(e) => {
+            println!("enrollment failed: {e}");
+            Ok(1)
+        }
+    }
+}
+
+async fn spool(command: SpoolCommand, config: &Configuration) -> Result<i32, Box<dyn std::error::Error>> {
+    if command == SpoolCommand::Flush {
+        return match ingest::flush_pending(config).await {
+            Ok(n) => {
+                println!("flushed {n} rows");
+                Ok(0)
+            }
+            Err(e) => {
+                println!("flush stopped: {e}");
+                Ok(1)
+            }
+        };
+    }
+
+    let spool = Spool::open(&config.spool_path)?;
+    match command {
+        SpoolCommand::Status => {
+            println!("spool:   {}", config.spool_path.display());
+            println!("pending: {}", spool.pending_count()?);
+            if config.sinks.len() > 1 {
+                let router = ingest::route::Router::new(&config.sinks);
+                for name in ingest::sink::sink_names(config) {
+                    println!("  {name}: {} pending", ingest::pending_for(&spool, &router, &name)?);
+                }
+            }
+            println!("sent:    {}", spool.sent_count()?);
+            println!("dead:    {}", spool.dead_letter_count()?);
+            if !config.archive_dir.as_os_str().is_empty() {
+                let months = Archive::open(&config.archive_dir)?.months()?;
+                match (months.first(), months.last()) {
+                    (Some(first), Some(last)) => println!(
+                        "archive: {} spans, {} to {} ({})",
+                        months.iter().map(|month| month.spans).sum::<i64>(),
+                        first.month,
+                        last.month,
+                        config.archive_dir.display()
+                    ),
+                    _ => println!("archive: empty ({})", config.archive_dir.display()),
+                }
+            }
+            match spool.oldest_pending_created_at()? {
+                Some(oldest) => println!(
+                    "oldest pending: {} ({}s ago)",
+                    oldest.to_rfc3339(),
+                    (chrono::Utc::now() - oldest).num_seconds()
+                ),
+                None => println!("oldest pending: -"),
+            }
+        }
+        SpoolCommand::List { limit, pending_only } => {
+            for entry in spool.list(limit, pending_only)? {
+                let summary = serde_json::from_str::<serde_json::Value>(&entry.payload)
+                    .map(|row| {
+                        format!(
+                            "{} {}ms {}",
+                            row["programName"].as_str().unwrap_or("?"),
+                            row["durationMs"].as_i64().unwrap_or(0),
+                            row["bucket"].as_str().unwrap_or("-")
+                        )
+                    })
+                    .unwrap_or_else(|e| format!("<unparsable payload: {e}>"));
+                println!(
+                    "{}  {}  {}  {}",
+                    entry.source_id,
+                    entry.created_at,
+                    entry.sent_at.as_deref().unwrap_or("pending"),
+                    summary
+                );
+            }
+        }
+        SpoolCommand::Purge { include_pending } => {
+            // Sent rows reach the archive first, as they would on a prune.
+            ingest::archive_sent(&spool, config, chrono::Utc::now() + chrono::Duration::days(1))?;
+            let removed = spool.purge(include_pending)?;
+            println!("purged {removed} rows");
+        }
+        SpoolCommand::Flush => unreachable!("handled above"),
+    }
+    Ok(0)
+}
+
+fn dead_letter(command: DeadLetterCommand, config: &Configuration) -> Result<i32, Box<dyn std::error::Error>> {
+    let mut spool = Spool::open(&config.spool_path)?;
+    match command {
+        DeadLetterCommand::List { sink, limit } => {
+            for letter in spool.dead_letters(sink.as_deref(), limit)? {
+                println!(
+                    "{}  {}  {}  {} attempt(s)  {}  {}",
+                    letter.source_id,
+                    letter.dead_at,
+                    letter.sink,
+                    letter.attempts,
+                    letter.http_status.map(|status| status.to_string()).unwrap_or_else(|| "-".to_string()),
+                    letter.last_error
+                );
+            }
+        }
+        DeadLetterCommand::Show { source_id } => {
+            let letters = spool.dead_letters_for(&source_id)?;
+            if letters.is_empty() {
+                println!("no dead letter for {source_id}");
+                return Ok(1);
+            }
+            for letter in letters {
+                print_dead_letter(&letter);
+            }
+        }
+        DeadLetterCommand::Repair { source_id, sink, payload } => {
+            // Whatever goes back in line has to be a well-formed wire row
+            // for this very span, or it would just be dead-lettered again.
+            let row: IngestRow =
+                serde_json::from_str(&payload).map_err(|e| format!("repaired payload is not a valid span: {e}"))?;
+            if row.source_id != source_id {
+                return Err(format!("repaired payload has sourceId {:?}, expected {source_id:?}", row.source_id).into());
+            }
+            let repaired = spool.repair_dead_letter(&source_id, sink.as_deref(), &serde_json::to_string(&row)?)?;
+            if repaired == 0 {
+                println!("no dead letter for {source_id}");
+                return Ok(1);
+            }
+            println!("repaired {repaired} dead letter(s), `dead-letter requeue {source_id}` to send again");
+        }
+        DeadLetterCommand::Requeue { source_id, sink } => {
+            let all_sinks = ingest::sink::sink_names(config);
+            let requeued = spool.requeue_dead_letters(source_id.as_deref(), sink.as_deref(), &all_sinks)?;
+            println!("requeued {requeued} dead letter(s)");
+            if requeued == 0 {
+                return Ok(1);
+            }
+        }
+    }
+    Ok(0)
+}
+
+fn print_dead_letter(letter: &DeadLetter) {
+    println!("sourceId:    {}", letter.source_id);
+    println!("sink:        {}", letter.sink);
+    println!("spooled:     {}", letter.created_at);
+    println!("dead since:  {}", letter.dead_at);
+    println!("attempts:    {}", letter.attempts);
+    println!("http status: {}", letter.http_status.map(|status| status.to_string()).unwrap_or_else(|| "-".to_string()));
+    println!("last error:  {}", letter.last_error);
+    println!("payload:     {}", letter.payload);
+    println!();
+}
+
+/// `backend import`: converts an ActivityWatch export and spools the spans.
+fn import_history(path: &Path, dry_run: bool, config: &Configuration) -> Result<i32, Box<dyn std::error::Error>> {
+    let converted = import::read(path, &import::Rules::load(config)?)?;
+    println!(
+        "{}: {} window events, {} afk stretches -> {} spans",
+        path.display(),
+        converted.window_events,
+        converted.afk_events,
+        converted.rows.len()
+    );
+    for bucket in &converted.skipped_buckets {
+        println!("  skipped bucket {bucket}");
+    }
+    if dry_run {
+        return Ok(0);
+    }
+
+    let spooled = Spool::open(&config.spool_path)?.enqueue_rows(&converted.rows)?;
+    println!(
+        "spooled {spooled} new spans ({} already there); they flush with the running tracker or `backend spool flush`",
+        converted.rows.len() - spooled
+    );
+    Ok(0)
+}
+
+/// Runs the same bucket -> privacy -> category -> actor pipeline as
+/// `LoggerV4::capture` on hand-supplied inputs. Never writes the scrub audit.
+fn classify(args: &ClassifyArgs, config: &Configuration) -> Result<(), Box<dyn std::error::Error>> {
+    let classifier = BucketClassifier::load(&config.bucket_config_path);
+    let scrubber = PrivacyScrubber::load(
+        &config.privacy_config_path,
+        &config.scrub_audit_path,
+        config.privacy_failure_policy,
+    )?
+    .without_audit();
+
+    let program = args.program.as_str();
+    let program_name = args.program_name.as_deref().unwrap_or(program);
+    let title = args.title.as_deref().unwrap_or("");
+    let (browser_title, site_name) =
+        logger_v4::browser_title_and_site_name(program, title).unwrap_or((None, None));
+
+    let initial_bucket = classifier.classify(
+        program,
+        args.title.as_deref(),
+        args.sub_program.as_deref(),
+        args.tmux_session.as_deref(),
+    );
+    let scrubbed = scrubber.scrub_fields(
+        program,
+        program_name,
+        title,
+        browser_title.as_deref(),
+        args.sub_program.as_deref(),
+        &initial_bucket,
+    );
+    let site_name = if scrubbed.scrubbed { None } else { site_name };
+    let category = CategoryMatcher::new().categorize(
+        &scrubbed.program_name,
+        &scrubbed.program_process_name,
+        scrubbed.browser_title.as_deref(),
+        site_name.as_deref(),
+        scrubbed.sub_program.as_deref(),
+    );
+    let actor = actor::resolve_actor(&scrubbed.title, &config.actor);
+
+    println!("bucket:        {}", scrubbed.bucket);
+    println!("rule bucket:   {initial_bucket}");
+    println!("category:      {category:?}");
+    println!("actor:         {actor}");
+    println!("scrubbed:      {}", scrubbed.scrubbed);
+    println!("program:       {}", scrubbed.program_process_name);
+    println!("program name:  {}", scrubbed.program_name);
+    println!("title:         {}", scrubbed.title);
+    println!("browser title: {}", scrubbed.browser_title.as_deref().unwrap_or("-"));
+    println!("sub_program:   {}", scrubbed.sub_program.as_deref().unwrap_or("-"));
+    println!("tmux_session:  {}", args.tmux_session.as_deref().unwrap_or("-"));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(raw: &[&str]) -> Vec<String> {
+        raw.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn no_subcommand_means_run_with_overrides() {
+        let invocation = parse(&args(&["--idle-threshold-ms", "1000"])).unwrap();
+        assert_eq!(invocation.command, Command::Run);
+        assert_eq!(invocation.config.overrides, vec![("idle-threshold-ms".to_string(), "1000".to_string())]);
+    }
+
+    #[test]
+    fn spool_list_takes_its_own_flags_before_config_overrides() {
+        let invocation =
+            parse(&args(&["spool", "list", "--limit=5", "--pending", "--spool-path", "/tmp/s.sqlite"])).unwrap();
+        assert_eq!(invocation.command, Command::Spool(SpoolCommand::List { limit: 5, pending_only: true }));
+        assert_eq!(invocation.config.overrides, vec![("spool-path".to_string(), "/tmp/s.sqlite".to_string())]);
+    }
+
+    #[test]
+    fn classify_requires_program() {
+        assert!(parse(&args(&["classify", "--title", "x"])).is_err());
+        let invocation = parse(&args(&["classify", "--program", "alacritty", "--sub-program", "nvim"])).unwrap();
+        let Command::Classify(classify) = invocation.command else { panic!("expected classify") };
+        assert_eq!(classify.program, "alacritty");
+        assert_eq!(classify.sub_program.as_deref(), Some("nvim"));
+    }
+
+    #[test]
+    fn unknown_command_and_spool_action_are_rejected() {
+        assert!(parse(&args(&["frobnicate"])).is_err());
+        assert!(parse(&args(&["spool", "vacuum"])).is_err());
+        assert!(parse(&args(&["spool"])).is_err());
+    }
+
+    #[test]
+    fn enroll_takes_print_before_config_overrides() {
+        let invocation = parse(&args(&["enroll", "--print", "--device-key-path", "/tmp/k"])).unwrap();
+        assert_eq!(invocation.command, Command::Enroll { print_only: true });
+        assert_eq!(invocation.config.overrides, vec![("device-key-path".to_string(), "/tmp/k".to_string())]);
+    }
+
+    #[test]
+    fn dead_letter_actions_take_an_id_or_all() {
+        let invocation = parse(&args(&["dead-letter", "requeue", "01ABC", "--sink", "convex"])).unwrap();
+        assert_eq!(
+            invocation.command,
+            Command::DeadLetter(DeadLetterCommand::Requeue {
+                source_id: Some("01ABC".to_string()),
+                sink: Some("convex".to_string())
+            })
+        );
+        let invocation = parse(&args(&["dead-letter", "repair", "01ABC", "--payload", "{}"])).unwrap();
+        assert!(matches!(invocation.command, Command::DeadLetter(DeadLetterCommand::Repair { .. })));
+
+        assert!(parse(&args(&["dead-letter", "requeue"])).is_err());
+        assert!(parse(&args(&["dead-letter", "requeue", "01ABC", "--all"])).is_err());
+        assert!(parse(&args(&["dead-letter", "show"])).is_err());
+        assert!(parse(&args(&["dead-letter", "repair", "01ABC"])).is_err());
+    }
+
+    #[test]
+    fn summary_takes_a_period_or_a_range() {
+        let invocation = parse(&args(&["summary", "--format", "json"])).unwrap();
+        assert_eq!(
+            invocation.command,
+            Command::Summary(SummaryArgs { period: Period::Today, format: summary::Format::Json })
+        );
+        let invocation = parse(&args(&["summary", "--from", "2026-10-01", "--to=2026-10-07"])).unwrap();
+        let Command::Summary(summary_args) = invocation.command else { panic!("expected summary") };
+        assert_eq!(
+            summary_args.period,
+            Period::Between { from: Some("2026-10-01".to_string()), to: Some("2026-10-07".to_string()) }
+        );
+        assert_eq!(summary_args.format, summary::Format::Table);
+
+        assert!(parse(&args(&["summary", "week", "--from", "2026-10-01"])).is_err());
+        assert!(parse(&args(&["summary", "month"])).is_err());
+        assert!(parse(&args(&["summary", "--format", "csv"])).is_err());
+    }
+
+    #[test]
+    fn export_takes_range_filters_and_format() {
+        let invocation = parse(&args(&[
+            "export", "--from", "2026-10-01", "--actor", "agent", "--include-idle", "--format", "csv", "--device=desk",
+        ]))
+        .unwrap();
+        assert_eq!(
+            invocation.command,
+            Command::Export(ExportArgs {
+                from: Some("2026-10-01".to_string()),
+                to: None,
+                filter: export::Filter {
+                    bucket: None,
+                    actor: Some("agent".to_string()),
+                    device: Some("desk".to_string()),
+                    include_idle: true,
+                },
+                format: export::Format::Csv,
+            })
+        );
+        let Command::Export(export_args) = parse(&args(&["export"])).unwrap().command else { panic!("expected export") };
+        assert_eq!((export_args.format, export_args.filter), (export::Format::Jsonl, export::Filter::default()));
+        assert!(parse(&args(&["export", "--format", "xlsx"])).is_err());
+    }
+
+    #[test]
+    fn import_needs_a_file() {
+        let invocation = parse(&args(&["import", "aw-export.json", "--dry-run"])).unwrap();
+        assert_eq!(invocation.command, Command::Import { path: PathBuf::from("aw-export.json"), dry_run: true });
+        assert!(parse(&args(&["import", "--dry-run"])).is_err());
+    }
+}
@@ -1,15 +1,106 @@
+//! Tracker configuration, layered lowest-to-highest precedence as:
+//!
+//!   1. built-in defaults (the `DEFAULT_*` constants below),
+//!   2. `~/.config/chronomaxi/tracker.toml` (or `$CHRONOMAXI_CONFIG`, or
+//!      `--config <path>`), keys named exactly like `Configuration`'s fields,
+//!   3. environment variables (`ENV_OVERRIDES`; `.env` is loaded first),
+//!   4. CLI flags, `--<field-name-with-dashes> <value>` or `--<field>=<value>`.
+//!
+//! Every layer is merged as a TOML table before deserializing once, so an
+//! env/CLI string is coerced to whatever type the field already has and an
+//! unknown key fails loudly instead of being silently ignored.
+
 use std::env;
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
 
 use dotenv::dotenv;
 
+pub use crate::ingest::sink::{SinkConfig, SinkKind};
+
 /// Default actor when neither a `cmx|` title tag nor CHRONOMAXI_ACTOR is set.
 pub const DEFAULT_ACTOR: &str = "human";
 
 /// Max rows a single flusher POST will send to the ingest endpoint.
 pub const SPOOL_BATCH_SIZE: usize = 500;
+/// Ceiling the adaptive batch size (crate::ingest::batch) grows to. Equal
+/// to convex/http.ts's `MAX_INGEST_BATCH_SIZE`, which answers anything
+/// bigger with a 413; raise it only for sinks that take larger batches.
+pub const DEFAULT_SPOOL_BATCH_SIZE_MAX: usize = 500;
+
+pub const DEFAULT_MAX_SPAN_SECONDS: i64 = 60;
+pub const DEFAULT_CHECKPOINT_SPAN_SECONDS: i64 = 40;
+pub const DEFAULT_OPEN_SPAN_PERSIST_SECONDS: i64 = 5;
+pub const DEFAULT_HEARTBEAT_SECONDS: i64 = 5;
+pub const DEFAULT_GAP_MIN_SECONDS: i64 = 60;
+pub const DEFAULT_IDLE_THRESHOLD_MS: i64 = 300_000;
+pub const DEFAULT_HYPR_RECONCILE_SECONDS: i64 = 5;
+pub const DEFAULT_INGEST_POLL_INTERVAL_SECONDS: u64 = 60;
+pub const DEFAULT_INGEST_DEBOUNCE_MS: u64 = 1_000;
+pub const DEFAULT_INGEST_MIN_BACKOFF_SECONDS: u64 = 5;
+pub const DEFAULT_INGEST_MAX_BACKOFF_SECONDS: u64 = 5 * 60;
+pub const DEFAULT_SPOOL_RETENTION_DAYS: i64 = 7;
+pub const DEFAULT_DEAD_LETTER_AFTER_ATTEMPTS: u32 = 5;
+pub const DEFAULT_TMUX_PUSH_FRESHNESS_MS: u64 = 10_000;
+pub const DEFAULT_TMUX_IPC_MIN_INTERVAL_MS: u64 = 2_000;
+pub const DEFAULT_EVDEV_RESCAN_INTERVAL_SECONDS: u64 = 30;
+
+/// What to do when privacy-denylist.json exists but fails to parse at
+/// startup. There is deliberately no "use the built-in list" option: the
+/// built-in list is more permissive than any customized one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PrivacyFailurePolicy {
+    /// Run, but scrub every span to "homework" until the file is fixed.
+    ScrubAll,
+    /// Exit with an error.
+    Refuse,
+}
+
+/// (field, env var) pairs consulted by the env layer. Field names match
+/// `Configuration` (and therefore tracker.toml keys) exactly.
+const ENV_OVERRIDES: [(&str, &str); 37] = [
+    ("log_interval_seconds", "CHRONOMAXI_LOG_INTERVAL_SECONDS"),
+    ("stats_every_n_seconds", "CHRONOMAXI_STATS_EVERY_N_SECONDS"),
+    ("log_iteration_pause_ms", "CHRONOMAXI_LOG_ITERATION_PAUSE_MS"),
+    ("ingest_url", "CHRONOMAXI_INGEST_URL"),
+    ("ingest_secret", "CHRONOMAXI_INGEST_SECRET"),
+    ("ingest_secret_file", "CHRONOMAXI_INGEST_SECRET_FILE"),
+    ("actor", "CHRONOMAXI_ACTOR"),
+    ("device_name", "CHRONOMAXI_DEVICE_NAME"),
+    ("spool_path", "CHRONOMAXI_SPOOL_PATH"),
+    ("device_key_path", "CHRONOMAXI_DEVICE_KEY_PATH"),
+    ("bucket_config_path", "CHRONOMAXI_BUCKET_CONFIG"),
+    ("privacy_config_path", "CHRONOMAXI_PRIVACY_CONFIG"),
+    ("scrub_audit_path", "CHRONOMAXI_SCRUB_AUDIT_PATH"),
+    ("privacy_failure_policy", "CHRONOMAXI_PRIVACY_FAILURE_POLICY"),
+    ("max_span_seconds", "CHRONOMAXI_MAX_SPAN_SECONDS"),
+    ("checkpoint_span_seconds", "CHRONOMAXI_CHECKPOINT_SPAN_SECONDS"),
+    ("open_span_persist_seconds", "CHRONOMAXI_OPEN_SPAN_PERSIST_SECONDS"),
+    ("heartbeat_path", "CHRONOMAXI_HEARTBEAT_PATH"),
+    ("heartbeat_seconds", "CHRONOMAXI_HEARTBEAT_SECONDS"),
+    ("gap_min_seconds", "CHRONOMAXI_GAP_MIN_SECONDS"),
+    ("idle_threshold_ms", "CHRONOMAXI_IDLE_THRESHOLD_MS"),
+    ("hypr_reconcile_seconds", "CHRONOMAXI_HYPR_RECONCILE_SECONDS"),
+    ("spool_batch_size", "CHRONOMAXI_SPOOL_BATCH_SIZE"),
+    ("spool_batch_size_max", "CHRONOMAXI_SPOOL_BATCH_SIZE_MAX"),
+    ("ingest_gzip", "CHRONOMAXI_INGEST_GZIP"),
+    ("ingest_poll_interval_seconds", "CHRONOMAXI_INGEST_POLL_INTERVAL_SECONDS"),
+    ("ingest_debounce_ms", "CHRONOMAXI_INGEST_DEBOUNCE_MS"),
+    ("ingest_min_backoff_seconds", "CHRONOMAXI_INGEST_MIN_BACKOFF_SECONDS"),
+    ("ingest_max_backoff_seconds", "CHRONOMAXI_INGEST_MAX_BACKOFF_SECONDS"),
+    ("spool_retention_days", "CHRONOMAXI_SPOOL_RETENTION_DAYS"),
+    ("archive_dir", "CHRONOMAXI_ARCHIVE_DIR"),
+    ("archive_retention_months", "CHRONOMAXI_ARCHIVE_RETENTION_MONTHS"),
+    ("dead_letter_after_attempts", "CHRONOMAXI_DEAD_LETTER_AFTER_ATTEMPTS"),
+    ("tmux_push_freshness_ms", "CHRONOMAXI_TMUX_PUSH_FRESHNESS_MS"),
+    ("tmux_ipc_min_interval_ms", "CHRONOMAXI_TMUX_IPC_MIN_INTERVAL_MS"),
+    ("evdev_rescan_interval_seconds", "CHRONOMAXI_EVDEV_RESCAN_INTERVAL_SECONDS"),
+    ("metrics_listen", "CHRONOMAXI_METRICS_LISTEN"),
+];
 
 #[derive(Clone, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct Configuration {
     pub log_interval_seconds: i64,
     pub stats_every_n_seconds: i64,
@@ -20,6 +111,11 @@ pub struct Configuration {
     pub ingest_url: String,
     /// Bearer token sent as `Authorization: Bearer <secret>` on every ingest POST.
     pub ingest_secret: String,
+    /// File with one secret per line, preferred over `ingest_secret` (which
+    /// leaks into the process environment). Defaults to
+    /// `$CREDENTIALS_DIRECTORY/ingest_secret` under systemd
+    /// `LoadCredential=`; empty means none. See crate::ingest::secret.
+    pub ingest_secret_file: PathBuf,
     /// "human" | "agent:<name>" -- fallback actor when the active window's
     /// title does not carry a `cmx|actor=...` tag.
     pub actor: String,
@@ -27,48 +123,305 @@ pub struct Configuration {
     pub device_name: String,
     /// Local durable spool db path.
     pub spool_path: PathBuf,
+    /// This device's ed25519 signing key, created on first run next to the
+    /// spool. See crate::ingest::signing and `backend enroll`.
+    pub device_key_path: PathBuf,
     pub bucket_config_path: PathBuf,
     pub privacy_config_path: PathBuf,
     pub scrub_audit_path: PathBuf,
+    /// "scrub_all" (default) | "refuse" -- see `PrivacyFailurePolicy`.
+    pub privacy_failure_policy: PrivacyFailurePolicy,
+
+    /// Hard cap on a single span's length; a span this old is ended even
+    /// if nothing else changed.
+    pub max_span_seconds: i64,
+    /// Spans are checkpointed (ended and restarted on the same window)
+    /// once this old.
+    pub checkpoint_span_seconds: i64,
+    /// The span still being captured is saved to the spool this often and
+    /// recovered on the next start, so a crash loses at most this much
+    /// time; 0 disables it. See `Spool::save_open_span`.
+    pub open_span_persist_seconds: i64,
+    /// Where the tracker records that it is alive; the time since the
+    /// last heartbeat is spooled as an untracked gap on the next start.
+    /// See crate::heartbeat.
+    pub heartbeat_path: PathBuf,
+    /// How often the heartbeat is rewritten, which bounds how early a
+    /// crash gap may start; 0 disables heartbeats and gap rows.
+    pub heartbeat_seconds: i64,
+    /// Downtime (or a frozen tick) shorter than this is not recorded as a
+    /// gap.
+    pub gap_min_seconds: i64,
+    /// No input/window change for this long flips a span to idle.
+    pub idle_threshold_ms: i64,
+    /// How often logger_v4 reconciles the Hyprland event-socket pushed
+    /// state against `hyprctl activewindow -j` ground truth.
+    pub hypr_reconcile_seconds: i64,
+
+    /// Flush destinations, `[[sinks]]` tables in tracker.toml (file layer
+    /// only). Defaults to Convex alone; see crate::ingest::sink.
+    pub sinks: Vec<SinkConfig>,
+    /// Rows per flusher POST to start with; each sink adapts from here
+    /// (crate::ingest::batch)...
+    pub spool_batch_size: usize,
+    /// ...up to this many.
+    pub spool_batch_size_max: usize,
+    /// Gzip Convex ingest bodies. Needs a deployment whose convex/http.ts
+    /// inflates them, hence off by default.
+    pub ingest_gzip: bool,
+    /// The flusher wakes when a span is spooled; this long poll only
+    /// re-tries rows a sink left pending (rejected once, not acked) and
+    /// covers rows spooled by another process.
+    pub ingest_poll_interval_seconds: u64,
+    /// How long the flusher waits after a wake-up for more spans, so a
+    /// burst of window switches goes out as one batch.
+    pub ingest_debounce_ms: u64,
+    /// Flusher retry backoff: starts here, doubles per failure (each wait
+    /// jittered to 50-100% so a fleet coming back online spreads out)...
+    pub ingest_min_backoff_seconds: u64,
+    /// ...and is capped here.
+    pub ingest_max_backoff_seconds: u64,
+    /// Sent rows older than this are pruned from the spool. Pending rows
+    /// are never pruned regardless of age.
+    pub spool_retention_days: i64,
+    /// Sent rows are rolled into per-month sqlite files here before they
+    /// are pruned, e.g. "~/.local/state/chronomaxi/archive"; empty (the
+    /// default) disables the archive. See crate::spool::archive.
+    pub archive_dir: PathBuf,
+    /// Archive months older than this are deleted; 0 keeps them forever.
+    pub archive_retention_months: u32,
+    /// A row a sink has rejected (a non-auth 4xx) this many times is moved
+    /// to the spool's dead-letter table for that sink instead of blocking
+    /// the rows behind it. See `backend dead-letter`.
+    pub dead_letter_after_attempts: u32,
+
+    /// tmux push state is preferred over the IPC fallback only while
+    /// younger than this.
+    pub tmux_push_freshness_ms: u64,
+    /// Minimum spacing between tmux IPC fallback attempts.
+    pub tmux_ipc_min_interval_ms: u64,
+    /// evdev hotplug rescan cadence.
+    pub evdev_rescan_interval_seconds: u64,
+
+    /// Loopback `ip:port` for the `/metrics` and `/healthz` listener, e.g.
+    /// "127.0.0.1:9464"; empty (the default) disables it. See crate::metrics.
+    pub metrics_listen: String,
 }
 
 impl Configuration {
+    /// Defaults + tracker.toml (if present) + env, no CLI flags.
     pub fn from_env() -> Result<Self, Box<dyn std::error::Error>> {
+        Self::load(None, &[])
+    }
+
+    /// Full layered load. `config_path` is the `--config` flag (an explicit
+    /// path must exist; the default path may be absent), `cli_overrides`
+    /// the already-split `--key value` pairs from `parse_cli_overrides`.
+    pub fn load(
+        config_path: Option<&Path>,
+        cli_overrides: &[(String, String)],
+    ) -> Result<Self, Box<dyn std::error::Error>> {
         dotenv().ok();
 
-        Ok(Self {
+        let explicit_path = config_path
+            .map(Path::to_path_buf)
+            .or_else(|| env::var("CHRONOMAXI_CONFIG").ok().map(PathBuf::from));
+        let path = explicit_path.clone().unwrap_or_else(default_config_path);
+
+        let file = match fs::read_to_string(&path) {
+            Ok(text) => Some(
+                text.parse::<toml::Table>()
+                    .map_err(|e| format!("failed to parse {}: {e}", path.display()))?,
+            ),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && explicit_path.is_none() => None,
+            Err(e) => return Err(format!("failed to read {}: {e}", path.display()).into()),
+        };
+
+        Ok(Self::layered(file, |name| env::var(name).ok(), cli_overrides)?)
+    }
+
+    /// Built-in defaults, before any layer is applied.
+    pub(crate) fn defaults() -> Self {
+        Self {
             log_interval_seconds: 1,
             stats_every_n_seconds: 30,
             log_iteration_pause_ms: 100,
-            ingest_url: env::var("CHRONOMAXI_INGEST_URL")
-                .unwrap_or_else(|_| "http://127.0.0.1:3211".to_string()),
-            ingest_secret: env::var("CHRONOMAXI_INGEST_SECRET").unwrap_or_default(),
-            actor: env::var("CHRONOMAXI_ACTOR").unwrap_or_else(|_| DEFAULT_ACTOR.to_string()),
-            device_name: env::var("CHRONOMAXI_DEVICE_NAME").unwrap_or_else(|_| whoami::devicename()),
-            spool_path: env::var("CHRONOMAXI_SPOOL_PATH")
-                .map(PathBuf::from)
-                .unwrap_or_else(|_| default_spool_path()),
-            bucket_config_path: env::var("CHRONOMAXI_BUCKET_CONFIG")
-                .map(PathBuf::from)
-                .unwrap_or_else(|_| crate::buckets::default_path()),
-            privacy_config_path: env::var("CHRONOMAXI_PRIVACY_CONFIG")
-                .map(PathBuf::from)
-                .unwrap_or_else(|_| crate::privacy::default_config_path()),
-            scrub_audit_path: env::var("CHRONOMAXI_SCRUB_AUDIT_PATH")
-                .map(PathBuf::from)
-                .unwrap_or_else(|_| crate::privacy::default_audit_path()),
-        })
+            ingest_url: "http://127.0.0.1:3211".to_string(),
+            ingest_secret: String::new(),
+            ingest_secret_file: default_ingest_secret_file(),
+            actor: DEFAULT_ACTOR.to_string(),
+            device_name: whoami::devicename(),
+            spool_path: default_state_dir().join("spool.sqlite"),
+            device_key_path: default_state_dir().join("device_key"),
+            bucket_config_path: crate::buckets::default_path(),
+            privacy_config_path: crate::privacy::default_config_path(),
+            scrub_audit_path: crate::privacy::default_audit_path(),
+            privacy_failure_policy: PrivacyFailurePolicy::ScrubAll,
+            max_span_seconds: DEFAULT_MAX_SPAN_SECONDS,
+            checkpoint_span_seconds: DEFAULT_CHECKPOINT_SPAN_SECONDS,
+            open_span_persist_seconds: DEFAULT_OPEN_SPAN_PERSIST_SECONDS,
+            heartbeat_path: default_state_dir().join("heartbeat.json"),
+            heartbeat_seconds: DEFAULT_HEARTBEAT_SECONDS,
+            gap_min_seconds: DEFAULT_GAP_MIN_SECONDS,
+            idle_threshold_ms: DEFAULT_IDLE_THRESHOLD_MS,
+            hypr_reconcile_seconds: DEFAULT_HYPR_RECONCILE_SECONDS,
+            sinks: vec![SinkConfig::convex()],
+            spool_batch_size: SPOOL_BATCH_SIZE,
+            spool_batch_size_max: DEFAULT_SPOOL_BATCH_SIZE_MAX,
+            ingest_gzip: false,
+            ingest_poll_interval_seconds: DEFAULT_INGEST_POLL_INTERVAL_SECONDS,
+            ingest_debounce_ms: DEFAULT_INGEST_DEBOUNCE_MS,
+            ingest_min_backoff_seconds: DEFAULT_INGEST_MIN_BACKOFF_SECONDS,
+            ingest_max_backoff_seconds: DEFAULT_INGEST_MAX_BACKOFF_SECONDS,
+            spool_retention_days: DEFAULT_SPOOL_RETENTION_DAYS,
+            archive_dir: PathBuf::new(),
+            archive_retention_months: 0,
+            dead_letter_after_attempts: DEFAULT_DEAD_LETTER_AFTER_ATTEMPTS,
+            tmux_push_freshness_ms: DEFAULT_TMUX_PUSH_FRESHNESS_MS,
+            tmux_ipc_min_interval_ms: DEFAULT_TMUX_IPC_MIN_INTERVAL_MS,
+            evdev_rescan_interval_seconds: DEFAULT_EVDEV_RESCAN_INTERVAL_SECONDS,
+            metrics_listen: String::new(),
+        }
+    }
+
+    /// Merges defaults < file < env < CLI and deserializes the result.
+    /// `env_lookup` is injected so tests never touch the process env.
+    fn layered(
+        file: Option<toml::Table>,
+        env_lookup: impl Fn(&str) -> Option<String>,
+        cli_overrides: &[(String, String)],
+    ) -> Result<Self, String> {
+        let mut table = match toml::Value::try_from(Self::defaults()) {
+            Ok(toml::Value::Table(table)) => table,
+            Ok(_) => return Err("defaults did not serialize to a table".to_string()),
+            Err(e) => return Err(format!("failed to serialize defaults: {e}")),
+        };
+
+        if let Some(file) = file {
+            for (key, value) in file {
+                if !table.contains_key(&key) {
+                    return Err(format!("unknown config key `{key}` in tracker.toml"));
+                }
+                table.insert(key, value);
+            }
+        }
+
+        for (field, var) in ENV_OVERRIDES {
+            if let Some(raw) = env_lookup(var) {
+                set_from_str(&mut table, field, &raw, var)?;
+            }
+        }
+
+        for (flag, raw) in cli_overrides {
+            let field = flag.replace('-', "_");
+            set_from_str(&mut table, &field, raw, &format!("--{flag}"))?;
+        }
+
+        let config: Self = toml::Value::Table(table)
+            .try_into()
+            .map_err(|e| format!("invalid configuration: {e}"))?;
+        crate::ingest::route::validate_sinks(&config.sinks).map_err(|e| format!("invalid configuration: {e}"))?;
+        Ok(config)
     }
 }
 
-/// $XDG_STATE_HOME/chronomaxi/spool.sqlite on Linux (falling back to
-/// ~/.local/state when XDG_STATE_HOME is unset), or
-/// ~/Library/Application Support/chronomaxi/spool.sqlite on macOS.
-fn default_spool_path() -> PathBuf {
+/// Coerces a raw env/CLI string to the type `field` already has in the
+/// merged table (every field is present from the defaults layer).
+fn set_from_str(table: &mut toml::Table, field: &str, raw: &str, source: &str) -> Result<(), String> {
+    let Some(existing) = table.get(field) else {
+        return Err(format!("unknown config key `{field}` (from {source})"));
+    };
+
+    let value = match existing {
+        toml::Value::Integer(_) => raw
+            .trim()
+            .parse::<i64>()
+            .map(toml::Value::Integer)
+            .map_err(|e| format!("{source}: expected an integer for `{field}`, got {raw:?} ({e})"))?,
+        toml::Value::Float(_) => raw
+            .trim()
+            .parse::<f64>()
+            .map(toml::Value::Float)
+            .map_err(|e| format!("{source}: expected a number for `{field}`, got {raw:?} ({e})"))?,
+        toml::Value::Boolean(_) => raw
+            .trim()
+            .parse::<bool>()
+            .map(toml::Value::Boolean)
+            .map_err(|e| format!("{source}: expected true/false for `{field}`, got {raw:?} ({e})"))?,
+        _ => toml::Value::String(raw.to_string()),
+    };
+
+    table.insert(field.to_string(), value);
+    Ok(())
+}
+
+/// Config-related CLI flags, split out of argv by `parse_cli_overrides`.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CliOverrides {
+    pub config_path: Option<PathBuf>,
+    /// (flag name without `--`, raw value) in command-line order.
+    pub overrides: Vec<(String, String)>,
+}
+
+/// Splits `--config <path>` plus any `--<field> <value>` / `--<field>=<value>`
+/// flags out of `args` (program name already stripped). Anything that isn't
+/// a `--flag` is rejected so a typo never silently falls through.
+pub fn parse_cli_overrides(args: &[String]) -> Result<CliOverrides, String> {
+    let mut config_path = None;
+    let mut overrides = Vec::new();
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        let Some(flag) = arg.strip_prefix("--") else {
+            return Err(format!("unexpected argument {arg:?}"));
+        };
+
+        let (key, value) = match flag.split_once('=') {
+            Some((key, value)) => (key.to_string(), value.to_string()),
+            None => {
+                let value = iter.next().ok_or_else(|| format!("--{flag} needs a value"))?;
+                (flag.to_string(), value.clone())
+            }
+        };
+
+        if key == "config" {
+            config_path = Some(PathBuf::from(value));
+        } else {
+            overrides.push((key, value));
+        }
+    }
+
+    Ok(CliOverrides { config_path, overrides })
+}
+
+/// $XDG_CONFIG_HOME/chronomaxi/tracker.toml (falling back to ~/.config),
+/// next to buckets.json and privacy-denylist.json.
+pub fn default_config_path() -> PathBuf {
+    let config_home = env::var("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|_| env::var("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .unwrap_or_else(|_| PathBuf::from(".config"));
+    config_home.join("chronomaxi/tracker.toml")
+}
+
+/// `$CREDENTIALS_DIRECTORY/ingest_secret` when systemd handed us a
+/// credentials directory containing one, else empty (no file).
+fn default_ingest_secret_file() -> PathBuf {
+    env::var("CREDENTIALS_DIRECTORY")
+        .map(|dir| PathBuf::from(dir).join("ingest_secret"))
+        .ok()
+        .filter(|path| path.is_file())
+        .unwrap_or_default()
+}
+
+/// $XDG_STATE_HOME/chronomaxi on Linux (falling back to ~/.local/state
+/// when XDG_STATE_HOME is unset), or ~/Library/Application Support/chronomaxi
+/// on macOS. Holds the spool and the device key.
+fn default_state_dir() -> PathBuf {
     if cfg!(target_os = "macos") {
         return env::var("HOME")
-            .map(|home| PathBuf::from(home).join("Library/Application Support/chronomaxi/spool.sqlite"))
-            .unwrap_or_else(|_| PathBuf::from("chronomaxi-spool.sqlite"));
+            .map(|home| PathBuf::from(home).join("Library/Application Support/chronomaxi"))
+            .unwrap_or_else(|_| PathBuf::from("chronomaxi"));
     }
 
     let state_home = env::var("XDG_STATE_HOME").map(PathBuf::from).unwrap_or_else(|_| {
@@ -77,5 +430,97 @@ fn default_spool_path() -> PathBuf {
             .unwrap_or_else(|_| PathBuf::from(".local/state"))
     });
 
-    state_home.join("chronomaxi/spool.sqlite")
+    state_home.join("chronomaxi")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_env(_: &str) -> Option<String> {
+        None
+    }
+
+    #[test]
+    fn defaults_apply_without_any_layer() {
+        let config = Configuration::layered(None, no_env, &[]).unwrap();
+        assert_eq!(config.max_span_seconds, DEFAULT_MAX_SPAN_SECONDS);
+        assert_eq!(config.idle_threshold_ms, DEFAULT_IDLE_THRESHOLD_MS);
+        assert_eq!(config.spool_batch_size, SPOOL_BATCH_SIZE);
+    }
+
+    #[test]
+    fn cli_beats_env_beats_file() {
+        let file: toml::Table = "idle_threshold_ms = 1000\nmax_span_seconds = 90\ningest_url = \"http://file\""
+            .parse()
+            .unwrap();
+        let env_lookup = |name: &str| match name {
+            "CHRONOMAXI_IDLE_THRESHOLD_MS" => Some("2000".to_string()),
+            "CHRONOMAXI_INGEST_URL" => Some("http://env".to_string()),
+            _ => None,
+        };
+        let cli = vec![("idle-threshold-ms".to_string(), "3000".to_string())];
+
+        let config = Configuration::layered(Some(file), env_lookup, &cli).unwrap();
+        assert_eq!(config.idle_threshold_ms, 3000);
+        assert_eq!(config.ingest_url, "http://env");
+        assert_eq!(config.max_span_seconds, 90);
+    }
+
+    #[test]
+    fn unknown_file_key_is_rejected() {
+        let file: toml::Table = "idle_treshold_ms = 1000".parse().unwrap();
+        let err = Configuration::layered(Some(file), no_env, &[]).err().unwrap();
+        assert!(err.contains("idle_treshold_ms"));
+    }
+
+    #[test]
+    fn non_numeric_env_value_is_rejected() {
+        let env_lookup = |name: &str| (name == "CHRONOMAXI_MAX_SPAN_SECONDS").then(|| "soon".to_string());
+        let err = Configuration::layered(None, env_lookup, &[]).err().unwrap();
+        assert!(err.contains("max_span_seconds"));
+    }
+
+    #[test]
+    fn sinks_table_replaces_the_convex_default() {
+        let config = Configuration::layered(None, no_env, &[]).unwrap();
+        assert_eq!(config.sinks, vec![SinkConfig::convex()]);
+
+        let file: toml::Table = "[[sinks]]\nkind = \"jsonl\"\npath = \"/tmp/spans.jsonl\"\n[[sinks]]\nkind = \"stdout\""
+            .parse()
+            .unwrap();
+        let config = Configuration::layered(Some(file), no_env, &[]).unwrap();
+        assert_eq!(
+            config.sinks,
+            vec![
+                SinkConfig::unrouted(SinkKind::Jsonl { path: PathBuf::from("/tmp/spans.jsonl") }),
+                SinkConfig::unrouted(SinkKind::Stdout {})
+            ]
+        );
+    }
+
+    #[test]
+    fn privacy_failure_policy_parses_from_env() {
+        let env_lookup =
+            |name: &str| (name == "CHRONOMAXI_PRIVACY_FAILURE_POLICY").then(|| "refuse".to_string());
+        let config = Configuration::layered(None, env_lookup, &[]).unwrap();
+        assert_eq!(config.privacy_failure_policy, PrivacyFailurePolicy::Refuse);
+    }
+
+    #[test]
+    fn parse_cli_overrides_handles_both_flag_forms() {
+        let args: Vec<String> = ["--config", "/tmp/t.toml", "--ingest-url=http://x", "--spool-batch-size", "50"]
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        let cli = parse_cli_overrides(&args).unwrap();
+        assert_eq!(cli.config_path, Some(PathBuf::from("/tmp/t.toml")));
+        assert_eq!(
+            cli.overrides,
+            vec![
+                ("ingest-url".to_string(), "http://x".to_string()),
+                ("spool-batch-size".to_string(), "50".to_string()),
+            ]
+        );
+    }
 }
@@ -0,0 +1,430 @@
+//! `doctor` mode: one report covering every capture prerequisite, instead
+//! of the one-off warnings the running tracker prints as it trips over
+//! them ("CHRONOMAXI INPUT COUNTS UNAVAILABLE" from input_evdev.rs, the X11
+//! `DISPLAY` check in `LoggerV4::new`, the macOS permission prompts).
+//!
+//! Checks never prompt and never mutate anything: the spool is opened
+//! without being created or migrated (an old schema is reported, not
+//! upgraded), its write check takes and releases the sqlite write lock,
+//! and the ingest check POSTs an empty batch (authenticated, then a no-op
+//! in convex/http.ts).
+//!
+//! Exit code, for the fleet script:
+//!   0  every check passed (or doesn't apply to this backend)
+//!   1  degraded: spans are captured and delivered, but something is
+//!      missing (input counts, tmux drill-down, event-socket push, ...)
+//!   2  broken: no capture, no durable spool, or no delivery
+
+use std::fmt;
+use std::path::Path;
+
+use crate::config::Configuration;
+use crate::ingest;
+use crate::ingest::signing::DeviceKey;
+use crate::logger_v4::{self, CaptureBackend};
+use crate::spool::Spool;
+use crate::spool::migrate::CURRENT_VERSION;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Status {
+    /// Not applicable on this machine/backend.
+    Skip,
+    Ok,
+    Warn,
+    Fail,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Status::Skip => "skip",
+            Status::Ok => "ok",
+            Status::Warn => "warn",
+            Status::Fail => "FAIL",
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: &'static str, status: Status, detail: impl Into<String>) -> Self {
+        Self { name, status, detail: detail.into() }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    pub fn worst(&self) -> Status {
+        self.checks.iter().map(|check| check.status).max().unwrap_or(Status::Ok)
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self.worst() {
+            Status::Skip | Status::Ok => 0,
+            Status::Warn => 1,
+            Status::Fail => 2,
+        }
+    }
+
+    pub fn print(&self) {
+        for check in &self.checks {
+            println!("{:<5} {:<14} {}", check.status, check.name, check.detail);
+        }
+    }
+}
+
+/// Runs every check and returns the report (unprinted).
+pub async fn run(config: &Configuration) -> Report {
+    let backend = logger_v4::select_backend();
+    let mut report = Report::default();
+
+    report.checks.push(check_backend(backend));
+    #[cfg(target_os = "linux")]
+    {
+        report.checks.push(check_hypr_socket(backend));
+        report.checks.push(check_evdev(backend));
+        report.checks.extend(check_tmux(config));
+    }
+    #[cfg(target_os = "macos")]
+    report.checks.extend(check_macos_permissions());
+    report.checks.push(check_spool(&config.spool_path));
+    report.checks.push(check_ingest(config).await);
+    report.checks.push(check_device_key(config));
+    report.checks.push(check_rules_file(
+        "buckets",
+        &config.bucket_config_path,
+        crate::buckets::validate_file(&config.bucket_config_path),
+    ));
+    report.checks.push(check_rules_file(
+        "privacy",
+        &config.privacy_config_path,
+        crate::privacy::validate_file(&config.privacy_config_path),
+    ));
+    report
+}
+
+#[cfg(target_os = "linux")]
+fn check_backend(backend: CaptureBackend) -> Check {
+    let (tool, missing_hint) = match backend {
+        CaptureBackend::Hyprland => ("hyprctl", "window identity unavailable"),
+        CaptureBackend::X11 => ("xdotool", "install xdotool and xprop"),
+    };
+    if !binary_on_path(tool) {
+        return Check::new("backend", Status::Fail, format!("{backend:?}, but {tool} is not on PATH ({missing_hint})"));
+    }
+    if backend == CaptureBackend::X11 && std::env::var("DISPLAY").unwrap_or_default().trim().is_empty() {
+        return Check::new(
+            "backend",
+            Status::Fail,
+            "X11, but DISPLAY is unset. Fix with: systemctl --user import-environment DISPLAY XAUTHORITY DBUS_SESSION_BUS_ADDRESS",
+        );
+    }
+    Check::new("backend", Status::Ok, format!("{backend:?}"))
+}
+
+#[cfg(target_os = "macos")]
+fn check_backend(backend: CaptureBackend) -> Check {
+    Check::new("backend", Status::Ok, format!("{backend:?}"))
+}
+
+#[cfg(target_os = "linux")]
+fn check_hypr_socket(backend: CaptureBackend) -> Check {
+    if backend != CaptureBackend::Hyprland {
+        return Check::new("hypr socket", Status::Skip, "X11 backend");
+    }
+    let Some(path) = crate::hypr_events::socket_path() else {
+        return Check::new(
+            "hypr socket",
+            Status::Warn,
+            "HYPRLAND_INSTANCE_SIGNATURE or XDG_RUNTIME_DIR unset, falling back to hyprctl polling every tick",
+        );
+    };
+    match std::os::unix::net::UnixStream::connect(&path) {
+        Ok(_) => Check::new("hypr socket", Status::Ok, path.display().to_string()),
+        Err(e) => Check::new(
+            "hypr socket",
+            Status::Warn,
+            format!("{} unreachable ({e}), falling back to hyprctl polling every tick", path.display()),
+        ),
+    }
+}
+
+/// Only the Hyprland backend reads evdev; X11 gets counts from device_query.
+#[cfg(target_os = "linux")]
+fn check_evdev(backend: CaptureBackend) -> Check {
+    if backend != CaptureBackend::Hyprland {
+        return Check::new("evdev", Status::Skip, "X11 backend counts input via device_query");
+    }
+    let probe = crate::input_evdev::probe_devices();
+    if !probe.denied.is_empty() {
+        let paths: Vec<String> = probe.denied.iter().map(|path| path.display().to_string()).collect();
+        return Check::new(
+            "evdev",
+            Status::Warn,
+            format!(
+                "permission denied on {} device(s) ({}), key/click counts unavailable. Fix: sudo usermod -aG input $USER and log back in",
+                paths.len(),
+                paths.join(", ")
+            ),
+        );
+    }
+    if probe.key_capable == 0 {
+        return Check::new("evdev", Status::Warn, "no readable keyboard/mouse under /dev/input");
+    }
+    Check::new("evdev", Status::Ok, format!("{} keyboard/mouse device(s) readable", probe.key_capable))
+}
+
+#[cfg(target_os = "linux")]
+fn check_tmux(config: &Configuration) -> Vec<Check> {
+    let binary = if binary_on_path("tmux") {
+        Check::new("tmux", Status::Ok, "tmux on PATH")
+    } else {
+        Check::new("tmux", Status::Warn, "tmux not on PATH, terminal sub-program drill-down unavailable")
+    };
+
+    let path = crate::tmux::foreground_state_path();
+    let push = match crate::tmux::push_state_age_ms() {
+        None => Check::new(
+            "tmux push",
+            Status::Warn,
+            format!("{} missing or unparsable, drill-down falls back to tmux IPC", path.display()),
+        ),
+        Some(age_ms) if (0..config.tmux_push_freshness_ms as i64).contains(&age_ms) => {
+            Check::new("tmux push", Status::Ok, format!("{} fresh ({age_ms}ms old)", path.display()))
+        }
+        Some(age_ms) => Check::new(
+            "tmux push",
+            Status::Warn,
+            format!(
+                "{} stale ({}s old, limit {}ms), drill-down falls back to tmux IPC",
+                path.display(),
+                age_ms / 1000,
+                config.tmux_push_freshness_ms
+            ),
+        ),
+    };
+    vec![binary, push]
+}
+
+#[cfg(target_os = "macos")]
+fn check_macos_permissions() -> Vec<Check> {
+    use crate::capture::macos::permissions;
+
+    let accessibility = if permissions::accessibility_trusted() {
+        Check::new("accessibility", Status::Ok, "granted")
+    } else {
+        Check::new(
+            "accessibility",
+            Status::Fail,
+            "not granted, window titles unavailable. System Settings > Privacy & Security > Accessibility",
+        )
+    };
+    let input = if permissions::input_monitoring_preflight() {
+        Check::new("input monitor", Status::Ok, "granted")
+    } else {
+        Check::new(
+            "input monitor",
+            Status::Warn,
+            "not granted, key/click counts unavailable. System Settings > Privacy & Security > Input Monitoring",
+        )
+    };
+    vec![accessibility, input]
+}
+
+fn check_device_key(config: &Configuration) -> Check {
+    let path = &config.device_key_path;
+    match DeviceKey::load(path) {
+        Ok(Some(key)) => Check::new("device key", Status::Ok, format!("{} (key id {})", path.display(), key.key_id())),
+        Ok(None) => Check::new(
+            "device key",
+            Status::Warn,
+            format!("no key at {}, batches go out unsigned (run `backend enroll`)", path.display()),
+        ),
+        Err(e) => Check::new("device key", Status::Fail, e),
+    }
+}
+
+fn check_spool(path: &Path) -> Check {
+    if !path.exists() {
+        return Check::new("spool", Status::Warn, format!("{} does not exist yet (created on first start)", path.display()));
+    }
+    let (spool, version) = match Spool::open_existing(path) {
+        Ok(opened) => opened,
+        Err(e) => return Check::new("spool", Status::Fail, format!("cannot open {} ({e})", path.display())),
+    };
+    if version > CURRENT_VERSION {
+        return Check::new(
+            "spool",
+            Status::Fail,
+            format!("{} has schema version {version}, newer than this tracker supports ({CURRENT_VERSION})", path.display()),
+        );
+    }
+    if let Err(e) = spool.check_writable() {
+        return Check::new("spool", Status::Fail, format!("{} not writable ({e})", path.display()));
+    }
+    if version < CURRENT_VERSION {
+        // The counts below read tables an old schema may not have yet.
+        return Check::new(
+            "spool",
+            Status::Warn,
+            format!("{} writable, schema version {version} (migrated to {CURRENT_VERSION} on next start)", path.display()),
+        );
+    }
+    let pending = match spool.pending_count() {
+        Ok(pending) => pending,
+        Err(e) => return Check::new("spool", Status::Fail, format!("cannot count {} ({e})", path.display())),
+    };
+    let oldest = spool
+        .oldest_pending_created_at()
+        .ok()
+        .flatten()
+        .map(|oldest| format!(", oldest {}s ago", (chrono::Utc::now() - oldest).num_seconds()))
+        .unwrap_or_default();
+    match spool.dead_letter_count() {
+        Ok(dead) if dead > 0 => Check::new(
+            "spool",
+            Status::Warn,
+            format!("{} writable, {pending} pending{oldest}, {dead} dead-lettered (see `backend dead-letter list`)", path.display()),
+        ),
+        _ => Check::new("spool", Status::Ok, format!("{} writable, {pending} pending{oldest}", path.display())),
+    }
+}
+
+async fn check_ingest(config: &Configuration) -> Check {
+    if !ingest::uses_convex(config) {
+        return Check::new("ingest", Status::Skip, "no convex sink configured");
+    }
+    if config.ingest_url.trim().is_empty() {
+        return Check::new("ingest", Status::Fail, "ingest_url is not configured");
+    }
+    match ingest::probe(config).await {
+        Ok(status) if status.is_success() => Check::new("ingest", Status::Ok, format!("{} accepted the secret", config.ingest_url)),
+        Ok(status) if status == reqwest::StatusCode::UNAUTHORIZED => Check::new(
+            "ingest",
+            Status::Fail,
+            format!("{} rejected every secret (401), check ingest_secret_file / ingest_secret", config.ingest_url),
+        ),
+        Ok(status) => Check::new("ingest", Status::Fail, format!("{} answered {status}", config.ingest_url)),
+        Err(e) => Check::new("ingest", Status::Fail, format!("{} unreachable ({e})", config.ingest_url)),
+    }
+}
+
+/// A missing rules file isn't broken -- the tracker seeds it with the
+/// built-in rules on startup -- but it's worth a look on a fleet machine.
+fn check_rules_file(name: &'static str, path: &Path, issues: Vec<crate::validate::ConfigIssue>) -> Check {
+    if !path.exists() {
+        return Check::new(name, Status::Warn, format!("{} missing, seeded with built-in rules on first run", path.display()));
+    }
+    match issues.as_slice() {
+        [] => Check::new(name, Status::Ok, format!("{} parses", path.display())),
+        [issue] => Check::new(name, Status::Fail, issue.to_string()),
+        [first, rest @ ..] => Check::new(name, Status::Fail, format!("{first} (+{} more, run `validate`)", rest.len())),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn binary_on_path(name: &str) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::env::var_os("PATH")
+        .map(|paths| {
+            std::env::split_paths(&paths).any(|dir| {
+                let candidate = dir.join(name);
+                candidate
+                    .metadata()
+                    .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+            })
+        })
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    /// Answers every request with `status_line` and an empty body.
+    async fn fake_ingest(status_line: &'static str) -> String {
+        test_support::serve(move |_, _| test_support::response(status_line, "", "")).await
+    }
+
+    fn config_with_ingest(url: String) -> Configuration {
+        let mut config = Configuration::defaults();
+        config.ingest_url = url;
+        config.ingest_secret = "secret".to_string();
+        config
+    }
+
+    #[test]
+    fn exit_code_follows_worst_status() {
+        let mut report = Report::default();
+        report.checks.push(Check::new("a", Status::Skip, ""));
+        report.checks.push(Check::new("b", Status::Ok, ""));
+        assert_eq!(report.exit_code(), 0);
+        report.checks.push(Check::new("c", Status::Warn, ""));
+        assert_eq!(report.exit_code(), 1);
+        report.checks.push(Check::new("d", Status::Fail, ""));
+        assert_eq!(report.exit_code(), 2);
+    }
+
+    #[tokio::test]
+    async fn ingest_check_tells_auth_failure_from_success() {
+        let ok = check_ingest(&config_with_ingest(fake_ingest("200 OK").await)).await;
+        assert_eq!(ok.status, Status::Ok);
+
+        let unauthorized = check_ingest(&config_with_ingest(fake_ingest("401 Unauthorized").await)).await;
+        assert_eq!(unauthorized.status, Status::Fail);
+        assert!(unauthorized.detail.contains("401"));
+
+        let unreachable = check_ingest(&config_with_ingest("http://127.0.0.1:1".to_string())).await;
+        assert_eq!(unreachable.status, Status::Fail);
+        assert!(unreachable.detail.contains("unreachable"));
+    }
+
+    #[test]
+    fn spool_check_reports_pending_and_unwritable() {
+        let dir = test_support::temp_path("doctor-spool");
+        let path = dir.join("spool.sqlite");
+        let check = check_spool(&path);
+        assert_eq!(check.status, Status::Warn, "{}", check.detail);
+        assert!(!path.exists(), "doctor must not create the spool");
+
+        drop(Spool::open(&path).unwrap());
+        let check = check_spool(&path);
+        assert_eq!(check.status, Status::Ok, "{}", check.detail);
+        assert!(check.detail.contains("0 pending"));
+
+        // A directory where the file should be can't be opened as a spool.
+        let check = check_spool(&dir);
+        assert_eq!(check.status, Status::Fail);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn spool_check_reports_an_old_schema_without_migrating_it() {
+        let dir = test_support::temp_path("doctor-old-spool");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("spool.sqlite");
+        rusqlite::Connection::open(&path).unwrap().pragma_update(None, "user_version", 1).unwrap();
+
+        let check = check_spool(&path);
+        assert_eq!(check.status, Status::Warn, "{}", check.detail);
+        assert!(check.detail.contains("schema version 1"), "{}", check.detail);
+        let version: u32 =
+            rusqlite::Connection::open(&path).unwrap().query_row("PRAGMA user_version", [], |row| row.get(0)).unwrap();
+        assert_eq!(version, 1);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,276 @@
+//! `backend export`: spans from the spool and archive (crate::history) as
+//! JSONL or CSV, for timesheets and anything else that wants our own data
+//! without going through Convex.
+//!
+//! JSONL lines are the `IngestRow` wire object plus the start/end times
+//! rendered in UTC and local time. CSV has a fixed header (`COLUMNS`) in a
+//! fixed order and never drops a column, empty or not; add new columns at
+//! the end so existing imports keep working.
+//!
+//! Filters: `--bucket`, `--device` match exactly; `--actor agent` also
+//! matches `agent:<name>`. Idle spans are left out unless `--include-idle`.
+
+use std::io::{self, Write};
+
+use chrono::{DateTime, SecondsFormat, TimeZone, Utc};
+
+use crate::spool::{category_name, IngestRow};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Jsonl,
+    Csv,
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(raw: &str) -> Result<Self, String> {
+        match raw {
+            "jsonl" => Ok(Self::Jsonl),
+            "csv" => Ok(Self::Csv),
+            other => Err(format!("unknown format {other:?}, expected jsonl or csv")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Filter {
+    pub bucket: Option<String>,
+    pub actor: Option<String>,
+    pub device: Option<String>,
+    pub include_idle: bool,
+}
+
+impl Filter {
+    pub fn matches(&self, span: &IngestRow) -> bool {
+        (self.include_idle || !span.is_idle)
+            && self.bucket.as_ref().is_none_or(|bucket| span.bucket.as_ref() == Some(bucket))
+            && self.device.as_ref().is_none_or(|device| span.device_name == *device)
+            && self.actor.as_ref().is_none_or(|actor| {
+                span.actor == *actor
+                    || span.actor.strip_prefix(actor.as_str()).is_some_and(|rest| rest.starts_with(':'))
+            })
+    }
+}
+
+/// CSV header, in output order.
+pub const COLUMNS: [&str; 25] = [
+    "sourceId",
+    "startedAtUtc",
+    "startedAtLocal",
+    "endedAtUtc",
+    "endedAtLocal",
+    "startedAtMs",
+    "durationMs",
+    "deviceName",
+    "actor",
+    "bucket",
+    "category",
+    "isIdle",
+    "programName",
+    "programProcessName",
+    "subProgram",
+    "tmuxSession",
+    "windowId",
+    "browserTitle",
+    "keysPressedCount",
+    "leftClickCount",
+    "rightClickCount",
+    "middleClickCount",
+    "mouseMovementInMM",
+    "tokensSpent",
+    "idleReason",
+];
+
+/// Writes the spans `filter` keeps to `out`, returning how many. `tz` is
+/// what "local" means (`chrono::Local` outside tests).
+pub fn write<Tz: TimeZone, W: Write>(
+    out: &mut W,
+    format: Format,
+    spans: &[IngestRow],
+    filter: &Filter,
+    tz: &Tz,
+) -> io::Result<usize>
+where
+    Tz::Offset: std::fmt::Display,
+{
+    if format == Format::Csv {
+        writeln!(out, "{}", COLUMNS.join(","))?;
+    }
+    let mut written = 0;
+    for span in spans.iter().filter(|span| filter.matches(span)) {
+        match format {
+            Format::Jsonl => {
+                let mut line = serde_json::to_value(span).map_err(io::Error::other)?;
+                if let Some(object) = line.as_object_mut() {
+                    for (key, value) in times(span, tz) {
+                        object.insert(key.to_string(), value.into());
+                    }
+                }
+                writeln!(out, "{line}")?;
+            }
+            Format::Csv => writeln!(out, "{}", csv_record(span, tz).join(","))?,
+        }
+        written += 1;
+    }
+    out.flush()?;
+    Ok(written)
+}
+
+/// Start and end as RFC 3339, in UTC and in `tz`.
+fn times<Tz: TimeZone>(span: &IngestRow, tz: &Tz) -> [(&'static str, String); 4]
+where
+    Tz::Offset: std::fmt::Display,
+{
+    let utc = |ms: i64| DateTime::<Utc>::from_timestamp_millis(ms).unwrap_or_default();
+    let started = utc(span.created_at);
+    let ended = utc(span.created_at.saturating_add(span.duration_ms.max(0)));
+    let rfc3339 = |at: DateTime<Utc>| at.to_rfc3339_opts(SecondsFormat::Millis, true);
+    let local = |at: DateTime<Utc>| at.with_timezone(tz).to_rfc3339_opts(SecondsFormat::Millis, true);
+    [
+        ("startedAtUtc", rfc3339(started)),
+        ("startedAtLocal", local(started)),
+        ("endedAtUtc", rfc3339(ended)),
+        ("endedAtLocal", local(ended)),
+    ]
+}
+
+fn csv_record<Tz: TimeZone>(span: &IngestRow, tz: &Tz) -> Vec<String>
+where
+    Tz::Offset: std::fmt::Display,
+{
+    let optional = |value: Option<String>| value.unwrap_or_default();
+    let [started_utc, started_local, ended_utc, ended_local] = times(span, tz).map(|(_, value)| value);
+    [
+        span.source_id.clone(),
+        started_utc,
+        started_local,
+        ended_utc,
+        ended_local,
+        span.created_at.to_string(),
+        span.duration_ms.to_string(),
+        span.device_name.clone(),
+        span.actor.clone(),
+        optional(span.bucket.clone()),
+        category_name(&span.category),
+        span.is_idle.to_string(),
+        span.program_name.clone(),
+        span.program_process_name.clone(),
+        optional(span.sub_program.clone()),
+        optional(span.tmux_session.clone()),
+        span.window_id.clone(),
+        optional(span.browser_title.clone()),
+        optional(span.keys_pressed_count.map(|count| count.to_string())),
+        optional(span.left_click_count.map(|count| count.to_string())),
+        optional(span.right_click_count.map(|count| count.to_string())),
+        optional(span.middle_click_count.map(|count| count.to_string())),
+        optional(span.mouse_movement_in_mm.map(|mm| mm.to_string())),
+        optional(span.tokens_spent.map(|tokens| tokens.to_string())),
+        optional(span.idle_reason.clone()),
+    ]
+    .iter()
+    .map(|field| csv_field(field))
+    .collect()
+}
+
+/// RFC 4180 quoting: only fields with a comma, quote or line break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::category::Category;
+    use chrono::FixedOffset;
+
+    fn span(source_id: &str, actor: &str, bucket: Option<&str>, idle: bool) -> IngestRow {
+        IngestRow {
+            source_id: source_id.to_string(),
+            // 2026-10-15T22:30:00Z
+            created_at: 1_792_103_400_000,
+            duration_ms: 90 * 60_000,
+            category: Category::Coding,
+            is_idle: idle,
+            device_name: "desk".to_string(),
+            actor: actor.to_string(),
+            window_id: "0x1".to_string(),
+            program_process_name: "kitty".to_string(),
+            program_name: "kitty".to_string(),
+            sub_program: Some("nvim".to_string()),
+            tmux_session: None,
+            bucket: bucket.map(str::to_string),
+            browser_title: Some("review, \"draft\"".to_string()),
+            keys_pressed_count: Some(12),
+            mouse_movement_in_mm: None,
+            left_click_count: Some(3),
+            right_click_count: None,
+            middle_click_count: None,
+            tokens_spent: None,
+            idle_reason: None,
+        }
+    }
+
+    fn export(format: Format, spans: &[IngestRow], filter: &Filter) -> (usize, String) {
+        let mut out = Vec::new();
+        let tz = FixedOffset::east_opt(2 * 3600).unwrap();
+        let written = write(&mut out, format, spans, filter, &tz).unwrap();
+        (written, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn filters_by_bucket_actor_device_and_idle() {
+        let spans = [
+            span("a", "human", Some("chronomaxi"), false),
+            span("b", "agent:claude", Some("chronomaxi"), false),
+            span("c", "agent", None, false),
+            span("d", "human", Some("chronomaxi"), true),
+            span("e", "agentic", None, false),
+        ];
+        let kept = |filter: Filter| -> Vec<&str> {
+            spans.iter().filter(|span| filter.matches(span)).map(|span| span.source_id.as_str()).collect()
+        };
+
+        assert_eq!(kept(Filter::default()), ["a", "b", "c", "e"]);
+        assert_eq!(kept(Filter { include_idle: true, ..Default::default() }).len(), 5);
+        assert_eq!(kept(Filter { actor: Some("agent".to_string()), ..Default::default() }), ["b", "c"]);
+        assert_eq!(kept(Filter { actor: Some("agent:claude".to_string()), ..Default::default() }), ["b"]);
+        assert_eq!(kept(Filter { bucket: Some("chronomaxi".to_string()), ..Default::default() }), ["a", "b"]);
+        assert!(kept(Filter { device: Some("laptop".to_string()), ..Default::default() }).is_empty());
+    }
+
+    #[test]
+    fn csv_has_a_stable_header_and_quotes_fields() {
+        let (written, csv) = export(Format::Csv, &[span("a", "human", None, false)], &Filter::default());
+        assert_eq!(written, 1);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], COLUMNS.join(","));
+        assert_eq!(
+            lines[1],
+            "a,2026-10-15T22:30:00.000Z,2026-10-16T00:30:00.000+02:00,2026-10-16T00:00:00.000Z,\
+             2026-10-16T02:00:00.000+02:00,1792103400000,5400000,desk,human,,Coding,false,kitty,kitty,\
+             nvim,,0x1,\"review, \"\"draft\"\"\",12,3,,,,,"
+        );
+
+        let (written, csv) = export(Format::Csv, &[], &Filter::default());
+        assert_eq!((written, csv.lines().count()), (0, 1));
+    }
+
+    #[test]
+    fn jsonl_is_the_wire_row_plus_rendered_times() {
+        let (written, jsonl) = export(Format::Jsonl, &[span("a", "human", Some("chronomaxi"), false)], &Filter::default());
+        assert_eq!(written, 1);
+        let line: serde_json::Value = serde_json::from_str(jsonl.trim_end()).unwrap();
+        assert_eq!(line["sourceId"], "a");
+        assert_eq!(line["bucket"], "chronomaxi");
+        assert_eq!(line["startedAtUtc"], "2026-10-15T22:30:00.000Z");
+        assert_eq!(line["endedAtLocal"], "2026-10-16T02:00:00.000+02:00");
+        let row: IngestRow = serde_json::from_value(line).unwrap();
+        assert_eq!(row.created_at, 1_792_103_400_000);
+    }
+}
@@ -0,0 +1,197 @@
+//! Downtime as data. Without this a stopped, crashed or powered-off
+//! tracker just leaves a hole in the timeline, indistinguishable from
+//! "nobody at the computer" on the dashboard.
+//!
+//! logger_v4 rewrites `heartbeat_path` (a small JSON file in the state dir)
+//! every `heartbeat_seconds` with the time, this boot's id and whether the
+//! run ended cleanly. On the next start the time since the last heartbeat
+//! becomes a gap row (`gap_row`): category `Untracked`, idle, programName
+//! `untracked` and the `GapReason` in idleReason. Convex stores it but
+//! leaves it out of every rollup (deriveSpanDeltas in
+//! convex/lib/aggregation.ts). On Linux logind's sleep/wake signals (crate::logind) give
+//! the exact suspend gap; failing that, a tick that arrives long after the
+//! previous one means the process was frozen and gets a suspend gap too.
+//!
+//! Gaps shorter than `gap_min_seconds` (a restart, a slow tick) are not
+//! recorded.
+
+use std::io;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+
+use crate::category::Category;
+use crate::spool::IngestRow;
+
+/// programName of every gap row.
+pub const GAP_PROGRAM: &str = "untracked";
+
+/// What the last heartbeat file said.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Heartbeat {
+    /// Unix ms of the last heartbeat.
+    pub at: i64,
+    /// See `boot_id`; `None` where it can't be read.
+    #[serde(rename = "bootId")]
+    pub boot_id: Option<String>,
+    /// Written by a tracker that was shutting down (SIGINT/SIGTERM).
+    pub clean: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GapReason {
+    /// The tracker was stopped.
+    Shutdown,
+    /// The tracker died (SIGKILL, OOM, panic) while the machine stayed up.
+    Crash,
+    /// The machine slept with the tracker running.
+    Suspend,
+    /// The machine was off or rebooted.
+    Boot,
+    /// A dirty stop where the boot id can't tell a crash from a reboot.
+    Unknown,
+}
+
+impl GapReason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Shutdown => "shutdown",
+            Self::Crash => "crash",
+            Self::Suspend => "suspend",
+            Self::Boot => "boot",
+            Self::Unknown => "unknown",
+        }
+    }
+
+    /// Why the tracker last stopped, from its last heartbeat and the
+    /// current boot id. A reboot wins over a clean stop: the machine being
+    /// off is the more useful thing to know.
+    pub fn since(last: &Heartbeat, boot_id: Option<&str>) -> Self {
+        match (last.boot_id.as_deref(), boot_id) {
+            (Some(last_boot), Some(boot)) if last_boot != boot => Self::Boot,
+            _ if last.clean => Self::Shutdown,
+            (Some(_), Some(_)) => Self::Crash,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+/// The last heartbeat, if there is a readable one.
+pub fn read(path: &Path) -> Option<Heartbeat> {
+    let raw = std::fs::read_to_string(path).ok()?;
+    match serde_json::from_str(&raw) {
+        Ok(heartbeat) => Some(heartbeat),
+        Err(e) => {
+            eprintln!("chronomaxi heartbeat: ignoring unreadable {} ({e})", path.display());
+            None
+        }
+    }
+}
+
+/// Replaces the heartbeat file via a rename, so a crash mid-write leaves
+/// the previous heartbeat rather than half of this one.
+pub fn write(path: &Path, heartbeat: &Heartbeat) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let temp = path.with_extension("json.tmp");
+    std::fs::write(&temp, serde_json::to_vec(heartbeat).map_err(io::Error::other)?)?;
+    std::fs::rename(&temp, path)
+}
+
+/// Identifies the current boot: the kernel's random boot id on Linux,
+/// `kern.boottime` on macOS.
+#[cfg(target_os = "linux")]
+pub fn boot_id() -> Option<String> {
+    let id = std::fs::read_to_string("/proc/sys/kernel/random/boot_id").ok()?;
+    Some(id.trim().to_string()).filter(|id| !id.is_empty())
+}
+
+#[cfg(target_os = "macos")]
+pub fn boot_id() -> Option<String> {
+    let output = std::process::Command::new("sysctl").args(["-n", "kern.boottime"]).output().ok()?;
+    let boottime = String::from_utf8_lossy(&output.stdout);
+    // "{ sec = 1760000000, usec = 123 } Thu Oct  9 ..." -- the struct is the id.
+    let id = boottime.split('}').next()?.trim_start_matches('{').trim();
+    Some(id.to_string()).filter(|id| output.status.success() && !id.is_empty())
+}
+
+/// The gap row for `[from, to)`. Its sourceId is derived from the device
+/// and start, so a gap spooled twice (a crash right after spooling it) is
+/// one row.
+pub fn gap_row(
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    reason: GapReason,
+    device_name: &str,
+    actor: &str,
+) -> IngestRow {
+    let from_ms = from.timestamp_millis();
+    IngestRow {
+        source_id: format!("gap:{device_name}:{from_ms}"),
+        created_at: from_ms,
+        duration_ms: (to.timestamp_millis() - from_ms).max(0),
+        category: Category::Untracked,
+        is_idle: true,
+        device_name: device_name.to_string(),
+        actor: actor.to_string(),
+        window_id: "none".to_string(),
+        program_process_name: "chronomaxi".to_string(),
+        program_name: GAP_PROGRAM.to_string(),
+        sub_program: None,
+        tmux_session: None,
+        bucket: None,
+        browser_title: None,
+        keys_pressed_count: None,
+        mouse_movement_in_mm: None,
+        left_click_count: None,
+        right_click_count: None,
+        middle_click_count: None,
+        tokens_spent: None,
+        idle_reason: Some(reason.as_str().to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heartbeat(boot_id: Option<&str>, clean: bool) -> Heartbeat {
+        Heartbeat { at: 1_792_103_400_000, boot_id: boot_id.map(str::to_string), clean }
+    }
+
+    #[test]
+    fn reason_comes_from_the_boot_id_and_clean_flag() {
+        assert_eq!(GapReason::since(&heartbeat(Some("a"), false), Some("b")), GapReason::Boot);
+        assert_eq!(GapReason::since(&heartbeat(Some("a"), true), Some("b")), GapReason::Boot);
+        assert_eq!(GapReason::since(&heartbeat(Some("a"), true), Some("a")), GapReason::Shutdown);
+        assert_eq!(GapReason::since(&heartbeat(None, true), None), GapReason::Shutdown);
+        assert_eq!(GapReason::since(&heartbeat(Some("a"), false), Some("a")), GapReason::Crash);
+        assert_eq!(GapReason::since(&heartbeat(None, false), Some("a")), GapReason::Unknown);
+    }
+
+    #[test]
+    fn heartbeat_round_trips_and_a_broken_file_reads_as_none() {
+        let dir = crate::test_support::temp_path("heartbeat");
+        let path = dir.join("state/heartbeat.json");
+        assert_eq!(read(&path), None);
+        write(&path, &heartbeat(Some("a"), false)).unwrap();
+        write(&path, &heartbeat(Some("a"), true)).unwrap();
+        assert_eq!(read(&path), Some(heartbeat(Some("a"), true)));
+
+        std::fs::write(&path, "{\"at\":").unwrap();
+        assert_eq!(read(&path), None);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn gap_rows_are_idle_untracked_and_carry_the_reason() {
+        let from = DateTime::<Utc>::from_timestamp_millis(1_792_103_400_000).unwrap();
+        let row = gap_row(from, from + chrono::Duration::hours(8), GapReason::Boot, "desk", "human");
+        assert_eq!(row.source_id, "gap:desk:1792103400000");
+        assert_eq!((row.duration_ms, row.is_idle, &row.category), (8 * 3_600_000, true, &Category::Untracked));
+        assert_eq!((row.program_name.as_str(), row.sub_program.as_deref()), ("untracked", None));
+        let wire = serde_json::to_value(&row).unwrap();
+        assert_eq!((&wire["category"], &wire["idleReason"]), (&"Untracked".into(), &"boot".into()));
+    }
+}
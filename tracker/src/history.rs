@@ -0,0 +1,158 @@
+//! Local span history for offline reporting (`backend summary`): the
+//! spool's rows plus, when `archive_dir` is set, the monthly archive
+//! (crate::spool::archive), so nothing here needs Convex.
+//!
+//! Ranges are in local time and select spans by start: `today` is local
+//! midnight to midnight, `week` the ISO week from Monday, and `--from` /
+//! `--to` take either a date (`--to` includes that whole day) or an RFC 3339
+//! timestamp (`--to` exclusive).
+
+use std::path::Path;
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone};
+
+use crate::config::Configuration;
+use crate::spool::archive::Archive;
+use crate::spool::migrate::CURRENT_VERSION;
+use crate::spool::{IngestRow, Spool};
+
+/// Half-open `[from_ms, to_ms)` in Unix ms, with how to describe it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Range {
+    pub from_ms: i64,
+    pub to_ms: i64,
+    pub label: String,
+}
+
+impl Range {
+    pub fn today<Tz: TimeZone>(now: &DateTime<Tz>) -> Self {
+        let day = now.date_naive();
+        let tz = now.timezone();
+        Self {
+            from_ms: midnight(&tz, day),
+            to_ms: midnight(&tz, day + Duration::days(1)),
+            label: day.to_string(),
+        }
+    }
+
+    pub fn this_week<Tz: TimeZone>(now: &DateTime<Tz>) -> Self {
+        let monday = now.date_naive() - Duration::days(now.weekday().num_days_from_monday() as i64);
+        let tz = now.timezone();
+        Self {
+            from_ms: midnight(&tz, monday),
+            to_ms: midnight(&tz, monday + Duration::days(7)),
+            label: format!("{} .. {}", monday, monday + Duration::days(6)),
+        }
+    }
+
+    /// `--from` / `--to`; a missing bound is open.
+    pub fn between<Tz: TimeZone>(tz: &Tz, from: Option<&str>, to: Option<&str>) -> Result<Self, String> {
+        let from_ms = from.map(|raw| bound(tz, raw, false)).transpose()?.unwrap_or(i64::MIN);
+        let to_ms = to.map(|raw| bound(tz, raw, true)).transpose()?.unwrap_or(i64::MAX);
+        if from_ms >= to_ms {
+            return Err(format!("--from {} is not before --to {}", from.unwrap_or("-"), to.unwrap_or("-")));
+        }
+        Ok(Self { from_ms, to_ms, label: format!("{} .. {}", from.unwrap_or("start"), to.unwrap_or("now")) })
+    }
+}
+
+fn midnight<Tz: TimeZone>(tz: &Tz, day: NaiveDate) -> i64 {
+    let naive = day.and_hms_opt(0, 0, 0).expect("midnight exists");
+    // A DST jump at midnight has no local 00:00; its UTC reading is close enough.
+    tz.from_local_datetime(&naive)
+        .earliest()
+        .unwrap_or_else(|| tz.from_utc_datetime(&naive))
+        .timestamp_millis()
+}
+
+/// One `--from`/`--to` value in Unix ms. An end date means the end of
+/// that day.
+fn bound<Tz: TimeZone>(tz: &Tz, raw: &str, end: bool) -> Result<i64, String> {
+    if let Ok(day) = NaiveDate::parse_from_str(raw, "%Y-%m-%d") {
+        return Ok(midnight(tz, if end { day + Duration::days(1) } else { day }));
+    }
+    DateTime::parse_from_rfc3339(raw)
+        .map(|at| at.timestamp_millis())
+        .map_err(|_| format!("{raw:?} is neither a date (YYYY-MM-DD) nor an RFC 3339 timestamp"))
+}
+
+/// Every span starting in `range`, from the spool and the archive,
+/// oldest first. A span in both (archived, not yet pruned) counts once.
+/// The spool is only read: a missing one is reported and skipped, and one
+/// the tracker hasn't migrated yet is an error rather than upgraded here.
+pub fn spans(config: &Configuration, range: &Range) -> Result<Vec<IngestRow>, Box<dyn std::error::Error>> {
+    let mut spans = spooled_spans(&config.spool_path, range)?;
+    if !config.archive_dir.as_os_str().is_empty() && config.archive_dir.exists() {
+        let spooled: std::collections::HashSet<String> = spans.iter().map(|span| span.source_id.clone()).collect();
+        let archived = Archive::open(&config.archive_dir)?.spans_between(range.from_ms, range.to_ms)?;
+        spans.extend(archived.into_iter().filter(|span| !spooled.contains(&span.source_id)));
+        spans.sort_by_key(|span| span.created_at);
+    }
+    Ok(spans)
+}
+
+fn spooled_spans(path: &Path, range: &Range) -> Result<Vec<IngestRow>, Box<dyn std::error::Error>> {
+    if !path.exists() {
+        eprintln!("chronomaxi history: no spool at {} (created on first start)", path.display());
+        return Ok(Vec::new());
+    }
+    let (spool, version) = Spool::open_existing(path).map_err(|e| format!("cannot open {} ({e})", path.display()))?;
+    if version > CURRENT_VERSION {
+        return Err(format!("{} has schema version {version}, newer than this tracker supports ({CURRENT_VERSION})", path.display()).into());
+    }
+    if version < CURRENT_VERSION {
+        return Err(format!("{} has schema version {version}, migrated to {CURRENT_VERSION} on next start", path.display()).into());
+    }
+    Ok(spool.spans_between(range.from_ms, range.to_ms)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::FixedOffset;
+
+    #[test]
+    fn ranges_follow_the_local_calendar() {
+        let tz = FixedOffset::east_opt(2 * 3600).unwrap();
+        // Thursday 2026-10-15, 00:30 local is still the 14th in UTC.
+        let now = tz.with_ymd_and_hms(2026, 10, 15, 0, 30, 0).unwrap();
+        let day_start = tz.with_ymd_and_hms(2026, 10, 15, 0, 0, 0).unwrap().timestamp_millis();
+
+        let today = Range::today(&now);
+        assert_eq!((today.from_ms, today.to_ms - today.from_ms), (day_start, 86_400_000));
+        assert_eq!(today.label, "2026-10-15");
+
+        let week = Range::this_week(&now);
+        assert_eq!(week.from_ms, tz.with_ymd_and_hms(2026, 10, 12, 0, 0, 0).unwrap().timestamp_millis());
+        assert_eq!(week.to_ms - week.from_ms, 7 * 86_400_000);
+
+        let range = Range::between(&tz, Some("2026-10-01"), Some("2026-10-15")).unwrap();
+        assert_eq!((range.from_ms, range.to_ms), (tz.with_ymd_and_hms(2026, 10, 1, 0, 0, 0).unwrap().timestamp_millis(), today.to_ms));
+        let exact = Range::between(&tz, Some("2026-10-15T00:00:00Z"), None).unwrap();
+        assert_eq!((exact.from_ms, exact.to_ms), (day_start + 2 * 3_600_000, i64::MAX));
+
+        assert!(Range::between(&tz, Some("2026-10-15"), Some("2026-10-14")).is_err());
+        assert!(Range::between(&tz, Some("yesterday"), None).is_err());
+    }
+
+    #[test]
+    fn reading_history_never_creates_or_migrates_the_spool() {
+        let range = Range::between(&chrono::Utc, None, None).unwrap();
+        let path = crate::test_support::temp_path("history-spool");
+        assert!(spooled_spans(&path, &range).unwrap().is_empty());
+        assert!(!path.exists());
+
+        rusqlite::Connection::open(&path)
+            .unwrap()
+            .execute_batch(include_str!("spool/fixtures/baseline.sql"))
+            .unwrap();
+        let error = spooled_spans(&path, &range).unwrap_err();
+        assert!(error.to_string().contains("schema version 0"), "{error}");
+        let version: u32 = rusqlite::Connection::open(&path).unwrap().query_row("PRAGMA user_version", [], |row| row.get(0)).unwrap();
+        assert_eq!(version, 0);
+
+        drop(Spool::open(&path).unwrap());
+        assert_eq!(spooled_spans(&path, &range).unwrap().len(), 3);
+        let _ = std::fs::remove_file(&path);
+    }
+}
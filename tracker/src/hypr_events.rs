@@ -97,7 +97,8 @@ impl HyprEventWatcher {
     }
 }
 
-fn socket_path() -> Option<PathBuf> {
+/// `None` when `XDG_RUNTIME_DIR` or `HYPRLAND_INSTANCE_SIGNATURE` is unset.
+pub fn socket_path() -> Option<PathBuf> {
     let runtime_dir = env::var("XDG_RUNTIME_DIR").ok()?;
     let signature = env::var("HYPRLAND_INSTANCE_SIGNATURE").ok()?;
     if signature.trim().is_empty() {
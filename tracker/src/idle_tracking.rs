@@ -21,8 +21,13 @@ pub struct IdleTracker {
 
 impl IdleTracker {
     pub fn new() -> Self {
+        Self::with_threshold_ms(crate::config::DEFAULT_IDLE_THRESHOLD_MS)
+    }
+
+    /// `idle_threshold_ms` comes from `Configuration::idle_threshold_ms`.
+    pub fn with_threshold_ms(idle_threshold_ms: i64) -> Self {
         Self {
-            idle_threshold_ms: 300000,
+            idle_threshold_ms,
             last_mouse_position: None,
             last_keys_pressed_count: None,
             last_window_id: None,
@@ -75,6 +80,12 @@ impl IdleTracker {
     }
 }
 
+impl Default for IdleTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
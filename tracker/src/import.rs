@@ -0,0 +1,334 @@
+//! `backend import`: ActivityWatch history into the spool, so the years
+//! before chronomaxi flush to Convex like any other span.
+//!
+//! Takes an aw-server export, either the full `/api/0/export` document
+//! (`{"buckets": {...}}`) or one bucket's export. `currentwindow` buckets
+//! (aw-watcher-window) become spans; `afkstatus` buckets (aw-watcher-afk)
+//! only decide idleness: a window event overlapping an `afk` stretch is
+//! split there, and the covered part is idle. Other bucket types are
+//! skipped and reported.
+//!
+//! Every span goes through the bucket rules, privacy scrubber, category
+//! matcher and actor tag exactly as capture does (`Rules::span`), under
+//! this machine's `device_name`. The sourceId is `aw:<bucket id>:<start
+//! ms>`, so importing the same export again adds nothing and Convex
+//! dedupes anything that was already sent and pruned.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+
+use crate::actor;
+use crate::buckets::BucketClassifier;
+use crate::category::CategoryMatcher;
+use crate::config::Configuration;
+use crate::logger_v4;
+use crate::privacy::PrivacyScrubber;
+use crate::spool::IngestRow;
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(untagged)]
+enum AwExport {
+    Full { buckets: BTreeMap<String, AwBucket> },
+    Bucket(AwBucket),
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct AwBucket {
+    #[serde(default)]
+    id: String,
+    #[serde(rename = "type", default)]
+    kind: String,
+    events: Vec<AwEvent>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct AwEvent {
+    timestamp: DateTime<Utc>,
+    /// Seconds.
+    duration: f64,
+    #[serde(default)]
+    data: serde_json::Map<String, serde_json::Value>,
+}
+
+impl AwEvent {
+    fn start_ms(&self) -> i64 {
+        self.timestamp.timestamp_millis()
+    }
+
+    fn end_ms(&self) -> i64 {
+        self.start_ms() + (self.duration.max(0.0) * 1000.0).round() as i64
+    }
+
+    fn text(&self, key: &str) -> &str {
+        self.data.get(key).and_then(serde_json::Value::as_str).unwrap_or("")
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Converted {
+    pub rows: Vec<IngestRow>,
+    pub window_events: usize,
+    pub afk_events: usize,
+    /// Buckets of a type we don't import, as `id (type)`.
+    pub skipped_buckets: Vec<String>,
+}
+
+/// The live capture rules (crate::logger_v4::LoggerV4::capture), loaded
+/// from the same files.
+pub struct Rules {
+    classifier: BucketClassifier,
+    categories: CategoryMatcher,
+    scrubber: PrivacyScrubber,
+    actor: String,
+    device_name: String,
+}
+
+impl Rules {
+    pub fn load(config: &Configuration) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            classifier: BucketClassifier::load(&config.bucket_config_path),
+            categories: CategoryMatcher::new(),
+            scrubber: PrivacyScrubber::load(
+                &config.privacy_config_path,
+                &config.scrub_audit_path,
+                config.privacy_failure_policy,
+            )?,
+            actor: config.actor.clone(),
+            device_name: config.device_name.clone(),
+        })
+    }
+
+    /// One span for `app` / `title`, classified and scrubbed in capture's
+    /// order: bucket on the raw inputs, then the scrubber, then category
+    /// and actor on what survived it.
+    fn span(&self, source_id: String, app: &str, title: &str, started_ms: i64, duration_ms: i64, idle: bool) -> IngestRow {
+        let (browser_title, site_name) = logger_v4::browser_title_and_site_name(app, title).unwrap_or((None, None));
+        let initial_bucket = self.classifier.classify(app, Some(title), None, None);
+        let scrubbed = self.scrubber.scrub_fields(app, app, title, browser_title.as_deref(), None, &initial_bucket);
+        let site_name = if scrubbed.scrubbed { None } else { site_name };
+        let category = self.categories.categorize(
+            &scrubbed.program_name,
+            &scrubbed.program_process_name,
+            scrubbed.browser_title.as_deref(),
+            site_name.as_deref(),
+            scrubbed.sub_program.as_deref(),
+        );
+
+        IngestRow {
+            source_id,
+            created_at: started_ms,
+            duration_ms,
+            category,
+            is_idle: idle,
+            device_name: self.device_name.clone(),
+            actor: actor::resolve_actor(&scrubbed.title, &self.actor),
+            window_id: "unknown".to_string(),
+            program_process_name: scrubbed.program_process_name,
+            program_name: scrubbed.program_name,
+            sub_program: scrubbed.sub_program,
+            tmux_session: None,
+            bucket: Some(scrubbed.bucket),
+            browser_title: scrubbed.browser_title,
+            keys_pressed_count: None,
+            mouse_movement_in_mm: None,
+            left_click_count: None,
+            right_click_count: None,
+            middle_click_count: None,
+            tokens_spent: None,
+            idle_reason: None,
+        }
+    }
+}
+
+pub fn read(path: &Path, rules: &Rules) -> Result<Converted, Box<dyn std::error::Error>> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    Ok(convert(&text, rules).map_err(|e| format!("{}: {e}", path.display()))?)
+}
+
+/// Converts an export document into spans, oldest first.
+pub fn convert(text: &str, rules: &Rules) -> Result<Converted, String> {
+    let export: AwExport =
+        serde_json::from_str(text).map_err(|e| format!("not an ActivityWatch export ({e})"))?;
+    let buckets: Vec<(String, AwBucket)> = match export {
+        AwExport::Full { buckets } => buckets.into_iter().collect(),
+        AwExport::Bucket(bucket) => vec![(bucket.id.clone(), bucket)],
+    };
+
+    let mut converted = Converted::default();
+    let mut windows = Vec::new();
+    let mut afk = Vec::new();
+    for (id, bucket) in buckets {
+        let id = if id.is_empty() { bucket.id.clone() } else { id };
+        match bucket.kind.as_str() {
+            "currentwindow" => windows.push((id, bucket.events)),
+            "afkstatus" => afk.extend(
+                bucket
+                    .events
+                    .iter()
+                    .filter(|event| event.text("status") == "afk")
+                    .map(|event| (event.start_ms(), event.end_ms())),
+            ),
+            other => converted.skipped_buckets.push(format!("{id} ({other})")),
+        }
+    }
+    converted.afk_events = afk.len();
+    let afk = merged(afk);
+
+    for (bucket_id, events) in windows {
+        converted.window_events += events.len();
+        for event in &events {
+            for (start, end, idle) in split(event.start_ms(), event.end_ms(), &afk) {
+                converted.rows.push(rules.span(
+                    format!("aw:{bucket_id}:{start}"),
+                    event.text("app"),
+                    event.text("title"),
+                    start,
+                    end - start,
+                    idle,
+                ));
+            }
+        }
+    }
+    converted.rows.sort_by_key(|row| row.created_at);
+    Ok(converted)
+}
+
+/// Sorted, non-overlapping afk stretches.
+fn merged(mut stretches: Vec<(i64, i64)>) -> Vec<(i64, i64)> {
+    stretches.sort_unstable();
+    let mut out: Vec<(i64, i64)> = Vec::with_capacity(stretches.len());
+    for (start, end) in stretches {
+        match out.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => out.push((start, end)),
+        }
+    }
+    out
+}
+
+/// `[start, end)` cut at the `afk` boundaries inside it, as
+/// `(start, end, idle)` pieces. Empty pieces are dropped, so a
+/// zero-length event yields nothing.
+fn split(start: i64, end: i64, afk: &[(i64, i64)]) -> Vec<(i64, i64, bool)> {
+    let mut pieces = Vec::new();
+    let mut at = start;
+    let first = afk.partition_point(|&(_, afk_end)| afk_end <= start);
+    for &(afk_start, afk_end) in &afk[first..] {
+        if afk_start >= end {
+            break;
+        }
+        if afk_start > at {
+            pieces.push((at, afk_start, false));
+        }
+        at = at.max(afk_start);
+        let idle_end = afk_end.min(end);
+        pieces.push((at, idle_end, true));
+        at = idle_end;
+    }
+    if at < end {
+        pieces.push((at, end, false));
+    }
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::category::Category;
+
+    /// Built-in bucket and privacy rules (seeded into a temp dir).
+    fn rules() -> Rules {
+        let dir = crate::test_support::temp_dir("import");
+        let mut config = Configuration::defaults();
+        config.bucket_config_path = dir.join("buckets.json");
+        config.privacy_config_path = dir.join("privacy-denylist.json");
+        config.scrub_audit_path = dir.join("scrub-audit.jsonl");
+        config.device_name = "old-laptop".to_string();
+        config.actor = "human".to_string();
+        Rules::load(&config).unwrap()
+    }
+
+    const EXPORT: &str = r#"{"buckets": {
+        "aw-watcher-window_old-laptop": {
+            "id": "aw-watcher-window_old-laptop", "type": "currentwindow", "client": "aw-watcher-window",
+            "events": [
+                {"id": 1, "timestamp": "2021-03-01T09:00:00.000000+00:00", "duration": 600.0,
+                 "data": {"app": "alacritty", "title": "nvim - github.com/andrew-pynch/chronomaxi"}},
+                {"id": 2, "timestamp": "2021-03-01T09:10:00+00:00", "duration": 0.0,
+                 "data": {"app": "alacritty", "title": "nvim - github.com/andrew-pynch/chronomaxi"}},
+                {"id": 3, "timestamp": "2021-03-01T09:20:00Z", "duration": 300.5,
+                 "data": {"app": "Firefox", "title": "some private search - Mozilla Firefox"}}
+            ]
+        },
+        "aw-watcher-afk_old-laptop": {
+            "id": "aw-watcher-afk_old-laptop", "type": "afkstatus",
+            "events": [
+                {"timestamp": "2021-03-01T09:00:00Z", "duration": 240.0, "data": {"status": "not-afk"}},
+                {"timestamp": "2021-03-01T09:04:00Z", "duration": 120.0, "data": {"status": "afk"}},
+                {"timestamp": "2021-03-01T09:05:00Z", "duration": 60.0, "data": {"status": "afk"}}
+            ]
+        },
+        "aw-watcher-web-firefox": {"id": "aw-watcher-web-firefox", "type": "web.tab.current", "events": []}
+    }}"#;
+
+    #[test]
+    fn window_events_are_split_at_afk_and_classified_like_live_spans() {
+        let converted = convert(EXPORT, &rules()).unwrap();
+        assert_eq!((converted.window_events, converted.afk_events), (3, 2));
+        assert_eq!(converted.skipped_buckets, ["aw-watcher-web-firefox (web.tab.current)"]);
+
+        let pieces: Vec<(i64, bool)> =
+            converted.rows.iter().map(|row| (row.duration_ms / 1000, row.is_idle)).collect();
+        assert_eq!(pieces, [(240, false), (120, true), (240, false), (300, false)]);
+
+        let code = &converted.rows[0];
+        assert_eq!(code.source_id, "aw:aw-watcher-window_old-laptop:1614589200000");
+        assert_eq!(code.created_at, 1_614_589_200_000);
+        assert_eq!(code.bucket.as_deref(), Some("coding"));
+        assert_eq!(code.category, Category::Coding);
+        assert_eq!((code.device_name.as_str(), code.actor.as_str()), ("old-laptop", "human"));
+
+        // Browser titles that aren't allowlisted are scrubbed, as in capture.
+        let firefox = &converted.rows[3];
+        assert_eq!(firefox.duration_ms, 300_500);
+        assert_eq!(firefox.browser_title.as_deref(), Some("homework"));
+        assert_eq!((firefox.program_name.as_str(), firefox.bucket.as_deref()), ("homework", Some("homework")));
+    }
+
+    #[test]
+    fn reimports_produce_the_same_source_ids() {
+        let rules = rules();
+        let ids = |converted: Converted| -> Vec<String> { converted.rows.into_iter().map(|row| row.source_id).collect() };
+        assert_eq!(ids(convert(EXPORT, &rules).unwrap()), ids(convert(EXPORT, &rules).unwrap()));
+
+        let mut spool = crate::spool::Spool::open_in_memory().unwrap();
+        let rows = convert(EXPORT, &rules).unwrap().rows;
+        assert_eq!(spool.enqueue_rows(&rows).unwrap(), 4);
+        assert_eq!(spool.enqueue_rows(&rows).unwrap(), 0);
+    }
+
+    #[test]
+    fn accepts_a_single_bucket_export() {
+        let single = r#"{"id": "aw-watcher-window_desk", "type": "currentwindow", "events": [
+            {"timestamp": "2021-03-01T09:00:00Z", "duration": 60, "data": {"app": "slack", "title": "general"}}
+        ]}"#;
+        let converted = convert(single, &rules()).unwrap();
+        assert_eq!(converted.rows.len(), 1);
+        assert_eq!(converted.rows[0].bucket.as_deref(), Some("comms"));
+        assert!(!converted.rows[0].is_idle);
+
+        assert!(convert(r#"{"events": "nope"}"#, &rules()).is_err());
+    }
+
+    #[test]
+    fn afk_stretches_merge_before_splitting() {
+        let afk = merged(vec![(50, 70), (10, 20), (15, 30)]);
+        assert_eq!(afk, [(10, 30), (50, 70)]);
+        assert_eq!(split(0, 100, &afk), [(0, 10, false), (10, 30, true), (30, 50, false), (50, 70, true), (70, 100, false)]);
+        assert_eq!(split(20, 60, &afk), [(20, 30, true), (30, 50, false), (50, 60, true)]);
+        assert_eq!(split(35, 45, &afk), [(35, 45, false)]);
+        assert!(split(40, 40, &afk).is_empty());
+    }
+}
@@ -0,0 +1,219 @@
+//! aw-server as a flush destination, so ActivityWatch's UI and query
+//! language see the same spans Convex does:
+//!
+//!   [[sinks]]
+//!   kind = "activitywatch"
+//!   url = "http://127.0.0.1:5600"          # optional, aw-server's default
+//!
+//! Each row's device gets the two buckets aw-watcher-window and
+//! aw-watcher-afk would have made, `aw-watcher-window_<deviceName>`
+//! (`currentwindow`) and `aw-watcher-afk_<deviceName>` (`afkstatus`),
+//! created on first use. Every span becomes one window event (`app` =
+//! programName, `title` = browserTitle, else subProgram, else programName,
+//! plus our bucket/category/actor/sourceId) and one afk event (`afk` when
+//! idle, `not-afk` otherwise), through `POST /api/0/buckets/<id>/events`.
+//!
+//! aw-server has no sourceId dedupe, so a batch retried after an
+//! unobserved success shows up twice there; Convex's numbers stay the
+//! reference.
+
+use std::collections::{BTreeMap, HashSet};
+use std::time::Duration;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::{json, Value};
+
+use super::sink::{Ack, SinkError, SpanSink};
+
+pub const DEFAULT_URL: &str = "http://127.0.0.1:5600";
+
+const CLIENT: &str = "chronomaxi";
+
+pub struct ActivityWatchSink {
+    http: reqwest::Client,
+    base_url: String,
+    /// Buckets known to exist on the server.
+    created: HashSet<String>,
+}
+
+impl ActivityWatchSink {
+    pub fn new(base_url: &str) -> Self {
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .connect_timeout(Duration::from_secs(5))
+            .build()
+            .unwrap_or_else(|_| reqwest::Client::new());
+        Self { http, base_url: base_url.trim_end_matches('/').to_string(), created: HashSet::new() }
+    }
+
+    /// Creates `bucket_id` unless we already did. aw-server answers 304
+    /// for a bucket that already exists.
+    async fn ensure_bucket(&mut self, bucket_id: &str, kind: &str, hostname: &str) -> Result<(), SinkError> {
+        if self.created.contains(bucket_id) {
+            return Ok(());
+        }
+        let response = self
+            .http
+            .post(format!("{}/api/0/buckets/{bucket_id}", self.base_url))
+            .json(&json!({ "client": CLIENT, "type": kind, "hostname": hostname }))
+            .send()
+            .await
+            .map_err(|e| SinkError::request(&e))?;
+        let status = response.status();
+        if !status.is_success() && status != reqwest::StatusCode::NOT_MODIFIED {
+            return Err(SinkError::from_response(&response, format!("aw-server refused bucket {bucket_id}: {status}")));
+        }
+        self.created.insert(bucket_id.to_string());
+        Ok(())
+    }
+
+    async fn post_events(&mut self, bucket_id: &str, events: &[Value]) -> Result<(), SinkError> {
+        let response = self
+            .http
+            .post(format!("{}/api/0/buckets/{bucket_id}/events", self.base_url))
+            .json(events)
+            .send()
+            .await
+            .map_err(|e| SinkError::request(&e))?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+        if status == reqwest::StatusCode::NOT_FOUND {
+            // Deleted in the aw UI: recreate it on the retry.
+            self.created.remove(bucket_id);
+        }
+        Err(SinkError::from_response(&response, format!("aw-server returned status {status} for {bucket_id}")))
+    }
+}
+
+impl SpanSink for ActivityWatchSink {
+    async fn send(&mut self, rows: &[Value]) -> Result<Ack, SinkError> {
+        let mut by_device: BTreeMap<&str, (Vec<Value>, Vec<Value>)> = BTreeMap::new();
+        for row in rows {
+            let (window, afk) = events(row).map_err(|e| SinkError { status: Some(400), ..SinkError::transient(e) })?;
+            let device = row["deviceName"].as_str().unwrap_or("unknown");
+            let entry = by_device.entry(device).or_default();
+            entry.0.push(window);
+            entry.1.push(afk);
+        }
+
+        for (device, (windows, afk)) in by_device {
+            let window_bucket = format!("aw-watcher-window_{device}");
+            let afk_bucket = format!("aw-watcher-afk_{device}");
+            self.ensure_bucket(&window_bucket, "currentwindow", device).await?;
+            self.ensure_bucket(&afk_bucket, "afkstatus", device).await?;
+            self.post_events(&window_bucket, &windows).await?;
+            self.post_events(&afk_bucket, &afk).await?;
+        }
+        Ok(Ack::All)
+    }
+}
+
+/// The window and afk events for one wire row. A row without a usable
+/// start or duration is an error (and rejected, so it gets dead-lettered
+/// rather than retried forever).
+fn events(row: &Value) -> Result<(Value, Value), String> {
+    let source_id = row["sourceId"].as_str().unwrap_or("?");
+    let started = row["createdAt"]
+        .as_i64()
+        .and_then(DateTime::<Utc>::from_timestamp_millis)
+        .ok_or_else(|| format!("row {source_id} has no createdAt"))?;
+    let duration_ms = row["durationMs"].as_i64().ok_or_else(|| format!("row {source_id} has no durationMs"))?;
+    let timestamp = started.to_rfc3339_opts(SecondsFormat::Millis, true);
+    let duration = duration_ms.max(0) as f64 / 1000.0;
+
+    let app = row["programName"].as_str().unwrap_or("unknown");
+    let title = ["browserTitle", "subProgram", "programName"]
+        .iter()
+        .find_map(|field| row[*field].as_str())
+        .unwrap_or("unknown");
+    let window = json!({
+        "timestamp": timestamp,
+        "duration": duration,
+        "data": {
+            "app": app,
+            "title": title,
+            "bucket": row["bucket"],
+            "category": row["category"],
+            "actor": row["actor"],
+            "sourceId": source_id,
+        },
+    });
+    let status = if row["isIdle"].as_bool().unwrap_or(false) { "afk" } else { "not-afk" };
+    let afk = json!({ "timestamp": timestamp, "duration": duration, "data": { "status": status } });
+    Ok((window, afk))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    /// aw-server stand-in: 304 for a bucket it already has, 200 otherwise;
+    /// reports every request as `(method and path, body)`.
+    async fn fake_aw_server() -> (String, tokio::sync::mpsc::UnboundedReceiver<(String, Value)>) {
+        let (captured, requests) = tokio::sync::mpsc::unbounded_channel();
+        let mut buckets = HashSet::new();
+        let url = test_support::serve(move |head, body| {
+            let request_line = head.lines().next().unwrap_or("").rsplit_once(' ').map(|(line, _)| line.to_string());
+            let request_line = request_line.unwrap_or_default();
+            let is_new_bucket = !request_line.ends_with("/events") && buckets.insert(request_line.clone());
+            let status = if request_line.ends_with("/events") || is_new_bucket { "200 OK" } else { "304 Not Modified" };
+            let _ = captured.send((request_line, serde_json::from_slice(&body).unwrap_or(Value::Null)));
+            test_support::response(status, "", "")
+        })
+        .await;
+        (url, requests)
+    }
+
+    fn row(source_id: &str, idle: bool) -> Value {
+        json!({
+            "sourceId": source_id, "createdAt": 1_792_103_400_000i64, "durationMs": 1500, "category": "Coding",
+            "isIdle": idle, "deviceName": "desk", "actor": "human", "windowId": "0x1",
+            "programProcessName": "kitty", "programName": "kitty", "subProgram": "nvim", "bucket": "chronomaxi",
+        })
+    }
+
+    #[tokio::test]
+    async fn spans_become_window_and_afk_events_in_per_device_buckets() {
+        let (url, mut requests) = fake_aw_server().await;
+        let mut sink = ActivityWatchSink::new(&url);
+        assert_eq!(sink.send(&[row("a", false), row("b", true)]).await.unwrap(), Ack::All);
+
+        let (line, body) = requests.recv().await.unwrap();
+        assert_eq!(line, "POST /api/0/buckets/aw-watcher-window_desk");
+        assert_eq!(body, json!({"client": "chronomaxi", "type": "currentwindow", "hostname": "desk"}));
+        assert_eq!(requests.recv().await.unwrap().0, "POST /api/0/buckets/aw-watcher-afk_desk");
+
+        let (line, windows) = requests.recv().await.unwrap();
+        assert_eq!(line, "POST /api/0/buckets/aw-watcher-window_desk/events");
+        assert_eq!(windows[0]["timestamp"], "2026-10-15T22:30:00.000Z");
+        assert_eq!(windows[0]["duration"], 1.5);
+        assert_eq!(windows[0]["data"]["app"], "kitty");
+        assert_eq!(windows[0]["data"]["title"], "nvim");
+        assert_eq!(windows[1]["data"]["sourceId"], "b");
+        let (_, afk) = requests.recv().await.unwrap();
+        assert_eq!((afk[0]["data"]["status"].as_str(), afk[1]["data"]["status"].as_str()), (Some("not-afk"), Some("afk")));
+
+        // Buckets are only created once per sink.
+        sink.send(&[row("c", false)]).await.unwrap();
+        assert_eq!(requests.recv().await.unwrap().0, "POST /api/0/buckets/aw-watcher-window_desk/events");
+    }
+
+    #[tokio::test]
+    async fn an_existing_bucket_is_not_an_error_and_a_broken_row_is_rejected() {
+        let (url, mut requests) = fake_aw_server().await;
+        ActivityWatchSink::new(&url).send(&[row("a", false)]).await.unwrap();
+        // A fresh sink (restart) gets 304s for both buckets and carries on.
+        ActivityWatchSink::new(&url).send(&[row("b", false)]).await.unwrap();
+        let mut lines = Vec::new();
+        for _ in 0..8 {
+            lines.push(requests.recv().await.unwrap().0);
+        }
+        assert_eq!(lines.iter().filter(|line| line.ends_with("/events")).count(), 4);
+
+        let error = ActivityWatchSink::new(&url).send(&[json!({"sourceId": "x"})]).await.unwrap_err();
+        assert!(error.is_rejection());
+    }
+}
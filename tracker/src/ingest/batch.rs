@@ -0,0 +1,96 @@
+//! Adaptive batch size for one sink's flusher. Starts at
+//! `spool_batch_size`, doubles after every full batch the sink took quickly
+//! (up to `spool_batch_size_max`), and halves on a timeout or a 413. A 413
+//! also records the size that was too large as a ceiling, so a deployment
+//! with a hard limit (convex/http.ts takes 500 rows) is found once instead
+//! of being re-probed after every few successes.
+
+use std::time::Duration;
+
+/// A full batch answered faster than this counts as "quick".
+const QUICK: Duration = Duration::from_secs(2);
+/// Never shrinks below this, however many timeouts in a row.
+const MIN_BATCH_SIZE: usize = 10;
+
+#[derive(Clone, Debug)]
+pub struct BatchSizer {
+    size: usize,
+    min: usize,
+    max: usize,
+    ceiling: Option<usize>,
+}
+
+impl BatchSizer {
+    pub fn new(start: usize, max: usize) -> Self {
+        let max = max.max(start).max(1);
+        Self { size: start.clamp(1, max), min: MIN_BATCH_SIZE.min(start.max(1)), max, ceiling: None }
+    }
+
+    /// A size that never changes, for one-off callers and tests.
+    pub fn fixed(size: usize) -> Self {
+        Self { size, min: size, max: size, ceiling: None }
+    }
+
+    /// Rows to claim for the next batch.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// `rows` went out in one request that took `elapsed`.
+    pub fn succeeded(&mut self, rows: usize, elapsed: Duration) {
+        if rows < self.size || elapsed >= QUICK {
+            return;
+        }
+        let limit = self.ceiling.map(|ceiling| ceiling.saturating_sub(1)).unwrap_or(self.max).min(self.max);
+        self.size = (self.size * 2).min(limit).max(self.size.min(limit));
+    }
+
+    /// A request with `rows` rows was refused as too large (413).
+    pub fn too_large(&mut self, rows: usize) {
+        self.ceiling = Some(self.ceiling.map_or(rows, |ceiling| ceiling.min(rows)));
+        self.size = (rows / 2).max(self.min).min(self.size);
+    }
+
+    /// A request timed out: smaller batches answer sooner.
+    pub fn timed_out(&mut self) {
+        self.size = (self.size / 2).max(self.min);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grows_on_quick_full_batches_and_remembers_a_413_ceiling() {
+        let mut sizer = BatchSizer::new(100, 2000);
+        sizer.succeeded(40, Duration::from_millis(100));
+        assert_eq!(sizer.size(), 100, "a partial batch says nothing about capacity");
+        sizer.succeeded(100, Duration::from_secs(5));
+        assert_eq!(sizer.size(), 100, "a slow batch doesn't grow");
+
+        for _ in 0..3 {
+            sizer.succeeded(sizer.size(), Duration::from_millis(100));
+        }
+        assert_eq!(sizer.size(), 800);
+        sizer.too_large(800);
+        assert_eq!(sizer.size(), 400);
+        for _ in 0..5 {
+            sizer.succeeded(sizer.size(), Duration::from_millis(100));
+        }
+        assert_eq!(sizer.size(), 799);
+
+        for _ in 0..10 {
+            sizer.timed_out();
+        }
+        assert_eq!(sizer.size(), MIN_BATCH_SIZE);
+    }
+
+    #[test]
+    fn fixed_never_moves() {
+        let mut sizer = BatchSizer::fixed(10);
+        sizer.succeeded(10, Duration::ZERO);
+        sizer.timed_out();
+        assert_eq!(sizer.size(), 10);
+    }
+}
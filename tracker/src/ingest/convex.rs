@@ -0,0 +1,139 @@
+//! The original destination: POST `{"batch": rows}` to the Convex HTTP
+//! action at `{ingest_url}/ingest` (convex/http.ts), Bearer-authenticated.
+//! Convex dedupes by sourceId, so a stale retry after a
+//! successful-but-unobserved response is always safe. A 200 carries a
+//! per-row ack (`accepted` / `duplicate` / `rejected`, see `Ack`).
+//!
+//! With `ingest_gzip` the body goes out `Content-Encoding: gzip`; span JSON
+//! is repetitive enough that a 500-row batch shrinks roughly tenfold, which
+//! is most of the time a backlog drain spends on a slow uplink. Only turn it
+//! on once the deployment runs a convex/http.ts that inflates request bodies
+//! -- an older one answers 400 to every gzipped batch.
+//!
+//! Once the device key exists (see crate::ingest::signing) every POST is
+//! also signed, on top of the bearer secret. convex/http.ts checks the
+//! signature once `backend enroll` has the key approved, and from then on
+//! refuses this device's unsigned batches.
+
+use std::io::Write;
+use std::time::Duration;
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use crate::config::Configuration;
+
+use super::secret::IngestSecrets;
+use super::signing::DeviceKey;
+use super::sink::{Ack, SinkError, SpanSink};
+
+pub struct ConvexSink {
+    http: reqwest::Client,
+    base_url: String,
+    pub(super) secrets: IngestSecrets,
+    gzip: bool,
+    device_name: String,
+    key: Option<Box<DeviceKey>>,
+}
+
+impl ConvexSink {
+    pub fn new(base_url: &str, secrets: IngestSecrets) -> Self {
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .connect_timeout(Duration::from_secs(5))
+            .build()
+            .unwrap_or_else(|_| reqwest::Client::new());
+
+        Self { http, base_url: base_url.to_string(), secrets, gzip: false, device_name: String::new(), key: None }
+    }
+
+    /// Gzip request bodies from now on.
+    pub fn gzip(mut self, gzip: bool) -> Self {
+        self.gzip = gzip;
+        self
+    }
+
+    /// Signs every request as `device_name` with `key`; `None` sends
+    /// bearer-only requests, as before enrollment.
+    pub fn signed_by(mut self, device_name: &str, key: Option<DeviceKey>) -> Self {
+        self.device_name = device_name.to_string();
+        self.key = key.map(Box::new);
+        self
+    }
+
+    /// The default sink: `ingest_url` with the global secret sources,
+    /// signed with the device key if one has been created.
+    pub fn from_config(config: &Configuration) -> Result<Self, String> {
+        Ok(Self::new(&config.ingest_url, IngestSecrets::from_config(config)?)
+            .gzip(config.ingest_gzip)
+            .signed_by(&config.device_name, device_key(config)))
+    }
+
+    /// POSTs `body` to `/ingest`, see `post`.
+    pub async fn post_ingest(&mut self, body: &serde_json::Value) -> Result<reqwest::Response, SinkError> {
+        self.post("/ingest", body).await
+    }
+
+    /// POSTs `body` to `path` under `base_url`. A 401 moves on to the next
+    /// known secret (re-reading the secret file once the list is exhausted,
+    /// see crate::ingest::secret) and retries, so the response is a 401
+    /// only when every secret was rejected. Each attempt is signed afresh:
+    /// re-sending a nonce would be refused as a replay.
+    pub async fn post(&mut self, path: &str, body: &serde_json::Value) -> Result<reqwest::Response, SinkError> {
+        let url = format!("{}{path}", self.base_url.trim_end_matches('/'));
+        let mut bytes = serde_json::to_vec(body).map_err(|e| SinkError::transient(e.to_string()))?;
+        if self.gzip {
+            bytes = gzip(&bytes).map_err(|e| SinkError::transient(format!("failed to gzip batch: {e}")))?;
+        }
+        loop {
+            let mut request = self
+                .http
+                .post(&url)
+                .bearer_auth(self.secrets.current())
+                .header(reqwest::header::CONTENT_TYPE, "application/json");
+            if self.gzip {
+                request = request.header(reqwest::header::CONTENT_ENCODING, "gzip");
+            }
+            if let Some(key) = &self.key {
+                for (name, value) in key.sign_now(&self.device_name, &bytes).pairs() {
+                    request = request.header(name, value);
+                }
+            }
+            let response = request.body(bytes.clone()).send().await.map_err(|e| SinkError::request(&e))?;
+
+            if response.status() != reqwest::StatusCode::UNAUTHORIZED || !self.secrets.rotate() {
+                return Ok(response);
+            }
+        }
+    }
+}
+
+/// The device key to sign with, if there is a usable one. A key file that
+/// can't be read or isn't a seed is logged and skipped: the batches go out
+/// unsigned rather than not at all.
+pub fn device_key(config: &Configuration) -> Option<DeviceKey> {
+    DeviceKey::load(&config.device_key_path).unwrap_or_else(|e| {
+        println!("chronomaxi ingest: {e}, sending unsigned batches");
+        None
+    })
+}
+
+fn gzip(bytes: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::with_capacity(bytes.len() / 4), Compression::default());
+    encoder.write_all(bytes)?;
+    encoder.finish()
+}
+
+impl SpanSink for ConvexSink {
+    async fn send(&mut self, rows: &[serde_json::Value]) -> Result<Ack, SinkError> {
+        let response = self.post_ingest(&serde_json::json!({ "batch": rows })).await?;
+        let status = response.status();
+        if status.is_success() {
+            // The rows are in either way; an unreadable body just loses
+            // the per-row detail.
+            Ok(Ack::from_body(&response.text().await.unwrap_or_default()))
+        } else {
+            Err(SinkError::from_response(&response, format!("ingest returned status {status}")))
+        }
+    }
+}
@@ -1,65 +1,282 @@
-//! Decoupled spool-to-Convex flusher. Runs as its own tokio task with its own
-//! sqlite connection to the spool file; capture/spooling on the main task
-//! never waits on this. On failure the whole batch stays pending and is
-//! retried with exponential backoff (5s doubling, capped 5min); on success
-//! the batch is marked sentAt in one UPDATE. Convex dedupes by sourceId, so a
-//! stale retry after a successful-but-unobserved response is always safe.
+//! Decoupled spool flusher. Each configured sink (crate::ingest::sink;
+//! Convex by default) runs as its own tokio task with its own sqlite
+//! connection to the spool file; capture/spooling on the main task never
+//! waits on any of them, and they never wait on each other. On failure the
+//! whole batch stays pending for that sink and is retried with exponential
+//! backoff (`ingest_min_backoff_seconds` doubling, capped at
+//! `ingest_max_backoff_seconds`; 5s/5min by default, each wait jittered);
+//! on success the batch is recorded against that sink, and a row is marked
+//! sentAt once every sink has it.
+//!
+//! An idle sink sleeps until `Spool::enqueue` pokes it (a watch channel
+//! handed to the capture side's spool by `cli::run`), then waits
+//! `ingest_debounce_ms` more so a burst of spans goes out as one batch, and
+//! drains back to back. The `ingest_poll_interval_seconds` long poll only
+//! runs while rows are waiting on a retry, so an offline, idle machine
+//! doesn't wake on a timer at all.
+//!
+//! 4xx and 5xx are handled differently. A 5xx (or no answer at all) fails
+//! the batch whole and backs off as above -- or for exactly as long as a
+//! 429/503's `Retry-After` asks. A batch the sink *rejects* (a row-level
+//! 4xx, see `SinkError::is_rejection`) is bisected instead
+//! (`send_bisecting`): the healthy rows are delivered and each row the sink
+//! refuses on its own gets a failed attempt recorded; after
+//! `dead_letter_after_attempts` of those it moves to the spool's dead-letter
+//! table, so one poison row never blocks the queue. A 2xx from a sink with
+//! per-row acks (Convex, see `Ack`) only counts the rows it accepted or
+//! already had; the ones it rejected by name are dead-lettered at once and
+//! the rest of the batch isn't resent. A spool row whose columns don't
+//! make a span (hand-edited) is dead-lettered on sight. Either way nothing is dropped
+//! silently: `backend dead-letter` lists, repairs and requeues them.
 
+pub mod activitywatch;
+pub mod batch;
+pub mod convex;
+pub mod route;
+pub mod secret;
+pub mod signing;
+pub mod sink;
+
+use std::sync::Arc;
 use std::time::Duration;
 
-use chrono::Utc;
+use chrono::{DateTime, Utc};
+
+use tokio::sync::watch;
 
-use crate::config::{Configuration, SPOOL_BATCH_SIZE};
+use crate::config::Configuration;
+use crate::metrics::Metrics;
+use crate::spool::archive::Archive;
 use crate::spool::Spool;
+use convex::ConvexSink;
+use batch::BatchSizer;
+use route::Router;
+use sink::{Ack, SinkError, SinkKind, SpanSink};
+
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Longest `Retry-After` honored as-is; anything longer is clamped.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60 * 60);
+
+/// `doctor`'s reachability/auth check: POSTs an empty batch, which
+/// convex/http.ts authenticates and then answers 200 without touching any
+/// table. Returns the HTTP status; `Err` only when no response arrived.
+pub async fn probe(config: &Configuration) -> Result<reqwest::StatusCode, String> {
+    let mut client = ConvexSink::from_config(config)?;
+    client
+        .post_ingest(&serde_json::json!({ "batch": [] }))
+        .await
+        .map(|response| response.status())
+        .map_err(|e| e.to_string())
+}
+
+/// `backend enroll`: registers `key` as this device's signing key via
+/// `{ingest_url}/enroll` (bearer-authenticated and signed with the key
+/// itself). Returns the status and response body.
+pub async fn enroll(config: &Configuration, key: signing::DeviceKey) -> Result<(reqwest::StatusCode, String), String> {
+    let body = serde_json::json!({
+        "deviceName": config.device_name,
+        "keyId": key.key_id(),
+        "publicKey": key.public_key_base64(),
+    });
+    let mut client = ConvexSink::new(&config.ingest_url, secret::IngestSecrets::from_config(config)?)
+        .signed_by(&config.device_name, Some(key));
+    let response = client.post("/enroll", &body).await.map_err(|e| e.to_string())?;
+    let status = response.status();
+    Ok((status, response.text().await.unwrap_or_default()))
+}
+
+/// Claims one batch `name` hasn't handled yet, sends the rows routed to it
+/// (see `send_bisecting`) and records the whole batch as handled (rows
+/// routed elsewhere are recorded without sending). Returns rows delivered;
+/// `Ok(0)` means nothing deliverable was pending for this sink. `Err` means
+/// a transient failure: the undelivered rows stay pending for a later retry.
+/// Takes `&mut` only because a shared `&Spool` (rusqlite `Connection` is
+/// `!Sync`) can't be held across an await inside a spawned task.
+async fn flush_batch<S: SpanSink>(
+    spool: &mut Spool,
+    sink: &mut S,
+    name: &str,
+    router: &Router,
+    all_sinks: &[String],
+    sizer: &mut BatchSizer,
+    dead_after: u32,
+) -> Result<usize, SinkError> {
+    loop {
+        let rows = spool
+            .claim_batch_for(name, sizer.size())
+            .map_err(|e| SinkError::transient(format!("claim_batch error: {e:?}")))?;
+        if rows.is_empty() {
+            return Ok(0);
+        }
 
-const POLL_INTERVAL: Duration = Duration::from_secs(10);
-const PRUNE_INTERVAL: chrono::Duration = chrono::Duration::seconds(60 * 60);
-const RETENTION: chrono::Duration = chrono::Duration::days(7);
-const MIN_BACKOFF: Duration = Duration::from_secs(5);
-const MAX_BACKOFF: Duration = Duration::from_secs(5 * 60);
+        let mut routed_ids: Vec<String> = Vec::new();
+        let mut skipped_ids: Vec<String> = Vec::new();
+        let mut values: Vec<serde_json::Value> = Vec::new();
+        for (id, row) in &rows {
+            match row.clone().and_then(|row| serde_json::to_value(row).map_err(|e| e.to_string())) {
+                Ok(value) if router.routes_to(name, &value) => {
+                    routed_ids.push(id.clone());
+                    values.push(value);
+                }
+                Ok(_) => skipped_ids.push(id.clone()),
+                Err(e) => {
+                    println!("chronomaxi ingest: {name}: unreadable row {id} ({e}), dead-lettered");
+                    spool
+                        .record_failure(name, std::slice::from_ref(id), &format!("unreadable row: {e}"), None, 1, all_sinks)
+                        .map_err(|e| SinkError::transient(format!("failed to dead-letter {id}: {e:?}")))?;
+                }
+            }
+        }
 
-struct IngestClient {
-    http: reqwest::Client,
-    base_url: String,
-    secret: String,
+        if !skipped_ids.is_empty() {
+            spool.mark_sent_for(name, &skipped_ids, all_sinks).map_err(|e| {
+                SinkError::transient(format!("failed to mark {} unrouted rows handled: {:?}", skipped_ids.len(), e))
+            })?;
+        }
+        if values.is_empty() {
+            continue;
+        }
+
+        return send_bisecting(spool, sink, name, &routed_ids, &values, all_sinks, sizer, dead_after).await;
+    }
 }
 
-impl IngestClient {
-    fn new(base_url: String, secret: String) -> Self {
-        let http = reqwest::Client::builder()
-            .timeout(Duration::from_secs(10))
-            .connect_timeout(Duration::from_secs(5))
-            .build()
-            .unwrap_or_else(|_| reqwest::Client::new());
+/// Sends `values` as one batch. When the sink *rejects* it (a 4xx, see
+/// `SinkError::is_rejection`) the batch is split in half and each half sent
+/// on its own, recursively, so the healthy rows still go out and only the
+/// row(s) the sink refuses on their own are left; each of those gets one
+/// failed attempt recorded (and is dead-lettered on its `dead_after`th).
+/// One poison row in a 500-row batch costs about 2*log2(500) = 18 extra
+/// requests, once per flush, until it's dead-lettered. A transient failure
+/// stops the walk; whatever was delivered so far stays recorded. Every
+/// request also feeds `sizer`: a 413 is bisected like any rejection, and
+/// later batches start below that size.
+#[allow(clippy::too_many_arguments)]
+async fn send_bisecting<S: SpanSink>(
+    spool: &mut Spool,
+    sink: &mut S,
+    name: &str,
+    ids: &[String],
+    values: &[serde_json::Value],
+    all_sinks: &[String],
+    sizer: &mut BatchSizer,
+    dead_after: u32,
+) -> Result<usize, SinkError> {
+    let mut delivered = 0;
+    // Ranges still to send, popped front-half first so rows go out in order.
+    let mut pending = vec![(0, values.len())];
+    while let Some((start, end)) = pending.pop() {
+        let started = std::time::Instant::now();
+        let sent = sink.send(&values[start..end]).await;
+        match &sent {
+            Ok(_) => sizer.succeeded(end - start, started.elapsed()),
+            Err(e) if e.status == Some(413) => sizer.too_large(end - start),
+            Err(e) if e.timed_out => sizer.timed_out(),
+            Err(_) => {}
+        }
+        match sent {
+            Ok(ack) => delivered += record_ack(spool, name, &ids[start..end], ack, all_sinks)?,
+            Err(e) if e.is_rejection() && end - start > 1 => {
+                let middle = start + (end - start) / 2;
+                pending.push((middle, end));
+                pending.push((start, middle));
+            }
+            Err(e) if e.is_rejection() => {
+                let id = &ids[start];
+                let outcome = match spool.record_failure(name, &ids[start..end], &e.message, e.status, dead_after, all_sinks)
+                {
+                    Ok(0) => "attempt recorded".to_string(),
+                    Ok(_) => format!("dead-lettered after {dead_after} attempts"),
+                    Err(record_error) => format!("failed to record the attempt: {record_error:?}"),
+                };
+                println!("chronomaxi ingest: {name}: {id} rejected ({e}), {outcome}");
+            }
+            Err(e) => {
+                let left = ids.len() - delivered;
+                return Err(SinkError { message: format!("send failed ({e}), {left} rows stay pending"), ..e });
+            }
+        }
+    }
+    Ok(delivered)
+}
 
-        Self { http, base_url, secret }
+/// Records what the sink took out of `ids`: everything on `Ack::All`;
+/// otherwise only the accepted and duplicate rows, with rejected ones
+/// dead-lettered straight away (the sink named them and said why, there's
+/// nothing to gain from resending) and unmentioned ones left pending.
+/// Returns rows delivered.
+fn record_ack(spool: &mut Spool, name: &str, ids: &[String], ack: Ack, all_sinks: &[String]) -> Result<usize, SinkError> {
+    let (accepted, duplicate, rejected) = match ack {
+        Ack::All => (ids.to_vec(), Vec::new(), Vec::new()),
+        Ack::Rows { accepted, duplicate, rejected } => (accepted, duplicate, rejected),
+    };
+    let mut rejected_here = 0;
+    for row in rejected.iter().filter(|row| ids.contains(&row.source_id)) {
+        println!("chronomaxi ingest: {name}: {} rejected ({}), dead-lettered", row.source_id, row.reason);
+        spool
+            .record_failure(name, std::slice::from_ref(&row.source_id), &format!("rejected: {}", row.reason), None, 1, all_sinks)
+            .map_err(|e| SinkError::transient(format!("failed to dead-letter {}: {e:?}", row.source_id)))?;
+        rejected_here += 1;
     }
+    let delivered: Vec<String> =
+        ids.iter().filter(|id| accepted.contains(id) || duplicate.contains(id)).cloned().collect();
+    let unacked = ids.len().saturating_sub(delivered.len() + rejected_here);
+    if unacked > 0 {
+        println!("chronomaxi ingest: {name}: {unacked} rows not acked, they stay pending");
+    }
+    spool
+        .mark_sent_for(name, &delivered, all_sinks)
+        .map_err(|e| SinkError::transient(format!("failed to mark {} rows sent: {:?}", delivered.len(), e)))?;
+    Ok(delivered.len())
+}
 
-    async fn send_batch(&self, rows: &[serde_json::Value]) -> Result<(), String> {
-        let url = format!("{}/ingest", self.base_url.trim_end_matches('/'));
-        let body = serde_json::json!({ "batch": rows });
+/// One-shot drain for `spool flush`: for each sink in turn, sends batches
+/// back to back until nothing is pending for it, stopping at the first
+/// failure. Returns rows delivered, summed over sinks.
+pub async fn flush_pending(config: &Configuration) -> Result<usize, String> {
+    let mut spool = Spool::open(&config.spool_path)
+        .map_err(|e| format!("failed to open spool at {:?}: {:?}", config.spool_path, e))?;
+    let all_sinks = sink::sink_names(config);
+    let router = Router::new(&config.sinks);
 
-        let response = self
-            .http
-            .post(&url)
-            .bearer_auth(&self.secret)
-            .json(&body)
-            .send()
+    let mut total = 0;
+    for sink_config in &config.sinks {
+        let name = sink_config.name();
+        let mut sink = sink_config.build(config).map_err(|e| format!("{name}: {e}"))?;
+        let mut sizer = BatchSizer::new(config.spool_batch_size, config.spool_batch_size_max);
+        loop {
+            match flush_batch(
+                &mut spool,
+                &mut sink,
+                &name,
+                &router,
+                &all_sinks,
+                &mut sizer,
+                config.dead_letter_after_attempts,
+            )
             .await
-            .map_err(|e| e.to_string())?;
-
-        if response.status().is_success() {
-            Ok(())
-        } else {
-            Err(format!("ingest returned status {}", response.status()))
+            .map_err(|e| format!("{name}: {e}"))?
+            {
+                0 => break,
+                n => total += n,
+            }
         }
     }
+    Ok(total)
 }
 
-/// Spawned once from main.rs. Never panics, never returns on the happy path
-/// (only exits early if the spool file itself cannot be opened, since that
-/// indicates a filesystem-level problem the capture side would also hit).
-pub async fn run_flusher(config: Configuration) {
+/// Capture side (`Spool::notify_on_enqueue`) and flusher ends of the
+/// "a span was spooled" signal.
+pub fn enqueue_signal() -> (watch::Sender<()>, watch::Receiver<()>) {
+    watch::channel(())
+}
+
+/// Spawned once from main.rs: starts one task per configured sink, then
+/// prunes old sent rows on this task. Never panics, never returns on the
+/// happy path (only exits early if the spool file itself cannot be opened,
+/// since that indicates a filesystem-level problem the capture side would
+/// also hit).
+pub async fn run_flusher(config: Configuration, wake: watch::Receiver<()>, metrics: Arc<Metrics>) {
     let spool = match Spool::open(&config.spool_path) {
         Ok(spool) => spool,
         Err(e) => {
@@ -68,58 +285,576 @@ pub async fn run_flusher(config: Configuration) {
         }
     };
 
-    let client = IngestClient::new(config.ingest_url.clone(), config.ingest_secret.clone());
-    let mut backoff = MIN_BACKOFF;
-    let mut last_prune = Utc::now();
+    let all_sinks = sink::sink_names(&config);
+    let router = Router::new(&config.sinks);
+    if let Err(e) = spool.settle(&all_sinks) {
+        println!("chronomaxi ingest: settle error: {:?}", e);
+    }
 
+    for sink_config in &config.sinks {
+        let name = sink_config.name();
+        match sink_config.build(&config) {
+            Ok(sink) => {
+                tokio::spawn(run_sink(
+                    config.clone(),
+                    name,
+                    sink,
+                    router.clone(),
+                    all_sinks.clone(),
+                    wake.clone(),
+                    Arc::clone(&metrics),
+                ));
+            }
+            Err(e) => println!("chronomaxi ingest: {name}: {e}, sink not started"),
+        }
+    }
+
+    let retention = chrono::Duration::days(config.spool_retention_days);
     loop {
-        tokio::time::sleep(POLL_INTERVAL).await;
+        tokio::time::sleep(PRUNE_INTERVAL).await;
+
+        let cutoff = Utc::now() - retention;
+        if let Err(e) = archive_sent(&spool, &config, cutoff) {
+            // Pruning now would lose the rows for good; try again next time.
+            println!("chronomaxi ingest: archive error, not pruning: {e:?}");
+            continue;
+        }
+        match spool.prune_sent_older_than(cutoff) {
+            Ok(n) if n > 0 => println!(
+                "chronomaxi ingest: pruned {n} sent rows older than {} days",
+                config.spool_retention_days
+            ),
+            Ok(_) => {}
+            Err(e) => println!("chronomaxi ingest: prune error: {:?}", e),
+        }
+    }
+}
+
+/// Rolls the sent rows older than `cutoff` into `archive_dir` (if set) and
+/// expires archive months past `archive_retention_months`. Must succeed
+/// before those rows are pruned.
+pub fn archive_sent(spool: &Spool, config: &Configuration, cutoff: DateTime<Utc>) -> rusqlite::Result<()> {
+    if config.archive_dir.as_os_str().is_empty() {
+        return Ok(());
+    }
+    let archive = Archive::open(&config.archive_dir)?;
+    let archived = archive.roll(spool, cutoff)?;
+    if archived > 0 {
+        println!("chronomaxi ingest: archived {archived} sent rows to {}", config.archive_dir.display());
+    }
+    for month in archive.expire(config.archive_retention_months, Utc::now())? {
+        println!(
+            "chronomaxi ingest: removed archive month {month} (older than {} months)",
+            config.archive_retention_months
+        );
+    }
+    Ok(())
+}
+
+/// One sink's flush loop: its own spool connection and its own backoff.
+async fn run_sink<S: SpanSink>(
+    config: Configuration,
+    name: String,
+    mut sink: S,
+    router: Router,
+    all_sinks: Vec<String>,
+    mut wake: watch::Receiver<()>,
+    metrics: Arc<Metrics>,
+) {
+    let mut spool = match Spool::open(&config.spool_path) {
+        Ok(spool) => spool,
+        Err(e) => {
+            println!("chronomaxi ingest: {name}: failed to open spool at {:?}: {:?}", config.spool_path, e);
+            return;
+        }
+    };
 
-        match spool.claim_batch(SPOOL_BATCH_SIZE) {
-            Ok(rows) if rows.is_empty() => {
-                backoff = MIN_BACKOFF;
+    let poll_interval = Duration::from_secs(config.ingest_poll_interval_seconds);
+    let debounce = Duration::from_millis(config.ingest_debounce_ms);
+    let min_backoff = Duration::from_secs(config.ingest_min_backoff_seconds);
+    let max_backoff = Duration::from_secs(config.ingest_max_backoff_seconds);
+    let mut backoff = min_backoff;
+    let mut sizer = BatchSizer::new(config.spool_batch_size, config.spool_batch_size_max);
+    // Set while working through a backlog bigger than one batch, for the
+    // start/finish log lines.
+    let mut draining: Option<(usize, std::time::Instant)> = None;
+
+    // First pass straight away: whatever the last run left pending.
+    loop {
+        if draining.is_none() {
+            let backlog = spool.pending_count_for(&name).unwrap_or(0) as usize;
+            if backlog > sizer.size() {
+                println!("chronomaxi ingest: {name}: draining {backlog} pending rows");
+                draining = Some((0, std::time::Instant::now()));
             }
-            Ok(rows) => {
-                let source_ids: Vec<String> = rows.iter().map(|(id, _)| id.clone()).collect();
-                let values: Vec<serde_json::Value> = rows
-                    .iter()
-                    .filter_map(|(_, payload)| serde_json::from_str::<serde_json::Value>(payload).ok())
-                    .collect();
-
-                match client.send_batch(&values).await {
-                    Ok(()) => {
-                        if let Err(e) = spool.mark_sent(&source_ids) {
-                            println!("chronomaxi ingest: failed to mark {} rows sent: {:?}", source_ids.len(), e);
-                        } else {
-                            println!("chronomaxi ingest: flushed {} rows", source_ids.len());
-                        }
-                        backoff = MIN_BACKOFF;
-                    }
-                    Err(e) => {
-                        println!(
-                            "chronomaxi ingest: send failed ({}), {} rows stay pending, retrying in {:?}",
-                            e,
-                            source_ids.len(),
-                            backoff
-                        );
-                        tokio::time::sleep(backoff).await;
-                        backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+
+        let flushed = flush_batch(
+            &mut spool,
+            &mut sink,
+            &name,
+            &router,
+            &all_sinks,
+            &mut sizer,
+            config.dead_letter_after_attempts,
+        )
+        .await;
+        match flushed {
+            Ok(0) => {
+                backoff = min_backoff;
+                metrics.sink_succeeded(&name, 0);
+                if let Some((sent, started)) = draining.take() {
+                    println!("chronomaxi ingest: {name}: drained {sent} rows in {:?}", started.elapsed());
+                }
+                // Anything still pending here is on a retry (rejected on its
+                // own but not yet dead-lettered, or not acked).
+                let retrying = spool.pending_count_for(&name).map(|n| n > 0).unwrap_or(true);
+                wait_for_work(&mut wake, retrying, poll_interval, debounce).await;
+            }
+            // Back to back, no poll interval, until the backlog is gone.
+            Ok(n) => {
+                // deploy/cutover-ron.sh and fleet-deploy.sh wait for the
+                // first "chronomaxi ingest: flushed" line after a restart,
+                // so the default sink keeps that exact prefix and a drain
+                // still logs its first batch.
+                let quiet = matches!(draining, Some((sent, _)) if sent > 0);
+                if let Some((sent, _)) = draining.as_mut() {
+                    *sent += n;
+                }
+                if !quiet {
+                    match name.as_str() {
+                        "convex" => println!("chronomaxi ingest: flushed {n} rows"),
+                        _ => println!("chronomaxi ingest: {name}: flushed {n} rows"),
                     }
                 }
+                backoff = min_backoff;
+                metrics.sink_succeeded(&name, n);
             }
             Err(e) => {
-                println!("chronomaxi ingest: claim_batch error: {:?}", e);
+                // A 429/503 that says when to come back is taken at its word
+                // (within reason) instead of our own guess.
+                let wait = e.retry_after.map(|wait| wait.min(MAX_RETRY_AFTER)).unwrap_or_else(|| jittered(backoff));
+                println!("chronomaxi ingest: {name}: {e}, retrying in {:?}", wait);
+                metrics.sink_failed(&name, wait);
+                tokio::time::sleep(wait).await;
+                backoff = (backoff * 2).min(max_backoff);
             }
         }
+    }
+}
+
+/// Parks an idle sink until there's something to send: the next spooled
+/// span (plus `debounce`, collapsing a burst into one wake-up) or, while
+/// `retrying`, the `long_poll`. Spans spooled during a failure backoff
+/// don't cut it short; they're picked up by the retry. Without a capture
+/// side (`wake`'s sender gone) it falls back to plain polling.
+async fn wait_for_work(wake: &mut watch::Receiver<()>, retrying: bool, long_poll: Duration, debounce: Duration) {
+    let notified = if retrying {
+        tokio::select! {
+            changed = wake.changed() => changed,
+            _ = tokio::time::sleep(long_poll) => return,
+        }
+    } else {
+        wake.changed().await
+    };
+    match notified {
+        Ok(()) => {
+            tokio::time::sleep(debounce).await;
+            wake.borrow_and_update();
+        }
+        Err(_) => tokio::time::sleep(long_poll).await,
+    }
+}
+
+/// `backoff` scaled to a random 50-100%, so trackers that lost the server
+/// together don't all retry in the same second when it comes back.
+fn jittered(backoff: Duration) -> Duration {
+    use rand_core::{OsRng, RngCore};
 
-        if Utc::now() - last_prune >= PRUNE_INTERVAL {
-            let cutoff = Utc::now() - RETENTION;
-            match spool.prune_sent_older_than(cutoff) {
-                Ok(n) if n > 0 => println!("chronomaxi ingest: pruned {n} sent rows older than 7 days"),
-                Ok(_) => {}
-                Err(e) => println!("chronomaxi ingest: prune error: {:?}", e),
+    backoff.mul_f64(0.5 + (OsRng.next_u32() % 1_000) as f64 / 2_000.0)
+}
+
+/// True when Convex is one of the configured sinks (`doctor` skips the
+/// ingest check otherwise).
+pub fn uses_convex(config: &Configuration) -> bool {
+    config.sinks.iter().any(|sink| matches!(sink.kind, SinkKind::Convex { .. }))
+}
+
+/// Rows still waiting on sink `name`, after routing (`spool status`).
+/// Pending rows the sink isn't routed don't count, even before its flusher
+/// has got round to recording them as handled.
+pub fn pending_for(spool: &Spool, router: &Router, name: &str) -> Result<usize, String> {
+    let mut args = vec![name.to_string()];
+    let filter = router.routes_to_sql(name, &mut args);
+    let count = spool.pending_count_where(&filter, &args).map_err(|e| format!("pending count error: {e:?}"))?;
+    Ok(count as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    /// Accepts only `Bearer <accepted>`, 401 for anything else.
+    async fn fake_ingest(accepted: &'static str) -> String {
+        test_support::serve(move |head, _| {
+            let authorized = head.to_lowercase().contains(&format!("authorization: bearer {accepted}\r\n"));
+            test_support::response(if authorized { "200 OK" } else { "401 Unauthorized" }, "", "")
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn unauthorized_rotates_to_the_next_secret() {
+        let path = test_support::temp_path("ingest-secret");
+        std::fs::write(&path, "old\nnew\n").unwrap();
+        let mut config = Configuration::defaults();
+        config.ingest_url = fake_ingest("new").await;
+        config.ingest_secret_file = path.clone();
+
+        let mut client = ConvexSink::from_config(&config).unwrap();
+        assert!(client.send(&[]).await.is_ok());
+        assert_eq!(client.secrets.current(), "new");
+
+        // Every secret rejected: a 401 comes back instead of looping.
+        std::fs::write(&path, "stale\n").unwrap();
+        config.ingest_url = fake_ingest("fresh").await;
+        let mut client = ConvexSink::from_config(&config).unwrap();
+        assert_eq!(probe(&config).await.unwrap(), reqwest::StatusCode::UNAUTHORIZED);
+        let error = client.send(&[]).await.unwrap_err();
+        assert_eq!(error.status, Some(401));
+        assert!(!error.is_rejection());
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn a_broken_device_key_sends_unsigned_instead_of_stopping_the_sink() {
+        let (captured, mut requests) = tokio::sync::mpsc::unbounded_channel();
+        let dir = test_support::temp_dir("broken-key");
+        let mut config = Configuration::defaults();
+        config.ingest_url = test_support::serve(move |head, _| {
+            let _ = captured.send(head);
+            test_support::response("200 OK", "", "{}")
+        })
+        .await;
+        config.ingest_secret_file = dir.join("secret");
+        config.device_key_path = dir.join("device_key");
+        std::fs::write(&config.ingest_secret_file, "s\n").unwrap();
+        std::fs::write(&config.device_key_path, "").unwrap();
+
+        let mut sink = config.sinks[0].build(&config).unwrap();
+        sink.send(&[]).await.unwrap();
+        let head = requests.recv().await.unwrap().to_lowercase();
+        assert!(head.contains("authorization: bearer s\r\n"));
+        assert!(!head.contains(signing::SIGNATURE_HEADER));
+        assert!(ConvexSink::from_config(&config).is_ok());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn signed_requests_verify_on_the_ingest_side_and_replays_are_refused() {
+        let (captured, mut requests) = tokio::sync::mpsc::unbounded_channel();
+        let url = test_support::serve(move |head, body| {
+            let _ = captured.send((head, body));
+            test_support::response("200 OK", "", "{}")
+        })
+        .await;
+
+        let key = signing::DeviceKey::from_seed(&[9; 32]);
+        let mut verifier = signing::Verifier::new(Duration::from_secs(300));
+        verifier.enroll("laptop", &key.public_key_base64()).unwrap();
+        let mut sink = ConvexSink::new(&url, secret::IngestSecrets::new(None, None).unwrap())
+            .gzip(true)
+            .signed_by("laptop", Some(key));
+
+        let row = serde_json::json!({"sourceId": "01SIGNED", "programName": "kitty"});
+        let mut signed = Vec::new();
+        for _ in 0..2 {
+            sink.send(std::slice::from_ref(&row)).await.unwrap();
+            let (head, body) = requests.recv().await.unwrap();
+            let headers = signing::SignedHeaders::from_lookup(|name| {
+                head.lines().find_map(|line| {
+                    let (header, value) = line.split_once(':')?;
+                    header.eq_ignore_ascii_case(name).then(|| value.trim().to_string())
+                })
+            })
+            .unwrap();
+            // The signature covers the gzipped bytes as sent.
+            assert_eq!(verifier.verify(&headers, &body, Utc::now().timestamp_millis()), Ok(()));
+            signed.push((headers, body));
+        }
+
+        assert_ne!(signed[0].0.nonce, signed[1].0.nonce);
+        let (headers, body) = &signed[0];
+        assert_eq!(verifier.verify(headers, body, Utc::now().timestamp_millis()), Err(signing::VerifyError::Replayed));
+    }
+
+    /// Records every row it's sent; fails while `failing` is set, with
+    /// `status` if given, and answers 400 to any batch holding a `poison`
+    /// sourceId.
+    #[derive(Default)]
+    struct RecordingSink {
+        sent: Vec<serde_json::Value>,
+        failing: bool,
+        status: Option<u16>,
+        poison: Vec<String>,
+        calls: usize,
+    }
+
+    impl SpanSink for RecordingSink {
+        async fn send(&mut self, rows: &[serde_json::Value]) -> Result<Ack, SinkError> {
+            self.calls += 1;
+            if self.failing {
+                return Err(SinkError { message: "down".to_string(), status: self.status, ..Default::default() });
+            }
+            if rows.iter().any(|row| self.poison.iter().any(|id| row["sourceId"] == id.as_str())) {
+                return Err(SinkError { message: "bad row".to_string(), status: Some(400), ..Default::default() });
             }
-            last_prune = Utc::now();
+            self.sent.extend_from_slice(rows);
+            Ok(Ack::All)
+        }
+    }
+
+    /// Convex stand-in: acks the first row of every batch as accepted, the
+    /// second as a duplicate, rejects any row whose programName is "bad"
+    /// and says nothing about the rest. Counts rows uploaded; answers 400 to
+    /// a body that isn't gzipped exactly when `gzip` says it should be.
+    async fn fake_convex_with_acks(uploaded: std::sync::Arc<std::sync::atomic::AtomicUsize>, gzip: bool) -> String {
+        test_support::serve(move |head, body| {
+            if head.to_ascii_lowercase().contains("content-encoding: gzip") != gzip {
+                return test_support::response("400 Bad Request", "", "");
+            }
+            let body: serde_json::Value = if gzip {
+                serde_json::from_reader(flate2::read::GzDecoder::new(body.as_slice())).unwrap()
+            } else {
+                serde_json::from_slice(&body).unwrap()
+            };
+            let batch = body["batch"].as_array().unwrap();
+            uploaded.fetch_add(batch.len(), std::sync::atomic::Ordering::SeqCst);
+            let (mut accepted, mut duplicate, mut rejected) = (Vec::new(), Vec::new(), Vec::new());
+            for (index, row) in batch.iter().enumerate() {
+                let id = row["sourceId"].clone();
+                match (row["programName"].as_str(), index) {
+                    (Some("bad"), _) => rejected.push(serde_json::json!({"sourceId": id, "reason": "malformed span item"})),
+                    (_, 0) => accepted.push(id),
+                    (_, 1) => duplicate.push(id),
+                    _ => {}
+                }
+            }
+            let ack = serde_json::json!({"accepted": accepted, "duplicate": duplicate, "rejected": rejected}).to_string();
+            test_support::response("200 OK", "content-type: application/json\r\n", &ack)
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn per_row_acks_settle_only_acked_rows() {
+        per_row_acks(false).await;
+    }
+
+    #[tokio::test]
+    async fn gzipped_batches_are_acked_the_same() {
+        per_row_acks(true).await;
+    }
+
+    async fn per_row_acks(gzip: bool) {
+        let uploaded = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let mut config = Configuration::defaults();
+        config.ingest_gzip = gzip;
+        config.ingest_secret = "s".to_string();
+        config.ingest_secret_file = std::path::PathBuf::new();
+        config.ingest_url = fake_convex_with_acks(uploaded.clone(), gzip).await;
+
+        let mut spool = Spool::open_in_memory().unwrap();
+        for program in ["a", "b", "bad", "c"] {
+            let mut log = crate::log::Log::new();
+            log.current_program_name = Some(program.to_string());
+            spool.enqueue(&log, "test-device").unwrap();
+        }
+        let sinks = vec![sink::SinkConfig::convex()];
+        let router = Router::new(&sinks);
+        let names = vec!["convex".to_string()];
+        let mut sink = ConvexSink::from_config(&config).unwrap();
+
+        // a accepted, b duplicate, bad dead-lettered, c unmentioned.
+        assert_eq!(flush_batch(&mut spool, &mut sink, "convex", &router, &names, &mut BatchSizer::fixed(10), 5).await.unwrap(), 2);
+        assert_eq!(uploaded.load(std::sync::atomic::Ordering::SeqCst), 4);
+        assert_eq!(spool.dead_letters(None, 10).unwrap()[0].last_error, "rejected: malformed span item");
+        let pending = spool.list(10, true).unwrap();
+        assert_eq!(pending.len(), 1);
+        assert!(pending[0].payload.contains(r#""programName":"c""#));
+
+        // Only the unacked row goes up again.
+        assert_eq!(flush_batch(&mut spool, &mut sink, "convex", &router, &names, &mut BatchSizer::fixed(10), 5).await.unwrap(), 1);
+        assert_eq!(uploaded.load(std::sync::atomic::Ordering::SeqCst), 5);
+        assert_eq!(spool.pending_count().unwrap(), 0);
+    }
+
+    #[test]
+    fn jitter_stays_within_half_to_full_backoff() {
+        let backoff = Duration::from_secs(60);
+        let waits: Vec<Duration> = (0..50).map(|_| jittered(backoff)).collect();
+        assert!(waits.iter().all(|wait| *wait >= backoff / 2 && *wait <= backoff));
+        assert!(waits.iter().any(|wait| *wait != waits[0]));
+    }
+
+    #[tokio::test]
+    async fn enqueue_wakes_an_idle_sink_once_per_burst() {
+        let (spooled, mut wake) = enqueue_signal();
+        let mut spool = Spool::open_in_memory().unwrap();
+        spool.notify_on_enqueue(spooled);
+        let long_poll = Duration::from_secs(60);
+        let debounce = Duration::from_millis(50);
+
+        // Idle with nothing to retry: no timer, it only wakes on a span.
+        let idle = tokio::time::timeout(Duration::from_millis(200), wait_for_work(&mut wake, false, long_poll, debounce));
+        assert!(idle.await.is_err());
+
+        let waiter = tokio::spawn(async move {
+            let started = std::time::Instant::now();
+            wait_for_work(&mut wake, false, long_poll, debounce).await;
+            (started.elapsed(), wake)
+        });
+        for _ in 0..3 {
+            spool.enqueue(&crate::log::Log::new(), "test-device").unwrap();
         }
+        let (waited, mut wake) = tokio::time::timeout(Duration::from_secs(5), waiter).await.unwrap().unwrap();
+        assert!(waited >= debounce && waited < long_poll);
+        // The three pokes were coalesced into that one wake-up.
+        assert!(!wake.has_changed().unwrap());
+
+        // While retrying, the long poll bounds the wait.
+        let started = std::time::Instant::now();
+        wait_for_work(&mut wake, true, Duration::from_millis(50), debounce).await;
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn retry_after_is_honored_on_429_and_503_only() {
+        let mut config = Configuration::defaults();
+        config.ingest_secret = "s".to_string();
+        config.ingest_secret_file = std::path::PathBuf::new();
+
+        config.ingest_url = test_support::serve(|_, _| test_support::response("429 Too Many Requests", "retry-after: 7\r\n", "")).await;
+        let error = ConvexSink::from_config(&config).unwrap().send(&[]).await.unwrap_err();
+        assert_eq!((error.status, error.retry_after), (Some(429), Some(Duration::from_secs(7))));
+        assert!(!error.is_rejection());
+
+        config.ingest_url = test_support::serve(|_, _| test_support::response("400 Bad Request", "retry-after: 7\r\n", "")).await;
+        let error = ConvexSink::from_config(&config).unwrap().send(&[]).await.unwrap_err();
+        assert_eq!((error.status, error.retry_after), (Some(400), None));
+        assert!(error.is_rejection());
+    }
+
+    #[tokio::test]
+    async fn a_poison_row_is_bisected_out_and_the_rest_delivered() {
+        let mut spool = Spool::open_in_memory().unwrap();
+        for _ in 0..8 {
+            spool.enqueue(&crate::log::Log::new(), "test-device").unwrap();
+        }
+        let ids: Vec<String> = spool.claim_batch(10).unwrap().into_iter().map(|(id, _)| id).collect();
+        let sinks = vec![sink::SinkConfig::unrouted(SinkKind::Stdout {})];
+        let router = Router::new(&sinks);
+        let names = vec!["stdout".to_string()];
+        let mut sink = RecordingSink { poison: vec![ids[5].clone()], ..Default::default() };
+
+        assert_eq!(flush_batch(&mut spool, &mut sink, "stdout", &router, &names, &mut BatchSizer::fixed(10), 2).await.unwrap(), 7);
+        // 8 -> 4+4 -> 2+2 -> 1+1: one request per level plus the healthy halves.
+        assert_eq!(sink.calls, 7);
+        let sent: Vec<&str> = sink.sent.iter().map(|row| row["sourceId"].as_str().unwrap()).collect();
+        let mut healthy: Vec<&str> = ids.iter().map(String::as_str).collect();
+        healthy.remove(5);
+        assert_eq!(sent, healthy);
+        assert_eq!(spool.pending_count().unwrap(), 1);
+        assert_eq!(spool.dead_letter_count().unwrap(), 0);
+
+        // Rejected on its own a second time: dead-lettered, queue clear.
+        assert_eq!(flush_batch(&mut spool, &mut sink, "stdout", &router, &names, &mut BatchSizer::fixed(10), 2).await.unwrap(), 0);
+        assert_eq!(spool.pending_count().unwrap(), 0);
+        assert_eq!(spool.dead_letters_for(&ids[5]).unwrap()[0].attempts, 2);
+    }
+
+    #[tokio::test]
+    async fn routes_flush_independently() {
+        let mut spool = Spool::open_in_memory().unwrap();
+        for bucket in ["client", "personal", "client"] {
+            let mut log = crate::log::Log::new();
+            log.bucket = Some(bucket.to_string());
+            spool.enqueue(&log, "test-device").unwrap();
+        }
+        let sinks: Vec<sink::SinkConfig> = toml::from_str::<std::collections::BTreeMap<String, Vec<sink::SinkConfig>>>(
+            r#"
+            [[sinks]]
+            kind = "stdout"
+            when = [{ field = "bucket", equals = "client" }]
+            [[sinks]]
+            kind = "jsonl"
+            path = "/tmp/unused.jsonl"
+            fallback = true
+            "#,
+        )
+        .unwrap()
+        .remove("sinks")
+        .unwrap();
+        let router = Router::new(&sinks);
+        let names: Vec<String> = sinks.iter().map(sink::SinkConfig::name).collect();
+
+        // The fallback route is down; the client route still drains.
+        let mut client = RecordingSink::default();
+        let mut rest = RecordingSink { failing: true, ..Default::default() };
+        assert_eq!(flush_batch(&mut spool, &mut client, &names[0], &router, &names, &mut BatchSizer::fixed(10), 5).await.unwrap(), 2);
+        assert!(flush_batch(&mut spool, &mut rest, &names[1], &router, &names, &mut BatchSizer::fixed(10), 5).await.is_err());
+        assert!(client.sent.iter().all(|row| row["bucket"] == "client"));
+        assert_eq!(pending_for(&spool, &router, &names[0]).unwrap(), 0);
+        assert_eq!(pending_for(&spool, &router, &names[1]).unwrap(), 1);
+        assert_eq!(spool.pending_count().unwrap(), 1);
+
+        rest.failing = false;
+        assert_eq!(flush_batch(&mut spool, &mut rest, &names[1], &router, &names, &mut BatchSizer::fixed(10), 5).await.unwrap(), 1);
+        assert_eq!(rest.sent[0]["bucket"], "personal");
+        assert_eq!(spool.pending_count().unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn rejected_and_unparsable_rows_are_dead_lettered_not_dropped() {
+        let path = test_support::temp_path("dead-letter");
+        let mut spool = Spool::open(&path).unwrap();
+        spool.enqueue(&crate::log::Log::new(), "test-device").unwrap();
+        let sinks = vec![sink::SinkConfig::unrouted(SinkKind::Stdout {})];
+        let router = Router::new(&sinks);
+        let names = vec!["stdout".to_string()];
+
+        // A 503 is retried indefinitely, a 400 counts toward the limit.
+        let mut sink = RecordingSink { failing: true, status: Some(503), ..Default::default() };
+        for _ in 0..3 {
+            assert!(flush_batch(&mut spool, &mut sink, "stdout", &router, &names, &mut BatchSizer::fixed(10), 2).await.is_err());
+        }
+        assert_eq!(spool.dead_letter_count().unwrap(), 0);
+        sink.status = Some(400);
+        assert_eq!(flush_batch(&mut spool, &mut sink, "stdout", &router, &names, &mut BatchSizer::fixed(10), 2).await.unwrap(), 0);
+        assert_eq!(spool.pending_count().unwrap(), 1);
+        assert_eq!(flush_batch(&mut spool, &mut sink, "stdout", &router, &names, &mut BatchSizer::fixed(10), 2).await.unwrap(), 0);
+        assert_eq!(spool.pending_count().unwrap(), 0);
+
+        spool.enqueue(&crate::log::Log::new(), "test-device").unwrap();
+        let (bad_id, _) = spool.claim_batch_for("stdout", 1).unwrap().remove(0);
+        rusqlite::Connection::open(&path)
+            .unwrap()
+            .execute("UPDATE spool SET category = 'Nonsense' WHERE sourceId = ?1", [&bad_id])
+            .unwrap();
+        sink.failing = false;
+        assert_eq!(flush_batch(&mut spool, &mut sink, "stdout", &router, &names, &mut BatchSizer::fixed(10), 2).await.unwrap(), 0);
+        assert!(sink.sent.is_empty());
+
+        let letters = spool.dead_letters(None, 10).unwrap();
+        assert_eq!(letters.len(), 2);
+        let unparsable = spool.dead_letters_for(&bad_id).unwrap();
+        assert!(unparsable[0].last_error.starts_with("unreadable row: unknown category"));
+        assert!(unparsable[0].payload.contains(r#""category":"Nonsense""#));
+        assert_eq!(letters.iter().filter(|letter| letter.http_status == Some(400)).count(), 1);
+        drop(spool);
+        let _ = std::fs::remove_file(path);
     }
 }
@@ -0,0 +1,266 @@
+//! Per-sink routing: which spool rows each sink is responsible for.
+//!
+//!   [[sinks]]                              # client work -> its own deployment
+//!   kind = "convex"
+//!   url = "https://client-ingest.example"
+//!   secret_file = "/run/credentials/chronomaxi-tracker.service/client_secret"
+//!   when = [{ field = "bucket", equals = "client" }]
+//!
+//!   [[sinks]]                              # agent spans -> a webhook
+//!   kind = "webhook"
+//!   url = "https://agents.example/hook"
+//!   when = [{ field = "actor", starts_with = "agent:" }]
+//!
+//!   [[sinks]]                              # everything else -> the default
+//!   kind = "convex"
+//!   fallback = true
+//!
+//! A sink with `when` takes the rows matching ANY of its rules (a rule
+//! matches when every operator it sets holds); `fallback = true` takes the
+//! rows no `when` sink took; a sink with neither takes every row, e.g. a
+//! local JSONL archive next to the routes above. A row matching two `when`
+//! sinks goes to both.
+//!
+//! Rules look at the wire row (crate::spool::IngestRow): `bucket`, `actor`,
+//! `category` (e.g. "Coding") and `device` (`deviceName`). Comparisons are
+//! exact and case-sensitive; an absent bucket never matches.
+//!
+//! Routing is decided when a sink's flusher claims a batch, not at enqueue
+//! time, so a config change also applies to rows already spooled. Rows a
+//! sink isn't routed are recorded as handled for it straight away, so its
+//! pending count (and the row's settling) only ever waits on real sends.
+
+use crate::config::SinkConfig;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteField {
+    Bucket,
+    Actor,
+    Category,
+    Device,
+}
+
+impl RouteField {
+    /// Also the spool column holding the field.
+    fn wire_key(self) -> &'static str {
+        match self {
+            RouteField::Bucket => "bucket",
+            RouteField::Actor => "actor",
+            RouteField::Category => "category",
+            RouteField::Device => "deviceName",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteRule {
+    pub field: RouteField,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub equals: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub starts_with: Option<String>,
+}
+
+impl RouteRule {
+    pub fn matches(&self, row: &serde_json::Value) -> bool {
+        let Some(value) = row.get(self.field.wire_key()).and_then(|value| value.as_str()) else {
+            return false;
+        };
+        self.equals.as_deref().is_none_or(|expected| value == expected)
+            && self.starts_with.as_deref().is_none_or(|prefix| value.starts_with(prefix))
+    }
+
+    /// `matches` as an SQL condition on the spool's columns. Values are
+    /// pushed onto `args` and referenced as `?N` by their position there.
+    fn sql(&self, args: &mut Vec<String>) -> String {
+        let column = self.field.wire_key();
+        let mut terms = vec![format!("{column} IS NOT NULL")];
+        if let Some(expected) = &self.equals {
+            args.push(expected.clone());
+            terms.push(format!("{column} = ?{}", args.len()));
+        }
+        if let Some(prefix) = &self.starts_with {
+            args.push(prefix.clone());
+            terms.push(format!("substr({column}, 1, length(?{n})) = ?{n}", n = args.len()));
+        }
+        format!("({})", terms.join(" AND "))
+    }
+}
+
+fn any_sql(rules: &[RouteRule], args: &mut Vec<String>) -> String {
+    if rules.is_empty() {
+        return "0".to_string();
+    }
+    let terms: Vec<String> = rules.iter().map(|rule| rule.sql(args)).collect();
+    format!("({})", terms.join(" OR "))
+}
+
+/// Rejects route configs that would silently match everything or nothing,
+/// and sinks that would share sent-tracking. Called from
+/// `Configuration::load`.
+pub fn validate_sinks(sinks: &[SinkConfig]) -> Result<(), String> {
+    let mut names: Vec<String> = Vec::with_capacity(sinks.len());
+    for sink in sinks {
+        let name = sink.name();
+        if names.contains(&name) {
+            return Err(format!("sink `{name}` is configured twice"));
+        }
+        if sink.fallback && !sink.when.is_empty() {
+            return Err(format!("sink `{name}` sets both `when` and `fallback`"));
+        }
+        if let Some(rule) = sink.when.iter().find(|rule| rule.equals.is_none() && rule.starts_with.is_none()) {
+            return Err(format!(
+                "sink `{name}`: rule on `{:?}` needs `equals` or `starts_with`",
+                rule.field
+            ));
+        }
+        names.push(name);
+    }
+    Ok(())
+}
+
+/// Routing table over every configured sink.
+#[derive(Clone, Debug)]
+pub struct Router {
+    sinks: Vec<(String, Vec<RouteRule>, bool)>,
+}
+
+impl Router {
+    pub fn new(sinks: &[SinkConfig]) -> Self {
+        Self { sinks: sinks.iter().map(|sink| (sink.name(), sink.when.clone(), sink.fallback)).collect() }
+    }
+
+    /// Whether `sink` is responsible for `row`. An unknown sink name takes
+    /// nothing.
+    pub fn routes_to(&self, sink: &str, row: &serde_json::Value) -> bool {
+        let Some((_, when, fallback)) = self.sinks.iter().find(|(name, _, _)| name == sink) else {
+            return false;
+        };
+        if !when.is_empty() {
+            return when.iter().any(|rule| rule.matches(row));
+        }
+        if *fallback {
+            return !self
+                .sinks
+                .iter()
+                .any(|(_, when, _)| !when.is_empty() && when.iter().any(|rule| rule.matches(row)));
+        }
+        true
+    }
+
+    /// `routes_to` as an SQL condition on the spool's columns, so a count
+    /// doesn't have to load every pending row. See `RouteRule::sql` for
+    /// `args`.
+    pub fn routes_to_sql(&self, sink: &str, args: &mut Vec<String>) -> String {
+        let Some((_, when, fallback)) = self.sinks.iter().find(|(name, _, _)| name == sink) else {
+            return "0".to_string();
+        };
+        if !when.is_empty() {
+            return any_sql(when, args);
+        }
+        if *fallback {
+            let taken: Vec<String> = self
+                .sinks
+                .iter()
+                .filter(|(_, when, _)| !when.is_empty())
+                .map(|(_, when, _)| any_sql(when, args))
+                .collect();
+            if taken.is_empty() {
+                return "1".to_string();
+            }
+            return format!("NOT ({})", taken.join(" OR "));
+        }
+        "1".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn sinks(toml_text: &str) -> Vec<SinkConfig> {
+        let mut table: std::collections::BTreeMap<String, Vec<SinkConfig>> = toml::from_str(toml_text).unwrap();
+        table.remove("sinks").unwrap()
+    }
+
+    const ROUTES: &str = r#"
+        [[sinks]]
+        kind = "convex"
+        url = "http://client"
+        when = [{ field = "bucket", equals = "client" }]
+        [[sinks]]
+        kind = "convex"
+        url = "http://agents"
+        when = [{ field = "actor", starts_with = "agent:" }]
+        [[sinks]]
+        kind = "convex"
+        fallback = true
+        [[sinks]]
+        kind = "jsonl"
+        path = "/tmp/all.jsonl"
+    "#;
+
+    #[test]
+    fn rows_go_to_matching_routes_and_the_rest_to_fallback() {
+        let sinks = sinks(ROUTES);
+        validate_sinks(&sinks).unwrap();
+        let router = Router::new(&sinks);
+        let names: Vec<String> = sinks.iter().map(SinkConfig::name).collect();
+        let targets = |row: serde_json::Value| -> Vec<&str> {
+            names.iter().filter(|name| router.routes_to(name, &row)).map(String::as_str).collect()
+        };
+
+        assert_eq!(
+            targets(json!({"bucket": "client", "actor": "human"})),
+            vec!["convex:http://client", "jsonl:/tmp/all.jsonl"]
+        );
+        assert_eq!(
+            targets(json!({"bucket": "client", "actor": "agent:codex"})),
+            vec!["convex:http://client", "convex:http://agents", "jsonl:/tmp/all.jsonl"]
+        );
+        assert_eq!(targets(json!({"actor": "human"})), vec!["convex", "jsonl:/tmp/all.jsonl"]);
+    }
+
+    #[test]
+    fn sql_routing_agrees_with_routes_to() {
+        let sinks = sinks(ROUTES);
+        let router = Router::new(&sinks);
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE spool (bucket TEXT, actor TEXT, category TEXT, deviceName TEXT)").unwrap();
+        let rows = [
+            json!({"bucket": "client", "actor": "human"}),
+            json!({"bucket": "client", "actor": "agent:codex"}),
+            json!({"bucket": null, "actor": "agent"}),
+            json!({"bucket": "clientele", "actor": "agent:"}),
+        ];
+        for row in &rows {
+            conn.execute("INSERT INTO spool VALUES (?1, ?2, 'Coding', 'desk')", [row["bucket"].as_str(), row["actor"].as_str()])
+                .unwrap();
+        }
+
+        for name in sinks.iter().map(SinkConfig::name).chain(["unknown".to_string()]) {
+            let mut args = Vec::new();
+            let filter = router.routes_to_sql(&name, &mut args);
+            let mut stmt = conn.prepare(&format!("SELECT {filter} FROM spool ORDER BY rowid")).unwrap();
+            let matched: Vec<bool> =
+                stmt.query_map(rusqlite::params_from_iter(&args), |row| row.get(0)).unwrap().map(Result::unwrap).collect();
+            let expected: Vec<bool> = rows.iter().map(|row| router.routes_to(&name, row)).collect();
+            assert_eq!(matched, expected, "{name}: {filter}");
+        }
+    }
+
+    #[test]
+    fn ambiguous_route_configs_are_rejected() {
+        let empty_rule = sinks("[[sinks]]\nkind = \"stdout\"\nwhen = [{ field = \"bucket\" }]");
+        assert!(validate_sinks(&empty_rule).unwrap_err().contains("equals"));
+
+        let both = sinks("[[sinks]]\nkind = \"stdout\"\nfallback = true\nwhen = [{ field = \"bucket\", equals = \"x\" }]");
+        assert!(validate_sinks(&both).is_err());
+
+        let twice = sinks("[[sinks]]\nkind = \"stdout\"\n[[sinks]]\nkind = \"stdout\"");
+        assert!(validate_sinks(&twice).unwrap_err().contains("twice"));
+    }
+}
@@ -0,0 +1,165 @@
+//! Where the ingest bearer secret comes from.
+//!
+//! `ingest_secret` (CHRONOMAXI_INGEST_SECRET) lands in `systemctl
+//! show-environment` and `/proc/<pid>/environ`, so the preferred source is a
+//! file: `ingest_secret_file` (CHRONOMAXI_INGEST_SECRET_FILE), which defaults
+//! to `$CREDENTIALS_DIRECTORY/ingest_secret` when systemd passes one in via
+//! `LoadCredential=ingest_secret:<path>`.
+//!
+//! The file holds one secret per line (blank lines and `#` comments are
+//! ignored). More than one line is a rotation window: the flusher sends the
+//! first, and on a 401 moves on to the next, then re-reads the file once the
+//! list is exhausted -- so a rotated secret is picked up without a restart,
+//! whichever of old/new the deployment currently expects. The secret that
+//! last worked stays in use until it gets a 401 itself. A non-empty
+//! `ingest_secret` is kept as the last candidate, which lets a machine move
+//! from env to file without a flag day.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::Configuration;
+
+pub struct IngestSecrets {
+    file: Option<PathBuf>,
+    inline: Option<String>,
+    candidates: Vec<String>,
+    current: usize,
+}
+
+impl IngestSecrets {
+    /// Reads the secret file (if configured) once up front. A configured
+    /// but unreadable file is an error -- silently sending no secret would
+    /// just turn into an endless run of 401s.
+    pub fn from_config(config: &Configuration) -> Result<Self, String> {
+        Self::new(
+            Some(config.ingest_secret_file.clone()).filter(|path| !path.as_os_str().is_empty()),
+            Some(config.ingest_secret.clone()).filter(|secret| !secret.trim().is_empty()),
+        )
+    }
+
+    /// Explicit sources, e.g. a routed Convex sink's own `secret_file`.
+    pub fn new(file: Option<PathBuf>, inline: Option<String>) -> Result<Self, String> {
+        let mut secrets = Self { file, inline, candidates: Vec::new(), current: 0 };
+        secrets.candidates = secrets.read_candidates()?;
+        Ok(secrets)
+    }
+
+    /// Secret to send right now. Empty when nothing is configured at all
+    /// (Convex then answers 401, which `doctor` reports).
+    pub fn current(&self) -> &str {
+        self.candidates.get(self.current).map(String::as_str).unwrap_or("")
+    }
+
+    /// Called after a 401 with `current()`. Moves to the next candidate, or
+    /// -- once every candidate has been tried -- re-reads the file. Returns
+    /// whether there is a different secret worth retrying with; `false`
+    /// means every known secret was rejected and the caller should back off.
+    pub fn rotate(&mut self) -> bool {
+        if self.current + 1 < self.candidates.len() {
+            self.current += 1;
+            return true;
+        }
+
+        let reread = match self.read_candidates() {
+            Ok(candidates) => candidates,
+            Err(e) => {
+                println!("chronomaxi ingest: {e}, keeping the secrets already loaded");
+                self.current = 0;
+                return false;
+            }
+        };
+        self.current = 0;
+        if reread == self.candidates {
+            return false;
+        }
+        println!(
+            "chronomaxi ingest: secret rejected, re-read {} ({} candidate(s))",
+            self.file.as_deref().map(Path::display).map(|path| path.to_string()).unwrap_or_default(),
+            reread.len()
+        );
+        self.candidates = reread;
+        true
+    }
+
+    fn read_candidates(&self) -> Result<Vec<String>, String> {
+        let mut candidates = match &self.file {
+            Some(path) => {
+                let text = fs::read_to_string(path)
+                    .map_err(|e| format!("failed to read ingest secret file {}: {e}", path.display()))?;
+                parse_secret_lines(&text)
+            }
+            None => Vec::new(),
+        };
+        if let Some(inline) = &self.inline {
+            if !candidates.contains(inline) {
+                candidates.push(inline.clone());
+            }
+        }
+        Ok(candidates)
+    }
+}
+
+fn parse_secret_lines(text: &str) -> Vec<String> {
+    let mut secrets: Vec<String> = Vec::new();
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') || secrets.iter().any(|seen| seen == line) {
+            continue;
+        }
+        secrets.push(line.to_string());
+    }
+    secrets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secret_file(label: &str, contents: &str) -> PathBuf {
+        let path = crate::test_support::temp_path(&format!("secret-{label}"));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn config(file: &Path, inline: &str) -> Configuration {
+        let mut config = Configuration::defaults();
+        config.ingest_secret_file = file.to_path_buf();
+        config.ingest_secret = inline.to_string();
+        config
+    }
+
+    #[test]
+    fn file_lines_come_first_and_inline_secret_is_last() {
+        let path = secret_file("order", "# rotated 2026-10\nnew\n\nold\nnew\n");
+        let secrets = IngestSecrets::from_config(&config(&path, "from-env")).unwrap();
+        assert_eq!(secrets.candidates, vec!["new", "old", "from-env"]);
+        assert_eq!(secrets.current(), "new");
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn rotate_walks_candidates_then_rereads_the_file() {
+        let path = secret_file("rotate", "a\nb\n");
+        let mut secrets = IngestSecrets::from_config(&config(&path, "")).unwrap();
+        assert!(secrets.rotate());
+        assert_eq!(secrets.current(), "b");
+
+        // Exhausted and the file is unchanged: nothing new to try.
+        assert!(!secrets.rotate());
+        assert_eq!(secrets.current(), "a");
+
+        // The next 401 cycle walks the old list again, then picks up the edit.
+        fs::write(&path, "c\n").unwrap();
+        assert!(secrets.rotate());
+        assert_eq!(secrets.current(), "b");
+        assert!(secrets.rotate());
+        assert_eq!(secrets.current(), "c");
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn missing_configured_file_is_an_error() {
+        let missing = crate::test_support::temp_path("secret-missing");
+        assert!(IngestSecrets::from_config(&config(&missing, "")).is_err());
+    }
+}
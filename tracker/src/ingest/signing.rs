@@ -0,0 +1,412 @@
+//! Per-device request signing, so a leaked bearer secret on its own is not
+//! enough to write spans.
+//!
+//! Each tracker keeps an ed25519 key in its state dir (`device_key_path`,
+//! next to the spool), created on the first `run` or `enroll`; the file is
+//! the 32-byte seed, base64 on one line, mode 0600. `backend enroll`
+//! registers the public half with the deployment under this device's name.
+//! From then on every POST the Convex sink makes carries:
+//!
+//! ```text
+//! X-Chronomaxi-Device:    device_name
+//! X-Chronomaxi-Key-Id:    first 16 hex chars of sha256(public key)
+//! X-Chronomaxi-Timestamp: unix ms at signing time
+//! X-Chronomaxi-Nonce:     16 random bytes, base64url, no padding
+//! X-Chronomaxi-Signature: base64 ed25519 signature over signing_message()
+//! ```
+//!
+//! The signed message binds all of those to the exact bytes on the wire
+//! (after gzip, if `ingest_gzip` is on):
+//!
+//! ```text
+//! chronomaxi-ingest-v1\n<device>\n<key id>\n<timestamp>\n<nonce>\n<hex sha256(body)>
+//! ```
+//!
+//! `Verifier` is the ingest-side half: it maps key ids to the enrolled
+//! (device, public key), refuses a key presented for a device it wasn't
+//! enrolled under, rejects timestamps outside the allowed skew and remembers
+//! every nonce it accepted for as long as its timestamp would still pass, so
+//! a captured request can't be replayed. `signing_vectors.json` holds fixed
+//! inputs and their expected key id, message and signature; the deployment's
+//! port (convex/lib/signing.ts, with its nonces in a table) is checked
+//! against them by scripts/signing.test.ts.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use chrono::Utc;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier as _, VerifyingKey};
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+
+pub const DEVICE_HEADER: &str = "x-chronomaxi-device";
+pub const KEY_ID_HEADER: &str = "x-chronomaxi-key-id";
+pub const TIMESTAMP_HEADER: &str = "x-chronomaxi-timestamp";
+pub const NONCE_HEADER: &str = "x-chronomaxi-nonce";
+pub const SIGNATURE_HEADER: &str = "x-chronomaxi-signature";
+
+const SIGNATURE_VERSION: &str = "chronomaxi-ingest-v1";
+
+pub struct DeviceKey {
+    signing: SigningKey,
+    path: PathBuf,
+}
+
+impl DeviceKey {
+    /// Reads an existing key; `Ok(None)` when there is none yet. Never
+    /// creates one, so `doctor` and one-shot commands stay read-only.
+    pub fn load(path: &Path) -> Result<Option<Self>, String> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("failed to read device key {}: {e}", path.display())),
+        };
+        let seed: [u8; 32] = STANDARD
+            .decode(text.trim())
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| format!("device key {} is not a base64 32-byte seed", path.display()))?;
+        Ok(Some(Self { signing: SigningKey::from_bytes(&seed), path: path.to_path_buf() }))
+    }
+
+    /// `load`, generating and writing a fresh key when there is none. The
+    /// bool is whether this call created it. The seed is written and
+    /// fsynced under a temporary name and then hard-linked into place, so
+    /// `path` is either absent or complete, and of two processes racing
+    /// only the one whose link lands reports creating it.
+    pub fn load_or_create(path: &Path) -> Result<(Self, bool), String> {
+        if let Some(key) = Self::load(path)? {
+            return Ok((key, false));
+        }
+
+        let mut seed = [0u8; 32];
+        OsRng.fill_bytes(&mut seed);
+        let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        fs::create_dir_all(parent).map_err(|e| format!("failed to create {}: {e}", parent.display()))?;
+
+        let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        let temp = parent.join(format!(".{name}.{:016x}.tmp", OsRng.next_u64()));
+        let written = write_seed(&temp, &seed).and_then(|()| fs::hard_link(&temp, path));
+        let _ = fs::remove_file(&temp);
+        let created = match written {
+            Ok(()) => true,
+            // Another process won the race; its key is complete, use it.
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => false,
+            Err(e) => return Err(format!("failed to create device key {}: {e}", path.display())),
+        };
+        if created {
+            // Make the new directory entry durable too; best effort.
+            let _ = fs::File::open(parent).and_then(|dir| dir.sync_all());
+        }
+
+        let key = Self::load(path)?.ok_or_else(|| format!("device key {} vanished after creation", path.display()))?;
+        Ok((key, created))
+    }
+
+    pub fn from_seed(seed: &[u8; 32]) -> Self {
+        Self { signing: SigningKey::from_bytes(seed), path: PathBuf::new() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn public_key_base64(&self) -> String {
+        STANDARD.encode(self.signing.verifying_key().as_bytes())
+    }
+
+    pub fn key_id(&self) -> String {
+        key_id(&self.signing.verifying_key())
+    }
+
+    /// Signs `body` as sent by `device` at `timestamp_ms` with `nonce`.
+    pub fn sign(&self, device: &str, body: &[u8], timestamp_ms: i64, nonce: &str) -> SignedHeaders {
+        let key_id = self.key_id();
+        let signature = self.signing.sign(&signing_message(device, &key_id, timestamp_ms, nonce, body));
+        SignedHeaders {
+            device: device.to_string(),
+            key_id,
+            timestamp_ms,
+            nonce: nonce.to_string(),
+            signature: STANDARD.encode(signature.to_bytes()),
+        }
+    }
+
+    /// `sign` with the current time and a fresh random nonce.
+    pub fn sign_now(&self, device: &str, body: &[u8]) -> SignedHeaders {
+        let mut nonce = [0u8; 16];
+        OsRng.fill_bytes(&mut nonce);
+        self.sign(device, body, Utc::now().timestamp_millis(), &URL_SAFE_NO_PAD.encode(nonce))
+    }
+}
+
+/// Writes `seed` to a new file at `path`, mode 0600, and fsyncs it.
+fn write_seed(path: &Path, seed: &[u8; 32]) -> std::io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    file.write_all(format!("{}\n", STANDARD.encode(seed)).as_bytes())?;
+    file.sync_all()
+}
+
+/// The five signature headers of one request.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SignedHeaders {
+    pub device: String,
+    pub key_id: String,
+    pub timestamp_ms: i64,
+    pub nonce: String,
+    pub signature: String,
+}
+
+impl SignedHeaders {
+    pub fn pairs(&self) -> [(&'static str, String); 5] {
+        [
+            (DEVICE_HEADER, self.device.clone()),
+            (KEY_ID_HEADER, self.key_id.clone()),
+            (TIMESTAMP_HEADER, self.timestamp_ms.to_string()),
+            (NONCE_HEADER, self.nonce.clone()),
+            (SIGNATURE_HEADER, self.signature.clone()),
+        ]
+    }
+
+    /// Reads the headers back through `lookup` (header name, lowercase).
+    pub fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, VerifyError> {
+        let get = |name: &'static str| lookup(name).filter(|value| !value.is_empty()).ok_or(VerifyError::MissingHeader(name));
+        Ok(Self {
+            device: get(DEVICE_HEADER)?,
+            key_id: get(KEY_ID_HEADER)?,
+            timestamp_ms: get(TIMESTAMP_HEADER)?
+                .parse()
+                .map_err(|_| VerifyError::Malformed(TIMESTAMP_HEADER))?,
+            nonce: get(NONCE_HEADER)?,
+            signature: get(SIGNATURE_HEADER)?,
+        })
+    }
+}
+
+/// First 8 bytes of sha256(public key), hex.
+pub fn key_id(public: &VerifyingKey) -> String {
+    hex(&Sha256::digest(public.as_bytes())[..8])
+}
+
+/// The bytes actually signed; see the module docs.
+pub fn signing_message(device: &str, key_id: &str, timestamp_ms: i64, nonce: &str, body: &[u8]) -> Vec<u8> {
+    format!("{SIGNATURE_VERSION}\n{device}\n{key_id}\n{timestamp_ms}\n{nonce}\n{}", hex(&Sha256::digest(body))).into_bytes()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[derive(Debug, PartialEq)]
+pub enum VerifyError {
+    MissingHeader(&'static str),
+    Malformed(&'static str),
+    UnknownKey(String),
+    /// The key is enrolled, but for a different device.
+    WrongDevice { key_id: String, enrolled: String },
+    Stale { skew_ms: i64 },
+    BadSignature,
+    Replayed,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingHeader(name) => write!(f, "missing {name} header"),
+            Self::Malformed(name) => write!(f, "malformed {name} header"),
+            Self::UnknownKey(key_id) => write!(f, "key {key_id} is not enrolled"),
+            Self::WrongDevice { key_id, enrolled } => write!(f, "key {key_id} is enrolled for {enrolled}"),
+            Self::Stale { skew_ms } => write!(f, "timestamp is {skew_ms}ms away from now"),
+            Self::BadSignature => write!(f, "signature does not match"),
+            Self::Replayed => write!(f, "nonce already used"),
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+/// Ingest-side verification with replay protection. Keys are added with
+/// `enroll`; `verify` is called once per request, in arrival order.
+pub struct Verifier {
+    keys: HashMap<String, (String, VerifyingKey)>,
+    max_skew_ms: i64,
+    /// (key id, nonce) -> timestamp, for every request accepted within the
+    /// skew window.
+    seen: HashMap<(String, String), i64>,
+}
+
+impl Verifier {
+    pub fn new(max_skew: Duration) -> Self {
+        Self { keys: HashMap::new(), max_skew_ms: max_skew.as_millis() as i64, seen: HashMap::new() }
+    }
+
+    /// Registers `device`'s base64 public key and returns its key id.
+    pub fn enroll(&mut self, device: &str, public_key_base64: &str) -> Result<String, VerifyError> {
+        let bytes: [u8; 32] = STANDARD
+            .decode(public_key_base64.trim())
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or(VerifyError::Malformed("public key"))?;
+        let public = VerifyingKey::from_bytes(&bytes).map_err(|_| VerifyError::Malformed("public key"))?;
+        let key_id = key_id(&public);
+        self.keys.insert(key_id.clone(), (device.to_string(), public));
+        Ok(key_id)
+    }
+
+    /// Checks `headers` against `body` (the bytes as received, before any
+    /// Content-Encoding is undone) at `now_ms`. A nonce is only remembered
+    /// once the signature checks out, so garbage requests can't burn
+    /// nonces a real tracker is about to use.
+    pub fn verify(&mut self, headers: &SignedHeaders, body: &[u8], now_ms: i64) -> Result<(), VerifyError> {
+        let (device, public) = self
+            .keys
+            .get(&headers.key_id)
+            .ok_or_else(|| VerifyError::UnknownKey(headers.key_id.clone()))?;
+        if *device != headers.device {
+            return Err(VerifyError::WrongDevice { key_id: headers.key_id.clone(), enrolled: device.clone() });
+        }
+
+        let skew_ms = headers.timestamp_ms - now_ms;
+        if skew_ms.abs() > self.max_skew_ms {
+            return Err(VerifyError::Stale { skew_ms });
+        }
+
+        let signature: [u8; 64] = STANDARD
+            .decode(&headers.signature)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or(VerifyError::Malformed(SIGNATURE_HEADER))?;
+        let message = signing_message(&headers.device, &headers.key_id, headers.timestamp_ms, &headers.nonce, body);
+        public
+            .verify(&message, &Signature::from_bytes(&signature))
+            .map_err(|_| VerifyError::BadSignature)?;
+
+        // Anything older than the window is rejected as stale above, so
+        // its nonce no longer needs remembering.
+        let oldest = now_ms - self.max_skew_ms;
+        self.seen.retain(|_, timestamp| *timestamp >= oldest);
+        match self.seen.entry((headers.key_id.clone(), headers.nonce.clone())) {
+            std::collections::hash_map::Entry::Occupied(_) => Err(VerifyError::Replayed),
+            std::collections::hash_map::Entry::Vacant(entry) => {
+                entry.insert(headers.timestamp_ms);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SKEW: Duration = Duration::from_secs(300);
+
+    #[derive(serde::Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Vector {
+        seed: String,
+        public_key: String,
+        key_id: String,
+        device: String,
+        timestamp_ms: i64,
+        nonce: String,
+        body: String,
+        message: String,
+        signature: String,
+    }
+
+    fn vectors() -> Vec<Vector> {
+        serde_json::from_str(include_str!("signing_vectors.json")).unwrap()
+    }
+
+    fn seed(vector: &Vector) -> [u8; 32] {
+        STANDARD.decode(&vector.seed).unwrap().try_into().unwrap()
+    }
+
+    #[test]
+    fn signing_matches_the_published_vectors() {
+        for vector in vectors() {
+            let key = DeviceKey::from_seed(&seed(&vector));
+            assert_eq!(key.public_key_base64(), vector.public_key);
+            assert_eq!(key.key_id(), vector.key_id);
+            let message = signing_message(&vector.device, &vector.key_id, vector.timestamp_ms, &vector.nonce, vector.body.as_bytes());
+            assert_eq!(String::from_utf8(message).unwrap(), vector.message);
+
+            let headers = key.sign(&vector.device, vector.body.as_bytes(), vector.timestamp_ms, &vector.nonce);
+            assert_eq!(headers.signature, vector.signature);
+
+            let mut verifier = Verifier::new(SKEW);
+            assert_eq!(verifier.enroll(&vector.device, &vector.public_key).unwrap(), vector.key_id);
+            assert_eq!(verifier.verify(&headers, vector.body.as_bytes(), vector.timestamp_ms + 1_000), Ok(()));
+        }
+    }
+
+    #[test]
+    fn tampering_staleness_replay_and_borrowed_keys_are_rejected() {
+        let vector = &vectors()[0];
+        let key = DeviceKey::from_seed(&seed(vector));
+        let body = vector.body.as_bytes();
+        let now = vector.timestamp_ms;
+        let mut verifier = Verifier::new(SKEW);
+        verifier.enroll(&vector.device, &vector.public_key).unwrap();
+        let headers = key.sign(&vector.device, body, now, &vector.nonce);
+
+        assert_eq!(verifier.verify(&headers, b"{\"batch\":[{}]}", now), Err(VerifyError::BadSignature));
+        let forged = SignedHeaders { timestamp_ms: now + 1, ..headers.clone() };
+        assert_eq!(verifier.verify(&forged, body, now), Err(VerifyError::BadSignature));
+        let later = now + SKEW.as_millis() as i64 + 1;
+        assert_eq!(verifier.verify(&headers, body, later), Err(VerifyError::Stale { skew_ms: now - later }));
+
+        // Failed attempts didn't consume the nonce; the first good one does.
+        assert_eq!(verifier.verify(&headers, body, now), Ok(()));
+        assert_eq!(verifier.verify(&headers, body, now + 10), Err(VerifyError::Replayed));
+        assert_eq!(verifier.verify(&key.sign(&vector.device, body, now, "fresh-nonce"), body, now + 10), Ok(()));
+
+        let borrowed = key.sign("some-other-laptop", body, now, "another-nonce");
+        assert!(matches!(verifier.verify(&borrowed, body, now), Err(VerifyError::WrongDevice { .. })));
+        let stranger = DeviceKey::from_seed(&[7; 32]).sign(&vector.device, body, now, "n");
+        assert!(matches!(verifier.verify(&stranger, body, now), Err(VerifyError::UnknownKey(_))));
+    }
+
+    #[test]
+    fn headers_round_trip_through_a_lookup() {
+        let headers = DeviceKey::from_seed(&[1; 32]).sign_now("laptop", b"body");
+        let pairs = headers.pairs();
+        let parsed = SignedHeaders::from_lookup(|name| pairs.iter().find(|(key, _)| *key == name).map(|(_, value)| value.clone()));
+        assert_eq!(parsed, Ok(headers));
+        assert_eq!(SignedHeaders::from_lookup(|_| None), Err(VerifyError::MissingHeader(DEVICE_HEADER)));
+    }
+
+    #[test]
+    fn the_key_is_created_once_and_then_reused() {
+        let dir = crate::test_support::temp_path("device-key");
+        let path = dir.join("device_key");
+
+        assert!(DeviceKey::load(&path).unwrap().is_none());
+        let (created, fresh) = DeviceKey::load_or_create(&path).unwrap();
+        assert!(fresh);
+        let (loaded, fresh) = DeviceKey::load_or_create(&path).unwrap();
+        assert!(!fresh);
+        assert_eq!(created.public_key_base64(), loaded.public_key_base64());
+        // Nothing left behind but the key itself.
+        let entries: Vec<_> = fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        assert_eq!(entries, ["device_key"]);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,521 @@
+//! Flush destinations. Every configured sink (tracker.toml `[[sinks]]`,
+//! default: just Convex) gets its own flusher task, its own spool
+//! connection, its own backoff and its own sent-tracking in the spool
+//! (`Spool::claim_batch_for` / `mark_sent_for`), so a slow or unreachable
+//! sink only ever delays itself. A spool row counts as sent -- and becomes
+//! prunable -- once every configured sink has it.
+//!
+//!   [[sinks]]
+//!   kind = "convex"                        # {ingest_url}/ingest, see convex.rs
+//!   url = "https://other.example"          # optional, default ingest_url
+//!   secret_file = "/path/to/secret"        # optional, default ingest_secret(_file)
+//!
+//!   [[sinks]]
+//!   kind = "jsonl"                         # append-only, one row per line
+//!   path = "/home/me/chronomaxi/spans.jsonl"
+//!
+//!   [[sinks]]
+//!   kind = "stdout"                        # one row per line, for debugging
+//!
+//!   [[sinks]]
+//!   kind = "webhook"
+//!   url = "https://example.org/hook"
+//!   headers = { Authorization = "Bearer ..." }
+//!   body_template = '{"spans": {{rows}}, "n": {{count}}}'
+//!   per_row = false                        # true: one POST per row
+//!
+//!   [[sinks]]
+//!   kind = "activitywatch"                 # aw-server buckets, see activitywatch.rs
+//!   url = "http://127.0.0.1:5600"          # optional
+//!
+//! Webhook templates substitute `{{rows}}` (JSON array), `{{count}}`,
+//! `{{row}}` (the row object) and `{{row.<field>}}` (that wire field,
+//! JSON-encoded, `null` when absent). Everything is inserted as JSON, so a
+//! template that is valid JSON around its placeholders renders to valid
+//! JSON. `{{row...}}` only makes sense with `per_row = true`; in batch mode
+//! it refers to the first row.
+//!
+//! Any sink may also set `when` / `fallback` to take only some rows, see
+//! crate::ingest::route.
+//!
+//! Delivery is at-least-once for every sink: a batch that fails part-way
+//! (a per-row webhook, a JSONL write that hits ENOSPC) is retried whole.
+//! A batch the destination *rejects* (`SinkError::is_rejection`) is split
+//! in half and retried until the rejected rows are isolated (see
+//! crate::ingest); each isolated row counts toward dead-lettering, see
+//! crate::spool's `dead_letter` table.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::future::Future;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::config::Configuration;
+
+use super::activitywatch::{self, ActivityWatchSink};
+use super::convex::{self, ConvexSink};
+use super::route::RouteRule;
+use super::secret::IngestSecrets;
+
+/// A destination the spool can be flushed to. `send` either fails the whole
+/// batch (the rows stay pending for this sink and are retried with backoff)
+/// or says which rows it took, see `Ack`.
+pub trait SpanSink: Send {
+    fn send(&mut self, rows: &[serde_json::Value]) -> impl Future<Output = Result<Ack, SinkError>> + Send;
+}
+
+/// A successful `send`'s answer. Sinks without a per-row protocol take the
+/// whole batch (`All`); Convex answers
+/// `{accepted: [sourceId], duplicate: [sourceId], rejected: [{sourceId, reason}]}`
+/// (convex/http.ts), and then only accepted and duplicate rows count as
+/// delivered, rejected ones are dead-lettered and anything it didn't
+/// mention stays pending.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Ack {
+    All,
+    Rows {
+        accepted: Vec<String>,
+        duplicate: Vec<String>,
+        rejected: Vec<RejectedRow>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize)]
+pub struct RejectedRow {
+    #[serde(rename = "sourceId")]
+    pub source_id: String,
+    pub reason: String,
+}
+
+impl Ack {
+    /// Reads a 2xx response body. A body without an `accepted` list (a
+    /// deployment that predates per-row acks, or no body at all) acks the
+    /// whole batch, as before.
+    pub fn from_body(body: &str) -> Self {
+        #[derive(serde::Deserialize)]
+        struct Body {
+            accepted: Vec<String>,
+            #[serde(default)]
+            duplicate: Vec<String>,
+            #[serde(default)]
+            rejected: Vec<RejectedRow>,
+        }
+        match serde_json::from_str::<Body>(body) {
+            Ok(Body { accepted, duplicate, rejected }) => Ack::Rows { accepted, duplicate, rejected },
+            Err(_) => Ack::All,
+        }
+    }
+}
+
+/// Why a `send` failed. `status` is the HTTP status when the destination
+/// answered at all; `retry_after` its `Retry-After` on a 429/503;
+/// `timed_out` that the request ran out of time (the batch size adapts to
+/// that, see crate::ingest::batch).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SinkError {
+    pub message: String,
+    pub status: Option<u16>,
+    pub retry_after: Option<Duration>,
+    pub timed_out: bool,
+}
+
+impl SinkError {
+    /// No answer (network, local I/O): always worth retrying.
+    pub fn transient(message: impl Into<String>) -> Self {
+        Self { message: message.into(), ..Self::default() }
+    }
+
+    pub fn http(status: reqwest::StatusCode, message: impl Into<String>) -> Self {
+        Self { message: message.into(), status: Some(status.as_u16()), ..Self::default() }
+    }
+
+    /// A request that never got a response.
+    pub fn request(error: &reqwest::Error) -> Self {
+        Self { message: error.to_string(), timed_out: error.is_timeout(), ..Self::default() }
+    }
+
+    /// `http` plus the response's `Retry-After`, which is only honored on
+    /// 429 and 503.
+    pub fn from_response(response: &reqwest::Response, message: impl Into<String>) -> Self {
+        let status = response.status();
+        let retry_after = match status.as_u16() {
+            429 | 503 => response
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(parse_retry_after),
+            _ => None,
+        };
+        Self { retry_after, ..Self::http(status, message) }
+    }
+
+    /// The destination understood the request and refused these rows: a
+    /// 4xx other than 401/403 (our credentials, not the rows), 404/405 (a
+    /// wrong URL refuses every row alike), 408 or 429 (try again later).
+    /// Only these are bisected and count toward dead-lettering; 5xx and
+    /// no-answer failures are retried whole for as long as it takes.
+    pub fn is_rejection(&self) -> bool {
+        matches!(self.status, Some(status) if (400..500).contains(&status) && !matches!(status, 401 | 403 | 404 | 405 | 408 | 429))
+    }
+}
+
+/// `Retry-After` is either delta-seconds or an HTTP-date. A date in the
+/// past means "now".
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    Some((date.with_timezone(&chrono::Utc) - chrono::Utc::now()).to_std().unwrap_or(Duration::ZERO))
+}
+
+impl fmt::Display for SinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl From<String> for SinkError {
+    fn from(message: String) -> Self {
+        Self::transient(message)
+    }
+}
+
+/// One `[[sinks]]` table: routing keys plus the destination itself.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct SinkConfig {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub when: Vec<RouteRule>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub fallback: bool,
+    // Unknown keys fall through to `SinkKind`, which rejects them.
+    #[serde(flatten)]
+    pub kind: SinkKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum SinkKind {
+    Convex {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        url: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        secret_file: Option<PathBuf>,
+    },
+    Jsonl {
+        path: PathBuf,
+    },
+    Stdout {},
+    Webhook {
+        url: String,
+        #[serde(default)]
+        headers: BTreeMap<String, String>,
+        #[serde(default = "default_body_template")]
+        body_template: String,
+        #[serde(default)]
+        per_row: bool,
+    },
+    #[serde(rename = "activitywatch")]
+    ActivityWatch {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        url: Option<String>,
+    },
+}
+
+fn default_body_template() -> String {
+    r#"{"batch": {{rows}}}"#.to_string()
+}
+
+impl SinkConfig {
+    /// The default: every row to `ingest_url` with the global secret.
+    pub fn convex() -> Self {
+        Self::unrouted(SinkKind::Convex { url: None, secret_file: None })
+    }
+
+    pub fn unrouted(kind: SinkKind) -> Self {
+        Self { when: Vec::new(), fallback: false, kind }
+    }
+
+    /// Key for this sink's sent-tracking rows in the spool. Derived from
+    /// the destination, so pointing a sink somewhere new starts it from
+    /// whatever is still pending rather than skipping it.
+    pub fn name(&self) -> String {
+        match &self.kind {
+            SinkKind::Convex { url: None, .. } => "convex".to_string(),
+            SinkKind::Convex { url: Some(url), .. } => format!("convex:{url}"),
+            SinkKind::Stdout {} => "stdout".to_string(),
+            SinkKind::Jsonl { path } => format!("jsonl:{}", path.display()),
+            SinkKind::Webhook { url, .. } => format!("webhook:{url}"),
+            SinkKind::ActivityWatch { url } => {
+                format!("activitywatch:{}", url.as_deref().unwrap_or(activitywatch::DEFAULT_URL))
+            }
+        }
+    }
+
+    pub fn build(&self, config: &Configuration) -> Result<AnySink, String> {
+        Ok(match &self.kind {
+            SinkKind::Convex { url, secret_file } => AnySink::Convex(
+                ConvexSink::new(
+                    url.as_deref().unwrap_or(&config.ingest_url),
+                    match secret_file {
+                        Some(path) => IngestSecrets::new(Some(path.clone()), None)?,
+                        None => IngestSecrets::from_config(config)?,
+                    },
+                )
+                .gzip(config.ingest_gzip)
+                .signed_by(&config.device_name, convex::device_key(config)),
+            ),
+            SinkKind::Stdout {} => AnySink::Stdout(StdoutSink),
+            SinkKind::Jsonl { path } => AnySink::Jsonl(JsonlSink { path: path.clone() }),
+            SinkKind::Webhook { url, headers, body_template, per_row } => {
+                // Catch a typo'd placeholder at startup, not on first flush.
+                render_template(body_template, &[serde_json::json!({})])?;
+                AnySink::Webhook(WebhookSink::new(url.clone(), headers.clone(), body_template.clone(), *per_row))
+            }
+            SinkKind::ActivityWatch { url } => AnySink::ActivityWatch(ActivityWatchSink::new(
+                url.as_deref().unwrap_or(activitywatch::DEFAULT_URL),
+            )),
+        })
+    }
+}
+
+/// Names of every configured sink, in config order.
+pub fn sink_names(config: &Configuration) -> Vec<String> {
+    config.sinks.iter().map(SinkConfig::name).collect()
+}
+
+/// Concrete sink picked by `SinkConfig::build`.
+pub enum AnySink {
+    Convex(ConvexSink),
+    Jsonl(JsonlSink),
+    Stdout(StdoutSink),
+    Webhook(WebhookSink),
+    ActivityWatch(ActivityWatchSink),
+}
+
+impl SpanSink for AnySink {
+    async fn send(&mut self, rows: &[serde_json::Value]) -> Result<Ack, SinkError> {
+        match self {
+            AnySink::Convex(sink) => sink.send(rows).await,
+            AnySink::Jsonl(sink) => sink.send(rows).await,
+            AnySink::Stdout(sink) => sink.send(rows).await,
+            AnySink::Webhook(sink) => sink.send(rows).await,
+            AnySink::ActivityWatch(sink) => sink.send(rows).await,
+        }
+    }
+}
+
+pub struct JsonlSink {
+    path: PathBuf,
+}
+
+impl SpanSink for JsonlSink {
+    /// Reopens in append mode on every batch, so the file can be rotated
+    /// or deleted underneath a running tracker.
+    async fn send(&mut self, rows: &[serde_json::Value]) -> Result<Ack, SinkError> {
+        if let Some(parent) = self.path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        let mut text = String::new();
+        for row in rows {
+            text.push_str(&row.to_string());
+            text.push('\n');
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| format!("failed to open {}: {e}", self.path.display()))?;
+        file.write_all(text.as_bytes())
+            .and_then(|_| file.sync_data())
+            .map_err(|e| SinkError::transient(format!("failed to append to {}: {e}", self.path.display())))?;
+        Ok(Ack::All)
+    }
+}
+
+pub struct StdoutSink;
+
+impl SpanSink for StdoutSink {
+    async fn send(&mut self, rows: &[serde_json::Value]) -> Result<Ack, SinkError> {
+        for row in rows {
+            println!("{row}");
+        }
+        Ok(Ack::All)
+    }
+}
+
+pub struct WebhookSink {
+    http: reqwest::Client,
+    url: String,
+    headers: BTreeMap<String, String>,
+    body_template: String,
+    per_row: bool,
+}
+
+impl WebhookSink {
+    fn new(url: String, headers: BTreeMap<String, String>, body_template: String, per_row: bool) -> Self {
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .connect_timeout(Duration::from_secs(5))
+            .build()
+            .unwrap_or_else(|_| reqwest::Client::new());
+        Self { http, url, headers, body_template, per_row }
+    }
+
+    async fn post(&self, rows: &[serde_json::Value]) -> Result<(), SinkError> {
+        let body = render_template(&self.body_template, rows)?;
+        let mut request = self.http.post(&self.url).header("content-type", "application/json");
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        let response = request.body(body).send().await.map_err(|e| SinkError::request(&e))?;
+        let status = response.status();
+        if status.is_success() {
+            Ok(())
+        } else {
+            Err(SinkError::from_response(&response, format!("webhook returned status {status}")))
+        }
+    }
+}
+
+impl SpanSink for WebhookSink {
+    async fn send(&mut self, rows: &[serde_json::Value]) -> Result<Ack, SinkError> {
+        if !self.per_row {
+            self.post(rows).await?;
+            return Ok(Ack::All);
+        }
+        for row in rows {
+            self.post(std::slice::from_ref(row)).await?;
+        }
+        Ok(Ack::All)
+    }
+}
+
+/// Substitutes the `{{...}}` placeholders listed in the module docs.
+/// `{{row...}}` refers to `rows[0]`.
+pub fn render_template(template: &str, rows: &[serde_json::Value]) -> Result<String, String> {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after.find("}}").ok_or_else(|| format!("unclosed `{{{{` in template {template:?}"))?;
+        let key = after[..end].trim();
+        let row = rows.first().unwrap_or(&serde_json::Value::Null);
+        let value = match key {
+            "rows" => serde_json::Value::from(rows.to_vec()).to_string(),
+            "count" => rows.len().to_string(),
+            "row" => row.to_string(),
+            _ => match key.strip_prefix("row.") {
+                Some(field) => row.get(field).unwrap_or(&serde_json::Value::Null).to_string(),
+                None => return Err(format!("unknown template placeholder `{{{{{key}}}}}`")),
+            },
+        };
+        out.push_str(&value);
+        rest = &after[end + 2..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn template_inserts_json_values() {
+        let rows = vec![json!({"programName": "nvim \"x\"", "durationMs": 1500}), json!({"programName": "zsh"})];
+        let rendered =
+            render_template(r#"{"n": {{count}}, "first": {{row.programName}}, "missing": {{ row.bucket }}, "all": {{rows}}}"#, &rows)
+                .unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(&rendered).unwrap();
+        assert_eq!(parsed["n"], 2);
+        assert_eq!(parsed["first"], "nvim \"x\"");
+        assert!(parsed["missing"].is_null());
+        assert_eq!(parsed["all"][1]["programName"], "zsh");
+    }
+
+    #[test]
+    fn template_rejects_unknown_or_unclosed_placeholders() {
+        assert!(render_template("{{rowz}}", &[]).is_err());
+        assert!(render_template("{\"a\": {{rows", &[]).is_err());
+    }
+
+    #[test]
+    fn ack_body_is_per_row_only_when_it_lists_accepted_rows() {
+        assert_eq!(Ack::from_body(""), Ack::All);
+        assert_eq!(Ack::from_body(r#"{"inserted": 2, "skipped": 0}"#), Ack::All);
+        assert_eq!(
+            Ack::from_body(r#"{"accepted": ["a"], "duplicate": ["b"], "rejected": [{"sourceId": "c", "reason": "bad"}]}"#),
+            Ack::Rows {
+                accepted: vec!["a".to_string()],
+                duplicate: vec!["b".to_string()],
+                rejected: vec![RejectedRow { source_id: "c".to_string(), reason: "bad".to_string() }],
+            }
+        );
+    }
+
+    #[test]
+    fn retry_after_takes_seconds_or_a_date() {
+        assert_eq!(parse_retry_after(" 120 "), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(Duration::ZERO));
+        let later = (chrono::Utc::now() + chrono::Duration::seconds(90)).to_rfc2822();
+        assert!(parse_retry_after(&later).is_some_and(|wait| wait > Duration::from_secs(80)));
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[test]
+    fn sinks_parse_from_toml_and_get_distinct_names() {
+        let parsed: BTreeMap<String, Vec<SinkConfig>> = toml::from_str(
+            r#"
+            [[sinks]]
+            kind = "convex"
+            [[sinks]]
+            kind = "jsonl"
+            path = "/tmp/spans.jsonl"
+            [[sinks]]
+            kind = "webhook"
+            url = "http://localhost:9/hook"
+            per_row = true
+            [[sinks]]
+            kind = "activitywatch"
+            "#,
+        )
+        .unwrap();
+        let names: Vec<String> = parsed["sinks"].iter().map(SinkConfig::name).collect();
+        assert_eq!(
+            names,
+            vec![
+                "convex",
+                "jsonl:/tmp/spans.jsonl",
+                "webhook:http://localhost:9/hook",
+                "activitywatch:http://127.0.0.1:5600"
+            ]
+        );
+        let SinkKind::Webhook { body_template, .. } = &parsed["sinks"][2].kind else { panic!("expected webhook") };
+        assert_eq!(body_template, &default_body_template());
+
+        assert!(toml::from_str::<BTreeMap<String, Vec<SinkConfig>>>("[[sinks]]\nkind = \"jsonl\"\npath = \"x\"\nurl = \"y\"").is_err());
+    }
+
+    #[tokio::test]
+    async fn jsonl_sink_appends_one_line_per_row() {
+        let path = crate::test_support::temp_path("sink").join("spans.jsonl");
+        let mut sink = JsonlSink { path: path.clone() };
+        sink.send(&[json!({"sourceId": "a"})]).await.unwrap();
+        sink.send(&[json!({"sourceId": "b"}), json!({"sourceId": "c"})]).await.unwrap();
+
+        let text = fs::read_to_string(&path).unwrap();
+        let ids: Vec<String> = text
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["sourceId"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(ids, vec!["a", "b", "c"]);
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+}
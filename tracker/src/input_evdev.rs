@@ -26,12 +26,8 @@ use std::time::Duration;
 
 use evdev::{Device, EventSummary, EventType, KeyCode};
 
-/// Hotplug rescan cadence: every tick, newly-appeared `/dev/input/event*`
-/// nodes are opened and (if `EV_KEY`-capable) get their own reader thread.
-const RESCAN_INTERVAL: Duration = Duration::from_secs(30);
-
 /// Permission-denied devices are only retried every other rescan tick
-/// (2 * RESCAN_INTERVAL = 60s) -- distinct from the hotplug cadence above
+/// (2 * `rescan_interval`, 60s by default) -- distinct from the hotplug cadence above
 /// so a udev rule landing mid-session is picked up without hammering
 /// `open()` on devices we already know we can't read.
 const DENIED_RETRY_EVERY_N_TICKS: u64 = 2;
@@ -47,6 +43,10 @@ pub struct InputCounters {
     pub left_clicks: AtomicU64,
     pub right_clicks: AtomicU64,
     pub middle_clicks: AtomicU64,
+    /// Devices with a live reader thread, and devices we lack permission
+    /// to open, as of the last rescan.
+    pub devices_tracked: AtomicU64,
+    pub devices_denied: AtomicU64,
 }
 
 impl InputCounters {
@@ -60,13 +60,15 @@ impl InputCounters {
 
 /// Spawns the background scanner thread and returns the shared counters
 /// immediately -- capture never blocks on device enumeration or waits on
-/// permissions.
-pub fn spawn() -> Arc<InputCounters> {
+/// permissions. `rescan_interval` is the hotplug cadence: every tick,
+/// newly-appeared `/dev/input/event*` nodes are opened and (if
+/// `EV_KEY`-capable) get their own reader thread.
+pub fn spawn(rescan_interval: Duration) -> Arc<InputCounters> {
     let counters = Arc::new(InputCounters::default());
     let scan_counters = Arc::clone(&counters);
     if thread::Builder::new()
         .name("cmx-evdev-scan".to_string())
-        .spawn(move || scan_loop(scan_counters))
+        .spawn(move || scan_loop(scan_counters, rescan_interval))
         .is_err()
     {
         println!("chronomaxi evdev: failed to spawn scanner thread, key/click counts unavailable");
@@ -74,7 +76,7 @@ pub fn spawn() -> Arc<InputCounters> {
     counters
 }
 
-fn scan_loop(counters: Arc<InputCounters>) {
+fn scan_loop(counters: Arc<InputCounters>, rescan_interval: Duration) {
     let tracked: Arc<Mutex<HashSet<PathBuf>>> = Arc::new(Mutex::new(HashSet::new()));
     let mut denied: HashSet<PathBuf> = HashSet::new();
     let mut logged_denied: HashSet<PathBuf> = HashSet::new();
@@ -82,9 +84,9 @@ fn scan_loop(counters: Arc<InputCounters>) {
 
     loop {
         let nodes = list_event_nodes();
-        let retry_denied_this_tick = tick % DENIED_RETRY_EVERY_N_TICKS == 0;
+        let retry_denied_this_tick = tick.is_multiple_of(DENIED_RETRY_EVERY_N_TICKS);
 
-        {
+        let tracked_count = {
             let mut tracked_guard = tracked.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
             for path in &nodes {
                 if tracked_guard.contains(path) {
@@ -113,7 +115,8 @@ fn scan_loop(counters: Arc<InputCounters>) {
                     }
                 }
             }
-        }
+            tracked_guard.len()
+        };
 
         // Devices that disappeared entirely (hot-unplug) stop being
         // "denied" once they no longer show up in /dev/input at all;
@@ -121,15 +124,41 @@ fn scan_loop(counters: Arc<InputCounters>) {
         // its device's fd errors out.
         let present: HashSet<PathBuf> = nodes.into_iter().collect();
         denied.retain(|path| present.contains(path));
+        counters.devices_tracked.store(tracked_count as u64, Ordering::Relaxed);
+        counters.devices_denied.store(denied.len() as u64, Ordering::Relaxed);
 
         if denied != logged_denied {
-            log_denied_state(&denied);
+            log_denied_state(&denied, rescan_interval);
             logged_denied = denied.clone();
         }
 
         tick = tick.wrapping_add(1);
-        thread::sleep(RESCAN_INTERVAL);
+        thread::sleep(rescan_interval);
+    }
+}
+
+/// One-shot readability probe of every `/dev/input/event*` node, for
+/// `doctor`. Opens (and immediately closes) each device; no reader threads.
+#[derive(Debug, Default)]
+pub struct DeviceProbe {
+    pub key_capable: usize,
+    pub other_readable: usize,
+    pub denied: Vec<PathBuf>,
+}
+
+pub fn probe_devices() -> DeviceProbe {
+    let mut probe = DeviceProbe::default();
+    let mut nodes = list_event_nodes();
+    nodes.sort();
+    for path in nodes {
+        match open_key_capable(&path) {
+            Ok(Some(_)) => probe.key_capable += 1,
+            Ok(None) => probe.other_readable += 1,
+            Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => probe.denied.push(path),
+            Err(_) => {}
+        }
     }
+    probe
 }
 
 fn list_event_nodes() -> Vec<PathBuf> {
@@ -171,14 +200,10 @@ fn spawn_reader(
     let reader_path = path.clone();
     let reader_tracked = Arc::clone(&tracked);
     let spawned = thread::Builder::new().name(thread_name).spawn(move || {
-        loop {
-            match device.fetch_events() {
-                Ok(events) => {
-                    for event in events {
-                        record_event(&counters, event.destructure());
-                    }
-                }
-                Err(_) => break, // device removed/errored -- let the scanner retry it
+        // Exits once the device is removed/errors -- the scanner retries it.
+        while let Ok(events) = device.fetch_events() {
+            for event in events {
+                record_event(&counters, event.destructure());
             }
         }
 
@@ -216,7 +241,7 @@ fn record_event(counters: &InputCounters, summary: EventSummary) {
     }
 }
 
-fn log_denied_state(denied: &HashSet<PathBuf>) {
+fn log_denied_state(denied: &HashSet<PathBuf>, rescan_interval: Duration) {
     if denied.is_empty() {
         println!("chronomaxi evdev: all input devices are now accessible");
         return;
@@ -228,7 +253,7 @@ fn log_denied_state(denied: &HashSet<PathBuf>) {
         "CHRONOMAXI INPUT COUNTS UNAVAILABLE: permission denied opening {} input device(s) ({}). Fix on Linux: sudo usermod -aG input $USER, then log out and back in, or install a udev TAG+=\"uaccess\" rule for /dev/input/event*. Retrying every {}s.",
         paths.len(),
         paths.join(", "),
-        RESCAN_INTERVAL.as_secs() * DENIED_RETRY_EVERY_N_TICKS,
+        rescan_interval.as_secs() * DENIED_RETRY_EVERY_N_TICKS,
     );
 }
 
@@ -4,15 +4,30 @@ pub mod actor;
 pub mod buckets;
 pub mod capture;
 pub mod category;
+pub mod cli;
 pub mod config;
+pub mod doctor;
+pub mod export;
+pub mod heartbeat;
+pub mod history;
 #[cfg(target_os = "linux")]
 pub mod hypr_events;
 pub mod idle_tracking;
+pub mod import;
 pub mod ingest;
 #[cfg(target_os = "linux")]
 pub mod input_evdev;
 pub mod log;
+#[cfg(target_os = "linux")]
+pub mod logind;
 mod privacy;
 pub mod logger_v4;
+pub mod metrics;
+pub mod rules_watch;
+pub mod session;
 pub mod spool;
+pub mod summary;
 pub mod tmux;
+#[cfg(test)]
+mod test_support;
+pub mod validate;
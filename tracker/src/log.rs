@@ -35,6 +35,9 @@ pub struct Log {
     pub actor: String,
 
     pub is_idle: bool,
+    /// Why the span is idle regardless of input: the session was `locked`
+    /// or had logind's `idle-hint` (crate::session).
+    pub idle_reason: Option<String>,
 }
 
 impl fmt::Display for Log {
@@ -42,7 +45,7 @@ impl fmt::Display for Log {
         let (mouse_x, mouse_y) = self.current_mouse_position.unwrap_or((0, 0));
         write!(
             f,
-            "Window ID: {:?}\nProgram Process Name: {:?}\nProgram Name: {:?}\nBrowser Title: {:?}\nMouse Position: ({:?}, {:?})\nDuration MS: {:?}\nKeys Pressed: {:?}\nCreated At: {:?}\n\nStart Time: {:?}\nEndTime: {:?}\nIsIdle: {:?}\n Category: {:?}\n Mouse Movement in (mm): {:?}\nLeft Clicks: {:?}\n Right Clicks: {:?}\n Middle Clicks: {:?}\nSubProgram: {:?}\nTmuxSession: {:?}\nBucket: {:?}\nActor: {:?}",
+            "Window ID: {:?}\nProgram Process Name: {:?}\nProgram Name: {:?}\nBrowser Title: {:?}\nMouse Position: ({:?}, {:?})\nDuration MS: {:?}\nKeys Pressed: {:?}\nCreated At: {:?}\n\nStart Time: {:?}\nEndTime: {:?}\nIsIdle: {:?}\n Category: {:?}\n Mouse Movement in (mm): {:?}\nLeft Clicks: {:?}\n Right Clicks: {:?}\n Middle Clicks: {:?}\nSubProgram: {:?}\nTmuxSession: {:?}\nBucket: {:?}\nActor: {:?}\nIdleReason: {:?}",
             self.current_window_id,
             self.current_program_process_name,
             self.current_program_name,
@@ -63,7 +66,8 @@ impl fmt::Display for Log {
             self.sub_program,
             self.tmux_session,
             self.bucket,
-            self.actor
+            self.actor,
+            self.idle_reason
         )
     }
 }
@@ -91,6 +95,7 @@ impl Log {
             tmux_session: None,
             bucket: None,
             actor: crate::config::DEFAULT_ACTOR.to_string(),
+            idle_reason: None,
         }
     }
 
@@ -104,3 +109,9 @@ impl Log {
         }
     }
 }
+
+impl Default for Log {
+    fn default() -> Self {
+        Self::new()
+    }
+}
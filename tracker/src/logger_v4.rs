@@ -4,7 +4,6 @@ use device_query::{DeviceQuery, MouseState};
 #[cfg(target_os = "linux")]
 use serde::Deserialize;
 use std::env;
-#[cfg(target_os = "linux")]
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use tokio::time;
@@ -15,16 +14,20 @@ use crate::{
     buckets::BucketClassifier,
     category::{self, Category},
     config::Configuration,
+    heartbeat::{self, GapReason, Heartbeat},
     idle_tracking::IdleTracker,
     log::Log,
+    metrics::Metrics,
     privacy::PrivacyScrubber,
+    rules_watch::RulesWatcher,
+    session::{SessionSpans, SessionState},
     spool::Spool,
 };
 #[cfg(target_os = "linux")]
-use crate::{hypr_events, input_evdev, tmux};
+use crate::{hypr_events, input_evdev, logind, tmux};
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
-enum CaptureBackend {
+pub enum CaptureBackend {
     #[cfg(target_os = "linux")]
     Hyprland,
     #[cfg(target_os = "linux")]
@@ -60,12 +63,6 @@ struct HyprClient {
     pid: Option<i64>,
 }
 
-const MAX_SPAN_SECONDS: i64 = 60;
-const CHECKPOINT_SPAN_SECONDS: i64 = 40;
-/// How often logger_v4 reconciles the Hyprland event-socket pushed state
-/// against `hyprctl activewindow -j` ground truth.
-#[cfg(target_os = "linux")]
-const HYPR_RECONCILE_SECONDS: i64 = 5;
 static SHUTDOWN_REQUESTED: AtomicBool = AtomicBool::new(false);
 const SIGINT: i32 = 2;
 const SIGTERM: i32 = 15;
@@ -96,12 +93,12 @@ fn run_cmd(cmd: &str, args: &[&str]) -> Option<String> {
 }
 
 #[cfg(target_os = "macos")]
-fn select_backend() -> CaptureBackend {
+pub fn select_backend() -> CaptureBackend {
     CaptureBackend::MacOS
 }
 
 #[cfg(target_os = "linux")]
-fn select_backend() -> CaptureBackend {
+pub fn select_backend() -> CaptureBackend {
     let has_hyprland = env::var("HYPRLAND_INSTANCE_SIGNATURE")
         .map(|value| !value.trim().is_empty())
         .unwrap_or(false);
@@ -124,8 +121,13 @@ pub struct LoggerV4 {
     pub idle_tracker: IdleTracker,
     pub config: Configuration,
     pub spool: Spool,
+    /// Shared with the flusher and the `/metrics` listener.
+    pub metrics: Arc<Metrics>,
     bucket_classifier: BucketClassifier,
     privacy_scrubber: PrivacyScrubber,
+    /// Parks re-parsed buckets.json / privacy-denylist.json edits until
+    /// the next tick swaps them in. `None` when hot reload is unavailable.
+    rules_watcher: Option<RulesWatcher>,
     #[cfg(target_os = "linux")]
     pub device_state: device_query::DeviceState,
     #[cfg(target_os = "macos")]
@@ -138,6 +140,15 @@ pub struct LoggerV4 {
     pending_click_counts: (usize, usize, usize),
 
     pub last_stats_time: chrono::DateTime<chrono::Utc>,
+    /// When `current_log` was last saved to the spool's `open_span`.
+    last_open_span_save: chrono::DateTime<chrono::Utc>,
+    /// When the heartbeat file was last rewritten (crate::heartbeat).
+    last_heartbeat: chrono::DateTime<chrono::Utc>,
+    boot_id: Option<String>,
+    /// Lock, idle hint and sleep from logind plus tick timing
+    /// (crate::session); a locked or idle-hinted session makes every span
+    /// idle, with the reason as its idleReason.
+    session: SessionState,
 
     pub current_log: Option<Log>,
     pub current_window_id: Option<String>,
@@ -175,20 +186,31 @@ pub struct LoggerV4 {
     x11_focus_pid: Option<i64>,
     #[cfg(target_os = "linux")]
     tmux_resolver: tmux::TmuxResolver,
+    #[cfg(target_os = "linux")]
+    logind_watcher: logind::LogindWatcher,
 }
 
 impl LoggerV4 {
     /// Creates a new instance of LoggerV4.
-    /// It initializes the configuration, local spool, and device state.
+    /// It initializes the local spool and device state from the already-layered
+    /// `config` (see crate::config).
     ///
     /// # Returns
     /// A `Result` containing the new `LoggerV4` instance or an error if initialization fails.
-    pub async fn new() -> Result<LoggerV4, Box<dyn std::error::Error>> {
+    pub async fn new(config: Configuration) -> Result<LoggerV4, Box<dyn std::error::Error>> {
         let backend = select_backend();
-        let config = Configuration::from_env()?;
         let spool = Spool::open(&config.spool_path)?;
         let bucket_classifier = BucketClassifier::load(&config.bucket_config_path);
-        let privacy_scrubber = PrivacyScrubber::load(&config.privacy_config_path, &config.scrub_audit_path);
+        let privacy_scrubber = PrivacyScrubber::load(
+            &config.privacy_config_path,
+            &config.scrub_audit_path,
+            config.privacy_failure_policy,
+        )?;
+        let rules_watcher = RulesWatcher::spawn(
+            &config.bucket_config_path,
+            &config.privacy_config_path,
+            &config.scrub_audit_path,
+        );
 
         #[cfg(target_os = "linux")]
         let device_state = device_query::DeviceState::new();
@@ -213,7 +235,7 @@ impl LoggerV4 {
         // evdev reader threads or an event-socket subscriber on bertha.
         #[cfg(target_os = "linux")]
         let evdev_counters = if backend == CaptureBackend::Hyprland {
-            input_evdev::spawn()
+            input_evdev::spawn(std::time::Duration::from_secs(config.evdev_rescan_interval_seconds))
         } else {
             Arc::new(input_evdev::InputCounters::default())
         };
@@ -225,6 +247,8 @@ impl LoggerV4 {
         };
 
         println!("Using {:?} capture backend", backend);
+        let metrics = Arc::new(Metrics::default());
+        metrics.set_backend(&format!("{backend:?}"));
         #[cfg(target_os = "linux")]
         if backend == CaptureBackend::X11 && env::var("DISPLAY").unwrap_or_default().trim().is_empty() {
             println!(
@@ -232,12 +256,20 @@ impl LoggerV4 {
             );
         }
 
+        #[cfg(target_os = "linux")]
+        let tmux_resolver = tmux::TmuxResolver::with_intervals(
+            std::time::Duration::from_millis(config.tmux_push_freshness_ms),
+            std::time::Duration::from_millis(config.tmux_ipc_min_interval_ms),
+        );
+
         Ok(LoggerV4 {
-            idle_tracker: IdleTracker::new(),
+            idle_tracker: IdleTracker::with_threshold_ms(config.idle_threshold_ms),
             config,
             spool,
+            metrics,
             bucket_classifier,
             privacy_scrubber,
+            rules_watcher,
             #[cfg(target_os = "linux")]
             device_state,
             #[cfg(target_os = "macos")]
@@ -250,6 +282,10 @@ impl LoggerV4 {
             pending_click_counts: (0, 0, 0),
 
             last_stats_time: chrono::Utc::now(),
+            last_open_span_save: chrono::Utc::now(),
+            last_heartbeat: chrono::Utc::now(),
+            boot_id: heartbeat::boot_id(),
+            session: SessionState::new(chrono::Utc::now()),
 
             current_log: None,
             current_window_id: None,
@@ -279,7 +315,9 @@ impl LoggerV4 {
             #[cfg(target_os = "linux")]
             x11_focus_pid: None,
             #[cfg(target_os = "linux")]
-            tmux_resolver: tmux::TmuxResolver::new(),
+            tmux_resolver,
+            #[cfg(target_os = "linux")]
+            logind_watcher: logind::LogindWatcher::spawn(),
         })
     }
 
@@ -292,6 +330,25 @@ impl LoggerV4 {
     pub async fn run(&mut self) -> Result<(), Box<dyn std::error::Error>> {
         println!("Starting chronomaxi logging service");
 
+        // A span the last run never got to end (SIGKILL, OOM, power loss)
+        // is closed at its last save before anything new is captured.
+        let recovered_until = match self.spool.recover_open_span() {
+            Ok(Some(row)) => {
+                println!(
+                    "chronomaxi spool: recovered a {}s {} span the last run didn't end",
+                    row.duration_ms / 1000,
+                    row.program_name
+                );
+                Some(row.created_at + row.duration_ms)
+            }
+            Ok(None) => None,
+            Err(e) => {
+                println!("Error recovering the open span: {:?}", e);
+                None
+            }
+        };
+        self.spool_downtime(recovered_until);
+
         self.current_window_id = Some(self.get_window_id());
         self.last_window_id = self.current_window_id.clone();
 
@@ -310,18 +367,28 @@ impl LoggerV4 {
                 if let Err(e) = self.end_current_log() {
                     println!("Error ending current log during shutdown: {:?}", e);
                 }
+                self.beat(true);
                 break;
             }
 
+            let started = std::time::Instant::now();
             if let Err(e) = self.tick().await {
                 println!("Error during log tick: {:?}", e);
             }
+            self.metrics.record_tick(started.elapsed());
         }
 
         Ok(())
     }
 
     async fn tick(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.apply_rule_reloads();
+        #[cfg(target_os = "linux")]
+        for event in self.logind_watcher.drain() {
+            self.apply_session_event(event)?;
+        }
+        self.check_for_stall(Utc::now())?;
+
         #[cfg(target_os = "macos")]
         if self.backend == CaptureBackend::MacOS {
             self.pending_click_counts = self.macos_capture.drain_clicks();
@@ -350,6 +417,21 @@ impl LoggerV4 {
         }
 
         self.log_on_window_change()?;
+        self.save_open_span();
+        if self.config.heartbeat_seconds > 0
+            && Utc::now() - self.last_heartbeat >= Duration::seconds(self.config.heartbeat_seconds)
+        {
+            self.beat(false);
+        }
+
+        #[cfg(target_os = "linux")]
+        {
+            self.metrics.set_evdev_devices(
+                self.evdev_counters.devices_tracked.load(Ordering::Relaxed),
+                self.evdev_counters.devices_denied.load(Ordering::Relaxed),
+            );
+            self.metrics.set_hypr_connected(self.hypr_watcher.as_ref().is_some_and(|watcher| watcher.is_connected()));
+        }
 
         let elapsed_since_stats = Utc::now() - self.last_stats_time;
         if elapsed_since_stats >= Duration::seconds(self.config.stats_every_n_seconds) {
@@ -377,6 +459,8 @@ impl LoggerV4 {
         let mouse_position = self.get_mouse_position();
         let mouse_movement_mm = self.get_mouse_movement_mm();
         let now = Utc::now();
+        let max_span = Duration::seconds(self.config.max_span_seconds);
+        let checkpoint_span = Duration::seconds(self.config.checkpoint_span_seconds);
 
         #[cfg(target_os = "linux")]
         let tmux_context = self.resolve_tmux_context(&active_window);
@@ -412,7 +496,7 @@ impl LoggerV4 {
             log.duration_ms = log.get_log_duration_ms();
         }
 
-        let mut idle_probe = self.current_log.clone().unwrap_or_else(Log::new);
+        let mut idle_probe = self.current_log.clone().unwrap_or_default();
         idle_probe.current_window_id = Some(active_window.id.clone());
         idle_probe.current_program_process_name = Some(active_window.program_process_name.clone());
         idle_probe.current_program_name = Some(active_window.program_name.clone());
@@ -436,10 +520,10 @@ impl LoggerV4 {
             let idle_changed = log.is_idle != is_idle;
             let span_capped = log
                 .log_start_time_utc
-                .is_some_and(|start_time| now - start_time >= Duration::seconds(MAX_SPAN_SECONDS));
-            let span_checkpointed = log.log_start_time_utc.is_some_and(|start_time| {
-                now - start_time >= Duration::seconds(CHECKPOINT_SPAN_SECONDS)
-            });
+                .is_some_and(|start_time| now - start_time >= max_span);
+            let span_checkpointed = log
+                .log_start_time_utc
+                .is_some_and(|start_time| now - start_time >= checkpoint_span);
 
             window_changed || idle_changed || span_capped || span_checkpointed
         });
@@ -458,11 +542,88 @@ impl LoggerV4 {
         Ok(should_end_current_log)
     }
 
+    /// Saves `current_log` to the spool every `open_span_persist_seconds`,
+    /// see `Spool::recover_open_span`. A span with no duration yet has
+    /// nothing worth recovering.
+    fn save_open_span(&mut self) {
+        let every = Duration::seconds(self.config.open_span_persist_seconds);
+        let now = Utc::now();
+        if every <= Duration::zero() || now - self.last_open_span_save < every {
+            return;
+        }
+        self.last_open_span_save = now;
+        let Some(log) = self.current_log.as_ref().filter(|log| log.duration_ms.unwrap_or(0) > 0) else {
+            return;
+        };
+        if let Err(e) = self.spool.save_open_span(log, &self.config.device_name) {
+            println!("Error saving the open span: {:?}", e);
+        }
+    }
+
+    /// Spools the time since the last run's final heartbeat as a gap, or
+    /// since the end of the span `recover_open_span` just closed if that is
+    /// later. Then starts this run's heartbeat.
+    fn spool_downtime(&mut self, recovered_until_ms: Option<i64>) {
+        if self.config.heartbeat_seconds <= 0 {
+            return;
+        }
+        if let Some(last) = heartbeat::read(&self.config.heartbeat_path) {
+            let from_ms = recovered_until_ms.map_or(last.at, |until| until.max(last.at));
+            let reason = GapReason::since(&last, self.boot_id.as_deref());
+            if let Some(from) = chrono::DateTime::<Utc>::from_timestamp_millis(from_ms) {
+                self.spool_gap(from, Utc::now(), reason);
+            }
+        }
+        self.beat(false);
+    }
+
+    fn spool_gap(&mut self, from: chrono::DateTime<Utc>, to: chrono::DateTime<Utc>, reason: GapReason) {
+        if to - from < Duration::seconds(self.config.gap_min_seconds) {
+            return;
+        }
+        let row = heartbeat::gap_row(from, to, reason, &self.config.device_name, &self.config.actor);
+        match self.spool.enqueue_rows(&[row]) {
+            Ok(_) => println!(
+                "chronomaxi: spooled a {}s untracked gap ({})",
+                (to - from).num_seconds(),
+                reason.as_str()
+            ),
+            Err(e) => println!("Error spooling untracked gap: {:?}", e),
+        }
+    }
+
+    fn beat(&mut self, clean: bool) {
+        if self.config.heartbeat_seconds <= 0 {
+            return;
+        }
+        self.last_heartbeat = Utc::now();
+        let beat = Heartbeat { at: self.last_heartbeat.timestamp_millis(), boot_id: self.boot_id.clone(), clean };
+        if let Err(e) = heartbeat::write(&self.config.heartbeat_path, &beat) {
+            println!("Error writing heartbeat: {:?}", e);
+        }
+    }
+
+    /// Swaps in any buckets.json / privacy-denylist.json edits the rules
+    /// watcher (crate::rules_watch) has parsed since the last tick, so
+    /// every classification from here on uses the new rules.
+    fn apply_rule_reloads(&mut self) {
+        let Some(watcher) = self.rules_watcher.as_ref() else { return };
+        if let Some(classifier) = watcher.take_buckets() {
+            self.bucket_classifier = classifier;
+        }
+        if let Some(scrubber) = watcher.take_privacy() {
+            self.privacy_scrubber = scrubber;
+        }
+    }
+
     /// Hyprland/X11 keep the existing mouse+keys+window-id heuristic
     /// (crate::idle_tracking). macOS uses the authoritative
     /// CGEventSourceSecondsSinceLastEventType signal instead, sharing only
     /// the configured threshold with the heuristic tracker.
     fn compute_is_idle(&mut self, idle_probe: &Log, window_title: Option<&str>) -> bool {
+        if self.session.idle_reason().is_some() {
+            return true;
+        }
         match self.backend {
             #[cfg(target_os = "linux")]
             CaptureBackend::Hyprland | CaptureBackend::X11 => self.idle_tracker.is_idle(idle_probe, window_title),
@@ -613,6 +774,7 @@ impl LoggerV4 {
             tmux_session,
             bucket: Some(bucket),
             actor,
+            idle_reason: self.session.idle_reason().map(str::to_string),
         };
         log.is_idle = self.compute_is_idle(&log, Some(safe_title.as_str()));
 
@@ -686,12 +848,13 @@ impl LoggerV4 {
 
     /// Periodic ground-truth check: compares the event-socket pushed state
     /// against a fresh `hyprctl activewindow -j`, correcting (and logging)
-    /// any divergence. Runs at most once every `HYPR_RECONCILE_SECONDS`.
+    /// any divergence. Runs at most once every
+    /// `Configuration::hypr_reconcile_seconds`.
     #[cfg(target_os = "linux")]
     fn reconcile_hypr_state(&mut self) {
         let Some(watcher) = self.hypr_watcher.as_ref() else { return };
         let now = Utc::now();
-        if now - self.hypr_last_reconcile < Duration::seconds(HYPR_RECONCILE_SECONDS) {
+        if now - self.hypr_last_reconcile < Duration::seconds(self.config.hypr_reconcile_seconds) {
             return;
         }
         self.hypr_last_reconcile = now;
@@ -801,20 +964,7 @@ impl LoggerV4 {
     /// # Returns
     /// A `bool` indicating whether the program is a web browser.
     pub fn is_current_program_browser(&self, current_program_process_name: String) -> bool {
-        let program = current_program_process_name.to_lowercase();
-        const FIREFOX: &str = "firefox";
-        const CHROME: &str = "chrome";
-        const BRAVE: &str = "brave";
-        const EDGE: &str = "edge";
-        const SAFARI: &str = "safari";
-        const ZEN: &str = "zen";
-
-        program.contains(FIREFOX)
-            || program.contains(CHROME)
-            || program.contains(BRAVE)
-            || program.contains(EDGE)
-            || program.contains(SAFARI)
-            || program.contains(ZEN)
+        is_browser_program(&current_program_process_name)
     }
 
     /// Retrieves the title of the web browser window associated with the given window ID.
@@ -842,23 +992,7 @@ impl LoggerV4 {
         current_program_process_name: &str,
         browser_title_str: &str,
     ) -> Option<(Option<String>, Option<String>)> {
-        if !self.is_current_program_browser(current_program_process_name.to_string()) {
-            return None;
-        }
-
-        let browser_title_parts: Vec<&str> = browser_title_str.trim().split(" - ").collect();
-
-        match browser_title_parts.len() {
-            3 => Some((
-                Some(browser_title_parts[0].trim().to_string()),
-                Some(browser_title_parts[1].trim().to_string()),
-            )),
-            2 => Some((
-                Some(browser_title_parts[0].trim().to_string()),
-                Some(browser_title_parts[1].trim().to_string()),
-            )),
-            _ => Some((Some(browser_title_str.trim().to_string()), None)),
-        }
+        browser_title_and_site_name(current_program_process_name, browser_title_str)
     }
 
     fn get_active_window_title(&mut self, current_window_id: String) -> String {
@@ -1141,6 +1275,81 @@ impl LoggerV4 {
     // ========================================================================
 }
 
+impl SessionSpans for LoggerV4 {
+    fn session(&mut self) -> &mut SessionState {
+        &mut self.session
+    }
+
+    fn open_span_start(&self) -> Option<chrono::DateTime<Utc>> {
+        self.current_log.as_ref().and_then(|log| log.log_start_time_utc)
+    }
+
+    fn gap_min(&self) -> Option<Duration> {
+        (self.config.heartbeat_seconds > 0).then(|| Duration::seconds(self.config.gap_min_seconds))
+    }
+
+    fn end_span_at(&mut self, at: chrono::DateTime<Utc>) -> Result<(), Box<dyn std::error::Error>> {
+        self.end_current_log_at(at)
+    }
+
+    fn restart_span(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.current_log = Some(self.capture()?);
+        Ok(())
+    }
+
+    fn spool_suspend(&mut self, from: chrono::DateTime<Utc>, to: chrono::DateTime<Utc>) {
+        self.spool_gap(from, to, GapReason::Suspend);
+        self.beat(false);
+    }
+
+    fn mark_active(&mut self) {
+        self.idle_tracker.last_activity_time = Utc::now();
+    }
+}
+
+fn is_browser_program(program_process_name: &str) -> bool {
+    let program = program_process_name.to_lowercase();
+    const FIREFOX: &str = "firefox";
+    const CHROME: &str = "chrome";
+    const BRAVE: &str = "brave";
+    const EDGE: &str = "edge";
+    const SAFARI: &str = "safari";
+    const ZEN: &str = "zen";
+
+    program.contains(FIREFOX)
+        || program.contains(CHROME)
+        || program.contains(BRAVE)
+        || program.contains(EDGE)
+        || program.contains(SAFARI)
+        || program.contains(ZEN)
+}
+
+/// Splits a browser window title ("Page - site - Browser") into (title,
+/// site name). `None` for non-browser programs. Shared by capture and the
+/// `classify` command so both see exactly the same inputs.
+pub fn browser_title_and_site_name(
+    program_process_name: &str,
+    browser_title_str: &str,
+) -> Option<(Option<String>, Option<String>)> {
+    if !is_browser_program(program_process_name) {
+        return None;
+    }
+
+    let browser_title_parts: Vec<&str> = browser_title_str.trim().split(" - ").collect();
+
+    match browser_title_parts.len() {
+        3 => Some((
+            Some(browser_title_parts[0].trim().to_string()),
+            Some(browser_title_parts[1].trim().to_string()),
+        )),
+        2 => Some((
+            Some(browser_title_parts[0].trim().to_string()),
+            Some(browser_title_parts[1].trim().to_string()),
+        )),
+        _ => Some((Some(browser_title_str.trim().to_string()), None)),
+    }
+}
+
 #[cfg(target_os = "linux")]
 fn parse_wm_class(output: &str) -> Option<String> {
     output.split('"').nth(1).map(|name| name.to_string())
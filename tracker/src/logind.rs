@@ -0,0 +1,327 @@
+//! systemd-logind subscriber: suspend/resume, screen lock and the
+//! session's idle hint, so logger_v4 can end spans at the moment the
+//! machine sleeps or locks instead of finding out on the next tick.
+//!
+//! On the system bus this listens for
+//!   - `Manager.PrepareForSleep(true/false)` on /org/freedesktop/login1,
+//!     holding a "delay" sleep inhibitor so the event is timestamped before
+//!     the machine actually goes down (released as soon as it is, re-taken
+//!     on resume);
+//!   - `Session.Lock` / `Session.Unlock` and the `LockedHint` property
+//!     (set by the locker itself) on our session;
+//!   - the session's `IdleHint` property (set by the compositor's idle
+//!     daemon).
+//!
+//! Every signal becomes a timestamped `SessionEvent` (crate::session) on a
+//! channel that logger_v4 drains at the top of each tick. Each (re)connect
+//! first reports the current state, wake included, so a signal lost while
+//! the bus was down doesn't leave the logger asleep or locked. Our session is
+//! `$XDG_SESSION_ID` (or logind's "auto"); a systemd user service has
+//! neither, so it falls back to the user's display session.
+//!
+//! Without a system bus (containers, no logind) the watcher keeps retrying
+//! in the background and the tracker carries on as before: a stalled tick
+//! still becomes a suspend gap (crate::heartbeat), locking still goes idle
+//! after `idle_threshold_ms`.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use chrono::Utc;
+use futures_util::StreamExt;
+use tokio::sync::mpsc;
+use zbus::zvariant::{OwnedFd, OwnedObjectPath, OwnedValue};
+use zbus::{Connection, MatchRule, MessageStream};
+
+use crate::session::{SessionChange, SessionEvent};
+
+const LOGIN1: &str = "org.freedesktop.login1";
+const MANAGER_PATH: &str = "/org/freedesktop/login1";
+const MANAGER_INTERFACE: &str = "org.freedesktop.login1.Manager";
+const SESSION_INTERFACE: &str = "org.freedesktop.login1.Session";
+const USER_INTERFACE: &str = "org.freedesktop.login1.User";
+const PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+pub struct LogindWatcher {
+    events: mpsc::UnboundedReceiver<SessionEvent>,
+}
+
+impl LogindWatcher {
+    /// Starts watching the system bus on a background task. Must be called
+    /// from inside the tokio runtime.
+    pub fn spawn() -> Self {
+        let (sender, events) = mpsc::unbounded_channel();
+        tokio::spawn(watch_system_bus(sender));
+        Self { events }
+    }
+
+    /// Watches an already-open connection, for tests against a stand-in.
+    #[cfg(test)]
+    fn on(connection: Connection) -> Self {
+        let (sender, events) = mpsc::unbounded_channel();
+        tokio::spawn(async move { watch(&connection, &sender).await });
+        Self { events }
+    }
+
+    /// Everything seen since the last call, oldest first.
+    pub fn drain(&mut self) -> Vec<SessionEvent> {
+        let mut drained = Vec::new();
+        while let Ok(event) = self.events.try_recv() {
+            drained.push(event);
+        }
+        drained
+    }
+}
+
+async fn watch_system_bus(sender: mpsc::UnboundedSender<SessionEvent>) {
+    let mut backoff = INITIAL_BACKOFF;
+    let mut reported = false;
+    while !sender.is_closed() {
+        let result = match Connection::system().await {
+            Ok(connection) => {
+                backoff = INITIAL_BACKOFF;
+                watch(&connection, &sender).await
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            // Once: on a machine without logind this would repeat forever.
+            if !reported {
+                println!("logind unavailable, suspend/lock will be inferred from ticks: {e}");
+                reported = true;
+            }
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+/// Forwards logind's signals until the connection drops or the logger
+/// goes away.
+async fn watch(connection: &Connection, sender: &mpsc::UnboundedSender<SessionEvent>) -> zbus::Result<()> {
+    let session = session_path(connection).await?;
+    let signal = || MatchRule::builder().msg_type(zbus::message::Type::Signal);
+    let sleep = signal().path(MANAGER_PATH)?.interface(MANAGER_INTERFACE)?.member("PrepareForSleep")?.build();
+    let lock = signal().path(session.as_str())?.interface(SESSION_INTERFACE)?.build();
+    let properties = signal()
+        .path(session.as_str())?
+        .interface(PROPERTIES_INTERFACE)?
+        .member("PropertiesChanged")?
+        .arg(0, SESSION_INTERFACE)?
+        .build();
+    let mut messages = futures_util::stream::select_all([
+        MessageStream::for_match_rule(sleep, connection, None).await?,
+        MessageStream::for_match_rule(lock, connection, None).await?,
+        MessageStream::for_match_rule(properties, connection, None).await?,
+    ]);
+    let send = |change| sender.send(SessionEvent { at: Utc::now(), change }).is_ok();
+
+    // Where things stand now: on a reconnect anything may have been missed
+    // while the bus was gone, the wake from a suspend included. Repeats of
+    // the logger's current state change nothing (crate::session).
+    let manager_proxy = proxy(connection, MANAGER_PATH, MANAGER_INTERFACE).await?;
+    if !manager_proxy.get_property::<bool>("PreparingForSleep").await.unwrap_or(false) {
+        send(SessionChange::Wake);
+    }
+    let session_proxy = proxy(connection, session.as_str(), SESSION_INTERFACE).await?;
+    let locked = session_proxy.get_property::<bool>("LockedHint").await.unwrap_or(false);
+    send(if locked { SessionChange::Lock } else { SessionChange::Unlock });
+    send(SessionChange::Idle(session_proxy.get_property::<bool>("IdleHint").await.unwrap_or(false)));
+
+    let mut inhibitor = inhibit_sleep(connection).await;
+    while let Some(message) = messages.next().await {
+        let message = message?;
+        let header = message.header();
+        let (Some(interface), Some(member)) = (header.interface(), header.member()) else { continue };
+        let changes = match (interface.as_str(), member.as_str()) {
+            (MANAGER_INTERFACE, "PrepareForSleep") => match message.body().deserialize::<bool>()? {
+                true => vec![SessionChange::Sleep],
+                false => vec![SessionChange::Wake],
+            },
+            (SESSION_INTERFACE, "Lock") => vec![SessionChange::Lock],
+            (SESSION_INTERFACE, "Unlock") => vec![SessionChange::Unlock],
+            (PROPERTIES_INTERFACE, "PropertiesChanged") => {
+                let (_, changed, _): (String, HashMap<String, OwnedValue>, Vec<String>) =
+                    message.body().deserialize()?;
+                property_changes(&changed)
+            }
+            _ => continue,
+        };
+        for change in changes {
+            if !send(change) {
+                return Ok(());
+            }
+            match change {
+                // Timestamped, so the machine may go down now.
+                SessionChange::Sleep => inhibitor = None,
+                SessionChange::Wake if inhibitor.is_none() => inhibitor = inhibit_sleep(connection).await,
+                _ => {}
+            }
+        }
+    }
+    drop(inhibitor);
+    Ok(())
+}
+
+fn property_changes(changed: &HashMap<String, OwnedValue>) -> Vec<SessionChange> {
+    let flag = |name: &str| changed.get(name).and_then(|value| bool::try_from(value).ok());
+    let mut changes = Vec::new();
+    if let Some(locked) = flag("LockedHint") {
+        changes.push(if locked { SessionChange::Lock } else { SessionChange::Unlock });
+    }
+    if let Some(idle) = flag("IdleHint") {
+        changes.push(SessionChange::Idle(idle));
+    }
+    changes
+}
+
+async fn proxy<'a>(connection: &Connection, path: &'a str, interface: &'a str) -> zbus::Result<zbus::Proxy<'a>> {
+    zbus::proxy::Builder::new(connection)
+        .destination(LOGIN1)?
+        .path(path)?
+        .interface(interface)?
+        .cache_properties(zbus::proxy::CacheProperties::No)
+        .build()
+        .await
+}
+
+async fn session_path(connection: &Connection) -> zbus::Result<OwnedObjectPath> {
+    let manager = proxy(connection, MANAGER_PATH, MANAGER_INTERFACE).await?;
+    let id = std::env::var("XDG_SESSION_ID").unwrap_or_else(|_| "auto".to_string());
+    match manager.call("GetSession", &(id,)).await {
+        Ok(path) => Ok(path),
+        Err(_) => {
+            let user = proxy(connection, "/org/freedesktop/login1/user/self", USER_INTERFACE).await?;
+            let (_, path): (String, OwnedObjectPath) = user.get_property("Display").await?;
+            Ok(path)
+        }
+    }
+}
+
+/// A "delay" sleep inhibitor: logind waits (up to its InhibitDelayMaxSec)
+/// for the fd to be closed before suspending. `None` if refused.
+async fn inhibit_sleep(connection: &Connection) -> Option<OwnedFd> {
+    let manager = proxy(connection, MANAGER_PATH, MANAGER_INTERFACE).await.ok()?;
+    manager.call("Inhibit", &("sleep", "chronomaxi", "ending the open span", "delay")).await.ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zbus::zvariant::Value;
+
+    const SESSION_PATH: &str = "/org/freedesktop/login1/session/_33";
+
+    struct FakeManager {
+        sleeping: bool,
+    }
+
+    #[zbus::interface(name = "org.freedesktop.login1.Manager")]
+    impl FakeManager {
+        fn get_session(&self, _id: String) -> OwnedObjectPath {
+            OwnedObjectPath::try_from(SESSION_PATH).unwrap()
+        }
+
+        #[zbus(property)]
+        fn preparing_for_sleep(&self) -> bool {
+            self.sleeping
+        }
+    }
+
+    struct FakeSession {
+        locked: bool,
+    }
+
+    #[zbus::interface(name = "org.freedesktop.login1.Session")]
+    impl FakeSession {
+        #[zbus(property)]
+        fn locked_hint(&self) -> bool {
+            self.locked
+        }
+
+        #[zbus(property)]
+        fn idle_hint(&self) -> bool {
+            false
+        }
+    }
+
+    /// logind stand-in on one end of a socket pair, the watcher on the
+    /// other; no bus daemon involved.
+    async fn fake_logind(sleeping: bool, locked: bool) -> (Connection, LogindWatcher) {
+        let (server, client) = tokio::net::UnixStream::pair().unwrap();
+        let server = zbus::connection::Builder::unix_stream(server)
+            .server(zbus::Guid::generate())
+            .unwrap()
+            .p2p()
+            .serve_at(MANAGER_PATH, FakeManager { sleeping })
+            .unwrap()
+            .serve_at(SESSION_PATH, FakeSession { locked })
+            .unwrap()
+            .build();
+        let client = zbus::connection::Builder::unix_stream(client).p2p().build();
+        let (server, client) = tokio::try_join!(server, client).unwrap();
+        (server, LogindWatcher::on(client))
+    }
+
+    async fn next(watcher: &mut LogindWatcher) -> SessionChange {
+        tokio::time::timeout(Duration::from_secs(5), watcher.events.recv()).await.unwrap().unwrap().change
+    }
+
+    async fn emit<B>(logind: &Connection, path: &str, interface: &str, member: &str, body: &B)
+    where
+        B: serde::Serialize + zbus::zvariant::DynamicType,
+    {
+        logind.emit_signal(None::<()>, path, interface, member, body).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn sleep_lock_and_idle_signals_become_events() {
+        let (logind, mut watcher) = fake_logind(false, false).await;
+        // Let the watcher resolve the session and subscribe before signalling.
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        watcher.drain();
+
+        emit(&logind, MANAGER_PATH, MANAGER_INTERFACE, "PrepareForSleep", &(true,)).await;
+        assert_eq!(next(&mut watcher).await, SessionChange::Sleep);
+        emit(&logind, MANAGER_PATH, MANAGER_INTERFACE, "PrepareForSleep", &(false,)).await;
+        assert_eq!(next(&mut watcher).await, SessionChange::Wake);
+
+        emit(&logind, SESSION_PATH, SESSION_INTERFACE, "Lock", &()).await;
+        assert_eq!(next(&mut watcher).await, SessionChange::Lock);
+        emit(&logind, SESSION_PATH, SESSION_INTERFACE, "Unlock", &()).await;
+        assert_eq!(next(&mut watcher).await, SessionChange::Unlock);
+
+        let changed = HashMap::from([("IdleHint", Value::from(true))]);
+        let body = (SESSION_INTERFACE, changed, Vec::<&str>::new());
+        emit(&logind, SESSION_PATH, PROPERTIES_INTERFACE, "PropertiesChanged", &body).await;
+        assert_eq!(next(&mut watcher).await, SessionChange::Idle(true));
+
+        // Another session's lock is not ours.
+        emit(&logind, "/org/freedesktop/login1/session/_34", SESSION_INTERFACE, "Lock", &()).await;
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(watcher.drain().is_empty());
+    }
+
+    fn changes(watcher: &mut LogindWatcher) -> Vec<SessionChange> {
+        watcher.drain().into_iter().map(|event| event.change).collect()
+    }
+
+    #[tokio::test]
+    async fn a_connect_reports_the_current_state_wake_included() {
+        let (_logind, mut watcher) = fake_logind(false, true).await;
+        assert_eq!(next(&mut watcher).await, SessionChange::Wake);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(changes(&mut watcher), vec![SessionChange::Lock, SessionChange::Idle(false)]);
+    }
+
+    #[tokio::test]
+    async fn a_connect_mid_suspend_does_not_report_a_wake() {
+        let (_logind, mut watcher) = fake_logind(true, false).await;
+        assert_eq!(next(&mut watcher).await, SessionChange::Unlock);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(changes(&mut watcher), vec![SessionChange::Idle(false)]);
+    }
+}
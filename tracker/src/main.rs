@@ -1,21 +1,20 @@
-use backend::ingest;
-use backend::logger_v4::LoggerV4;
+use backend::cli;
+use backend::config::Configuration;
 use dotenv::dotenv;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     dotenv().ok();
 
-    let mut logger = LoggerV4::new().await?;
-
-    // Decoupled spool-to-Convex flusher: its own task, its own spool
-    // connection, never blocks capture on network.
-    let flusher_config = logger.config.clone();
-    tokio::spawn(async move {
-        ingest::run_flusher(flusher_config).await;
-    });
-
-    logger.run().await?;
+    // Subcommand + tracker.toml < env < CLI flags, see crate::cli and
+    // crate::config.
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let invocation = cli::parse(&args)?;
+    let config = Configuration::load(invocation.config.config_path.as_deref(), &invocation.config.overrides)?;
 
+    let code = cli::dispatch(invocation.command, config).await?;
+    if code != 0 {
+        std::process::exit(code);
+    }
     Ok(())
 }
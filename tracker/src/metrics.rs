@@ -0,0 +1,336 @@
+//! Optional loopback HTTP listener exposing tracker and flusher health,
+//! for node_exporter-style scraping instead of grepping stdout.
+//!
+//! Off unless `metrics_listen` (CHRONOMAXI_METRICS_LISTEN) is set, e.g.
+//! `127.0.0.1:9464`; a non-loopback address is refused, since the span
+//! counts and program activity it reveals are nobody else's business.
+//!
+//!   GET /metrics   Prometheus text format (version 0.0.4)
+//!   GET /healthz   200 "ok" while the capture loop is ticking, 503 once it
+//!                  has stalled for `STALL_AFTER`; the body lists each
+//!                  sink's failures and backoff either way
+//!
+//! `Metrics` is shared (`Arc`) between `LoggerV4`, which records ticks and
+//! capture-source state, and the flusher's per-sink loops, which record
+//! successes and failures. Spool depth is read at scrape time from the
+//! listener's own spool connection, so it's never stale.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+use crate::spool::Spool;
+
+/// `/healthz` turns 503 once the capture loop hasn't ticked for this long.
+const STALL_AFTER: Duration = Duration::from_secs(30);
+/// Window `chronomaxi_ticks_per_second` is averaged over.
+const TICK_RATE_WINDOW: Duration = Duration::from_secs(5);
+/// Upper bounds of the tick duration histogram buckets, in seconds.
+const TICK_BUCKETS: [f64; 9] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0];
+
+#[derive(Default)]
+pub struct Metrics {
+    backend: Mutex<String>,
+    ticks: AtomicU64,
+    last_tick_ms: AtomicI64,
+    /// f64 bits.
+    ticks_per_second: AtomicU64,
+    rate_window: Mutex<Option<(Instant, u64)>>,
+    tick_buckets: [AtomicU64; TICK_BUCKETS.len()],
+    tick_seconds_sum_micros: AtomicU64,
+    evdev_tracked: AtomicU64,
+    evdev_denied: AtomicU64,
+    hypr_connected: AtomicBool,
+    sinks: Mutex<BTreeMap<String, SinkHealth>>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SinkHealth {
+    /// Unix seconds of the last batch the sink took.
+    pub last_success: Option<i64>,
+    /// Wait before the next retry; zero while healthy.
+    pub backoff: Duration,
+    pub consecutive_failures: u64,
+}
+
+impl Metrics {
+    pub fn set_backend(&self, backend: &str) {
+        *self.backend.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = backend.to_string();
+    }
+
+    /// One capture tick took `elapsed`.
+    pub fn record_tick(&self, elapsed: Duration) {
+        let ticks = self.ticks.fetch_add(1, Ordering::Relaxed) + 1;
+        self.last_tick_ms.store(Utc::now().timestamp_millis(), Ordering::Relaxed);
+        let seconds = elapsed.as_secs_f64();
+        if let Some(bucket) = TICK_BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.tick_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        self.tick_seconds_sum_micros.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+
+        let mut window = self.rate_window.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        match *window {
+            Some((started, at)) if started.elapsed() >= TICK_RATE_WINDOW => {
+                let rate = (ticks - at) as f64 / started.elapsed().as_secs_f64();
+                self.ticks_per_second.store(rate.to_bits(), Ordering::Relaxed);
+                *window = Some((Instant::now(), ticks));
+            }
+            Some(_) => {}
+            None => *window = Some((Instant::now(), ticks)),
+        }
+    }
+
+    pub fn set_evdev_devices(&self, tracked: u64, denied: u64) {
+        self.evdev_tracked.store(tracked, Ordering::Relaxed);
+        self.evdev_denied.store(denied, Ordering::Relaxed);
+    }
+
+    pub fn set_hypr_connected(&self, connected: bool) {
+        self.hypr_connected.store(connected, Ordering::Relaxed);
+    }
+
+    /// A flush attempt for `sink` went through; `delivered` rows were taken.
+    pub fn sink_succeeded(&self, sink: &str, delivered: usize) {
+        let mut sinks = self.sinks.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let health = sinks.entry(sink.to_string()).or_default();
+        if delivered > 0 {
+            health.last_success = Some(Utc::now().timestamp());
+        }
+        health.backoff = Duration::ZERO;
+        health.consecutive_failures = 0;
+    }
+
+    /// A flush attempt for `sink` failed and will be retried after `backoff`.
+    pub fn sink_failed(&self, sink: &str, backoff: Duration) {
+        let mut sinks = self.sinks.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let health = sinks.entry(sink.to_string()).or_default();
+        health.backoff = backoff;
+        health.consecutive_failures += 1;
+    }
+
+    pub fn sink_health(&self, sink: &str) -> Option<SinkHealth> {
+        self.sinks.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).get(sink).cloned()
+    }
+
+    /// Prometheus text exposition. `spool` is `None` when the spool
+    /// couldn't be opened; the spool gauges are left out then.
+    pub fn render(&self, spool: Option<&Spool>) -> String {
+        let mut out = String::new();
+
+        if let Some(spool) = spool {
+            if let Ok(pending) = spool.pending_count() {
+                gauge(&mut out, "chronomaxi_spool_pending_rows", "Spool rows not yet delivered to every sink.");
+                let _ = writeln!(out, "chronomaxi_spool_pending_rows {pending}");
+            }
+            if let Ok(oldest) = spool.oldest_pending_created_at() {
+                let age = oldest.map(|oldest| (Utc::now() - oldest).num_milliseconds() as f64 / 1000.0).unwrap_or(0.0);
+                gauge(&mut out, "chronomaxi_spool_oldest_pending_age_seconds", "Age of the oldest pending spool row, 0 when none.");
+                let _ = writeln!(out, "chronomaxi_spool_oldest_pending_age_seconds {age}");
+            }
+        }
+
+        let sinks = self.sinks.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+        gauge(&mut out, "chronomaxi_ingest_last_success_timestamp_seconds", "Unix time a sink last took a batch.");
+        for (name, health) in &sinks {
+            if let Some(at) = health.last_success {
+                let _ = writeln!(out, "chronomaxi_ingest_last_success_timestamp_seconds{{sink=\"{name}\"}} {at}");
+            }
+        }
+        gauge(&mut out, "chronomaxi_ingest_backoff_seconds", "Current retry backoff per sink, 0 while healthy.");
+        for (name, health) in &sinks {
+            let _ = writeln!(out, "chronomaxi_ingest_backoff_seconds{{sink=\"{name}\"}} {}", health.backoff.as_secs_f64());
+        }
+        gauge(&mut out, "chronomaxi_ingest_consecutive_failures", "Failed flush attempts per sink since its last success.");
+        for (name, health) in &sinks {
+            let _ = writeln!(out, "chronomaxi_ingest_consecutive_failures{{sink=\"{name}\"}} {}", health.consecutive_failures);
+        }
+
+        let ticks = self.ticks.load(Ordering::Relaxed);
+        let _ = writeln!(out, "# HELP chronomaxi_ticks_total Capture loop ticks since start.");
+        let _ = writeln!(out, "# TYPE chronomaxi_ticks_total counter");
+        let _ = writeln!(out, "chronomaxi_ticks_total {ticks}");
+        gauge(&mut out, "chronomaxi_ticks_per_second", "Capture loop tick rate over the last few seconds.");
+        let _ = writeln!(out, "chronomaxi_ticks_per_second {}", f64::from_bits(self.ticks_per_second.load(Ordering::Relaxed)));
+
+        let _ = writeln!(out, "# HELP chronomaxi_tick_duration_seconds Time spent in one capture tick.");
+        let _ = writeln!(out, "# TYPE chronomaxi_tick_duration_seconds histogram");
+        let mut cumulative = 0;
+        for (bound, count) in TICK_BUCKETS.iter().zip(&self.tick_buckets) {
+            cumulative += count.load(Ordering::Relaxed);
+            let _ = writeln!(out, "chronomaxi_tick_duration_seconds_bucket{{le=\"{bound}\"}} {cumulative}");
+        }
+        let _ = writeln!(out, "chronomaxi_tick_duration_seconds_bucket{{le=\"+Inf\"}} {ticks}");
+        let sum = self.tick_seconds_sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let _ = writeln!(out, "chronomaxi_tick_duration_seconds_sum {sum}");
+        let _ = writeln!(out, "chronomaxi_tick_duration_seconds_count {ticks}");
+
+        let backend = self.backend.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+        gauge(&mut out, "chronomaxi_capture_backend", "Active capture backend (always 1).");
+        let _ = writeln!(out, "chronomaxi_capture_backend{{backend=\"{backend}\"}} 1");
+        gauge(&mut out, "chronomaxi_evdev_devices", "Input devices read via evdev, and those denied by permissions.");
+        let _ = writeln!(out, "chronomaxi_evdev_devices{{state=\"tracked\"}} {}", self.evdev_tracked.load(Ordering::Relaxed));
+        let _ = writeln!(out, "chronomaxi_evdev_devices{{state=\"denied\"}} {}", self.evdev_denied.load(Ordering::Relaxed));
+        gauge(&mut out, "chronomaxi_hypr_socket_connected", "1 while the Hyprland event socket is connected.");
+        let _ = writeln!(out, "chronomaxi_hypr_socket_connected {}", u8::from(self.hypr_connected.load(Ordering::Relaxed)));
+
+        out
+    }
+
+    /// `/healthz`: whether the capture loop is alive, and a short report.
+    pub fn health(&self) -> (bool, String) {
+        let last_tick = self.last_tick_ms.load(Ordering::Relaxed);
+        let since_ms = Utc::now().timestamp_millis() - last_tick;
+        let ticking = last_tick > 0 && since_ms <= STALL_AFTER.as_millis() as i64;
+
+        let mut body = if ticking {
+            "ok\n".to_string()
+        } else if last_tick == 0 {
+            "stalled: no capture tick yet\n".to_string()
+        } else {
+            format!("stalled: last capture tick {}s ago\n", since_ms / 1000)
+        };
+        for (name, health) in self.sinks.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).iter() {
+            let _ = writeln!(
+                body,
+                "sink {name}: {} consecutive failure(s), backoff {:?}",
+                health.consecutive_failures, health.backoff
+            );
+        }
+        (ticking, body)
+    }
+}
+
+fn gauge(out: &mut String, name: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} gauge");
+}
+
+/// Binds `listen`, which must be a loopback `ip:port`.
+pub async fn bind(listen: &str) -> Result<TcpListener, String> {
+    let address: SocketAddr = listen.parse().map_err(|e| format!("metrics_listen {listen:?}: {e}"))?;
+    if !address.ip().is_loopback() {
+        return Err(format!("metrics_listen {listen:?} is not a loopback address"));
+    }
+    TcpListener::bind(address).await.map_err(|e| format!("failed to bind metrics listener on {listen}: {e}"))
+}
+
+/// Answers `/metrics` and `/healthz` on `listener`, one connection at a
+/// time (scrapes are rare and tiny), until the process exits.
+pub async fn serve(listener: TcpListener, metrics: Arc<Metrics>, spool_path: PathBuf) {
+    let mut spool = None;
+    loop {
+        let Ok((mut stream, _)) = listener.accept().await else {
+            continue;
+        };
+
+        let mut buf = vec![0u8; 4096];
+        let n = match tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buf)).await {
+            Ok(Ok(n)) => n,
+            _ => continue,
+        };
+        let request = String::from_utf8_lossy(&buf[..n]);
+        let mut parts = request.lines().next().unwrap_or("").split_whitespace();
+        let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+
+        let (status, content_type, body) = match (method, path.split('?').next().unwrap_or("")) {
+            ("GET", "/metrics") => {
+                if spool.is_none() {
+                    spool = Spool::open(&spool_path).ok();
+                }
+                ("200 OK", "text/plain; version=0.0.4", metrics.render(spool.as_ref()))
+            }
+            ("GET", "/healthz") => match metrics.health() {
+                (true, body) => ("200 OK", "text/plain", body),
+                (false, body) => ("503 Service Unavailable", "text/plain", body),
+            },
+            ("GET", _) => ("404 Not Found", "text/plain", "not found\n".to_string()),
+            _ => ("405 Method Not Allowed", "text/plain", "method not allowed\n".to_string()),
+        };
+        let response = format!(
+            "HTTP/1.1 {status}\r\ncontent-type: {content_type}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+            body.len()
+        );
+        let _ = stream.write_all(response.as_bytes()).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ticks_and_sink_state_render_as_prometheus_text() {
+        let metrics = Metrics::default();
+        metrics.set_backend("Hyprland");
+        metrics.record_tick(Duration::from_millis(3));
+        metrics.record_tick(Duration::from_millis(40));
+        metrics.record_tick(Duration::from_secs(2));
+        metrics.set_evdev_devices(3, 1);
+        metrics.set_hypr_connected(true);
+        metrics.sink_failed("convex", Duration::from_secs(4));
+        metrics.sink_failed("convex", Duration::from_secs(8));
+        metrics.sink_succeeded("jsonl", 5);
+
+        let text = metrics.render(None);
+        for line in [
+            "chronomaxi_ticks_total 3",
+            "chronomaxi_tick_duration_seconds_bucket{le=\"0.001\"} 0",
+            "chronomaxi_tick_duration_seconds_bucket{le=\"0.005\"} 1",
+            "chronomaxi_tick_duration_seconds_bucket{le=\"0.05\"} 2",
+            "chronomaxi_tick_duration_seconds_bucket{le=\"1\"} 2",
+            "chronomaxi_tick_duration_seconds_bucket{le=\"+Inf\"} 3",
+            "chronomaxi_tick_duration_seconds_count 3",
+            "chronomaxi_capture_backend{backend=\"Hyprland\"} 1",
+            "chronomaxi_evdev_devices{state=\"tracked\"} 3",
+            "chronomaxi_evdev_devices{state=\"denied\"} 1",
+            "chronomaxi_hypr_socket_connected 1",
+            "chronomaxi_ingest_backoff_seconds{sink=\"convex\"} 8",
+            "chronomaxi_ingest_consecutive_failures{sink=\"convex\"} 2",
+            "chronomaxi_ingest_consecutive_failures{sink=\"jsonl\"} 0",
+        ] {
+            assert!(text.lines().any(|l| l == line), "missing {line:?} in\n{text}");
+        }
+        assert!(text.contains("chronomaxi_ingest_last_success_timestamp_seconds{sink=\"jsonl\"}"));
+        assert!(!text.contains("chronomaxi_ingest_last_success_timestamp_seconds{sink=\"convex\"}"));
+
+        metrics.sink_succeeded("convex", 0);
+        assert_eq!(metrics.sink_health("convex").unwrap(), SinkHealth::default());
+    }
+
+    #[tokio::test]
+    async fn listener_serves_metrics_and_health_on_loopback_only() {
+        assert!(bind("0.0.0.0:0").await.is_err());
+        assert!(bind("localhost:9464").await.is_err());
+
+        let spool_path = crate::test_support::temp_path("metrics");
+        let listener = bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let metrics = Arc::new(Metrics::default());
+        tokio::spawn(serve(listener, Arc::clone(&metrics), spool_path.clone()));
+
+        let health = reqwest::get(format!("{base}/healthz")).await.unwrap();
+        assert_eq!(health.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+
+        metrics.record_tick(Duration::from_millis(1));
+        let health = reqwest::get(format!("{base}/healthz")).await.unwrap();
+        assert_eq!(health.status(), reqwest::StatusCode::OK);
+        assert!(health.text().await.unwrap().starts_with("ok"));
+
+        let text = reqwest::get(format!("{base}/metrics")).await.unwrap().text().await.unwrap();
+        assert!(text.lines().any(|line| line == "chronomaxi_spool_pending_rows 0"));
+        assert!(text.lines().any(|line| line == "chronomaxi_ticks_total 1"));
+
+        let missing = reqwest::get(format!("{base}/nope")).await.unwrap();
+        assert_eq!(missing.status(), reqwest::StatusCode::NOT_FOUND);
+
+        let _ = std::fs::remove_file(spool_path);
+    }
+}
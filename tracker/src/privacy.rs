@@ -3,12 +3,16 @@ use std::io::Write;
 use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
 use std::path::{Path, PathBuf};
 
+use crate::config::PrivacyFailurePolicy;
+use crate::validate::{self, ConfigIssue};
+
 const SCRUB_LABEL: &str = "homework";
 
 const CONFIG_COMMENT: &str =
     "Extend allowlist_domains with browser domains whose titles are safe to log.";
 
 #[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct PrivacyConfig {
     #[serde(default)]
     pub adult_domains: Vec<String>,
@@ -27,7 +31,11 @@ pub struct PrivacyConfig {
 #[derive(Clone, Debug)]
 pub struct PrivacyScrubber {
     config: PrivacyConfig,
-    audit_path: PathBuf,
+    /// `None` only for `without_audit` previews.
+    audit_path: Option<PathBuf>,
+    /// Fail-closed mode after a broken config under
+    /// `PrivacyFailurePolicy::ScrubAll`: every span is scrubbed.
+    scrub_all: bool,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -42,15 +50,49 @@ pub struct ScrubDecision {
 }
 
 impl PrivacyScrubber {
-    pub fn load(config_path: &Path, audit_path: &Path) -> Self {
-        let config = load_or_seed(config_path).unwrap_or_else(|e| {
-            println!(
-                "chronomaxi privacy: failed to load {} ({e}), using in-process denylist",
-                config_path.display()
-            );
-            default_config()
-        });
-        Self { config, audit_path: audit_path.to_path_buf() }
+    /// Startup load. A missing file is seeded with the built-in denylist;
+    /// a file that exists but fails to parse never silently falls back to
+    /// the (more permissive) built-in list -- `policy` decides between
+    /// refusing to start and scrubbing every span until the file is fixed
+    /// (hot reload picks up the fix, see crate::rules_watch).
+    pub fn load(
+        config_path: &Path,
+        audit_path: &Path,
+        policy: PrivacyFailurePolicy,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        match load_or_seed(config_path) {
+            Ok(config) => Ok(Self { config, audit_path: Some(audit_path.to_path_buf()), scrub_all: false }),
+            Err(e) => match policy {
+                PrivacyFailurePolicy::Refuse => Err(format!(
+                    "chronomaxi privacy: failed to load {} ({e}), refusing to start (privacy_failure_policy = \"refuse\")",
+                    config_path.display()
+                )
+                .into()),
+                PrivacyFailurePolicy::ScrubAll => {
+                    println!(
+                        "CHRONOMAXI PRIVACY CONFIG BROKEN: failed to load {} ({e}), scrubbing every span until it parses",
+                        config_path.display()
+                    );
+                    Ok(Self { config: default_config(), audit_path: Some(audit_path.to_path_buf()), scrub_all: true })
+                }
+            },
+        }
+    }
+
+    /// Strict reload path for the rules watcher (crate::rules_watch): never
+    /// seeds and never falls back to the built-in denylist, so a broken edit
+    /// surfaces as an error and the caller keeps its last-good scrubber.
+    pub fn try_load(config_path: &Path, audit_path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let text = fs::read_to_string(config_path)?;
+        let config = parse_config(&text)?;
+        Ok(Self { config, audit_path: Some(audit_path.to_path_buf()), scrub_all: false })
+    }
+
+    /// Same rules, but never writes the local scrub audit -- for the
+    /// `classify` command, which only previews decisions.
+    pub fn without_audit(mut self) -> Self {
+        self.audit_path = None;
+        self
     }
 
     pub fn scrub_fields(
@@ -62,6 +104,12 @@ impl PrivacyScrubber {
         sub_program: Option<&str>,
         bucket: &str,
     ) -> ScrubDecision {
+        if self.scrub_all {
+            // No audit line here: the audit log records raw titles, and in
+            // this mode that would be every title of every tick.
+            return scrubbed_decision();
+        }
+
         let fields = [
             program_process_name,
             program_name,
@@ -87,15 +135,7 @@ impl PrivacyScrubber {
         }
 
         self.audit(program_process_name, program_name, title, browser_title, sub_program, bucket);
-        ScrubDecision {
-            scrubbed: true,
-            program_process_name: SCRUB_LABEL.to_string(),
-            program_name: SCRUB_LABEL.to_string(),
-            title: SCRUB_LABEL.to_string(),
-            browser_title: Some(SCRUB_LABEL.to_string()),
-            sub_program: None,
-            bucket: SCRUB_LABEL.to_string(),
-        }
+        scrubbed_decision()
     }
 
     fn should_force_scrub(&self, value: &str) -> bool {
@@ -147,14 +187,15 @@ impl PrivacyScrubber {
         sub_program: Option<&str>,
         bucket: &str,
     ) {
-        if let Some(parent) = self.audit_path.parent() {
+        let Some(audit_path) = self.audit_path.as_ref() else { return };
+        if let Some(parent) = audit_path.parent() {
             let _ = fs::create_dir_all(parent);
         }
         let mut file = match fs::OpenOptions::new()
             .create(true)
             .append(true)
             .mode(0o600)
-            .open(&self.audit_path)
+            .open(audit_path)
         {
             Ok(file) => file,
             Err(e) => {
@@ -176,6 +217,39 @@ impl PrivacyScrubber {
     }
 }
 
+fn scrubbed_decision() -> ScrubDecision {
+    ScrubDecision {
+        scrubbed: true,
+        program_process_name: SCRUB_LABEL.to_string(),
+        program_name: SCRUB_LABEL.to_string(),
+        title: SCRUB_LABEL.to_string(),
+        browser_title: Some(SCRUB_LABEL.to_string()),
+        sub_program: None,
+        bucket: SCRUB_LABEL.to_string(),
+    }
+}
+
+/// Strict check for `validate` mode (crate::validate): parse errors with
+/// line/column, unknown keys, and empty/duplicate entries in every list.
+pub fn validate_file(path: &Path) -> Vec<ConfigIssue> {
+    let config: PrivacyConfig = match validate::parse_json_file(path) {
+        Ok(config) => config,
+        Err(issue) => return vec![issue],
+    };
+
+    let mut issues = Vec::new();
+    for (field, patterns) in [
+        ("adult_domains", &config.adult_domains),
+        ("flagged_terms", &config.flagged_terms),
+        ("search_markers", &config.search_markers),
+        ("private_window_markers", &config.private_window_markers),
+        ("allowlist_domains", &config.allowlist_domains),
+    ] {
+        validate::check_patterns(path, field, patterns, &mut issues);
+    }
+    issues
+}
+
 pub fn default_config_path() -> PathBuf {
     let config_home = std::env::var("XDG_CONFIG_HOME")
         .map(PathBuf::from)
@@ -197,7 +271,11 @@ fn load_or_seed(path: &Path) -> Result<PrivacyConfig, Box<dyn std::error::Error>
         seed_file(path)?;
     }
     let text = fs::read_to_string(path)?;
-    let mut config: PrivacyConfig = serde_json::from_str(&text)?;
+    parse_config(&text)
+}
+
+fn parse_config(text: &str) -> Result<PrivacyConfig, Box<dyn std::error::Error>> {
+    let mut config: PrivacyConfig = serde_json::from_str(text)?;
     // Old configs predate browser fail-closed allowlisting. Treat an empty
     // allowlist as "not configured yet" so loading one does not scrub all browsing.
     if config.allowlist_domains.is_empty() {
@@ -387,7 +465,11 @@ mod tests {
     use super::*;
 
     fn scrubber() -> PrivacyScrubber {
-        PrivacyScrubber { config: default_config(), audit_path: PathBuf::from("/tmp/chronomaxi-test-scrub-audit.log") }
+        PrivacyScrubber {
+            config: default_config(),
+            audit_path: Some(crate::test_support::temp_path("scrub-audit")),
+            scrub_all: false,
+        }
     }
 
     #[test]
@@ -475,11 +557,7 @@ mod tests {
 
     #[test]
     fn old_config_without_allowlist_gets_default_allowlist_and_stays_fail_closed() {
-        let path = std::env::temp_dir().join(format!(
-            "chronomaxi-old-privacy-{}-{}.json",
-            std::process::id(),
-            chrono::Utc::now().timestamp_nanos_opt().unwrap()
-        ));
+        let path = crate::test_support::temp_path("old-privacy");
         fs::write(
             &path,
             serde_json::json!({
@@ -495,7 +573,8 @@ mod tests {
         let config = load_or_seed(&path).unwrap();
         let scrubber = PrivacyScrubber {
             config,
-            audit_path: PathBuf::from("/tmp/chronomaxi-test-scrub-audit.log"),
+            audit_path: Some(crate::test_support::temp_path("scrub-audit")),
+            scrub_all: false,
         };
         let unknown = scrubber.scrub_fields(
             "Chrome",
@@ -522,13 +601,25 @@ mod tests {
         let _ = fs::remove_file(path);
     }
 
+    #[test]
+    fn broken_config_scrubs_everything_or_refuses() {
+        let path = crate::test_support::temp_path("broken-privacy");
+        fs::write(&path, r#"{"adult_domain": ["typo"]}"#).unwrap();
+        let audit = crate::test_support::temp_path("scrub-audit");
+
+        assert!(PrivacyScrubber::load(&path, &audit, PrivacyFailurePolicy::Refuse).is_err());
+
+        let scrubber = PrivacyScrubber::load(&path, &audit, PrivacyFailurePolicy::ScrubAll).unwrap();
+        let decision = scrubber.scrub_fields("Alacritty", "Alacritty", "notes", None, Some("nvim"), "coding");
+        assert!(decision.scrubbed);
+        assert_eq!(decision.bucket, "homework");
+
+        let _ = fs::remove_file(path);
+    }
+
     #[test]
     fn seed_file_writes_allowlist_and_user_comment() {
-        let path = std::env::temp_dir().join(format!(
-            "chronomaxi-seed-privacy-{}-{}.json",
-            std::process::id(),
-            chrono::Utc::now().timestamp_nanos_opt().unwrap()
-        ));
+        let path = crate::test_support::temp_path("seed-privacy");
 
         seed_file(&path).unwrap();
         let text = fs::read_to_string(&path).unwrap();
@@ -0,0 +1,241 @@
+//! Hot reload of buckets.json and privacy-denylist.json.
+//!
+//! `BucketClassifier::load` / `PrivacyScrubber::load` run once at startup;
+//! this watches both files' parent directories (inotify on Linux, FSEvents
+//! on macOS, via `notify`) so an edit lands without a `systemctl --user
+//! restart` dropping the in-flight span. Directories rather than the files
+//! themselves, because most editors save by writing a temp file and
+//! renaming it over the original, which would orphan a file-level watch.
+//!
+//! A changed file is re-parsed strictly (`try_load`: no seeding, no
+//! built-in fallback). On success the new classifier/scrubber is parked
+//! here until logger_v4.rs swaps it in at the top of its next tick, so
+//! every span is classified by exactly one rule set. On failure the parse
+//! error is logged and nothing is parked -- the last-good rules stay live.
+
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use notify::{EventKind, RecursiveMode, Watcher};
+
+use crate::buckets::BucketClassifier;
+use crate::privacy::PrivacyScrubber;
+
+/// Editors often emit several events per save (truncate, write, rename,
+/// chmod); wait for this much quiet before re-parsing so a half-written
+/// file isn't reported as a parse error.
+const DEBOUNCE: Duration = Duration::from_millis(250);
+
+#[derive(Default)]
+struct PendingRules {
+    buckets: Option<BucketClassifier>,
+    privacy: Option<PrivacyScrubber>,
+}
+
+pub struct RulesWatcher {
+    pending: Arc<Mutex<PendingRules>>,
+    // Dropping the watcher closes the event channel, which ends the
+    // reload thread.
+    _watcher: notify::RecommendedWatcher,
+}
+
+impl RulesWatcher {
+    /// Starts watching. Returns `None` (after logging why) when the
+    /// platform watcher can't be created or a parent directory can't be
+    /// watched -- rules then just stay as loaded at startup.
+    pub fn spawn(bucket_path: &Path, privacy_path: &Path, audit_path: &Path) -> Option<Self> {
+        let (tx, rx) = mpsc::channel::<notify::Result<notify::Event>>();
+        let mut watcher = match notify::recommended_watcher(tx) {
+            Ok(watcher) => watcher,
+            Err(e) => {
+                println!("chronomaxi rules: hot reload unavailable ({e}), edits need a restart");
+                return None;
+            }
+        };
+
+        // A bare "buckets.json" has parent "", which can't be watched and
+        // never matches the absolute paths notify reports.
+        let bucket_path = absolute(bucket_path);
+        let privacy_path = absolute(privacy_path);
+        let mut dirs: Vec<PathBuf> = [&bucket_path, &privacy_path]
+            .iter()
+            .filter_map(|path| path.parent().map(Path::to_path_buf))
+            .collect();
+        dirs.dedup();
+        for dir in &dirs {
+            if let Err(e) = watcher.watch(dir, RecursiveMode::NonRecursive) {
+                println!(
+                    "chronomaxi rules: failed to watch {} ({e}), edits need a restart",
+                    dir.display()
+                );
+                return None;
+            }
+        }
+
+        let pending = Arc::new(Mutex::new(PendingRules::default()));
+        let thread_pending = Arc::clone(&pending);
+        let audit_path = audit_path.to_path_buf();
+
+        thread::Builder::new()
+            .name("cmx-rules-watch".to_string())
+            .spawn(move || reload_loop(rx, thread_pending, bucket_path, privacy_path, audit_path))
+            .ok()?;
+
+        Some(Self { pending, _watcher: watcher })
+    }
+
+    /// Takes the newest successfully-parsed bucket rules, if any arrived
+    /// since the last call.
+    pub fn take_buckets(&self) -> Option<BucketClassifier> {
+        self.pending.lock().ok().and_then(|mut guard| guard.buckets.take())
+    }
+
+    /// Takes the newest successfully-parsed privacy rules, if any arrived
+    /// since the last call.
+    pub fn take_privacy(&self) -> Option<PrivacyScrubber> {
+        self.pending.lock().ok().and_then(|mut guard| guard.privacy.take())
+    }
+}
+
+fn reload_loop(
+    rx: mpsc::Receiver<notify::Result<notify::Event>>,
+    pending: Arc<Mutex<PendingRules>>,
+    bucket_path: PathBuf,
+    privacy_path: PathBuf,
+    audit_path: PathBuf,
+) {
+    while let Ok(first) = rx.recv() {
+        let mut buckets_touched = false;
+        let mut privacy_touched = false;
+        let mut note = |event: notify::Result<notify::Event>| {
+            let Ok(event) = event else { return };
+            // Our own reads show up as access events; never reload on those.
+            if matches!(event.kind, EventKind::Access(_)) {
+                return;
+            }
+            buckets_touched |= event.paths.iter().any(|path| same_file(path, &bucket_path));
+            privacy_touched |= event.paths.iter().any(|path| same_file(path, &privacy_path));
+        };
+
+        note(first);
+        loop {
+            match rx.recv_timeout(DEBOUNCE) {
+                Ok(event) => note(event),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+
+        if buckets_touched {
+            match BucketClassifier::try_load(&bucket_path) {
+                Ok(classifier) => {
+                    println!("chronomaxi rules: reloaded {}", bucket_path.display());
+                    if let Ok(mut guard) = pending.lock() {
+                        guard.buckets = Some(classifier);
+                    }
+                }
+                Err(e) => println!(
+                    "chronomaxi rules: {} failed to parse ({e}), keeping last-good bucket rules",
+                    bucket_path.display()
+                ),
+            }
+        }
+
+        if privacy_touched {
+            match PrivacyScrubber::try_load(&privacy_path, &audit_path) {
+                Ok(scrubber) => {
+                    println!("chronomaxi rules: reloaded {}", privacy_path.display());
+                    if let Ok(mut guard) = pending.lock() {
+                        guard.privacy = Some(scrubber);
+                    }
+                }
+                Err(e) => println!(
+                    "chronomaxi rules: {} failed to parse ({e}), keeping last-good privacy rules",
+                    privacy_path.display()
+                ),
+            }
+        }
+    }
+}
+
+/// Event paths come back absolute under the watched directory; compare
+/// by (parent, file name) so a relative configured path still matches.
+/// `path` against the current directory; as given if that fails.
+fn absolute(path: &Path) -> PathBuf {
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn same_file(event_path: &Path, target: &Path) -> bool {
+    if event_path.file_name() != target.file_name() {
+        return false;
+    }
+    let canonical = |path: Option<&Path>| path.and_then(|dir| dir.canonicalize().ok());
+    canonical(event_path.parent()) == canonical(target.parent())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use std::fs;
+    use std::time::Instant;
+
+    fn wait_for<T>(mut probe: impl FnMut() -> Option<T>) -> Option<T> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if let Some(value) = probe() {
+                return Some(value);
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        None
+    }
+
+    const RULES: &str = r#"{"default_bucket":"other","rules":[{"bucket":"writing","program_patterns":["obsidian"]}]}"#;
+
+    #[test]
+    fn edited_bucket_file_is_swapped_in() {
+        let dir = test_support::temp_dir("rules-swap");
+        let buckets = dir.join("buckets.json");
+        let privacy = dir.join("privacy-denylist.json");
+        fs::write(&buckets, r#"{"default_bucket":"other","rules":[]}"#).unwrap();
+
+        let watcher = RulesWatcher::spawn(&buckets, &privacy, &dir.join("audit.log")).unwrap();
+        fs::write(&buckets, RULES).unwrap();
+
+        let classifier = wait_for(|| watcher.take_buckets()).expect("reloaded classifier");
+        assert_eq!(classifier.classify("obsidian", None, None, None), "writing");
+        assert!(watcher.take_privacy().is_none());
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn a_bare_file_name_is_watched_in_the_current_directory() {
+        let bucket_path = absolute(Path::new("buckets.json"));
+        assert_eq!(bucket_path.parent(), Some(std::env::current_dir().unwrap().as_path()));
+        assert!(same_file(&std::env::current_dir().unwrap().join("buckets.json"), &bucket_path));
+    }
+
+    #[test]
+    fn broken_edit_parks_nothing() {
+        let dir = test_support::temp_dir("rules-broken");
+        let buckets = dir.join("buckets.json");
+        let privacy = dir.join("privacy-denylist.json");
+        fs::write(&buckets, RULES).unwrap();
+
+        let watcher = RulesWatcher::spawn(&buckets, &privacy, &dir.join("audit.log")).unwrap();
+        fs::write(&buckets, "{ \"rules\": [ oops").unwrap();
+        thread::sleep(DEBOUNCE * 4);
+        assert!(watcher.take_buckets().is_none());
+
+        // A later fix still lands.
+        fs::write(&buckets, RULES).unwrap();
+        assert!(wait_for(|| watcher.take_buckets()).is_some());
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,338 @@
+//! Where spans end around suspend, screen lock and the session's idle
+//! hint. logind (crate::logind) reports those as `SessionEvent`s; a tick
+//! that arrives long after the previous one means the process was frozen
+//! through a suspend nobody reported. logger_v4 owns the capture and the
+//! spool and implements `SessionSpans`; the decisions are made here.
+//!
+//! A locked or idle-hinted session makes every span idle, with `locked` or
+//! `idle-hint` as its idleReason. A suspend becomes a gap row
+//! (crate::heartbeat) from the moment the machine went down. If logind's
+//! wake never arrives (the bus dropped across the suspend), the first tick
+//! after the stall stands in for it, and the watcher sends a synthetic
+//! wake when it reconnects.
+
+use chrono::{DateTime, Duration, Utc};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SessionChange {
+    Sleep,
+    Wake,
+    Lock,
+    Unlock,
+    /// The session's `IdleHint` flipped.
+    Idle(bool),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SessionEvent {
+    /// When the watcher saw the signal.
+    pub at: DateTime<Utc>,
+    pub change: SessionChange,
+}
+
+#[derive(Clone, Debug)]
+pub struct SessionState {
+    locked: bool,
+    idle_hint: bool,
+    /// Set between logind's sleep and wake signals.
+    asleep_since: Option<DateTime<Utc>>,
+    /// Start of the previous tick; a much older one means we were frozen.
+    last_tick: DateTime<Utc>,
+}
+
+impl SessionState {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self { locked: false, idle_hint: false, asleep_since: None, last_tick: now }
+    }
+
+    /// Why the session counts as idle regardless of input, if it does.
+    pub fn idle_reason(&self) -> Option<&'static str> {
+        if self.locked {
+            Some("locked")
+        } else if self.idle_hint {
+            Some("idle-hint")
+        } else {
+            None
+        }
+    }
+}
+
+/// The logger as seen from here: an open span that can be ended or
+/// restarted, and a spool for suspend gaps. Every new span must take its
+/// idleness from `SessionState::idle_reason`.
+pub trait SessionSpans {
+    fn session(&mut self) -> &mut SessionState;
+
+    /// Start of the span being extended, if any.
+    fn open_span_start(&self) -> Option<DateTime<Utc>>;
+
+    /// `gap_min_seconds`, or `None` when heartbeats are off and no gaps
+    /// are recorded.
+    fn gap_min(&self) -> Option<Duration>;
+
+    /// Spools the open span as ending at `at` and starts the next one.
+    fn end_span_at(&mut self, at: DateTime<Utc>) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Drops the open span unspooled and starts a fresh one, with no idle
+    /// time carried over.
+    fn restart_span(&mut self) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Spools `[from, to)` as a suspend gap.
+    fn spool_suspend(&mut self, from: DateTime<Utc>, to: DateTime<Utc>);
+
+    /// Counts as input now, so leaving a lock doesn't read as idle.
+    fn mark_active(&mut self);
+
+    /// Applies one logind event at the moment it happened: sleep ends the
+    /// span there and wake spools the time between as a suspend gap and
+    /// starts over; a lock, unlock or idle-hint flip ends the span there
+    /// so the next one is (or stops being) idle.
+    fn apply_session_event(&mut self, event: SessionEvent) -> Result<(), Box<dyn std::error::Error>> {
+        // Never before the start of the span it ends (an event drained late).
+        let at = self.open_span_start().map_or(event.at, |started| event.at.max(started));
+        let state = self.session().clone();
+        match event.change {
+            SessionChange::Sleep => {
+                if state.asleep_since.is_none() {
+                    self.end_span_at(at)?;
+                    self.session().asleep_since = Some(at);
+                }
+                Ok(())
+            }
+            SessionChange::Wake => self.wake(event.at),
+            SessionChange::Lock => self.set_session_state(at, true, state.idle_hint),
+            SessionChange::Unlock => self.set_session_state(at, false, state.idle_hint),
+            SessionChange::Idle(idle) => self.set_session_state(at, state.locked, idle),
+        }
+    }
+
+    /// Ends the span at `at` if the lock or idle hint actually changed.
+    fn set_session_state(
+        &mut self,
+        at: DateTime<Utc>,
+        locked: bool,
+        idle_hint: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let state = self.session();
+        if (locked, idle_hint) == (state.locked, state.idle_hint) {
+            return Ok(());
+        }
+        state.locked = locked;
+        state.idle_hint = idle_hint;
+        if !locked && !idle_hint {
+            self.mark_active();
+        }
+        self.end_span_at(at)
+    }
+
+    /// The end of a sleep at `at`. A wake without a sleep (a reconnecting
+    /// watcher re-seeding its state) changes nothing.
+    fn wake(&mut self, at: DateTime<Utc>) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(since) = self.session().asleep_since.take() else { return Ok(()) };
+        if self.gap_min().is_some() {
+            self.spool_suspend(since, at);
+        }
+        // Whatever was captured around the sleep spans it; drop it and
+        // start fresh, with no idle time carried over.
+        self.mark_active();
+        self.restart_span()?;
+        let state = self.session();
+        state.last_tick = state.last_tick.max(at);
+        Ok(())
+    }
+
+    /// A tick that starts `gap_min` after the previous one means the
+    /// process was frozen, which in practice is a suspend: the span ends
+    /// at the previous tick and the rest is a gap. Frozen after logind's
+    /// sleep, the wake was lost, and this tick is it.
+    fn check_for_stall(&mut self, now: DateTime<Utc>) -> Result<(), Box<dyn std::error::Error>> {
+        let previous = std::mem::replace(&mut self.session().last_tick, now);
+        let Some(gap_min) = self.gap_min() else { return Ok(()) };
+        if now - previous < gap_min {
+            return Ok(());
+        }
+        if self.session().asleep_since.is_some() {
+            return self.wake(now);
+        }
+        self.end_span_at(previous)?;
+        self.spool_suspend(previous, now);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heartbeat::{self, GapReason};
+    use crate::log::Log;
+    use crate::spool::{IngestRow, Spool};
+
+    const GAP_MIN: i64 = 60;
+
+    /// (start, length, idle, idleReason, subProgram) of a spooled row, in seconds.
+    type Row = (i64, i64, bool, Option<String>, Option<String>);
+
+    /// The logger with a fixed terminal window in focus and a real spool.
+    struct Timeline {
+        session: SessionState,
+        spool: Spool,
+        current_log: Option<Log>,
+        /// Stands in for `Utc::now()` when a span is captured.
+        clock: DateTime<Utc>,
+    }
+
+    impl Timeline {
+        fn new() -> Self {
+            let mut timeline =
+                Self { session: SessionState::new(t(0)), spool: Spool::open_in_memory().unwrap(), current_log: None, clock: t(0) };
+            timeline.current_log = Some(timeline.capture());
+            timeline
+        }
+
+        fn capture(&self) -> Log {
+            let idle_reason = self.session.idle_reason();
+            Log {
+                current_program_name: Some("kitty".to_string()),
+                sub_program: Some("nvim".to_string()),
+                created_at: Some(self.clock),
+                log_start_time_utc: Some(self.clock),
+                is_idle: idle_reason.is_some(),
+                idle_reason: idle_reason.map(str::to_string),
+                ..Log::new()
+            }
+        }
+
+        fn event(&mut self, seconds: i64, change: SessionChange) {
+            self.clock = t(seconds);
+            self.apply_session_event(SessionEvent { at: t(seconds), change }).unwrap();
+        }
+
+        fn tick(&mut self, seconds: i64) {
+            self.clock = t(seconds);
+            self.check_for_stall(t(seconds)).unwrap();
+        }
+
+        fn rows(&self) -> Vec<Row> {
+            let spans: Vec<IngestRow> = self.spool.spans_between(0, i64::MAX).unwrap();
+            spans
+                .into_iter()
+                .map(|row| {
+                    let start = (row.created_at - t(0).timestamp_millis()) / 1000;
+                    (start, row.duration_ms / 1000, row.is_idle, row.idle_reason, row.sub_program)
+                })
+                .collect()
+        }
+    }
+
+    impl SessionSpans for Timeline {
+        fn session(&mut self) -> &mut SessionState {
+            &mut self.session
+        }
+
+        fn open_span_start(&self) -> Option<DateTime<Utc>> {
+            self.current_log.as_ref().and_then(|log| log.log_start_time_utc)
+        }
+
+        fn gap_min(&self) -> Option<Duration> {
+            Some(Duration::seconds(GAP_MIN))
+        }
+
+        fn end_span_at(&mut self, at: DateTime<Utc>) -> Result<(), Box<dyn std::error::Error>> {
+            if let Some(log) = self.current_log.as_mut() {
+                log.log_end_time_utc = Some(at);
+                log.duration_ms = log.get_log_duration_ms();
+                self.spool.enqueue(log, "desk")?;
+            }
+            self.current_log = Some(self.capture());
+            Ok(())
+        }
+
+        fn restart_span(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+            self.current_log = Some(self.capture());
+            Ok(())
+        }
+
+        fn spool_suspend(&mut self, from: DateTime<Utc>, to: DateTime<Utc>) {
+            if to - from >= Duration::seconds(GAP_MIN) {
+                self.spool.enqueue_row(&heartbeat::gap_row(from, to, GapReason::Suspend, "desk", "human")).unwrap();
+            }
+        }
+
+        fn mark_active(&mut self) {}
+    }
+
+    fn t(seconds: i64) -> DateTime<Utc> {
+        DateTime::<Utc>::from_timestamp(1_792_103_400 + seconds, 0).unwrap()
+    }
+
+    fn span(start: i64, length: i64, idle_reason: Option<&str>) -> Row {
+        (start, length, idle_reason.is_some(), idle_reason.map(str::to_string), Some("nvim".to_string()))
+    }
+
+    fn gap(start: i64, length: i64) -> Row {
+        (start, length, true, Some("suspend".to_string()), None)
+    }
+
+    #[test]
+    fn lock_and_idle_hint_spans_carry_the_reason_and_keep_the_sub_program() {
+        let mut timeline = Timeline::new();
+        timeline.event(10, SessionChange::Lock);
+        timeline.event(20, SessionChange::Idle(true));
+        timeline.event(30, SessionChange::Unlock);
+        // Repeats (a reconnecting watcher re-seeding) end nothing.
+        timeline.event(35, SessionChange::Unlock);
+        timeline.event(40, SessionChange::Idle(false));
+
+        assert_eq!(
+            timeline.rows(),
+            vec![span(0, 10, None), span(10, 10, Some("locked")), span(20, 10, Some("locked")), span(30, 10, Some("idle-hint"))]
+        );
+        assert_eq!(timeline.current_log.as_ref().unwrap().idle_reason, None);
+    }
+
+    #[test]
+    fn sleep_and_wake_spool_a_suspend_gap_and_drop_what_spanned_it() {
+        let mut timeline = Timeline::new();
+        timeline.tick(1);
+        timeline.event(30, SessionChange::Sleep);
+        timeline.event(30 + 3600, SessionChange::Wake);
+        timeline.tick(30 + 3601);
+
+        assert_eq!(timeline.rows(), vec![span(0, 30, None), gap(30, 3600)]);
+        assert_eq!(timeline.open_span_start(), Some(t(30 + 3600)));
+    }
+
+    #[test]
+    fn a_stalled_tick_is_a_suspend_gap_from_the_previous_tick() {
+        let mut timeline = Timeline::new();
+        timeline.tick(1);
+        timeline.tick(2);
+        timeline.tick(2 + 600);
+
+        assert_eq!(timeline.rows(), vec![span(0, 2, None), gap(2, 600)]);
+        assert_eq!(timeline.open_span_start(), Some(t(602)));
+    }
+
+    #[test]
+    fn a_lost_wake_is_recovered_by_the_next_stalled_tick() {
+        let mut timeline = Timeline::new();
+        timeline.tick(1);
+        timeline.event(5, SessionChange::Sleep);
+        // The bus drops; PrepareForSleep(false) never arrives. Ticks around
+        // the sleep itself are not a wake.
+        timeline.tick(6);
+        timeline.tick(6 + 3600);
+
+        assert_eq!(timeline.rows(), vec![span(0, 5, None), gap(5, 3601)]);
+        assert!(timeline.session.asleep_since.is_none());
+
+        // Stall detection works again.
+        timeline.tick(6 + 3600 + 600);
+        assert!(timeline.rows().contains(&gap(6 + 3600, 600)));
+
+        // And the reconnected watcher's synthetic wake changes nothing.
+        let before = timeline.rows();
+        timeline.event(6 + 3600 + 601, SessionChange::Wake);
+        assert_eq!(timeline.rows(), before);
+    }
+}
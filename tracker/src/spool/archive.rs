@@ -0,0 +1,350 @@
+//! Long-term local history. The spool only keeps sent rows for
+//! `spool_retention_days`; with `archive_dir` set, the flusher rolls them
+//! into one sqlite file per month of span start (`spans-YYYY-MM.sqlite`,
+//! the spool's typed columns, indexed like it) right before pruning, so
+//! this machine keeps its complete history even while Convex is being
+//! rebuilt or is unreachable.
+//!
+//! `index.sqlite` next to them lists each month with its span count and
+//! first/last start, so a range query (`spans_between`) only opens the
+//! months it overlaps. Rolling is idempotent (`INSERT OR IGNORE` on
+//! sourceId), so a crash between archiving and pruning just archives the
+//! same rows again. Months older than `archive_retention_months` are
+//! deleted by `expire`; 0 keeps everything.
+
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Datelike, Utc};
+use rusqlite::{params, Connection, OpenFlags};
+
+use super::{row_from_columns, IngestRow, Spool, ROW_COLUMNS};
+
+const INDEX_FILE: &str = "index.sqlite";
+
+/// A month file's schema, created through the `month` attachment.
+const MONTH_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS month.spans (
+        sourceId TEXT PRIMARY KEY,
+        createdAt TEXT NOT NULL,
+        sentAt TEXT,
+        startedAt INTEGER NOT NULL,
+        durationMs INTEGER NOT NULL,
+        category TEXT NOT NULL,
+        isIdle INTEGER NOT NULL,
+        deviceName TEXT NOT NULL,
+        actor TEXT NOT NULL,
+        windowId TEXT NOT NULL,
+        programProcessName TEXT NOT NULL,
+        programName TEXT NOT NULL,
+        subProgram TEXT,
+        tmuxSession TEXT,
+        bucket TEXT,
+        browserTitle TEXT,
+        keysPressedCount INTEGER,
+        mouseMovementInMM REAL,
+        leftClickCount INTEGER,
+        rightClickCount INTEGER,
+        middleClickCount INTEGER,
+        tokensSpent REAL,
+        idleReason TEXT
+    );
+    CREATE INDEX IF NOT EXISTS month.idx_spans_started ON spans (startedAt);
+    CREATE INDEX IF NOT EXISTS month.idx_spans_bucket ON spans (bucket, startedAt);";
+
+/// One `index.sqlite` row as shown by `spool status`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchivedMonth {
+    /// "YYYY-MM", UTC.
+    pub month: String,
+    pub spans: i64,
+    /// Unix ms of the earliest and latest span start in the file.
+    pub first_started_at: i64,
+    pub last_started_at: i64,
+}
+
+pub struct Archive {
+    dir: PathBuf,
+    index: Connection,
+}
+
+impl Archive {
+    pub fn open(dir: &Path) -> rusqlite::Result<Self> {
+        std::fs::create_dir_all(dir).map_err(|e| {
+            rusqlite::Error::SqliteFailure(
+                rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_CANTOPEN),
+                Some(format!("failed to create {}: {e}", dir.display())),
+            )
+        })?;
+        let index = Connection::open(dir.join(INDEX_FILE))?;
+        index.pragma_update(None, "busy_timeout", 5000)?;
+        index.execute_batch(
+            "CREATE TABLE IF NOT EXISTS months (
+                month TEXT PRIMARY KEY,
+                file TEXT NOT NULL,
+                spans INTEGER NOT NULL,
+                firstStartedAt INTEGER NOT NULL,
+                lastStartedAt INTEGER NOT NULL,
+                updatedAt TEXT NOT NULL
+            )",
+        )?;
+        Ok(Self { dir: dir.to_path_buf(), index })
+    }
+
+    fn month_file(month: &str) -> String {
+        format!("spans-{month}.sqlite")
+    }
+
+    /// Copies the sent rows `prune_sent_older_than(cutoff)` would delete
+    /// into their month files and refreshes the index. Returns how many
+    /// were new to the archive.
+    pub fn roll(&self, spool: &Spool, cutoff: DateTime<Utc>) -> rusqlite::Result<usize> {
+        let cutoff = cutoff.to_rfc3339();
+        let months: Vec<String> = spool
+            .conn
+            .prepare(
+                "SELECT DISTINCT strftime('%Y-%m', startedAt / 1000, 'unixepoch') FROM spool
+                 WHERE sentAt IS NOT NULL AND createdAt < ?1",
+            )?
+            .query_map(params![cutoff], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+
+        let mut archived = 0;
+        for month in months {
+            let path = self.dir.join(Self::month_file(&month));
+            spool.conn.execute("ATTACH DATABASE ?1 AS month", params![path.to_string_lossy()])?;
+            let rolled = roll_month(&spool.conn, &month, &cutoff);
+            spool.conn.execute("DETACH DATABASE month", [])?;
+            let (inserted, entry) = rolled?;
+            archived += inserted;
+
+            self.index.execute(
+                "INSERT OR REPLACE INTO months (month, file, spans, firstStartedAt, lastStartedAt, updatedAt)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    entry.month,
+                    Self::month_file(&entry.month),
+                    entry.spans,
+                    entry.first_started_at,
+                    entry.last_started_at,
+                    Utc::now().to_rfc3339()
+                ],
+            )?;
+        }
+        Ok(archived)
+    }
+
+    /// Deletes the month files (and index rows) more than `keep_months`
+    /// before `now`'s month; the current month always counts as one.
+    /// Returns the months removed.
+    pub fn expire(&self, keep_months: u32, now: DateTime<Utc>) -> rusqlite::Result<Vec<String>> {
+        if keep_months == 0 {
+            return Ok(Vec::new());
+        }
+        let months_since_epoch = now.year() as i64 * 12 + now.month0() as i64 - (keep_months as i64 - 1);
+        let oldest_kept = format!("{:04}-{:02}", months_since_epoch.div_euclid(12), months_since_epoch.rem_euclid(12) + 1);
+
+        let expired: Vec<(String, String)> = self
+            .index
+            .prepare("SELECT month, file FROM months WHERE month < ?1 ORDER BY month")?
+            .query_map(params![oldest_kept], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+        for (month, file) in &expired {
+            match std::fs::remove_file(self.dir.join(file)) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    println!("chronomaxi archive: failed to remove {file}: {e}, keeping it indexed");
+                    continue;
+                }
+            }
+            self.index.execute("DELETE FROM months WHERE month = ?1", params![month])?;
+        }
+        Ok(expired.into_iter().map(|(month, _)| month).collect())
+    }
+
+    /// Every indexed month, oldest first.
+    pub fn months(&self) -> rusqlite::Result<Vec<ArchivedMonth>> {
+        self.index
+            .prepare("SELECT month, spans, firstStartedAt, lastStartedAt FROM months ORDER BY month")?
+            .query_map([], |row| {
+                Ok(ArchivedMonth {
+                    month: row.get(0)?,
+                    spans: row.get(1)?,
+                    first_started_at: row.get(2)?,
+                    last_started_at: row.get(3)?,
+                })
+            })?
+            .collect()
+    }
+
+    /// Archived spans starting in `[from_ms, to_ms)`, by start time. Only
+    /// the month files the index says overlap are opened.
+    pub fn spans_between(&self, from_ms: i64, to_ms: i64) -> rusqlite::Result<Vec<IngestRow>> {
+        let files: Vec<String> = self
+            .index
+            .prepare("SELECT file FROM months WHERE lastStartedAt >= ?1 AND firstStartedAt < ?2 ORDER BY month")?
+            .query_map(params![from_ms, to_ms], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+
+        let mut spans = Vec::new();
+        for file in files {
+            let conn = Connection::open_with_flags(self.dir.join(&file), OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+            let columns = if has_idle_reason(&conn, "main")? {
+                ROW_COLUMNS.to_string()
+            } else {
+                ROW_COLUMNS.replace("idleReason", "NULL")
+            };
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {columns} FROM spans WHERE startedAt >= ?1 AND startedAt < ?2 ORDER BY startedAt"
+            ))?;
+            let rows = stmt.query_map(params![from_ms, to_ms], |row| Ok(row_from_columns(row, 0)))?;
+            for row in rows {
+                match row? {
+                    Ok(span) => spans.push(span),
+                    Err(e) => eprintln!("chronomaxi archive: skipping unreadable span in {file}: {e}"),
+                }
+            }
+        }
+        Ok(spans)
+    }
+}
+
+/// Month files rolled before `idleReason` existed don't have the column.
+fn has_idle_reason(conn: &Connection, schema: &str) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "SELECT COUNT(*) FROM pragma_table_info('spans', ?1) WHERE name = 'idleReason'",
+        params![schema],
+        |row| row.get::<_, i64>(0),
+    )
+    .map(|found| found > 0)
+}
+
+/// Copies one month's prunable rows through the `month` attachment;
+/// returns the new rows and the month's index entry.
+fn roll_month(conn: &Connection, month: &str, cutoff: &str) -> rusqlite::Result<(usize, ArchivedMonth)> {
+    conn.execute_batch(MONTH_SCHEMA)?;
+    if !has_idle_reason(conn, "month")? {
+        conn.execute_batch("ALTER TABLE month.spans ADD COLUMN idleReason TEXT")?;
+    }
+    let inserted = conn.execute(
+        &format!(
+            "INSERT OR IGNORE INTO month.spans (createdAt, sentAt, {ROW_COLUMNS})
+             SELECT createdAt, sentAt, {ROW_COLUMNS} FROM main.spool
+             WHERE sentAt IS NOT NULL AND createdAt < ?1
+               AND strftime('%Y-%m', startedAt / 1000, 'unixepoch') = ?2"
+        ),
+        params![cutoff, month],
+    )?;
+    let entry = conn.query_row(
+        "SELECT COUNT(*), MIN(startedAt), MAX(startedAt) FROM month.spans",
+        [],
+        |row| {
+            Ok(ArchivedMonth {
+                month: month.to_string(),
+                spans: row.get(0)?,
+                first_started_at: row.get(1)?,
+                last_started_at: row.get(2)?,
+            })
+        },
+    )?;
+    Ok((inserted, entry))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use chrono::TimeZone;
+
+    fn sent_span(spool: &Spool, started_at: DateTime<Utc>, program: &str) {
+        let mut log = crate::log::Log::new();
+        log.created_at = Some(started_at);
+        log.duration_ms = Some(60_000);
+        log.current_program_name = Some(program.to_string());
+        spool.enqueue(&log, "desk").unwrap();
+        let id = spool.list(1, true).unwrap().remove(0).source_id;
+        spool.mark_sent(&[id]).unwrap();
+    }
+
+    #[test]
+    fn sent_rows_roll_into_indexed_month_files_before_pruning() {
+        let dir = test_support::temp_path("archive-roll");
+        let spool = Spool::open_in_memory().unwrap();
+        let archive = Archive::open(&dir).unwrap();
+        let september = Utc.with_ymd_and_hms(2026, 9, 30, 23, 0, 0).unwrap();
+        let october = Utc.with_ymd_and_hms(2026, 10, 1, 9, 0, 0).unwrap();
+        sent_span(&spool, september, "nvim");
+        sent_span(&spool, october, "firefox");
+        spool.enqueue(&crate::log::Log::new(), "desk").unwrap();
+
+        let cutoff = Utc::now() + chrono::Duration::days(1);
+        assert_eq!(archive.roll(&spool, cutoff).unwrap(), 2);
+        // Rolling again (say, after a crash before the prune) adds nothing.
+        assert_eq!(archive.roll(&spool, cutoff).unwrap(), 0);
+        assert_eq!(spool.prune_sent_older_than(cutoff).unwrap(), 2);
+        assert_eq!(spool.pending_count().unwrap(), 1, "pending rows are never archived or pruned");
+
+        let months = archive.months().unwrap();
+        assert_eq!(months.iter().map(|m| (m.month.as_str(), m.spans)).collect::<Vec<_>>(), [("2026-09", 1), ("2026-10", 1)]);
+        assert!(dir.join("spans-2026-09.sqlite").exists());
+
+        let all = archive.spans_between(0, i64::MAX).unwrap();
+        assert_eq!(all.iter().map(|span| span.program_name.as_str()).collect::<Vec<_>>(), ["nvim", "firefox"]);
+        let october_only = archive.spans_between(october.timestamp_millis(), i64::MAX).unwrap();
+        assert_eq!(october_only.len(), 1);
+        assert_eq!(october_only[0].created_at, october.timestamp_millis());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn month_files_from_before_idle_reason_are_read_and_upgraded_on_the_next_roll() {
+        let dir = test_support::temp_path("archive-idle-reason");
+        let spool = Spool::open_in_memory().unwrap();
+        let archive = Archive::open(&dir).unwrap();
+        let started = Utc.with_ymd_and_hms(2026, 9, 1, 9, 0, 0).unwrap();
+        sent_span(&spool, started, "nvim");
+        let cutoff = Utc::now() + chrono::Duration::days(1);
+        archive.roll(&spool, cutoff).unwrap();
+        Connection::open(dir.join("spans-2026-09.sqlite"))
+            .unwrap()
+            .execute_batch("ALTER TABLE spans DROP COLUMN idleReason")
+            .unwrap();
+        assert_eq!(archive.spans_between(0, i64::MAX).unwrap().len(), 1);
+
+        let gap = crate::heartbeat::gap_row(
+            started + chrono::Duration::hours(1),
+            started + chrono::Duration::hours(2),
+            crate::heartbeat::GapReason::Suspend,
+            "desk",
+            "human",
+        );
+        spool.enqueue_row(&gap).unwrap();
+        spool.mark_sent(std::slice::from_ref(&gap.source_id)).unwrap();
+        assert_eq!(archive.roll(&spool, cutoff).unwrap(), 1);
+        let reasons: Vec<Option<String>> =
+            archive.spans_between(0, i64::MAX).unwrap().into_iter().map(|span| span.idle_reason).collect();
+        assert_eq!(reasons, [None, Some("suspend".to_string())]);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn months_past_retention_are_deleted() {
+        let dir = test_support::temp_path("archive-expire");
+        let spool = Spool::open_in_memory().unwrap();
+        let archive = Archive::open(&dir).unwrap();
+        for month in [11, 12] {
+            sent_span(&spool, Utc.with_ymd_and_hms(2025, month, 15, 12, 0, 0).unwrap(), "nvim");
+        }
+        sent_span(&spool, Utc.with_ymd_and_hms(2026, 1, 15, 12, 0, 0).unwrap(), "nvim");
+        archive.roll(&spool, Utc::now() + chrono::Duration::days(1)).unwrap();
+
+        let now = Utc.with_ymd_and_hms(2026, 1, 20, 0, 0, 0).unwrap();
+        assert!(archive.expire(0, now).unwrap().is_empty(), "0 keeps everything");
+        assert_eq!(archive.expire(2, now).unwrap(), ["2025-11"]);
+        assert!(!dir.join("spans-2025-11.sqlite").exists());
+        assert_eq!(archive.months().unwrap().len(), 2);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,13 @@
+-- baseline.sql with its first pending row's category hand-edited to one
+-- the baseline Category enum never had, which step 4 must set aside.
+CREATE TABLE spool (
+    sourceId TEXT PRIMARY KEY,
+    payload TEXT NOT NULL,
+    createdAt TEXT NOT NULL,
+    sentAt TEXT
+);
+CREATE INDEX idx_spool_pending ON spool (sentAt, createdAt);
+INSERT INTO spool VALUES
+    ('01M5445SJ346SZ56VTXNPRYC65', '{"sourceId":"01M5445SJ346SZ56VTXNPRYC65","createdAt":1792213354307,"durationMs":40000,"category":"Coding","isIdle":false,"deviceName":"big-bertha","actor":"human","windowId":"0x3a00007","programProcessName":"alacritty","programName":"Alacritty","subProgram":"nvim","tmuxSession":"chronomaxi","bucket":"chronomaxi","keysPressedCount":120,"mouseMovementInMM":14.5,"leftClickCount":2,"rightClickCount":0,"middleClickCount":0}', '2026-10-17T05:08:26.307843354+00:00', '2026-10-17T05:08:26.318380260+00:00'),
+    ('01M5445SJ8DS7H1393EAB6HHS3', '{"sourceId":"01M5445SJ8DS7H1393EAB6HHS3","createdAt":1792213394307,"durationMs":12000,"category":"Browsing","isIdle":false,"deviceName":"big-bertha","actor":"human","windowId":"0x4200003","programProcessName":"firefox","programName":"Firefox","browserTitle":"rusqlite - Rust","keysPressedCount":0,"mouseMovementInMM":88.25,"leftClickCount":3,"rightClickCount":0,"middleClickCount":1}', '2026-10-17T05:08:26.313031771+00:00', NULL),
+    ('01M5445SJEGZQCK2JRVSJNPD9G', '{"sourceId":"01M5445SJEGZQCK2JRVSJNPD9G","createdAt":1792213406307,"durationMs":300000,"category":"Other","isIdle":true,"deviceName":"big-bertha","actor":"human","windowId":"0x4200003","programProcessName":"firefox","programName":"Firefox","browserTitle":"rusqlite - Rust","keysPressedCount":0,"mouseMovementInMM":0.0,"leftClickCount":0,"rightClickCount":0,"middleClickCount":0}', '2026-10-17T05:08:26.318252914+00:00', NULL);
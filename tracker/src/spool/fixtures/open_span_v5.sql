@@ -0,0 +1,68 @@
+-- Version 5: columns_v4.sql plus the open_span table, before the
+-- idleReason column existed.
+CREATE TABLE sink_sent (
+    sink TEXT NOT NULL,
+    sourceId TEXT NOT NULL,
+    sentAt TEXT NOT NULL,
+    PRIMARY KEY (sink, sourceId)
+);
+CREATE INDEX idx_sink_sent_source ON sink_sent (sourceId);
+CREATE TABLE delivery_failures (
+    sink TEXT NOT NULL,
+    sourceId TEXT NOT NULL,
+    attempts INTEGER NOT NULL,
+    lastError TEXT NOT NULL,
+    httpStatus INTEGER,
+    lastAttemptAt TEXT NOT NULL,
+    PRIMARY KEY (sink, sourceId)
+);
+CREATE TABLE dead_letter (
+    sink TEXT NOT NULL,
+    sourceId TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    createdAt TEXT NOT NULL,
+    attempts INTEGER NOT NULL,
+    lastError TEXT NOT NULL,
+    httpStatus INTEGER,
+    deadAt TEXT NOT NULL,
+    PRIMARY KEY (sink, sourceId)
+);
+CREATE TABLE spool (
+    sourceId TEXT PRIMARY KEY,
+    createdAt TEXT NOT NULL,
+    sentAt TEXT,
+    startedAt INTEGER NOT NULL,
+    durationMs INTEGER NOT NULL,
+    category TEXT NOT NULL,
+    isIdle INTEGER NOT NULL,
+    deviceName TEXT NOT NULL,
+    actor TEXT NOT NULL,
+    windowId TEXT NOT NULL,
+    programProcessName TEXT NOT NULL,
+    programName TEXT NOT NULL,
+    subProgram TEXT,
+    tmuxSession TEXT,
+    bucket TEXT,
+    browserTitle TEXT,
+    keysPressedCount INTEGER,
+    mouseMovementInMM REAL,
+    leftClickCount INTEGER,
+    rightClickCount INTEGER,
+    middleClickCount INTEGER,
+    tokensSpent REAL
+);
+CREATE INDEX idx_spool_pending ON spool (sentAt, createdAt);
+CREATE INDEX idx_spool_started ON spool (startedAt);
+CREATE INDEX idx_spool_bucket ON spool (bucket, startedAt);
+INSERT INTO spool VALUES
+    ('01HZX0AAAAAAAAAAAAAAAAAAAA', '2024-06-01T00:00:40+00:00', '2024-06-01T00:00:41+00:00', 1717200000000, 40000, 'Coding', 0, 'big-bertha', 'human', '0x1', 'alacritty', 'Alacritty', NULL, NULL, NULL, NULL, 120, NULL, NULL, NULL, NULL, NULL),
+    ('01HZX0BBBBBBBBBBBBBBBBBBBB', '2024-06-01T00:00:52+00:00', NULL, 1717200040000, 12000, 'Research', 0, 'big-bertha', 'human', '0x2', 'firefox', 'Firefox', NULL, NULL, NULL, NULL, NULL, NULL, NULL, NULL, NULL, NULL),
+    ('01HZX0CCCCCCCCCCCCCCCCCCCC', '2024-06-01T00:05:52+00:00', NULL, 1717200052000, 300000, 'Other', 1, 'big-bertha', 'human', '0x2', 'firefox', 'Firefox', NULL, NULL, NULL, NULL, NULL, NULL, NULL, NULL, NULL, NULL);
+INSERT INTO sink_sent VALUES ('jsonl:/tmp/spans.jsonl', '01HZX0BBBBBBBBBBBBBBBBBBBB', '2024-06-01T00:00:53+00:00');
+INSERT INTO delivery_failures VALUES ('jsonl:/tmp/spans.jsonl', '01HZX0CCCCCCCCCCCCCCCCCCCC', 1, 'jsonl sink failed to write', NULL, '2024-06-01T00:05:53+00:00');
+CREATE TABLE open_span (
+    slot INTEGER PRIMARY KEY CHECK (slot = 1),
+    payload TEXT NOT NULL,
+    savedAt TEXT NOT NULL
+);
+PRAGMA user_version = 5;
@@ -0,0 +1,43 @@
+-- Version 3: the same layout as dead_letter.sql, now stamped with
+-- user_version; the last spool that stored JSON payloads.
+CREATE TABLE spool (
+    sourceId TEXT PRIMARY KEY,
+    payload TEXT NOT NULL,
+    createdAt TEXT NOT NULL,
+    sentAt TEXT
+);
+CREATE INDEX idx_spool_pending ON spool (sentAt, createdAt);
+CREATE TABLE sink_sent (
+    sink TEXT NOT NULL,
+    sourceId TEXT NOT NULL,
+    sentAt TEXT NOT NULL,
+    PRIMARY KEY (sink, sourceId)
+);
+CREATE INDEX idx_sink_sent_source ON sink_sent (sourceId);
+CREATE TABLE delivery_failures (
+    sink TEXT NOT NULL,
+    sourceId TEXT NOT NULL,
+    attempts INTEGER NOT NULL,
+    lastError TEXT NOT NULL,
+    httpStatus INTEGER,
+    lastAttemptAt TEXT NOT NULL,
+    PRIMARY KEY (sink, sourceId)
+);
+CREATE TABLE dead_letter (
+    sink TEXT NOT NULL,
+    sourceId TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    createdAt TEXT NOT NULL,
+    attempts INTEGER NOT NULL,
+    lastError TEXT NOT NULL,
+    httpStatus INTEGER,
+    deadAt TEXT NOT NULL,
+    PRIMARY KEY (sink, sourceId)
+);
+INSERT INTO spool VALUES
+    ('01HZX0AAAAAAAAAAAAAAAAAAAA', '{"sourceId":"01HZX0AAAAAAAAAAAAAAAAAAAA","createdAt":1717200000000,"durationMs":40000,"category":"Coding","isIdle":false,"deviceName":"big-bertha","actor":"human","windowId":"0x1","programProcessName":"alacritty","programName":"Alacritty","keysPressedCount":120}', '2024-06-01T00:00:40+00:00', '2024-06-01T00:00:41+00:00'),
+    ('01HZX0BBBBBBBBBBBBBBBBBBBB', '{"sourceId":"01HZX0BBBBBBBBBBBBBBBBBBBB","createdAt":1717200040000,"durationMs":12000,"category":"Research","isIdle":false,"deviceName":"big-bertha","actor":"human","windowId":"0x2","programProcessName":"firefox","programName":"Firefox"}', '2024-06-01T00:00:52+00:00', NULL),
+    ('01HZX0CCCCCCCCCCCCCCCCCCCC', '{"sourceId":"01HZX0CCCCCCCCCCCCCCCCCCCC","createdAt":1717200052000,"durationMs":300000,"category":"Other","isIdle":true,"deviceName":"big-bertha","actor":"human","windowId":"0x2","programProcessName":"firefox","programName":"Firefox"}', '2024-06-01T00:05:52+00:00', NULL);
+INSERT INTO sink_sent VALUES ('jsonl:/tmp/spans.jsonl', '01HZX0BBBBBBBBBBBBBBBBBBBB', '2024-06-01T00:00:53+00:00');
+INSERT INTO delivery_failures VALUES ('jsonl:/tmp/spans.jsonl', '01HZX0CCCCCCCCCCCCCCCCCCCC', 1, 'jsonl sink failed to write', NULL, '2024-06-01T00:05:53+00:00');
+PRAGMA user_version = 3;
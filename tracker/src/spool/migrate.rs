@@ -0,0 +1,290 @@
+//! Spool schema versioning via `PRAGMA user_version`.
+//!
+//! `MIGRATIONS[i]` takes a database from version `i` to `i + 1`; `open`
+//! runs whatever steps are missing, each in its own `BEGIN IMMEDIATE`
+//! transaction together with the version bump, so a crash mid-upgrade
+//! leaves the previous version intact and two processes opening the same
+//! spool at once can't both apply a step. Steps are forward-only: never
+//! edit one that has shipped, append a new one instead, and add a fixture
+//! for the version it upgrades from (see the tests).
+//!
+//! Spools written before versioning existed are all `user_version` 0 but
+//! may already hold some of the tables of steps 1-3 (they were created with
+//! `IF NOT EXISTS` on every open), which is why those steps keep that
+//! guard. A spool newer than this binary is refused rather than written to.
+
+use rusqlite::{Connection, TransactionBehavior};
+
+/// Dead-letter `sink` for pending rows step 4 couldn't turn into columns;
+/// `dead-letter requeue` sends a repaired one to every sink.
+pub const MIGRATION_SINK: &str = "spool-migration";
+
+/// One forward step: the SQL that upgrades the previous version.
+const MIGRATIONS: [&str; 6] = [
+    // 1: the original contract table.
+    "CREATE TABLE IF NOT EXISTS spool (
+        sourceId TEXT PRIMARY KEY,
+        payload TEXT NOT NULL,
+        createdAt TEXT NOT NULL,
+        sentAt TEXT
+    );
+    CREATE INDEX IF NOT EXISTS idx_spool_pending ON spool (sentAt, createdAt);",
+    // 2: per-sink delivery of still-pending rows (crate::ingest::sink). A
+    // row's entries are dropped once every configured sink has it and
+    // `spool.sentAt` is set.
+    "CREATE TABLE IF NOT EXISTS sink_sent (
+        sink TEXT NOT NULL,
+        sourceId TEXT NOT NULL,
+        sentAt TEXT NOT NULL,
+        PRIMARY KEY (sink, sourceId)
+    );
+    CREATE INDEX IF NOT EXISTS idx_sink_sent_source ON sink_sent (sourceId);",
+    // 3: rejection bookkeeping and the dead-letter table.
+    "CREATE TABLE IF NOT EXISTS delivery_failures (
+        sink TEXT NOT NULL,
+        sourceId TEXT NOT NULL,
+        attempts INTEGER NOT NULL,
+        lastError TEXT NOT NULL,
+        httpStatus INTEGER,
+        lastAttemptAt TEXT NOT NULL,
+        PRIMARY KEY (sink, sourceId)
+    );
+    CREATE TABLE IF NOT EXISTS dead_letter (
+        sink TEXT NOT NULL,
+        sourceId TEXT NOT NULL,
+        payload TEXT NOT NULL,
+        createdAt TEXT NOT NULL,
+        attempts INTEGER NOT NULL,
+        lastError TEXT NOT NULL,
+        httpStatus INTEGER,
+        deadAt TEXT NOT NULL,
+        PRIMARY KEY (sink, sourceId)
+    );",
+    // 4: the JSON payload becomes typed columns (crate::spool::IngestRow).
+    // A pending payload that doesn't parse into them is set aside as a
+    // MIGRATION_SINK dead letter with its JSON intact; an unparsable row
+    // that was already sent is just dropped.
+    "CREATE TEMP TABLE spool_valid AS
+        SELECT sourceId, CASE WHEN json_valid(payload) THEN
+            json_type(payload, '$.sourceId') = 'text' AND json_extract(payload, '$.sourceId') = sourceId
+            AND json_type(payload, '$.createdAt') = 'integer'
+            AND json_type(payload, '$.durationMs') = 'integer'
+            AND json_extract(payload, '$.category') IN ('Coding', 'Entertainment', 'Communication', 'Research', 'Other')
+            AND json_type(payload, '$.isIdle') IN ('true', 'false')
+            AND json_type(payload, '$.deviceName') = 'text'
+            AND json_type(payload, '$.actor') = 'text'
+            AND json_type(payload, '$.windowId') = 'text'
+            AND json_type(payload, '$.programProcessName') = 'text'
+            AND json_type(payload, '$.programName') = 'text'
+        ELSE 0 END AS ok
+        FROM spool;
+    INSERT OR REPLACE INTO dead_letter (sink, sourceId, payload, createdAt, attempts, lastError, httpStatus, deadAt)
+        SELECT 'spool-migration', s.sourceId, s.payload, s.createdAt, 0,
+            'set aside by spool schema migration 4: payload is not a valid span', NULL,
+            strftime('%Y-%m-%dT%H:%M:%S+00:00', 'now')
+        FROM spool s JOIN spool_valid v ON v.sourceId = s.sourceId
+        WHERE NOT v.ok AND s.sentAt IS NULL;
+    CREATE TABLE spool_new (
+        sourceId TEXT PRIMARY KEY,
+        createdAt TEXT NOT NULL,
+        sentAt TEXT,
+        startedAt INTEGER NOT NULL,
+        durationMs INTEGER NOT NULL,
+        category TEXT NOT NULL,
+        isIdle INTEGER NOT NULL,
+        deviceName TEXT NOT NULL,
+        actor TEXT NOT NULL,
+        windowId TEXT NOT NULL,
+        programProcessName TEXT NOT NULL,
+        programName TEXT NOT NULL,
+        subProgram TEXT,
+        tmuxSession TEXT,
+        bucket TEXT,
+        browserTitle TEXT,
+        keysPressedCount INTEGER,
+        mouseMovementInMM REAL,
+        leftClickCount INTEGER,
+        rightClickCount INTEGER,
+        middleClickCount INTEGER,
+        tokensSpent REAL
+    );
+    INSERT INTO spool_new
+        SELECT s.sourceId, s.createdAt, s.sentAt,
+            json_extract(payload, '$.createdAt'), json_extract(payload, '$.durationMs'),
+            json_extract(payload, '$.category'), json_extract(payload, '$.isIdle'),
+            json_extract(payload, '$.deviceName'), json_extract(payload, '$.actor'),
+            json_extract(payload, '$.windowId'), json_extract(payload, '$.programProcessName'),
+            json_extract(payload, '$.programName'), json_extract(payload, '$.subProgram'),
+            json_extract(payload, '$.tmuxSession'), json_extract(payload, '$.bucket'),
+            json_extract(payload, '$.browserTitle'), json_extract(payload, '$.keysPressedCount'),
+            json_extract(payload, '$.mouseMovementInMM'), json_extract(payload, '$.leftClickCount'),
+            json_extract(payload, '$.rightClickCount'), json_extract(payload, '$.middleClickCount'),
+            json_extract(payload, '$.tokensSpent')
+        FROM spool s JOIN spool_valid v ON v.sourceId = s.sourceId
+        WHERE v.ok;
+    DELETE FROM sink_sent WHERE sourceId NOT IN (SELECT sourceId FROM spool_new);
+    DELETE FROM delivery_failures WHERE sourceId NOT IN (SELECT sourceId FROM spool_new);
+    DROP TABLE spool_valid;
+    DROP TABLE spool;
+    ALTER TABLE spool_new RENAME TO spool;
+    CREATE INDEX idx_spool_pending ON spool (sentAt, createdAt);
+    CREATE INDEX idx_spool_started ON spool (startedAt);
+    CREATE INDEX idx_spool_bucket ON spool (bucket, startedAt);",
+    // 5: the span capture hasn't finished yet, as wire JSON, rewritten
+    // every few seconds and moved into `spool` when it ends or on the next
+    // start after a crash. At most one row.
+    "CREATE TABLE open_span (
+        slot INTEGER PRIMARY KEY CHECK (slot = 1),
+        payload TEXT NOT NULL,
+        savedAt TEXT NOT NULL
+    );",
+    // 6: `IngestRow::idle_reason`, NULL for every row written before it.
+    "ALTER TABLE spool ADD COLUMN idleReason TEXT;",
+];
+
+/// Schema version this binary reads and writes.
+pub const CURRENT_VERSION: u32 = MIGRATIONS.len() as u32;
+
+pub fn user_version(conn: &Connection) -> rusqlite::Result<u32> {
+    conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+}
+
+/// Brings `conn` up to `CURRENT_VERSION`. Returns the version it started at.
+pub fn migrate(conn: &mut Connection) -> rusqlite::Result<u32> {
+    let started_at = user_version(conn)?;
+    loop {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        // Re-read under the write lock: another connection may have just
+        // applied this step.
+        let version = user_version(&tx)?;
+        if version > CURRENT_VERSION {
+            return Err(rusqlite::Error::SqliteFailure(
+                rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_ERROR),
+                Some(format!("spool schema version {version} is newer than this tracker supports ({CURRENT_VERSION})")),
+            ));
+        }
+        if version == CURRENT_VERSION {
+            tx.commit()?;
+            break;
+        }
+
+        tx.execute_batch(MIGRATIONS[version as usize])?;
+        tx.pragma_update(None, "user_version", version + 1)?;
+        tx.commit()?;
+    }
+    // Version 0 is a fresh file or a pre-versioning spool; not worth a line.
+    if started_at > 0 && started_at < CURRENT_VERSION {
+        println!("chronomaxi spool: migrated schema from version {started_at} to {CURRENT_VERSION}");
+    }
+    Ok(started_at)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::category::Category;
+    use crate::spool::Spool;
+
+    /// Spools as they were left by each earlier tracker, oldest first, with
+    /// the version they report: (name, user_version, sql).
+    const FIXTURES: [(&str, u32, &str); 6] = [
+        ("baseline (no sink tables)", 0, include_str!("fixtures/baseline.sql")),
+        ("routed sinks", 0, include_str!("fixtures/sink_sent.sql")),
+        ("dead letters", 0, include_str!("fixtures/dead_letter.sql")),
+        ("JSON payloads", 3, include_str!("fixtures/payload_v3.sql")),
+        ("typed columns", 4, include_str!("fixtures/columns_v4.sql")),
+        ("open span", 5, include_str!("fixtures/open_span_v5.sql")),
+    ];
+
+    fn temp_spool(label: &str) -> std::path::PathBuf {
+        crate::test_support::temp_path(&format!("migrate-{label}"))
+    }
+
+    #[test]
+    fn every_historical_spool_migrates_to_the_current_version() {
+        for (index, (name, version, sql)) in FIXTURES.iter().enumerate() {
+            let path = temp_spool(&index.to_string());
+            Connection::open(&path).unwrap().execute_batch(sql).unwrap();
+            assert_eq!(user_version(&Connection::open(&path).unwrap()).unwrap(), *version, "{name}");
+
+            let mut spool = Spool::open(&path).unwrap_or_else(|e| panic!("{name}: {e}"));
+            assert_eq!(user_version(&Connection::open(&path).unwrap()).unwrap(), CURRENT_VERSION, "{name}");
+
+            // Every fixture holds one sent and two pending rows; nothing is
+            // lost and the new tables work.
+            assert_eq!(spool.sent_count().unwrap(), 1, "{name}");
+            assert_eq!(spool.pending_count().unwrap(), 2, "{name}");
+            let sinks = vec!["convex".to_string()];
+            let pending = spool.claim_batch_for("convex", 10).unwrap();
+            assert_eq!(pending.len(), 2, "{name}");
+            let first = pending[0].1.as_ref().unwrap_or_else(|e| panic!("{name}: {e}"));
+            assert_eq!((&first.category, first.program_name.as_str()), (&Category::Research, "Firefox"), "{name}");
+            spool.record_failure("convex", &[pending[0].0.clone()], "rejected", Some(400), 1, &sinks).unwrap();
+            assert_eq!(spool.dead_letter_count().unwrap(), 1, "{name}");
+            spool.mark_sent_for("convex", &[pending[1].0.clone()], &sinks).unwrap();
+            assert_eq!(spool.pending_count().unwrap(), 0, "{name}");
+
+            // Re-opening is a no-op.
+            drop(spool);
+            let mut conn = Connection::open(&path).unwrap();
+            assert_eq!(migrate(&mut conn).unwrap(), CURRENT_VERSION, "{name}");
+
+            let _ = std::fs::remove_file(&path);
+        }
+    }
+
+    #[test]
+    fn payloads_that_are_not_spans_are_set_aside_by_the_column_migration() {
+        let path = temp_spool("columns");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(FIXTURES[3].2).unwrap();
+        conn.execute_batch(
+            "INSERT INTO spool VALUES
+                ('01HZX0DDDDDDDDDDDDDDDDDDDD', 'not json', '2024-06-01T00:06:00+00:00', NULL),
+                ('01HZX0EEEEEEEEEEEEEEEEEEEE', '{\"sourceId\":\"01HZX0EEEEEEEEEEEEEEEEEEEE\"}', '2024-06-01T00:06:01+00:00', '2024-06-01T00:06:02+00:00');
+             INSERT INTO sink_sent VALUES ('convex', '01HZX0EEEEEEEEEEEEEEEEEEEE', '2024-06-01T00:06:02+00:00');",
+        )
+        .unwrap();
+        drop(conn);
+
+        let spool = Spool::open(&path).unwrap();
+        assert_eq!((spool.sent_count().unwrap(), spool.pending_count().unwrap()), (1, 2));
+        let letters = spool.dead_letters_for("01HZX0DDDDDDDDDDDDDDDDDDDD").unwrap();
+        assert_eq!(letters.len(), 1);
+        assert_eq!((letters[0].sink.as_str(), letters[0].payload.as_str()), (MIGRATION_SINK, "not json"));
+        // The sent one is gone, bookkeeping included.
+        assert!(spool.dead_letters_for("01HZX0EEEEEEEEEEEEEEEEEEEE").unwrap().is_empty());
+        let conn = Connection::open(&path).unwrap();
+        let orphans: i64 = conn.query_row("SELECT COUNT(*) FROM sink_sent WHERE sourceId LIKE '01HZX0E%'", [], |row| row.get(0)).unwrap();
+        assert_eq!(orphans, 0);
+        drop(spool);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn a_baseline_row_with_an_unknown_category_becomes_a_migration_dead_letter() {
+        let path = temp_spool("category");
+        Connection::open(&path).unwrap().execute_batch(include_str!("fixtures/invalid_category.sql")).unwrap();
+
+        let spool = Spool::open(&path).unwrap();
+        assert_eq!((spool.sent_count().unwrap(), spool.pending_count().unwrap()), (1, 1));
+        let letters = spool.dead_letters_for("01M5445SJ8DS7H1393EAB6HHS3").unwrap();
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].sink, MIGRATION_SINK);
+        assert!(letters[0].payload.contains(r#""category":"Browsing""#), "{}", letters[0].payload);
+        drop(spool);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn a_spool_from_a_newer_tracker_is_refused() {
+        let path = temp_spool("newer");
+        Connection::open(&path)
+            .unwrap()
+            .pragma_update(None, "user_version", CURRENT_VERSION + 1)
+            .unwrap();
+        let error = Spool::open(&path).err().expect("newer schema must not open");
+        assert!(error.to_string().contains("newer than this tracker supports"), "{error}");
+        let _ = std::fs::remove_file(&path);
+    }
+}
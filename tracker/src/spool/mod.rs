@@ -1,13 +1,38 @@
 //! Local durable spool. Every completed span is written here synchronously
 //! (local disk only, never network) before the decoupled ingest flusher
 //! (crate::ingest) ever sees it, so capture durability never depends on
-//! Convex/network availability. Table shape is exactly the one specified in
-//! the contract: spool(sourceId TEXT PK, payload JSON, createdAt, sentAt NULL).
+//! Convex/network availability.
+//!
+//! Each `IngestRow` field is its own typed column (span start, duration,
+//! program, sub-program, bucket, category, actor, idle flag, counters), so
+//! local questions -- "how long in nvim today?" -- are plain SQL over
+//! indexed `startedAt`/`bucket` instead of parsing every row. The JSON the
+//! sinks send is derived from those columns when a batch is claimed; the
+//! spool's own `createdAt`/`sentAt` are insert and delivery times.
+//!
+//! Delivery bookkeeping lives in side tables keyed by (sink, sourceId):
+//! `sink_sent` (handled by that sink), `delivery_failures` (rejected by
+//! that sink so far: attempts, last error, HTTP status) and `dead_letter`
+//! (rejected too often, or never parsable). A dead-lettered row keeps a copy
+//! of its payload and counts as handled for that sink, so it stops blocking
+//! the rows behind it; `backend dead-letter requeue` puts it back in line
+//! for that sink alone.
+//!
+//! The span capture is still extending sits in `open_span` (one row,
+//! rewritten every `open_span_persist_seconds`). Ending it moves it into
+//! `spool` in the same transaction; a tracker that died mid-span leaves it
+//! behind for `recover_open_span` on the next start, so a crash loses at
+//! most the time since the last save and nothing is counted twice.
+//!
+//! The schema is versioned with `PRAGMA user_version`; see `migrate`.
+
+pub mod archive;
+pub mod migrate;
 
 use std::path::Path;
 
 use chrono::{DateTime, Utc};
-use rusqlite::{params, Connection};
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
 use ulid::Ulid;
 
 use crate::category::Category;
@@ -55,6 +80,11 @@ pub struct IngestRow {
     pub middle_click_count: Option<usize>,
     #[serde(rename = "tokensSpent", skip_serializing_if = "Option::is_none")]
     pub tokens_spent: Option<f64>,
+    /// Why an idle span is idle beyond "no input": a gap row's
+    /// `GapReason` (crate::heartbeat), or `locked` / `idle-hint` for a
+    /// span captured while logind said so (crate::session).
+    #[serde(rename = "idleReason", skip_serializing_if = "Option::is_none")]
+    pub idle_reason: Option<String>,
 }
 
 impl IngestRow {
@@ -83,12 +113,46 @@ impl IngestRow {
             right_click_count: log.right_click_count,
             middle_click_count: log.middle_click_count,
             tokens_spent: None,
+            idle_reason: log.idle_reason.clone(),
         }
     }
 }
 
+/// One `dead_letter` row as shown by `dead-letter list` / `show`.
+#[derive(Debug, Clone)]
+pub struct DeadLetter {
+    pub sink: String,
+    pub source_id: String,
+    pub payload: String,
+    pub created_at: String,
+    pub attempts: u32,
+    pub last_error: String,
+    pub http_status: Option<u16>,
+    pub dead_at: String,
+}
+
+/// Span columns, in `IngestRow` order, read back by `row_from_columns`.
+const ROW_COLUMNS: &str = "sourceId, startedAt, durationMs, category, isIdle, deviceName, actor, windowId,
+    programProcessName, programName, subProgram, tmuxSession, bucket, browserTitle, keysPressedCount,
+    mouseMovementInMM, leftClickCount, rightClickCount, middleClickCount, tokensSpent, idleReason";
+
+/// A claimed row: its sourceId and the span, or why the columns don't
+/// make one (only possible after a hand edit).
+pub type Claimed = (String, Result<IngestRow, String>);
+
+/// One spool row as shown by `spool list`; `payload` is the derived JSON.
+#[derive(Debug, Clone)]
+pub struct SpoolEntry {
+    pub source_id: String,
+    pub payload: String,
+    pub created_at: String,
+    pub sent_at: Option<String>,
+}
+
 pub struct Spool {
     conn: Connection,
+    /// Poked after every `enqueue`, see `notify_on_enqueue`.
+    enqueued: Option<tokio::sync::watch::Sender<()>>,
 }
 
 impl Spool {
@@ -97,50 +161,131 @@ impl Spool {
             let _ = std::fs::create_dir_all(parent);
         }
 
-        let conn = Connection::open(path)?;
+        let mut conn = Connection::open(path)?;
         conn.pragma_update(None, "journal_mode", "WAL")?;
         conn.pragma_update(None, "synchronous", "NORMAL")?;
         conn.pragma_update(None, "busy_timeout", 5000)?;
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS spool (
-                sourceId TEXT PRIMARY KEY,
-                payload TEXT NOT NULL,
-                createdAt TEXT NOT NULL,
-                sentAt TEXT
-            )",
-            [],
-        )?;
-        conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_spool_pending ON spool (sentAt, createdAt)",
-            [],
-        )?;
+        migrate::migrate(&mut conn)?;
 
-        Ok(Self { conn })
+        Ok(Self { conn, enqueued: None })
+    }
+
+    /// Opens an existing spool as-is for inspection (`doctor`): never
+    /// creates the file, never migrates it. Returns the schema version
+    /// alongside, which may be older than `migrate::CURRENT_VERSION`.
+    pub fn open_existing(path: &Path) -> rusqlite::Result<(Self, u32)> {
+        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_NO_MUTEX)?;
+        conn.pragma_update(None, "busy_timeout", 5000)?;
+        let version = migrate::user_version(&conn)?;
+        Ok((Self { conn, enqueued: None }, version))
+    }
+
+    /// Private in-memory spool for tests.
+    #[cfg(test)]
+    pub(crate) fn open_in_memory() -> rusqlite::Result<Self> {
+        Self::open(Path::new(":memory:"))
+    }
+
+    /// Wakes the flusher (crate::ingest) after each `enqueue` on this
+    /// connection. A watch channel, so pokes the flusher hasn't seen yet
+    /// coalesce into one and sending never blocks or fails.
+    pub fn notify_on_enqueue(&mut self, sender: tokio::sync::watch::Sender<()>) {
+        self.enqueued = Some(sender);
     }
 
     /// Builds the wire row from a just-completed span and durably inserts it.
     /// Local-disk only -- never blocks on network.
+    /// Clears `open_span` in the same transaction.
     pub fn enqueue(&self, log: &Log, device_name: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let row = IngestRow::from_log(log, device_name);
-        let payload = serde_json::to_string(&row)?;
-        let created_at = Utc::now().to_rfc3339();
+        let tx = self.conn.unchecked_transaction()?;
+        let inserted = insert_row(&tx, &IngestRow::from_log(log, device_name), &Utc::now().to_rfc3339(), false)?;
+        tx.execute("DELETE FROM open_span", [])?;
+        tx.commit()?;
+        if inserted {
+            if let Some(enqueued) = &self.enqueued {
+                enqueued.send_replace(());
+            }
+        }
+        Ok(())
+    }
 
+    /// Saves the span capture is still extending, as far as it has got,
+    /// over the previous save. Local-disk only.
+    pub fn save_open_span(&self, log: &Log, device_name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let payload = serde_json::to_string(&IngestRow::from_log(log, device_name))?;
         self.conn.execute(
-            "INSERT OR IGNORE INTO spool (sourceId, payload, createdAt, sentAt) VALUES (?1, ?2, ?3, NULL)",
-            params![row.source_id, payload, created_at],
+            "INSERT OR REPLACE INTO open_span (slot, payload, savedAt) VALUES (1, ?1, ?2)",
+            params![payload, Utc::now().to_rfc3339()],
         )?;
-
         Ok(())
     }
 
-    /// Oldest-first, unsent rows, up to `limit`. Returns (sourceId, payload).
-    pub fn claim_batch(&self, limit: usize) -> rusqlite::Result<Vec<(String, String)>> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT sourceId, payload FROM spool WHERE sentAt IS NULL ORDER BY createdAt ASC LIMIT ?1")?;
+    /// Moves a span left in `open_span` by a tracker that stopped without
+    /// ending it into the spool, ending where it was last saved. Returns
+    /// it, if there was one. A payload that no longer parses is dropped.
+    pub fn recover_open_span(&self) -> rusqlite::Result<Option<IngestRow>> {
+        let tx = self.conn.unchecked_transaction()?;
+        let payload: Option<String> =
+            tx.query_row("SELECT payload FROM open_span WHERE slot = 1", [], |row| row.get(0)).optional()?;
+        let Some(payload) = payload else { return Ok(None) };
+        let row = match serde_json::from_str::<IngestRow>(&payload) {
+            Ok(row) => {
+                insert_row(&tx, &row, &Utc::now().to_rfc3339(), false)?;
+                Some(row)
+            }
+            Err(e) => {
+                println!("chronomaxi spool: dropping unreadable open span ({e}): {payload}");
+                None
+            }
+        };
+        tx.execute("DELETE FROM open_span", [])?;
+        tx.commit()?;
+        if row.is_some() {
+            if let Some(enqueued) = &self.enqueued {
+                enqueued.send_replace(());
+            }
+        }
+        Ok(row)
+    }
+
+    /// Inserts an already-built row; `false` when its sourceId is already
+    /// spooled (the existing row is kept).
+    pub fn enqueue_row(&self, row: &IngestRow) -> rusqlite::Result<bool> {
+        let inserted = insert_row(&self.conn, row, &Utc::now().to_rfc3339(), false)?;
+        if inserted {
+            if let Some(enqueued) = &self.enqueued {
+                enqueued.send_replace(());
+            }
+        }
+        Ok(inserted)
+    }
+
+    /// `enqueue_row` for many rows in one transaction (bulk imports).
+    /// Returns how many were new.
+    pub fn enqueue_rows(&mut self, rows: &[IngestRow]) -> rusqlite::Result<usize> {
+        let now = Utc::now().to_rfc3339();
+        let tx = self.conn.transaction()?;
+        let mut inserted = 0;
+        for row in rows {
+            inserted += insert_row(&tx, row, &now, false)? as usize;
+        }
+        tx.commit()?;
+        if inserted > 0 {
+            if let Some(enqueued) = &self.enqueued {
+                enqueued.send_replace(());
+            }
+        }
+        Ok(inserted)
+    }
+
+    /// Oldest-first, unsent rows, up to `limit`.
+    pub fn claim_batch(&self, limit: usize) -> rusqlite::Result<Vec<Claimed>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {ROW_COLUMNS} FROM spool WHERE sentAt IS NULL ORDER BY createdAt ASC LIMIT ?1"
+        ))?;
 
         let rows = stmt
-            .query_map(params![limit as i64], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?
+            .query_map(params![limit as i64], claimed_from_row)?
             .collect::<Result<Vec<_>, _>>()?;
 
         Ok(rows)
@@ -166,6 +311,250 @@ impl Spool {
         Ok(())
     }
 
+    /// Like `claim_batch`, but skips rows `sink` already delivered.
+    pub fn claim_batch_for(&self, sink: &str, limit: usize) -> rusqlite::Result<Vec<Claimed>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {ROW_COLUMNS} FROM spool
+             WHERE sentAt IS NULL
+               AND NOT EXISTS (SELECT 1 FROM sink_sent WHERE sink = ?1 AND sink_sent.sourceId = spool.sourceId)
+             ORDER BY createdAt ASC LIMIT ?2"
+        ))?;
+
+        let rows = stmt
+            .query_map(params![sink, limit as i64], claimed_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(rows)
+    }
+
+    /// Records that `sink` delivered `source_ids`, then settles every row
+    /// all of `all_sinks` now have (see `settle`).
+    pub fn mark_sent_for(&mut self, sink: &str, source_ids: &[String], all_sinks: &[String]) -> rusqlite::Result<()> {
+        let now = Utc::now().to_rfc3339();
+        let tx = self.conn.transaction()?;
+        {
+            let mut stmt =
+                tx.prepare("INSERT OR IGNORE INTO sink_sent (sink, sourceId, sentAt) VALUES (?1, ?2, ?3)")?;
+            let mut clear = tx.prepare("DELETE FROM delivery_failures WHERE sink = ?1 AND sourceId = ?2")?;
+            for id in source_ids {
+                stmt.execute(params![sink, id, now])?;
+                clear.execute(params![sink, id])?;
+            }
+        }
+        tx.commit()?;
+        self.settle(all_sinks)?;
+        Ok(())
+    }
+
+    /// Counts one rejection by `sink` against each of `source_ids`. Rows
+    /// that have now been rejected `dead_after` times are moved to
+    /// `dead_letter` (and recorded as handled for `sink`); returns how many.
+    /// `dead_after = 1` dead-letters straight away, e.g. for a payload that
+    /// doesn't even parse.
+    pub fn record_failure(
+        &mut self,
+        sink: &str,
+        source_ids: &[String],
+        error: &str,
+        http_status: Option<u16>,
+        dead_after: u32,
+        all_sinks: &[String],
+    ) -> rusqlite::Result<usize> {
+        let now = Utc::now().to_rfc3339();
+        let mut dead = 0;
+        let tx = self.conn.transaction()?;
+        {
+            let mut bump = tx.prepare(
+                "INSERT INTO delivery_failures (sink, sourceId, attempts, lastError, httpStatus, lastAttemptAt)
+                 VALUES (?1, ?2, 1, ?3, ?4, ?5)
+                 ON CONFLICT (sink, sourceId) DO UPDATE SET
+                    attempts = attempts + 1,
+                    lastError = excluded.lastError,
+                    httpStatus = excluded.httpStatus,
+                    lastAttemptAt = excluded.lastAttemptAt
+                 RETURNING attempts",
+            )?;
+            let mut payload_of = tx.prepare(&format!("SELECT {ROW_COLUMNS} FROM spool WHERE sourceId = ?1"))?;
+            let mut bury = tx.prepare(
+                "INSERT OR REPLACE INTO dead_letter
+                    (sink, sourceId, payload, createdAt, attempts, lastError, httpStatus, deadAt)
+                 SELECT f.sink, f.sourceId, ?4, s.createdAt, f.attempts, f.lastError, f.httpStatus, ?3
+                 FROM delivery_failures f JOIN spool s ON s.sourceId = f.sourceId
+                 WHERE f.sink = ?1 AND f.sourceId = ?2",
+            )?;
+            let mut handled =
+                tx.prepare("INSERT OR IGNORE INTO sink_sent (sink, sourceId, sentAt) VALUES (?1, ?2, ?3)")?;
+            let mut clear = tx.prepare("DELETE FROM delivery_failures WHERE sink = ?1 AND sourceId = ?2")?;
+            for id in source_ids {
+                let attempts: u32 = bump.query_row(params![sink, id, error, http_status, now], |row| row.get(0))?;
+                if attempts >= dead_after.max(1) {
+                    let payload: Option<String> = payload_of.query_row(params![id], payload_from_row).optional()?;
+                    bury.execute(params![sink, id, now, payload])?;
+                    handled.execute(params![sink, id, now])?;
+                    clear.execute(params![sink, id])?;
+                    dead += 1;
+                }
+            }
+        }
+        tx.commit()?;
+        if dead > 0 {
+            self.settle(all_sinks)?;
+        }
+        Ok(dead)
+    }
+
+    /// Newest-first dead letters, optionally for one sink only.
+    pub fn dead_letters(&self, sink: Option<&str>, limit: usize) -> rusqlite::Result<Vec<DeadLetter>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT sink, sourceId, payload, createdAt, attempts, lastError, httpStatus, deadAt FROM dead_letter
+             WHERE ?1 IS NULL OR sink = ?1
+             ORDER BY deadAt DESC LIMIT ?2",
+        )?;
+        let rows = stmt
+            .query_map(params![sink, limit as i64], dead_letter_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    /// Every dead letter for `source_id` (one per sink that gave up on it).
+    pub fn dead_letters_for(&self, source_id: &str) -> rusqlite::Result<Vec<DeadLetter>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT sink, sourceId, payload, createdAt, attempts, lastError, httpStatus, deadAt FROM dead_letter
+             WHERE sourceId = ?1 ORDER BY sink",
+        )?;
+        let rows = stmt.query_map(params![source_id], dead_letter_from_row)?.collect::<Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    pub fn dead_letter_count(&self) -> rusqlite::Result<i64> {
+        self.conn.query_row("SELECT COUNT(*) FROM dead_letter", [], |row| row.get(0))
+    }
+
+    /// Replaces the stored payload of `source_id`'s dead letters (every
+    /// sink's, or just `sink`'s). Takes effect on `requeue`.
+    pub fn repair_dead_letter(&self, source_id: &str, sink: Option<&str>, payload: &str) -> rusqlite::Result<usize> {
+        self.conn.execute(
+            "UPDATE dead_letter SET payload = ?3 WHERE sourceId = ?1 AND (?2 IS NULL OR sink = ?2)",
+            params![source_id, sink, payload],
+        )
+    }
+
+    /// Puts dead letters back in line for the sink that gave up on them
+    /// alone: the (possibly repaired) payload is written back to the spool
+    /// row's columns -- recreated if it was pruned meanwhile -- and every
+    /// other configured sink is recorded as already having it, so nothing
+    /// is resent anywhere else. A letter from a sink that is no longer
+    /// configured (or set aside by `migrate::MIGRATION_SINK`) goes to every
+    /// sink. Letters whose payload doesn't parse as an `IngestRow` stay put
+    /// until repaired. `source_id = None` requeues everything (optionally
+    /// just `sink`'s). Returns how many were requeued.
+    pub fn requeue_dead_letters(
+        &mut self,
+        source_id: Option<&str>,
+        sink: Option<&str>,
+        all_sinks: &[String],
+    ) -> rusqlite::Result<usize> {
+        let now = Utc::now().to_rfc3339();
+        let tx = self.conn.transaction()?;
+        let letters = {
+            let mut stmt = tx.prepare(
+                "SELECT sink, sourceId, payload, createdAt, attempts, lastError, httpStatus, deadAt FROM dead_letter
+                 WHERE (?1 IS NULL OR sourceId = ?1) AND (?2 IS NULL OR sink = ?2)",
+            )?;
+            let rows = stmt.query_map(params![source_id, sink], dead_letter_from_row)?.collect::<Result<Vec<_>, _>>()?;
+            rows
+        };
+        let mut requeued = 0;
+        for letter in &letters {
+            let Ok(row) = serde_json::from_str::<IngestRow>(&letter.payload) else {
+                continue;
+            };
+            insert_row(&tx, &row, &letter.created_at, true)?;
+            let still_configured = all_sinks.contains(&letter.sink);
+            for other in all_sinks.iter().filter(|other| still_configured && **other != letter.sink) {
+                tx.execute(
+                    "INSERT OR IGNORE INTO sink_sent (sink, sourceId, sentAt) VALUES (?1, ?2, ?3)",
+                    params![other, letter.source_id, now],
+                )?;
+            }
+            tx.execute(
+                "DELETE FROM sink_sent WHERE sink = ?1 AND sourceId = ?2",
+                params![letter.sink, letter.source_id],
+            )?;
+            tx.execute(
+                "DELETE FROM dead_letter WHERE sink = ?1 AND sourceId = ?2",
+                params![letter.sink, letter.source_id],
+            )?;
+            requeued += 1;
+        }
+        tx.commit()?;
+        Ok(requeued)
+    }
+
+    /// Sets `sentAt` on every pending row that each of `all_sinks` has
+    /// delivered and drops their `sink_sent` entries. Also run at flusher
+    /// startup, so removing a sink from the config releases rows that were
+    /// only waiting on it.
+    pub fn settle(&self, all_sinks: &[String]) -> rusqlite::Result<usize> {
+        if all_sinks.is_empty() {
+            return Ok(0);
+        }
+
+        let now = Utc::now().to_rfc3339();
+        let placeholders = all_sinks.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+        let sql = format!(
+            "UPDATE spool SET sentAt = ?
+             WHERE sentAt IS NULL
+               AND (SELECT COUNT(DISTINCT sink) FROM sink_sent
+                    WHERE sink_sent.sourceId = spool.sourceId AND sink IN ({placeholders})) = ?"
+        );
+        let sink_count = all_sinks.len() as i64;
+        let mut param_values: Vec<&dyn rusqlite::ToSql> = Vec::with_capacity(all_sinks.len() + 2);
+        param_values.push(&now);
+        for sink in all_sinks {
+            param_values.push(sink);
+        }
+        param_values.push(&sink_count);
+        let settled = self.conn.execute(&sql, param_values.as_slice())?;
+
+        for table in ["sink_sent", "delivery_failures"] {
+            self.conn.execute(
+                &format!(
+                    "DELETE FROM {table} WHERE sourceId IN (SELECT sourceId FROM spool WHERE sentAt IS NOT NULL)
+                        OR sourceId NOT IN (SELECT sourceId FROM spool)"
+                ),
+                [],
+            )?;
+        }
+        Ok(settled)
+    }
+
+    /// Rows still waiting on `sink` specifically.
+    pub fn pending_count_for(&self, sink: &str) -> rusqlite::Result<i64> {
+        self.conn.query_row(
+            "SELECT COUNT(*) FROM spool
+             WHERE sentAt IS NULL
+               AND NOT EXISTS (SELECT 1 FROM sink_sent WHERE sink = ?1 AND sink_sent.sourceId = spool.sourceId)",
+            params![sink],
+            |row| row.get(0),
+        )
+    }
+
+    /// `pending_count_for(args[0])` restricted to rows matching `filter`,
+    /// an SQL condition on the spool's columns with `?N` bound to `args`.
+    pub fn pending_count_where(&self, filter: &str, args: &[String]) -> rusqlite::Result<i64> {
+        self.conn.query_row(
+            &format!(
+                "SELECT COUNT(*) FROM spool
+                 WHERE sentAt IS NULL
+                   AND NOT EXISTS (SELECT 1 FROM sink_sent WHERE sink = ?1 AND sink_sent.sourceId = spool.sourceId)
+                   AND {filter}"
+            ),
+            rusqlite::params_from_iter(args),
+            |row| row.get(0),
+        )
+    }
+
     /// Deletes sent rows older than `cutoff` (by spool-insert time, not span
     /// time). Pending rows are never pruned regardless of age.
     pub fn prune_sent_older_than(&self, cutoff: DateTime<Utc>) -> rusqlite::Result<usize> {
@@ -175,8 +564,369 @@ impl Spool {
         )
     }
 
+    /// Spooled spans (sent or not) starting in `[from_ms, to_ms)`, by start
+    /// time. Rows the columns can't turn into a span are skipped.
+    pub fn spans_between(&self, from_ms: i64, to_ms: i64) -> rusqlite::Result<Vec<IngestRow>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {ROW_COLUMNS} FROM spool WHERE startedAt >= ?1 AND startedAt < ?2 ORDER BY startedAt"
+        ))?;
+        let rows = stmt.query_map(params![from_ms, to_ms], claimed_from_row)?;
+        let mut spans = Vec::new();
+        for row in rows {
+            match row? {
+                (_, Ok(span)) => spans.push(span),
+                (id, Err(e)) => eprintln!("chronomaxi spool: skipping unreadable row {id}: {e}"),
+            }
+        }
+        Ok(spans)
+    }
+
     pub fn pending_count(&self) -> rusqlite::Result<i64> {
         self.conn
             .query_row("SELECT COUNT(*) FROM spool WHERE sentAt IS NULL", [], |row| row.get(0))
     }
+
+    pub fn sent_count(&self) -> rusqlite::Result<i64> {
+        self.conn
+            .query_row("SELECT COUNT(*) FROM spool WHERE sentAt IS NOT NULL", [], |row| row.get(0))
+    }
+
+    /// Takes and immediately releases the write lock, so `doctor` can tell a
+    /// read-only file/directory or a wedged lock holder apart from a healthy
+    /// spool without inserting anything.
+    pub fn check_writable(&self) -> rusqlite::Result<()> {
+        self.conn.execute_batch("BEGIN IMMEDIATE; ROLLBACK;")
+    }
+
+    /// Spool-insert time of the oldest unsent row, if any.
+    pub fn oldest_pending_created_at(&self) -> rusqlite::Result<Option<DateTime<Utc>>> {
+        let oldest: Option<String> =
+            self.conn
+                .query_row("SELECT MIN(createdAt) FROM spool WHERE sentAt IS NULL", [], |row| row.get(0))?;
+        Ok(oldest
+            .and_then(|value| DateTime::parse_from_rfc3339(&value).ok())
+            .map(|value| value.with_timezone(&Utc)))
+    }
+
+    /// Newest-first rows for inspection, optionally only unsent ones.
+    pub fn list(&self, limit: usize, pending_only: bool) -> rusqlite::Result<Vec<SpoolEntry>> {
+        let filter = if pending_only { "WHERE sentAt IS NULL" } else { "" };
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT createdAt, sentAt, {ROW_COLUMNS} FROM spool {filter} ORDER BY createdAt DESC LIMIT ?1"
+        ))?;
+        let rows = stmt
+            .query_map(params![limit as i64], |row| {
+                Ok(SpoolEntry {
+                    source_id: row.get(2)?,
+                    payload: payload_at(row, 2)?,
+                    created_at: row.get(0)?,
+                    sent_at: row.get(1)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    /// Deletes every sent row regardless of age, and every pending row too
+    /// when `include_pending` is set (that drops undelivered spans for good).
+    pub fn purge(&self, include_pending: bool) -> rusqlite::Result<usize> {
+        if include_pending {
+            self.conn.execute("DELETE FROM sink_sent", [])?;
+            self.conn.execute("DELETE FROM delivery_failures", [])?;
+            self.conn.execute("DELETE FROM spool", [])
+        } else {
+            self.conn.execute("DELETE FROM spool WHERE sentAt IS NOT NULL", [])
+        }
+    }
+}
+
+/// Writes `row` into the span columns. `requeue` resets an existing row to
+/// pending with these values; otherwise an existing sourceId is left
+/// alone. Returns whether a row was written.
+fn insert_row(conn: &Connection, row: &IngestRow, created_at: &str, requeue: bool) -> rusqlite::Result<bool> {
+    let conflict = if requeue {
+        "ON CONFLICT (sourceId) DO UPDATE SET
+            startedAt = excluded.startedAt, durationMs = excluded.durationMs, category = excluded.category,
+            isIdle = excluded.isIdle, deviceName = excluded.deviceName, actor = excluded.actor,
+            windowId = excluded.windowId, programProcessName = excluded.programProcessName,
+            programName = excluded.programName, subProgram = excluded.subProgram,
+            tmuxSession = excluded.tmuxSession, bucket = excluded.bucket, browserTitle = excluded.browserTitle,
+            keysPressedCount = excluded.keysPressedCount, mouseMovementInMM = excluded.mouseMovementInMM,
+            leftClickCount = excluded.leftClickCount, rightClickCount = excluded.rightClickCount,
+            middleClickCount = excluded.middleClickCount, tokensSpent = excluded.tokensSpent,
+            idleReason = excluded.idleReason, sentAt = NULL"
+    } else {
+        "ON CONFLICT (sourceId) DO NOTHING"
+    };
+    let written = conn.execute(
+        &format!(
+            "INSERT INTO spool (createdAt, sentAt, {ROW_COLUMNS})
+             VALUES (?1, NULL, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22)
+             {conflict}"
+        ),
+        params![
+            created_at,
+            row.source_id,
+            row.created_at,
+            row.duration_ms,
+            category_name(&row.category),
+            row.is_idle,
+            row.device_name,
+            row.actor,
+            row.window_id,
+            row.program_process_name,
+            row.program_name,
+            row.sub_program,
+            row.tmux_session,
+            row.bucket,
+            row.browser_title,
+            row.keys_pressed_count.map(|count| count as i64),
+            row.mouse_movement_in_mm,
+            row.left_click_count.map(|count| count as i64),
+            row.right_click_count.map(|count| count as i64),
+            row.middle_click_count.map(|count| count as i64),
+            row.tokens_spent,
+            row.idle_reason,
+        ],
+    )?;
+    Ok(written > 0)
+}
+
+/// "Coding", "Other", ... -- the same string `Category` has on the wire.
+pub(crate) fn category_name(category: &Category) -> String {
+    serde_json::to_value(category)
+        .ok()
+        .and_then(|value| value.as_str().map(str::to_string))
+        .unwrap_or_else(|| "Other".to_string())
+}
+
+/// Reads `ROW_COLUMNS` starting at column `offset` back into an `IngestRow`.
+fn row_from_columns(row: &rusqlite::Row<'_>, offset: usize) -> Result<IngestRow, String> {
+    let count = |index: usize| -> rusqlite::Result<Option<usize>> {
+        Ok(row.get::<_, Option<i64>>(offset + index)?.map(|count| count.max(0) as usize))
+    };
+    let read = || -> rusqlite::Result<(IngestRow, String)> {
+        let ingest_row = IngestRow {
+            source_id: row.get(offset)?,
+            created_at: row.get(offset + 1)?,
+            duration_ms: row.get(offset + 2)?,
+            category: Category::Other,
+            is_idle: row.get(offset + 4)?,
+            device_name: row.get(offset + 5)?,
+            actor: row.get(offset + 6)?,
+            window_id: row.get(offset + 7)?,
+            program_process_name: row.get(offset + 8)?,
+            program_name: row.get(offset + 9)?,
+            sub_program: row.get(offset + 10)?,
+            tmux_session: row.get(offset + 11)?,
+            bucket: row.get(offset + 12)?,
+            browser_title: row.get(offset + 13)?,
+            keys_pressed_count: count(14)?,
+            mouse_movement_in_mm: row.get(offset + 15)?,
+            left_click_count: count(16)?,
+            right_click_count: count(17)?,
+            middle_click_count: count(18)?,
+            tokens_spent: row.get(offset + 19)?,
+            idle_reason: row.get(offset + 20)?,
+        };
+        Ok((ingest_row, row.get(offset + 3)?))
+    };
+    let (mut ingest_row, category) = read().map_err(|e| e.to_string())?;
+    ingest_row.category = serde_json::from_value(serde_json::Value::String(category.clone()))
+        .map_err(|_| format!("unknown category {category:?}"))?;
+    Ok(ingest_row)
+}
+
+fn claimed_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Claimed> {
+    Ok((row.get(0)?, row_from_columns(row, 0)))
+}
+
+/// The wire JSON for `ROW_COLUMNS` at `offset`, or -- for a row the
+/// columns can't turn into an `IngestRow` -- the raw column values, so a
+/// dead letter still shows what was there.
+fn payload_at(row: &rusqlite::Row<'_>, offset: usize) -> rusqlite::Result<String> {
+    if let Some(payload) = row_from_columns(row, offset).ok().and_then(|ingest_row| serde_json::to_string(&ingest_row).ok()) {
+        return Ok(payload);
+    }
+    let mut raw = serde_json::Map::new();
+    for (index, name) in ROW_COLUMNS.split(',').map(str::trim).enumerate() {
+        let value = match row.get_ref(offset + index)? {
+            rusqlite::types::ValueRef::Null => serde_json::Value::Null,
+            rusqlite::types::ValueRef::Integer(value) => value.into(),
+            rusqlite::types::ValueRef::Real(value) => value.into(),
+            rusqlite::types::ValueRef::Text(value) | rusqlite::types::ValueRef::Blob(value) => {
+                String::from_utf8_lossy(value).into_owned().into()
+            }
+        };
+        raw.insert(name.to_string(), value);
+    }
+    Ok(serde_json::Value::Object(raw).to_string())
+}
+
+fn payload_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<String> {
+    payload_at(row, 0)
+}
+
+fn dead_letter_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<DeadLetter> {
+    Ok(DeadLetter {
+        sink: row.get(0)?,
+        source_id: row.get(1)?,
+        payload: row.get(2)?,
+        created_at: row.get(3)?,
+        attempts: row.get(4)?,
+        last_error: row.get(5)?,
+        http_status: row.get(6)?,
+        dead_at: row.get(7)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enqueue_three(spool: &Spool) -> Vec<String> {
+        for _ in 0..3 {
+            spool.enqueue(&Log::new(), "test-device").unwrap();
+        }
+        spool.claim_batch(10).unwrap().into_iter().map(|(source_id, _)| source_id).collect()
+    }
+
+    #[test]
+    fn an_open_span_is_recovered_once_and_cleared_by_a_normal_end() {
+        let spool = Spool::open_in_memory().unwrap();
+        let started = Utc::now() - chrono::Duration::seconds(30);
+        let mut log = Log::new();
+        log.current_program_name = Some("kitty".to_string());
+        log.log_start_time_utc = Some(started);
+        log.created_at = Some(started);
+        for seconds in [5, 10] {
+            log.log_end_time_utc = Some(started + chrono::Duration::seconds(seconds));
+            log.duration_ms = log.get_log_duration_ms();
+            spool.save_open_span(&log, "desk").unwrap();
+        }
+        assert_eq!(spool.pending_count().unwrap(), 0);
+
+        // Restart after a crash: the span ends at its last save, once.
+        let recovered = spool.recover_open_span().unwrap().unwrap();
+        assert_eq!((recovered.duration_ms, recovered.program_name.as_str()), (10_000, "kitty"));
+        assert_eq!(spool.pending_count().unwrap(), 1);
+        assert!(spool.recover_open_span().unwrap().is_none());
+        assert_eq!(spool.pending_count().unwrap(), 1);
+
+        // Ended normally: the spooled row replaces the saved one.
+        spool.save_open_span(&log, "desk").unwrap();
+        spool.enqueue(&log, "desk").unwrap();
+        assert!(spool.recover_open_span().unwrap().is_none());
+        assert_eq!(spool.pending_count().unwrap(), 2);
+    }
+
+    #[test]
+    fn list_and_purge_respect_pending_rows() {
+        let spool = Spool::open_in_memory().unwrap();
+        let ids = enqueue_three(&spool);
+        spool.mark_sent(&ids[..1]).unwrap();
+
+        assert_eq!(spool.pending_count().unwrap(), 2);
+        assert_eq!(spool.sent_count().unwrap(), 1);
+        assert!(spool.oldest_pending_created_at().unwrap().is_some());
+        assert_eq!(spool.list(10, false).unwrap().len(), 3);
+        assert!(spool.list(10, true).unwrap().iter().all(|entry| entry.sent_at.is_none()));
+
+        assert_eq!(spool.purge(false).unwrap(), 1);
+        assert_eq!(spool.pending_count().unwrap(), 2);
+        assert_eq!(spool.purge(true).unwrap(), 2);
+        assert!(spool.oldest_pending_created_at().unwrap().is_none());
+    }
+
+    #[test]
+    fn row_stays_pending_until_every_sink_has_it() {
+        let mut spool = Spool::open_in_memory().unwrap();
+        let ids = enqueue_three(&spool);
+        let sinks = vec!["convex".to_string(), "jsonl:/tmp/x".to_string()];
+
+        spool.mark_sent_for("jsonl:/tmp/x", &ids, &sinks).unwrap();
+        assert_eq!(spool.pending_count().unwrap(), 3);
+        assert_eq!(spool.pending_count_for("jsonl:/tmp/x").unwrap(), 0);
+        assert!(spool.claim_batch_for("jsonl:/tmp/x", 10).unwrap().is_empty());
+        assert_eq!(spool.claim_batch_for("convex", 10).unwrap().len(), 3);
+
+        spool.mark_sent_for("convex", &ids[..2], &sinks).unwrap();
+        assert_eq!(spool.pending_count().unwrap(), 1);
+        assert_eq!(spool.pending_count_for("convex").unwrap(), 1);
+
+        // Dropping the slow sink from the config releases the rest.
+        assert_eq!(spool.settle(&sinks[1..]).unwrap(), 1);
+        assert_eq!(spool.pending_count().unwrap(), 0);
+    }
+
+    #[test]
+    fn rejected_rows_dead_letter_and_requeue_for_that_sink_only() {
+        let mut spool = Spool::open_in_memory().unwrap();
+        let ids = enqueue_three(&spool);
+        let sinks = vec!["convex".to_string(), "stdout".to_string()];
+        spool.mark_sent_for("stdout", &ids, &sinks).unwrap();
+
+        assert_eq!(spool.record_failure("convex", &ids[..1], "status 400", Some(400), 2, &sinks).unwrap(), 0);
+        assert_eq!(spool.record_failure("convex", &ids[..1], "status 422", Some(422), 2, &sinks).unwrap(), 1);
+        assert_eq!(spool.pending_count_for("convex").unwrap(), 2);
+        assert_eq!(spool.sent_count().unwrap(), 1);
+
+        let letters = spool.dead_letters(None, 10).unwrap();
+        assert_eq!(letters.len(), 1);
+        assert_eq!((letters[0].attempts, letters[0].http_status), (2, Some(422)));
+        assert_eq!(letters[0].last_error, "status 422");
+
+        // The settled row gets pruned before anyone looks at the dead letter.
+        spool.prune_sent_older_than(Utc::now() + chrono::Duration::days(1)).unwrap();
+        let mut fixed: IngestRow = serde_json::from_str(&letters[0].payload).unwrap();
+        fixed.program_name = "fixed".to_string();
+        assert_eq!(spool.repair_dead_letter(&ids[0], None, &serde_json::to_string(&fixed).unwrap()).unwrap(), 1);
+        assert_eq!(spool.requeue_dead_letters(Some(&ids[0]), None, &sinks).unwrap(), 1);
+        assert_eq!(spool.dead_letter_count().unwrap(), 0);
+        assert_eq!(spool.pending_count_for("stdout").unwrap(), 0);
+        let batch = spool.claim_batch_for("convex", 10).unwrap();
+        assert_eq!(batch.len(), 3);
+        assert!(batch.iter().any(|(id, row)| *id == ids[0] && row.as_ref().unwrap().program_name == "fixed"));
+    }
+
+    #[test]
+    fn spans_round_trip_through_the_columns_and_are_indexed() {
+        let spool = Spool::open_in_memory().unwrap();
+        let mut log = Log::new();
+        log.created_at = Some(Utc::now());
+        log.duration_ms = Some(4_200);
+        log.category = Some(Category::Coding);
+        log.current_program_name = Some("nvim".to_string());
+        log.sub_program = Some("cargo".to_string());
+        log.bucket = Some("chronomaxi".to_string());
+        log.keys_pressed_count = Some(17);
+        log.mouse_movement_mm = Some(1.5);
+        spool.enqueue(&log, "desk").unwrap();
+
+        let (id, row) = spool.claim_batch(1).unwrap().remove(0);
+        let row = row.unwrap();
+        assert_eq!(row.source_id, id);
+        assert_eq!(row.created_at, log.created_at.unwrap().timestamp_millis());
+        assert_eq!((row.duration_ms, &row.category, row.device_name.as_str()), (4_200, &Category::Coding, "desk"));
+        assert_eq!((row.sub_program.as_deref(), row.bucket.as_deref()), (Some("cargo"), Some("chronomaxi")));
+        assert_eq!((row.keys_pressed_count, row.mouse_movement_in_mm, row.left_click_count), (Some(17), Some(1.5), None));
+        assert_eq!(spool.list(1, true).unwrap()[0].payload, serde_json::to_string(&row).unwrap());
+        assert!(!spool.enqueue_row(&row).unwrap(), "a sourceId is only spooled once");
+
+        let bucket_time: i64 = spool
+            .conn
+            .query_row("SELECT SUM(durationMs) FROM spool WHERE bucket = 'chronomaxi' AND startedAt >= 0", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(bucket_time, 4_200);
+        let plan: String = spool
+            .conn
+            .query_row("EXPLAIN QUERY PLAN SELECT * FROM spool WHERE startedAt >= 0", [], |row| row.get(3))
+            .unwrap();
+        assert!(plan.contains("idx_spool_started"), "{plan}");
+        let plan: String = spool
+            .conn
+            .query_row("EXPLAIN QUERY PLAN SELECT * FROM spool WHERE bucket = 'x'", [], |row| row.get(3))
+            .unwrap();
+        assert!(plan.contains("idx_spool_bucket"), "{plan}");
+    }
 }
@@ -0,0 +1,339 @@
+//! `backend summary`: time per bucket, category, program and sub-program,
+//! the active/idle split, input counts and human vs agent time over a
+//! crate::history range, computed from the local spool and archive so it
+//! works with Convex down or offline.
+//!
+//! Breakdowns count active (non-idle) time only; idle time is reported as
+//! its own total, and so is time the tracker wasn't running (gap rows,
+//! crate::heartbeat). An actor is "agent" when it is `agent` or `agent:<name>`,
+//! "human" when it is `human`; anything else only shows up per actor.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+
+use crate::category::Category;
+use crate::history::Range;
+use crate::spool::{category_name, IngestRow};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Table,
+    Json,
+    Markdown,
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(raw: &str) -> Result<Self, String> {
+        match raw {
+            "table" => Ok(Self::Table),
+            "json" => Ok(Self::Json),
+            "markdown" | "md" => Ok(Self::Markdown),
+            other => Err(format!("unknown format {other:?}, expected table, json or markdown")),
+        }
+    }
+}
+
+/// One breakdown line: a bucket, category, ... and its active time.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct Entry {
+    pub name: String,
+    #[serde(rename = "activeMs")]
+    pub active_ms: i64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Summary {
+    pub range: String,
+    pub from_ms: i64,
+    pub to_ms: i64,
+    pub spans: usize,
+    pub active_ms: i64,
+    pub idle_ms: i64,
+    pub gap_ms: i64,
+    pub human_ms: i64,
+    pub agent_ms: i64,
+    pub keys_pressed: u64,
+    pub left_clicks: u64,
+    pub right_clicks: u64,
+    pub middle_clicks: u64,
+    pub by_actor: Vec<Entry>,
+    pub by_bucket: Vec<Entry>,
+    pub by_category: Vec<Entry>,
+    pub by_program: Vec<Entry>,
+    pub by_sub_program: Vec<Entry>,
+}
+
+/// Label for spans without a bucket.
+const NO_BUCKET: &str = "(none)";
+
+impl Summary {
+    pub fn from_spans(range: &Range, spans: &[IngestRow]) -> Self {
+        let mut summary = Summary {
+            range: range.label.clone(),
+            from_ms: range.from_ms,
+            to_ms: range.to_ms,
+            spans: spans.len(),
+            ..Default::default()
+        };
+        let mut by_actor = HashMap::new();
+        let mut by_bucket = HashMap::new();
+        let mut by_category = HashMap::new();
+        let mut by_program = HashMap::new();
+        let mut by_sub_program = HashMap::new();
+
+        for span in spans {
+            let ms = span.duration_ms.max(0);
+            summary.keys_pressed += span.keys_pressed_count.unwrap_or(0) as u64;
+            summary.left_clicks += span.left_click_count.unwrap_or(0) as u64;
+            summary.right_clicks += span.right_click_count.unwrap_or(0) as u64;
+            summary.middle_clicks += span.middle_click_count.unwrap_or(0) as u64;
+            if span.category == Category::Untracked {
+                summary.gap_ms += ms;
+                continue;
+            }
+            if span.is_idle {
+                summary.idle_ms += ms;
+                continue;
+            }
+
+            summary.active_ms += ms;
+            if span.actor == "human" {
+                summary.human_ms += ms;
+            } else if span.actor == "agent" || span.actor.starts_with("agent:") {
+                summary.agent_ms += ms;
+            }
+            *by_actor.entry(span.actor.clone()).or_default() += ms;
+            *by_bucket.entry(span.bucket.clone().unwrap_or_else(|| NO_BUCKET.to_string())).or_default() += ms;
+            *by_category.entry(category_name(&span.category)).or_default() += ms;
+            *by_program.entry(span.program_name.clone()).or_default() += ms;
+            if let Some(sub_program) = &span.sub_program {
+                *by_sub_program.entry(sub_program.clone()).or_default() += ms;
+            }
+        }
+
+        summary.by_actor = ranked(by_actor);
+        summary.by_bucket = ranked(by_bucket);
+        summary.by_category = ranked(by_category);
+        summary.by_program = ranked(by_program);
+        summary.by_sub_program = ranked(by_sub_program);
+        summary
+    }
+
+    pub fn render(&self, format: Format) -> String {
+        match format {
+            Format::Json => serde_json::to_string_pretty(self).expect("summary serializes"),
+            Format::Table => self.table(),
+            Format::Markdown => self.markdown(),
+        }
+    }
+
+    fn breakdowns(&self) -> [(&'static str, &[Entry]); 5] {
+        [
+            ("bucket", &self.by_bucket),
+            ("category", &self.by_category),
+            ("program", &self.by_program),
+            ("sub_program", &self.by_sub_program),
+            ("actor", &self.by_actor),
+        ]
+    }
+
+    fn table(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "summary {} ({} spans)", self.range, self.spans);
+        let _ = writeln!(out, "  active  {}", duration(self.active_ms));
+        let _ = writeln!(out, "  idle    {}", duration(self.idle_ms));
+        if self.gap_ms > 0 {
+            let _ = writeln!(out, "  gaps    {}", duration(self.gap_ms));
+        }
+        let _ = writeln!(out, "  human   {}", duration(self.human_ms));
+        let _ = writeln!(out, "  agent   {}", duration(self.agent_ms));
+        let _ = writeln!(out, "  keys    {}", self.keys_pressed);
+        let _ = writeln!(
+            out,
+            "  clicks  {} left, {} right, {} middle",
+            self.left_clicks, self.right_clicks, self.middle_clicks
+        );
+        for (title, entries) in self.breakdowns() {
+            if entries.is_empty() {
+                continue;
+            }
+            let width = entries.iter().map(|entry| entry.name.chars().count()).max().unwrap_or(0);
+            let _ = writeln!(out, "\n{title}");
+            for entry in entries {
+                let _ = writeln!(
+                    out,
+                    "  {:<width$}  {:>8}  {:>3}%",
+                    entry.name,
+                    duration(entry.active_ms),
+                    self.share(entry.active_ms)
+                );
+            }
+        }
+        out
+    }
+
+    fn markdown(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "## Summary {}\n", self.range);
+        let _ = writeln!(out, "| | |\n|---|---:|");
+        let _ = writeln!(out, "| Spans | {} |", self.spans);
+        let _ = writeln!(out, "| Active | {} |", duration(self.active_ms));
+        let _ = writeln!(out, "| Idle | {} |", duration(self.idle_ms));
+        if self.gap_ms > 0 {
+            let _ = writeln!(out, "| Gaps | {} |", duration(self.gap_ms));
+        }
+        let _ = writeln!(out, "| Human | {} |", duration(self.human_ms));
+        let _ = writeln!(out, "| Agent | {} |", duration(self.agent_ms));
+        let _ = writeln!(out, "| Keys | {} |", self.keys_pressed);
+        let _ = writeln!(
+            out,
+            "| Clicks | {} left, {} right, {} middle |",
+            self.left_clicks, self.right_clicks, self.middle_clicks
+        );
+        for (title, entries) in self.breakdowns() {
+            if entries.is_empty() {
+                continue;
+            }
+            let _ = writeln!(out, "\n### By {title}\n\n| {title} | active | share |\n|---|---:|---:|");
+            for entry in entries {
+                let _ = writeln!(
+                    out,
+                    "| {} | {} | {}% |",
+                    entry.name.replace('|', "\\|"),
+                    duration(entry.active_ms),
+                    self.share(entry.active_ms)
+                );
+            }
+        }
+        out
+    }
+
+    /// Whole-percent share of active time.
+    fn share(&self, ms: i64) -> i64 {
+        if self.active_ms == 0 {
+            0
+        } else {
+            (ms * 100 + self.active_ms / 2) / self.active_ms
+        }
+    }
+}
+
+/// Most time first, then by name so equal entries keep a stable order.
+fn ranked(totals: HashMap<String, i64>) -> Vec<Entry> {
+    let mut entries: Vec<Entry> = totals.into_iter().map(|(name, active_ms)| Entry { name, active_ms }).collect();
+    entries.sort_by(|a, b| b.active_ms.cmp(&a.active_ms).then_with(|| a.name.cmp(&b.name)));
+    entries
+}
+
+/// "2h 05m", "12m", "40s".
+fn duration(ms: i64) -> String {
+    let seconds = ms / 1000;
+    match (seconds / 3600, seconds % 3600 / 60) {
+        (0, 0) => format!("{seconds}s"),
+        (0, minutes) => format!("{minutes}m"),
+        (hours, minutes) => format!("{hours}h {minutes:02}m"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heartbeat::GapReason;
+
+    fn span(program: &str, minutes: i64, actor: &str, bucket: Option<&str>, idle: bool) -> IngestRow {
+        IngestRow {
+            source_id: format!("{program}-{minutes}"),
+            created_at: 0,
+            duration_ms: minutes * 60_000,
+            category: if program == "nvim" { Category::Coding } else { Category::Research },
+            is_idle: idle,
+            device_name: "desk".to_string(),
+            actor: actor.to_string(),
+            window_id: "0x1".to_string(),
+            program_process_name: program.to_string(),
+            program_name: program.to_string(),
+            sub_program: (program == "nvim").then(|| "cargo".to_string()),
+            tmux_session: None,
+            bucket: bucket.map(str::to_string),
+            browser_title: None,
+            keys_pressed_count: Some(10),
+            mouse_movement_in_mm: None,
+            left_click_count: Some(2),
+            right_click_count: None,
+            middle_click_count: Some(1),
+            tokens_spent: None,
+            idle_reason: None,
+        }
+    }
+
+    fn example() -> Summary {
+        let range = Range { from_ms: 0, to_ms: 86_400_000, label: "2026-10-15".to_string() };
+        Summary::from_spans(
+            &range,
+            &[
+                span("nvim", 90, "human", Some("chronomaxi"), false),
+                span("nvim", 30, "agent:claude", Some("chronomaxi"), false),
+                span("firefox", 60, "human", None, false),
+                span("firefox", 20, "human", None, true),
+            ],
+        )
+    }
+
+    #[test]
+    fn active_time_is_broken_down_and_idle_kept_apart() {
+        let summary = example();
+        assert_eq!((summary.spans, summary.active_ms, summary.idle_ms), (4, 180 * 60_000, 20 * 60_000));
+        assert_eq!((summary.human_ms, summary.agent_ms), (150 * 60_000, 30 * 60_000));
+        assert_eq!((summary.keys_pressed, summary.left_clicks, summary.middle_clicks), (40, 8, 4));
+        assert_eq!(
+            summary.by_bucket,
+            [
+                Entry { name: "chronomaxi".to_string(), active_ms: 120 * 60_000 },
+                Entry { name: NO_BUCKET.to_string(), active_ms: 60 * 60_000 }
+            ]
+        );
+        assert_eq!(summary.by_category[0], Entry { name: "Coding".to_string(), active_ms: 120 * 60_000 });
+        assert_eq!(summary.by_sub_program, [Entry { name: "cargo".to_string(), active_ms: 120 * 60_000 }]);
+        assert_eq!(summary.by_actor.len(), 2);
+    }
+
+    #[test]
+    fn gap_rows_are_neither_active_nor_idle() {
+        let range = Range { from_ms: 0, to_ms: 86_400_000, label: "2026-10-15".to_string() };
+        let from = chrono::DateTime::<chrono::Utc>::from_timestamp_millis(0).unwrap();
+        let gap = crate::heartbeat::gap_row(from, from + chrono::Duration::hours(8), GapReason::Boot, "desk", "human");
+        let summary = Summary::from_spans(&range, &[span("nvim", 30, "human", None, false), gap]);
+        assert_eq!((summary.active_ms, summary.idle_ms, summary.gap_ms), (30 * 60_000, 0, 8 * 3_600_000));
+        assert_eq!(summary.by_category.len(), 1);
+        assert!(summary.render(Format::Table).contains("  gaps    8h 00m"));
+    }
+
+    #[test]
+    fn renders_as_table_json_and_markdown() {
+        let summary = example();
+        let table = summary.render(Format::Table);
+        assert!(table.contains("active  3h 00m"), "{table}");
+        assert!(table.contains("  chronomaxi    2h 00m   67%"), "{table}");
+
+        let json: serde_json::Value = serde_json::from_str(&summary.render(Format::Json)).unwrap();
+        assert_eq!(json["activeMs"], 180 * 60_000);
+        assert_eq!(json["byProgram"][0]["name"], "nvim");
+        assert_eq!(json["byProgram"][0]["activeMs"], 120 * 60_000);
+
+        let markdown = summary.render(Format::Markdown);
+        assert!(markdown.starts_with("## Summary 2026-10-15\n"), "{markdown}");
+        assert!(markdown.contains("| firefox | 1h 00m | 33% |"), "{markdown}");
+        assert_eq!("md".parse::<Format>(), Ok(Format::Markdown));
+        assert!("csv".parse::<Format>().is_err());
+    }
+
+    #[test]
+    fn durations_read_naturally() {
+        assert_eq!(duration(40_000), "40s");
+        assert_eq!(duration(12 * 60_000), "12m");
+        assert_eq!(duration(125 * 60_000), "2h 05m");
+    }
+}
@@ -0,0 +1,78 @@
+//! Helpers shared by the unit tests: unique scratch paths and a loopback
+//! HTTP stand-in for the sinks, `doctor` and the Convex client to talk to.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// A path under the system temp dir that no other test (in this run or a
+/// concurrent one) gets: pid, wall clock and a per-process counter. Nothing
+/// is created; see `temp_dir`.
+pub fn temp_path(label: &str) -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos();
+    std::env::temp_dir().join(format!(
+        "chronomaxi-{label}-{}-{nanos}-{}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+/// `temp_path`, created as an empty directory.
+pub fn temp_dir(label: &str) -> PathBuf {
+    let dir = temp_path(label);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Serves `127.0.0.1` on a free port until the test's runtime shuts down,
+/// answering every request `(head, raw body)` with what `respond` returns
+/// (see `response`). Returns the base URL.
+pub async fn serve<F>(mut respond: F) -> String
+where
+    F: FnMut(String, Vec<u8>) -> String + Send + 'static,
+{
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let (head, body) = read_request(&mut stream).await;
+            let _ = stream.write_all(respond(head, body).as_bytes()).await;
+        }
+    });
+    format!("http://{address}")
+}
+
+/// A whole `connection: close` response. `headers` is zero or more
+/// `"name: value\r\n"` lines.
+pub fn response(status_line: &str, headers: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 {status_line}\r\n{headers}content-length: {}\r\nconnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+/// Reads one whole request: (head, raw `content-length` body).
+async fn read_request(stream: &mut tokio::net::TcpStream) -> (String, Vec<u8>) {
+    let mut buf: Vec<u8> = Vec::new();
+    let mut chunk = vec![0u8; 16 * 1024];
+    loop {
+        let n = stream.read(&mut chunk).await.unwrap_or(0);
+        buf.extend_from_slice(&chunk[..n]);
+        let Some(header_end) = buf.windows(4).position(|window| window == b"\r\n\r\n") else {
+            if n == 0 {
+                return (String::from_utf8_lossy(&buf).into_owned(), Vec::new());
+            }
+            continue;
+        };
+        let head = String::from_utf8_lossy(&buf[..header_end]).into_owned();
+        let length = head
+            .lines()
+            .find_map(|line| line.to_ascii_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse().unwrap_or(0)))
+            .unwrap_or(0);
+        if n == 0 || buf.len() >= header_end + 4 + length {
+            return (head, buf[header_end + 4..].to_vec());
+        }
+    }
+}
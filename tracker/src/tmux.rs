@@ -10,11 +10,12 @@
 //!      `$XDG_STATE_HOME/chronomaxi/foreground` (or
 //!      `~/.local/state/chronomaxi/foreground`) on every prompt/pane/
 //!      window/session change. Free to read (no subprocess) and preferred
-//!      whenever fresh (< `PUSH_FRESHNESS`).
+//!      whenever fresh (< `Configuration::tmux_push_freshness_ms`).
 //!   2. IPC PULL fallback: focused-window pid -> `/proc` walk to the
 //!      youngest descendant `tmux` (client) process -> that client's
 //!      controlling tty -> `tmux display-message` for the active pane's
-//!      `#{pane_current_command}`. Rate-limited (`IPC_MIN_INTERVAL`) since
+//!      `#{pane_current_command}`. Rate-limited
+//!      (`Configuration::tmux_ipc_min_interval_ms`) since
 //!      it forks 2-3 processes; only invoked when the push file is
 //!      missing or stale. Most terminal windows have nothing to drill into
 //!      (no tmux, or a pane not attached to any client we can find) and
@@ -32,15 +33,6 @@ use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 /// Terminal-emulator window classes that trigger sub-program drill-down.
 const TERMINAL_CLASSES: [&str; 2] = ["alacritty", "kitty"];
 
-/// Push state is preferred over the IPC fallback only while younger than
-/// this.
-const PUSH_FRESHNESS: Duration = Duration::from_secs(10);
-
-/// Minimum spacing between IPC fallback attempts (each one forks up to 3
-/// processes: a /proc walk is pure syscalls, but the two `tmux
-/// display-message` calls are real subprocesses).
-const IPC_MIN_INTERVAL: Duration = Duration::from_secs(2);
-
 /// Whether `program_process_name` (already lower-cased by callers, but
 /// this normalizes defensively) is a terminal emulator we drill into.
 pub fn is_terminal_class(program_process_name: &str) -> bool {
@@ -51,7 +43,7 @@ pub fn is_terminal_class(program_process_name: &str) -> bool {
 /// Basename + strip args, e.g. "/usr/bin/nvim file.rs" -> "nvim",
 /// "cargo build --release" -> "cargo". Returns `None` for empty input.
 pub fn normalize(raw: &str) -> Option<String> {
-    let first_word = raw.trim().split_whitespace().next()?;
+    let first_word = raw.split_whitespace().next()?;
     let base = Path::new(first_word).file_name()?.to_str()?;
     if base.is_empty() {
         None
@@ -60,7 +52,7 @@ pub fn normalize(raw: &str) -> Option<String> {
     }
 }
 
-fn foreground_state_path() -> PathBuf {
+pub fn foreground_state_path() -> PathBuf {
     let state_home = std::env::var("XDG_STATE_HOME").map(PathBuf::from).unwrap_or_else(|_| {
         std::env::var("HOME")
             .map(|home| PathBuf::from(home).join(".local/state"))
@@ -102,9 +94,9 @@ fn now_epoch_ms() -> i64 {
         .unwrap_or(0)
 }
 
-fn is_fresh(state: &PushState) -> bool {
+fn is_fresh(state: &PushState, freshness: Duration) -> bool {
     let age_ms = now_epoch_ms() - state.epoch_ms;
-    (0..PUSH_FRESHNESS.as_millis() as i64).contains(&age_ms)
+    (0..freshness.as_millis() as i64).contains(&age_ms)
 }
 
 fn read_push_state() -> Option<PushState> {
@@ -112,17 +104,37 @@ fn read_push_state() -> Option<PushState> {
     parse_push_line(content.lines().next()?)
 }
 
+/// Age of the current push line in milliseconds, for `doctor`. `None` when
+/// the file is missing or its first line doesn't parse.
+pub fn push_state_age_ms() -> Option<i64> {
+    read_push_state().map(|state| now_epoch_ms() - state.epoch_ms)
+}
+
 /// Resolves the terminal sub-program across ticks, throttling the IPC
 /// fallback so a busy capture loop never forks a `tmux` process more than
-/// once per `IPC_MIN_INTERVAL`.
+/// once per `ipc_min_interval`.
 pub struct TmuxResolver {
+    /// Push state is preferred over the IPC fallback only while younger
+    /// than this.
+    push_freshness: Duration,
+    /// Minimum spacing between IPC fallback attempts (each one forks up to
+    /// 3 processes: a /proc walk is pure syscalls, but the two `tmux
+    /// display-message` calls are real subprocesses).
+    ipc_min_interval: Duration,
     last_ipc_attempt: Option<Instant>,
     last_ipc_result: Option<TmuxContext>,
 }
 
 impl TmuxResolver {
     pub fn new() -> Self {
-        Self { last_ipc_attempt: None, last_ipc_result: None }
+        Self::with_intervals(
+            Duration::from_millis(crate::config::DEFAULT_TMUX_PUSH_FRESHNESS_MS),
+            Duration::from_millis(crate::config::DEFAULT_TMUX_IPC_MIN_INTERVAL_MS),
+        )
+    }
+
+    pub fn with_intervals(push_freshness: Duration, ipc_min_interval: Duration) -> Self {
+        Self { push_freshness, ipc_min_interval, last_ipc_attempt: None, last_ipc_result: None }
     }
 
     /// Resolves the sub-program for a terminal-class focused window.
@@ -131,7 +143,7 @@ impl TmuxResolver {
     /// here on every tick.
     pub fn resolve(&mut self, focused_pid: Option<i64>) -> TmuxContext {
         if let Some(push) = read_push_state() {
-            if is_fresh(&push) {
+            if is_fresh(&push, self.push_freshness) {
                 return TmuxContext {
                     sub_program: normalize(&push.cmd),
                     session: if push.session.is_empty() { None } else { Some(push.session) },
@@ -144,7 +156,7 @@ impl TmuxResolver {
 
     fn resolve_via_ipc(&mut self, focused_pid: Option<i64>) -> Option<TmuxContext> {
         let now = Instant::now();
-        let due = self.last_ipc_attempt.is_none_or(|last| now.duration_since(last) >= IPC_MIN_INTERVAL);
+        let due = self.last_ipc_attempt.is_none_or(|last| now.duration_since(last) >= self.ipc_min_interval);
         if !due {
             return self.last_ipc_result.clone();
         }
@@ -306,8 +318,9 @@ mod tests {
         let fresh = PushState { epoch_ms: now - 1000, session: "s".to_string(), cmd: "nvim".to_string() };
         let stale = PushState { epoch_ms: now - 20_000, session: "s".to_string(), cmd: "nvim".to_string() };
         let future = PushState { epoch_ms: now + 5_000, session: "s".to_string(), cmd: "nvim".to_string() };
-        assert!(is_fresh(&fresh));
-        assert!(!is_fresh(&stale));
-        assert!(!is_fresh(&future));
+        let freshness = Duration::from_millis(crate::config::DEFAULT_TMUX_PUSH_FRESHNESS_MS);
+        assert!(is_fresh(&fresh, freshness));
+        assert!(!is_fresh(&stale, freshness));
+        assert!(!is_fresh(&future, freshness));
     }
 }
@@ -0,0 +1,128 @@
+//! `validate` mode: strictly parses buckets.json and privacy-denylist.json
+//! and reports every problem at once instead of the runtime's "log and fall
+//! back" behavior. Parse errors carry serde_json's line/column, unknown keys
+//! are reported (privacy's config is `deny_unknown_fields`; buckets.json is
+//! loaded leniently at runtime and checked here), and pattern lists
+//! are checked for empty and duplicate entries -- both are silently skipped
+//! by the matchers, so they're almost always a typo.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::Configuration;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigIssue {
+    pub path: PathBuf,
+    /// 1-based, only known for parse errors.
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => write!(f, "{}:{}:{}: {}", self.path.display(), line, column, self.message),
+            _ => write!(f, "{}: {}", self.path.display(), self.message),
+        }
+    }
+}
+
+impl ConfigIssue {
+    pub fn new(path: &Path, message: impl Into<String>) -> Self {
+        Self { path: path.to_path_buf(), line: None, column: None, message: message.into() }
+    }
+
+    fn from_json_error(path: &Path, error: &serde_json::Error) -> Self {
+        // serde_json appends " at line L column C"; it's already the prefix.
+        let message = error.to_string();
+        let suffix = format!(" at line {} column {}", error.line(), error.column());
+        Self {
+            path: path.to_path_buf(),
+            line: Some(error.line()),
+            column: Some(error.column()),
+            message: message.strip_suffix(&suffix).unwrap_or(&message).to_string(),
+        }
+    }
+}
+
+/// Reads and strictly deserializes `path`, converting I/O and parse
+/// failures into a single issue.
+pub fn parse_json_file<T: serde::de::DeserializeOwned>(path: &Path) -> Result<T, ConfigIssue> {
+    let text = fs::read_to_string(path).map_err(|e| ConfigIssue::new(path, format!("cannot read: {e}")))?;
+    serde_json::from_str(&text).map_err(|e| ConfigIssue::from_json_error(path, &e))
+}
+
+/// Flags keys of the object `value` outside `known`. `field` is its JSON
+/// path, empty for the top level.
+pub fn check_keys(path: &Path, field: &str, value: &serde_json::Value, known: &[&str], issues: &mut Vec<ConfigIssue>) {
+    for key in value.as_object().into_iter().flat_map(|object| object.keys()) {
+        if !known.contains(&key.as_str()) {
+            let key = if field.is_empty() { key.clone() } else { format!("{field}.{key}") };
+            issues.push(ConfigIssue::new(path, format!("unknown key {key:?}")));
+        }
+    }
+}
+
+/// Flags empty/whitespace-only and case-insensitive duplicate patterns.
+/// `field` is the JSON path of the list, e.g. `rules[2].title_patterns`.
+pub fn check_patterns(path: &Path, field: &str, patterns: &[String], issues: &mut Vec<ConfigIssue>) {
+    let mut seen = HashSet::new();
+    for (index, pattern) in patterns.iter().enumerate() {
+        let normalized = pattern.trim().to_lowercase();
+        if normalized.is_empty() {
+            issues.push(ConfigIssue::new(path, format!("{field}[{index}] is an empty pattern")));
+        } else if !seen.insert(normalized) {
+            issues.push(ConfigIssue::new(path, format!("{field}[{index}] duplicates an earlier pattern {pattern:?}")));
+        }
+    }
+}
+
+/// Prints one line per issue (or "ok") for both rule files and returns
+/// whether everything validated cleanly.
+pub fn run(config: &Configuration) -> bool {
+    let mut clean = true;
+    for (path, issues) in [
+        (&config.bucket_config_path, crate::buckets::validate_file(&config.bucket_config_path)),
+        (&config.privacy_config_path, crate::privacy::validate_file(&config.privacy_config_path)),
+    ] {
+        if issues.is_empty() {
+            println!("{}: ok", path.display());
+        } else {
+            clean = false;
+            for issue in issues {
+                println!("{issue}");
+            }
+        }
+    }
+    clean
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_patterns_flags_empty_and_duplicate() {
+        let path = Path::new("buckets.json");
+        let mut issues = Vec::new();
+        let patterns = vec!["nvim".to_string(), "  ".to_string(), "NVim ".to_string()];
+        check_patterns(path, "rules[0].sub_program_patterns", &patterns, &mut issues);
+        assert_eq!(issues.len(), 2);
+        assert!(issues[0].message.contains("[1] is an empty pattern"));
+        assert!(issues[1].message.contains("[2] duplicates"));
+    }
+
+    #[test]
+    fn parse_error_carries_line_and_column() {
+        let path = crate::test_support::temp_path("validate");
+        fs::write(&path, "{\n  \"rules\": [,]\n}").unwrap();
+        let issue = parse_json_file::<serde_json::Value>(&path).unwrap_err();
+        assert_eq!(issue.line, Some(2));
+        assert!(issue.to_string().starts_with(&format!("{}:2:", path.display())));
+        let _ = fs::remove_file(path);
+    }
+}